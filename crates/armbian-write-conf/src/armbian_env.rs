@@ -0,0 +1,470 @@
+//! Structured editing of `armbianEnv.txt`, the u-boot environment Armbian's boot script
+//! imports: device-tree overlays, console, verbosity, extra kernel arguments, `fdtfile`
+//! and `rootdev`. The file is parsed as `key=value` lines; comments, blank lines, key
+//! order and unknown keys are kept as they are. It is edited wherever `/boot` lives:
+//! `/boot/armbianEnv.txt` on the rootfs, or `/armbianEnv.txt` on a separate ext4 or FAT
+//! boot partition.
+
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use ext4_view::{Ext4 as Ext4Ro, FileType};
+
+use crate::detect::Filesystem;
+use crate::inspect::{self, ImageLayout};
+use crate::validate::PartReader;
+use crate::{
+    batch, ext4_features, fat, validate, Entry, FileAttrs, WriteConfError, WriteConfReport,
+};
+
+/// Where the file sits on an ext4 partition: the rootfs' `/boot`, or the top of a boot partition.
+const EXT4_PATHS: [&str; 2] = ["/boot/armbianEnv.txt", "/armbianEnv.txt"];
+/// Where the file sits on a FAT boot partition.
+const FAT_PATH: &str = "/armbianEnv.txt";
+/// Highest kernel console log level.
+const MAX_VERBOSITY: u8 = 7;
+
+/// Where Armbian's boot script sends the kernel console (`console=`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    Serial,
+    Display,
+    Both,
+}
+
+impl Console {
+    /// Value written to `console=`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Console::Serial => "serial",
+            Console::Display => "display",
+            Console::Both => "both",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Pair {
+        key: String,
+        value: String,
+    },
+    /// Comment, blank or malformed line, kept verbatim.
+    Other(String),
+}
+
+/// Parsed `armbianEnv.txt`. Like u-boot's `env import`, the last of repeated keys wins,
+/// so reads and writes go to the last occurrence.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArmbianEnv {
+    lines: Vec<Line>,
+}
+
+impl ArmbianEnv {
+    pub fn parse(text: &str) -> Self {
+        let lines = text
+            .lines()
+            .map(|line| match line.split_once('=') {
+                Some((key, value)) if is_key(key) => Line::Pair {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                },
+                _ => Line::Other(line.to_string()),
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|line| match line {
+            Line::Pair { key: k, value } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Replace the value of `key` in place, or append it at the end.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), WriteConfError> {
+        if !is_key(key) {
+            return Err(WriteConfError::InvalidEntry(format!(
+                "armbianEnv key {key:?} must be non-empty, without whitespace, '=' or a leading '#'"
+            )));
+        }
+        if value.contains(['\n', '\r', '\0']) {
+            return Err(WriteConfError::InvalidEntry(format!(
+                "armbianEnv {key}: value must be a single line"
+            )));
+        }
+
+        let existing = self.lines.iter_mut().rev().find_map(|line| match line {
+            Line::Pair { key: k, value } if k == key => Some(value),
+            _ => None,
+        });
+        match existing {
+            Some(v) => *v = value.to_string(),
+            None => self.lines.push(Line::Pair {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+        }
+        Ok(())
+    }
+
+    /// Drop every occurrence of `key`.
+    pub fn remove(&mut self, key: &str) {
+        self.lines
+            .retain(|line| !matches!(line, Line::Pair { key: k, .. } if k == key));
+    }
+
+    /// Overlay names listed in `overlays=`, e.g. `uart1` for `<prefix>-uart1.dtbo`.
+    pub fn overlays(&self) -> Vec<&str> {
+        self.get("overlays")
+            .map(|v| v.split_whitespace().collect())
+            .unwrap_or_default()
+    }
+
+    /// Append `name` to `overlays=` unless it is already listed.
+    pub fn add_overlay(&mut self, name: &str) -> Result<(), WriteConfError> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(WriteConfError::InvalidEntry(format!(
+                "overlay name {name:?} must be non-empty and without whitespace"
+            )));
+        }
+        let mut overlays = self.overlays();
+        if overlays.contains(&name) {
+            return Ok(());
+        }
+        overlays.push(name);
+        let value = overlays.join(" ");
+        self.set("overlays", &value)
+    }
+
+    /// Remove `name` from `overlays=`; the key goes away with its last overlay.
+    pub fn remove_overlay(&mut self, name: &str) -> Result<(), WriteConfError> {
+        let overlays = self.overlays();
+        if !overlays.contains(&name) {
+            return Ok(());
+        }
+        let value = overlays
+            .into_iter()
+            .filter(|o| *o != name)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if value.is_empty() {
+            self.remove("overlays");
+            Ok(())
+        } else {
+            self.set("overlays", &value)
+        }
+    }
+
+    /// Kernel console log level, 0 (quiet) to 7 (debug).
+    pub fn set_verbosity(&mut self, level: u8) -> Result<(), WriteConfError> {
+        if level > MAX_VERBOSITY {
+            return Err(WriteConfError::InvalidEntry(format!(
+                "verbosity {level} is out of range 0..={MAX_VERBOSITY}"
+            )));
+        }
+        self.set("verbosity", &level.to_string())
+    }
+
+    pub fn set_console(&mut self, console: Console) -> Result<(), WriteConfError> {
+        self.set("console", console.as_str())
+    }
+
+    /// Extra kernel command-line arguments, appended by the boot script.
+    pub fn set_extraargs(&mut self, args: &str) -> Result<(), WriteConfError> {
+        self.set("extraargs", args)
+    }
+
+    /// Device tree to load, relative to the dtb directory (e.g. `rockchip/rk3588-foo.dtb`).
+    pub fn set_fdtfile(&mut self, fdtfile: &str) -> Result<(), WriteConfError> {
+        self.set("fdtfile", fdtfile)
+    }
+
+    /// Root filesystem device (e.g. `UUID=...` or `/dev/mmcblk0p2`).
+    pub fn set_rootdev(&mut self, rootdev: &str) -> Result<(), WriteConfError> {
+        self.set("rootdev", rootdev)
+    }
+}
+
+/// Renders the file back, one line per entry with a trailing newline.
+impl fmt::Display for ArmbianEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Pair { key, value } => writeln!(f, "{key}={value}")?,
+                Line::Other(text) => writeln!(f, "{text}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('#')
+        && !key.contains(|c: char| c.is_whitespace() || c == '=')
+}
+
+/// Partition holding `armbianEnv.txt`, with the path it was found under.
+struct Located {
+    offset: u64,
+    len: u64,
+    filesystem: Filesystem,
+    path: &'static str,
+    /// Mode and owner of the existing ext4 file, kept on rewrite.
+    attrs: FileAttrs,
+    text: String,
+}
+
+/// Read `armbianEnv.txt` from the image, apply `edit`, and write it back to the same
+/// partition, then validate read-only (see [`WriteConfReport`]). Partitions are tried in
+/// table order, so a separate boot partition wins over a stale copy on the rootfs.
+/// Errors ([`WriteConfError`]) when no partition holds the file, on an invalid edit, or
+/// on a failed write or validation.
+pub fn edit_armbian_env_in_image(
+    image_path: &Path,
+    edit: impl FnOnce(&mut ArmbianEnv) -> Result<(), WriteConfError>,
+) -> Result<WriteConfReport, WriteConfError> {
+    let layout = inspect::list_partitions(image_path)?;
+    let found = find(image_path, &layout)?;
+
+    let mut env = ArmbianEnv::parse(&found.text);
+    edit(&mut env)?;
+    let content = env.to_string().into_bytes();
+
+    let (written, ext4_features) = match found.filesystem {
+        Filesystem::Vfat => {
+            let written = fat::write(image_path, found.offset, found.len, found.path, &content)?;
+            fat::validate(image_path, found.offset, found.len, found.path, &content)?;
+            (written, None)
+        }
+        _ => {
+            let features = ext4_features::check_writable(image_path, found.offset)?;
+            let entries = [Entry::file(found.path, content).with_attrs(found.attrs)];
+            let written = batch::apply(image_path, found.offset, &entries)?;
+            validate::validate_entries(image_path, found.offset, &entries)?;
+            (written, Some(features))
+        }
+    };
+
+    Ok(WriteConfReport {
+        scheme: layout.scheme.as_str(),
+        partition_offset: found.offset,
+        partition_len: found.len,
+        dest_path: found.path.to_string(),
+        bytes_written: written,
+        validated: true,
+        ext4_features,
+    })
+}
+
+/// Parse `armbianEnv.txt` from whichever partition holds it, read-only.
+/// Errors with [`WriteConfError::UnsupportedImage`] when no partition does.
+pub fn read_armbian_env_from_image(image_path: &Path) -> Result<ArmbianEnv, WriteConfError> {
+    let layout = inspect::list_partitions(image_path)?;
+    let found = find(image_path, &layout)?;
+    Ok(ArmbianEnv::parse(&found.text))
+}
+
+/// First partition, in table order, that holds `armbianEnv.txt`.
+fn find(image_path: &Path, layout: &ImageLayout) -> Result<Located, WriteConfError> {
+    for p in &layout.partitions {
+        if let Some(found) = locate(image_path, p.offset, p.len, p.filesystem)? {
+            return Ok(found);
+        }
+    }
+    Err(WriteConfError::UnsupportedImage(
+        "no ext4 or FAT partition holds armbianEnv.txt".to_string(),
+    ))
+}
+
+/// Look for `armbianEnv.txt` on one partition; `None` if it is not there.
+fn locate(
+    image_path: &Path,
+    offset: u64,
+    len: u64,
+    filesystem: Filesystem,
+) -> Result<Option<Located>, WriteConfError> {
+    let located = |path: &'static str, attrs, bytes: Vec<u8>| {
+        let text = String::from_utf8(bytes)
+            .map_err(|_| WriteConfError::UnsupportedImage(format!("{path} is not valid UTF-8")))?;
+        Ok::<_, WriteConfError>(Some(Located {
+            offset,
+            len,
+            filesystem,
+            path,
+            attrs,
+            text,
+        }))
+    };
+
+    match filesystem {
+        Filesystem::Ext4 => {
+            let fs = Ext4Ro::load(Box::new(PartReader {
+                file: File::open(image_path)?,
+                base: offset,
+            }))
+            .map_err(|e| WriteConfError::Ext4Read(format!("ext4-view load failed: {e}")))?;
+            for path in EXT4_PATHS {
+                let Ok(md) = fs.metadata(path) else { continue };
+                if md.file_type() != FileType::Regular {
+                    continue;
+                }
+                let bytes = fs
+                    .read(path)
+                    .map_err(|e| WriteConfError::Ext4Read(format!("read {path}: {e}")))?;
+                let attrs = FileAttrs::new(md.mode() & 0o7777).with_owner(md.uid(), md.gid());
+                return located(path, attrs, bytes);
+            }
+            Ok(None)
+        }
+        Filesystem::Vfat => match fat::read(image_path, offset, len, FAT_PATH) {
+            Ok(bytes) => located(FAT_PATH, FileAttrs::default(), bytes),
+            Err(WriteConfError::Fat(_)) => Ok(None),
+            Err(e) => Err(e),
+        },
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    use fatfs::{FatType, FormatVolumeOptions};
+
+    const SAMPLE: &str = "verbosity=1\n\
+        bootlogo=false\n\
+        # keep this comment\n\
+        console=both\n\
+        overlay_prefix=rockchip\n\
+        overlays=uart1 i2c7\n\
+        rootdev=UUID=1234-abcd\n\
+        \n\
+        usbstoragequirks=0x2537:0x1066:u\n";
+
+    #[test]
+    fn parse_and_render_round_trip() {
+        let env = ArmbianEnv::parse(SAMPLE);
+        assert_eq!(env.to_string(), SAMPLE);
+        assert_eq!(env.get("rootdev"), Some("UUID=1234-abcd"));
+        assert_eq!(env.get("missing"), None);
+        assert_eq!(env.overlays(), ["uart1", "i2c7"]);
+
+        // Repeated keys: the last one wins and is the one edited.
+        let mut env = ArmbianEnv::parse("fdtfile=a.dtb\nfdtfile=b.dtb\n");
+        assert_eq!(env.get("fdtfile"), Some("b.dtb"));
+        env.set_fdtfile("c.dtb").unwrap();
+        assert_eq!(env.to_string(), "fdtfile=a.dtb\nfdtfile=c.dtb\n");
+    }
+
+    #[test]
+    fn typed_edits_keep_order_and_comments() {
+        let mut env = ArmbianEnv::parse(SAMPLE);
+        env.add_overlay("spi-spidev").unwrap();
+        env.add_overlay("uart1").unwrap();
+        env.remove_overlay("i2c7").unwrap();
+        env.set_verbosity(7).unwrap();
+        env.set_console(Console::Serial).unwrap();
+        env.set_rootdev("/dev/mmcblk0p2").unwrap();
+        env.set_extraargs("cma=256M net.ifnames=0").unwrap();
+        env.set_fdtfile("rockchip/rk3588-foo.dtb").unwrap();
+
+        assert_eq!(
+            env.to_string(),
+            "verbosity=7\n\
+             bootlogo=false\n\
+             # keep this comment\n\
+             console=serial\n\
+             overlay_prefix=rockchip\n\
+             overlays=uart1 spi-spidev\n\
+             rootdev=/dev/mmcblk0p2\n\
+             \n\
+             usbstoragequirks=0x2537:0x1066:u\n\
+             extraargs=cma=256M net.ifnames=0\n\
+             fdtfile=rockchip/rk3588-foo.dtb\n"
+        );
+
+        env.remove_overlay("uart1").unwrap();
+        env.remove_overlay("spi-spidev").unwrap();
+        assert_eq!(env.get("overlays"), None);
+        env.add_overlay("w1-gpio").unwrap();
+        assert!(env.to_string().ends_with("overlays=w1-gpio\n"));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let mut env = ArmbianEnv::parse(SAMPLE);
+        let before = env.clone();
+        for result in [
+            env.set_verbosity(8),
+            env.add_overlay("uart1 i2c7"),
+            env.add_overlay(""),
+            env.set_extraargs("quiet\nrootdev=/dev/sda1"),
+            env.set("#key", "x"),
+            env.set("a b", "x"),
+        ] {
+            assert!(matches!(result, Err(WriteConfError::InvalidEntry(_))));
+        }
+        assert_eq!(env, before);
+    }
+
+    #[test]
+    fn edits_file_on_fat_boot_partition() {
+        // MBR image with one FAT16 partition at 1 MiB holding armbianEnv.txt.
+        let (start, len) = (2048u64, 8u64 << 20);
+        let mut cur = Cursor::new(vec![0u8; (start * 512 + len) as usize]);
+        let mut mbr = mbrman::MBR::new_from(&mut cur, 512, [0x12, 0x34, 0x56, 0x78]).unwrap();
+        mbr[1] = mbrman::MBRPartitionEntry {
+            boot: mbrman::BOOT_INACTIVE,
+            first_chs: mbrman::CHS::empty(),
+            sys: 0x0E,
+            last_chs: mbrman::CHS::empty(),
+            starting_lba: start as u32,
+            sectors: (len / 512) as u32,
+        };
+        mbr.write_into(&mut cur).unwrap();
+
+        let mut volume = Cursor::new(vec![0u8; len as usize]);
+        let options = FormatVolumeOptions::new()
+            .fat_type(FatType::Fat16)
+            .total_sectors((len / 512) as u32);
+        fatfs::format_volume(&mut volume, options).unwrap();
+        {
+            let fs = fatfs::FileSystem::new(&mut volume, fatfs::FsOptions::new()).unwrap();
+            let mut f = fs.root_dir().create_file("armbianEnv.txt").unwrap();
+            f.write_all(SAMPLE.as_bytes()).unwrap();
+        }
+        let mut bytes = cur.into_inner();
+        bytes[(start * 512) as usize..].copy_from_slice(&volume.into_inner());
+
+        let mut tf = tempfile::NamedTempFile::new().unwrap();
+        tf.write_all(&bytes).unwrap();
+        tf.flush().unwrap();
+
+        let report = edit_armbian_env_in_image(tf.path(), |env| {
+            env.remove_overlay("uart1")?;
+            env.set_verbosity(3)
+        })
+        .unwrap();
+        assert!(report.validated);
+        assert_eq!(report.dest_path, "/armbianEnv.txt");
+        assert_eq!(report.partition_offset, start * 512);
+
+        let text = crate::read_file_from_fat_image(tf.path(), "/armbianEnv.txt").unwrap();
+        let env = ArmbianEnv::parse(std::str::from_utf8(&text).unwrap());
+        assert_eq!(env.overlays(), ["i2c7"]);
+        assert_eq!(env.get("verbosity"), Some("3"));
+
+        // A failed edit leaves the image untouched.
+        let err = edit_armbian_env_in_image(tf.path(), |env| env.set_verbosity(9)).unwrap_err();
+        assert!(matches!(err, WriteConfError::InvalidEntry(_)));
+        assert_eq!(
+            crate::read_file_from_fat_image(tf.path(), "/armbianEnv.txt").unwrap(),
+            text
+        );
+    }
+}
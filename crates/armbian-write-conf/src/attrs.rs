@@ -0,0 +1,128 @@
+//! Inode attributes (permission bits, owner, timestamps) for written files, set
+//! after the write and checked again on validation. sshd and sudo ignore key and
+//! drop-in files whose mode or owner is too open.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use armbian_ext4fs::{Ext4, Ext4Error};
+
+use crate::WriteConfError;
+
+/// File-type bits of an inode mode.
+pub(crate) const MODE_TYPE_MASK: u16 = 0o170000;
+/// Permission bits of an inode mode, including setuid/setgid/sticky.
+pub(crate) const MODE_PERM_MASK: u16 = 0o7777;
+
+/// Mode, owner and times given to a written inode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttrs {
+    /// Permission bits (e.g. `0o600`); the file type is never taken from here.
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// Access, modification and change times, seconds since the Unix epoch.
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+}
+
+impl FileAttrs {
+    /// Root-owned with `mode`, all three times set to now.
+    pub fn new(mode: u16) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        Self {
+            mode,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: u16) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Set all three times to `time`, so the same input always yields the same inode.
+    pub fn with_mtime(self, time: u32) -> Self {
+        self.with_times(time, time, time)
+    }
+
+    pub fn with_times(mut self, atime: u32, mtime: u32, ctime: u32) -> Self {
+        self.atime = atime;
+        self.mtime = mtime;
+        self.ctime = ctime;
+        self
+    }
+
+    /// Reject modes carrying file-type bits before anything is written.
+    pub(crate) fn check(&self, path: &str) -> Result<(), WriteConfError> {
+        if self.mode & !MODE_PERM_MASK != 0 {
+            return Err(WriteConfError::InvalidEntry(format!(
+                "{path}: mode {:o} has bits outside 0o7777",
+                self.mode
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write these attributes into inode `ino`, keeping its file type. The high 16
+    /// bits of uid/gid live in `osd2`.
+    pub(crate) fn apply(&self, fs: &Ext4, ino: u32) -> Result<(), Ext4Error> {
+        let mut inode = fs.get_inode_ref(ino)?;
+        let kind = inode.inode.mode() & MODE_TYPE_MASK;
+        inode.inode.set_mode(kind | (self.mode & MODE_PERM_MASK));
+        inode.inode.set_uid(self.uid as u16);
+        inode.inode.set_gid(self.gid as u16);
+        inode.inode.osd2.l_i_uid_high = (self.uid >> 16) as u16;
+        inode.inode.osd2.l_i_gid_high = (self.gid >> 16) as u16;
+        inode.inode.set_atime(self.atime);
+        inode.inode.set_mtime(self.mtime);
+        inode.inode.set_ctime(self.ctime);
+        fs.write_back_inode(&mut inode)
+    }
+}
+
+/// Root-owned 0644, stamped with the current time.
+impl Default for FileAttrs {
+    fn default() -> Self {
+        Self::new(0o644)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builders_set_fields_and_check_rejects_type_bits() {
+        let attrs = FileAttrs::new(0o600)
+            .with_owner(70000, 1000)
+            .with_mtime(1_700_000_000);
+        assert_eq!((attrs.uid, attrs.gid), (70000, 1000));
+        assert_eq!(
+            (attrs.atime, attrs.mtime, attrs.ctime),
+            (1_700_000_000, 1_700_000_000, 1_700_000_000)
+        );
+        assert!(attrs.check("/root/.ssh/authorized_keys").is_ok());
+        assert!(attrs.with_mode(0o4755).check("/usr/bin/x").is_ok());
+
+        let err = attrs
+            .with_mode(0o100644)
+            .check("/etc/hostname")
+            .unwrap_err();
+        assert!(matches!(err, WriteConfError::InvalidEntry(_)));
+        assert_eq!(FileAttrs::default().mode, 0o644);
+    }
+}
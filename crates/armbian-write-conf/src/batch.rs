@@ -0,0 +1,500 @@
+//! Batch injection: files, directories and symlinks, each with mode, owner and
+//! times, applied in one open of the rootfs and validated once afterwards.
+//! Missing parent directories are created (0755, root-owned); symlinked parents
+//! such as `/lib -> usr/lib` are followed.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use armbian_ext4fs::{Errno, Ext4, Ext4Error, InodeFileType};
+
+use crate::attrs::{FileAttrs, MODE_TYPE_MASK};
+use crate::{detect, ext4_features, validate, Ext4Features, PartDev, WriteConfError};
+
+/// Inode number of the root directory.
+const ROOT_INODE: u32 = 2;
+/// Inode flag: data is mapped by an extent tree (fast symlinks must not have it).
+const EXTENTS_FLAG: u32 = 0x0008_0000;
+/// Targets shorter than this are stored in the inode itself ("fast" symlinks).
+const FAST_SYMLINK_MAX: usize = 60;
+/// Longest symlink target ext4 accepts.
+const SYMLINK_MAX: usize = 4095;
+/// Longest name of one path component.
+const NAME_MAX: usize = 255;
+/// Symlinks followed while resolving parents before giving up (as Linux does).
+const MAX_SYMLINK_HOPS: usize = 40;
+/// Mode of parent directories created on the way.
+const PARENT_DIR_MODE: u16 = 0o755;
+
+/// What an [`Entry`] puts at its path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Regular file with this content; an existing file is replaced.
+    File(Vec<u8>),
+    /// Directory; an existing one is kept and only its attributes updated.
+    Dir,
+    /// Symbolic link to this target; an existing link is replaced.
+    Symlink(String),
+}
+
+/// One file, directory or symlink to write with [`write_entries_into_image`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Absolute destination path inside the rootfs.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Mode, owner and times; the file type comes from `kind`.
+    pub attrs: FileAttrs,
+}
+
+impl Entry {
+    /// A root-owned 0644 file, stamped with the current time.
+    pub fn file(path: &str, content: impl Into<Vec<u8>>) -> Self {
+        Self::new(path, EntryKind::File(content.into()), 0o644)
+    }
+
+    /// A root-owned 0755 directory, stamped with the current time.
+    pub fn dir(path: &str) -> Self {
+        Self::new(path, EntryKind::Dir, 0o755)
+    }
+
+    /// A root-owned symlink to `target`, stamped with the current time.
+    pub fn symlink(path: &str, target: &str) -> Self {
+        Self::new(path, EntryKind::Symlink(target.to_string()), 0o777)
+    }
+
+    pub fn with_attrs(mut self, attrs: FileAttrs) -> Self {
+        self.attrs = attrs;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: u16) -> Self {
+        self.attrs = self.attrs.with_mode(mode);
+        self
+    }
+
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.attrs = self.attrs.with_owner(uid, gid);
+        self
+    }
+
+    /// Set atime, mtime and ctime all to `mtime`.
+    pub fn with_mtime(mut self, mtime: u32) -> Self {
+        self.attrs = self.attrs.with_mtime(mtime);
+        self
+    }
+
+    fn new(path: &str, kind: EntryKind, mode: u16) -> Self {
+        Self {
+            path: path.to_string(),
+            kind,
+            attrs: FileAttrs::new(mode),
+        }
+    }
+
+    fn file_type(&self) -> InodeFileType {
+        match self.kind {
+            EntryKind::File(_) => InodeFileType::S_IFREG,
+            EntryKind::Dir => InodeFileType::S_IFDIR,
+            EntryKind::Symlink(_) => InodeFileType::S_IFLNK,
+        }
+    }
+}
+
+/// Outcome of a successful batch write-and-validate.
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    /// Partition scheme of the image ("GPT", "MBR" or "bare-ext4").
+    pub scheme: &'static str,
+    /// Byte offset of the rootfs partition within the image.
+    pub partition_offset: u64,
+    /// Byte length of the rootfs partition.
+    pub partition_len: u64,
+    /// Number of entries applied.
+    pub entries: usize,
+    /// File content bytes written, over all file entries.
+    pub bytes_written: usize,
+    /// True only when post-write validation fully succeeded.
+    pub validated: bool,
+    /// Feature flags of the ext4 filesystem written into.
+    pub ext4_features: Ext4Features,
+}
+
+/// Apply `entries` in order to the image's ext4 rootfs, then validate read-only once.
+/// The batch is checked up front ([`WriteConfError::InvalidEntry`]) so a bad entry
+/// leaves the image untouched.
+pub fn write_entries_into_image(
+    image_path: &Path,
+    entries: &[Entry],
+) -> Result<BatchReport, WriteConfError> {
+    check_entries(entries)?;
+    let part = detect::detect_rootfs(image_path)?;
+    let ext4_features = ext4_features::check_writable(image_path, part.offset)?;
+
+    let bytes_written = apply(image_path, part.offset, entries)?;
+    validate::validate_entries(image_path, part.offset, entries)?;
+
+    Ok(BatchReport {
+        scheme: part.scheme.as_str(),
+        partition_offset: part.offset,
+        partition_len: part.len,
+        entries: entries.len(),
+        bytes_written,
+        validated: true,
+        ext4_features,
+    })
+}
+
+/// [`write_entries_into_image`] for a BARE ext4 image (superblock at byte 0, no partition table).
+pub fn write_entries_into_bare_ext4_image(
+    image_path: &Path,
+    entries: &[Entry],
+) -> Result<BatchReport, WriteConfError> {
+    check_entries(entries)?;
+    detect::verify_ext4(image_path, 0)?;
+    let ext4_features = ext4_features::check_writable(image_path, 0)?;
+
+    let bytes_written = apply(image_path, 0, entries)?;
+    validate::validate_entries(image_path, 0, entries)?;
+
+    Ok(BatchReport {
+        scheme: "bare-ext4",
+        partition_offset: 0,
+        partition_len: std::fs::metadata(image_path)?.len(),
+        entries: entries.len(),
+        bytes_written,
+        validated: true,
+        ext4_features,
+    })
+}
+
+/// Reject the whole batch before anything is written.
+fn check_entries(entries: &[Entry]) -> Result<(), WriteConfError> {
+    let invalid = |path: &str, why: &str| WriteConfError::InvalidEntry(format!("{path}: {why}"));
+    let mut seen = HashSet::new();
+
+    for entry in entries {
+        let path = entry.path.as_str();
+        if !path.starts_with('/') {
+            return Err(invalid(path, "path must be absolute"));
+        }
+        let components: Vec<&str> = path[1..].split('/').collect();
+        if components
+            .iter()
+            .any(|c| c.is_empty() || *c == "." || *c == "..")
+        {
+            return Err(invalid(path, "path must be normalized and not the root"));
+        }
+        if components.iter().any(|c| c.len() > NAME_MAX) {
+            return Err(invalid(path, "path component longer than 255 bytes"));
+        }
+        entry.attrs.check(path)?;
+        if let EntryKind::Symlink(target) = &entry.kind {
+            if target.is_empty() || target.len() > SYMLINK_MAX || target.contains('\0') {
+                return Err(invalid(
+                    path,
+                    "symlink target must be 1..=4095 bytes without NUL",
+                ));
+            }
+        }
+        if !seen.insert(path) {
+            return Err(invalid(path, "listed more than once"));
+        }
+    }
+    Ok(())
+}
+
+/// Open the filesystem once, apply every entry, flush. Returns file bytes written.
+pub(crate) fn apply(
+    image_path: &Path,
+    base: u64,
+    entries: &[Entry],
+) -> Result<usize, WriteConfError> {
+    let file = OpenOptions::new().read(true).write(true).open(image_path)?;
+    let dev = PartDev::new(file, base);
+    let fs = dev.open()?;
+
+    let mut written = 0;
+    for entry in entries {
+        written += apply_entry(&fs, entry).map_err(|e| dev.io_error_or(e))?;
+    }
+
+    // Flush to disk before reloading for validation, then release handles.
+    dev.sync()?;
+    Ok(written)
+}
+
+fn apply_entry(fs: &Ext4, entry: &Entry) -> Result<usize, WriteConfError> {
+    let path = entry.path.as_str();
+    let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let parent = ensure_dir(fs, parent_path, entry.attrs.mtime, &mut 0)?;
+    let want = entry.file_type();
+
+    let existing = lookup(fs, parent, name)
+        .map_err(|e| WriteConfError::Ext4(format!("look up {path}: {e:?}")))?;
+    let ino = match existing {
+        Some((ino, kind)) if kind == want && want != InodeFileType::S_IFLNK => ino,
+        Some((_, kind)) if kind == InodeFileType::S_IFDIR || want == InodeFileType::S_IFDIR => {
+            return Err(WriteConfError::Ext4(format!(
+                "{path} already exists as a {}",
+                type_name(kind)
+            )));
+        }
+        Some((old, _)) => {
+            // Check before unlinking, so a refused entry is not left removed.
+            ext4_features::check_dir_insertable(fs, parent, parent_path)?;
+            remove(fs, parent, old, name, entry.attrs.ctime)
+                .map_err(|e| WriteConfError::Ext4(format!("replace {path}: {e:?}")))?;
+            create(fs, parent, name, want, path)?
+        }
+        None => {
+            ext4_features::check_dir_insertable(fs, parent, parent_path)?;
+            create(fs, parent, name, want, path)?
+        }
+    };
+
+    let written = match &entry.kind {
+        EntryKind::File(content) => {
+            let mut inode = fs
+                .get_inode_ref(ino)
+                .map_err(|e| WriteConfError::Ext4(format!("read inode of {path}: {e:?}")))?;
+            if inode.inode.size() > 0 {
+                fs.truncate_inode(&mut inode, 0)
+                    .map_err(|e| WriteConfError::Ext4(format!("truncate {path}: {e:?}")))?;
+            }
+            fs.write_at(ino, 0, content)
+                .map_err(|e| WriteConfError::Ext4(format!("write {path}: {e:?}")))?
+        }
+        EntryKind::Dir => 0,
+        EntryKind::Symlink(target) => {
+            write_symlink_target(fs, ino, target, path)?;
+            0
+        }
+    };
+
+    entry
+        .attrs
+        .apply(fs, ino)
+        .map_err(|e| WriteConfError::Ext4(format!("set attributes of {path}: {e:?}")))?;
+    Ok(written)
+}
+
+/// Inode of directory `path`, creating missing components. A component that is a
+/// symlink is resolved against the directories walked so far.
+fn ensure_dir(fs: &Ext4, path: &str, mtime: u32, hops: &mut usize) -> Result<u32, WriteConfError> {
+    let mut ino = ROOT_INODE;
+    let mut resolved = String::new();
+
+    for name in path.split('/').filter(|c| !c.is_empty()) {
+        let child_path = format!("{resolved}/{name}");
+        let found = lookup(fs, ino, name)
+            .map_err(|e| WriteConfError::Ext4(format!("look up {child_path}: {e:?}")))?;
+        ino = match found {
+            Some((child, InodeFileType::S_IFDIR)) => {
+                resolved = child_path;
+                child
+            }
+            Some((child, InodeFileType::S_IFLNK)) => {
+                *hops += 1;
+                if *hops > MAX_SYMLINK_HOPS {
+                    return Err(WriteConfError::Ext4(format!(
+                        "{child_path}: too many levels of symbolic links"
+                    )));
+                }
+                let target = read_symlink_target(fs, child, &child_path)?;
+                resolved = if target.starts_with('/') {
+                    normalize(&target)
+                } else {
+                    normalize(&format!("{resolved}/{target}"))
+                };
+                ensure_dir(fs, &resolved, mtime, hops)?
+            }
+            Some((_, kind)) => {
+                return Err(WriteConfError::Ext4(format!(
+                    "{child_path} is a {}, not a directory",
+                    type_name(kind)
+                )));
+            }
+            None => {
+                ext4_features::check_dir_insertable(fs, ino, &resolved)?;
+                let child = create(fs, ino, name, InodeFileType::S_IFDIR, &child_path)?;
+                FileAttrs::new(PARENT_DIR_MODE)
+                    .with_mtime(mtime)
+                    .apply(fs, child)
+                    .map_err(|e| {
+                        WriteConfError::Ext4(format!("set attributes of {child_path}: {e:?}"))
+                    })?;
+                resolved = child_path;
+                child
+            }
+        };
+    }
+    Ok(ino)
+}
+
+/// Resolve `.` and `..` in an absolute path (`..` at the root stays at the root).
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            c => parts.push(c),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+/// Inode number and file type of `name` in directory `parent`, `None` if missing.
+fn lookup(fs: &Ext4, parent: u32, name: &str) -> Result<Option<(u32, InodeFileType)>, Ext4Error> {
+    let attr = match fs.fuse_lookup(parent as u64, name) {
+        Ok(attr) => attr,
+        Err(e) if e.error() == Errno::ENOENT => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let ino = attr.ino as u32;
+    let mode = fs.get_inode_ref(ino)?.inode.mode();
+    Ok(Some((
+        ino,
+        InodeFileType::from_bits_truncate(mode & MODE_TYPE_MASK),
+    )))
+}
+
+fn create(
+    fs: &Ext4,
+    parent: u32,
+    name: &str,
+    kind: InodeFileType,
+    path: &str,
+) -> Result<u32, WriteConfError> {
+    fs.create_with_attr(parent, name, kind.bits(), 0, 0)
+        .map(|inode| inode.inode_num)
+        .map_err(|e| WriteConfError::Ext4(format!("create {path}: {e:?}")))
+}
+
+/// Short targets go into the inode's block map area; longer ones into a data block.
+fn write_symlink_target(
+    fs: &Ext4,
+    ino: u32,
+    target: &str,
+    path: &str,
+) -> Result<(), WriteConfError> {
+    if target.len() >= FAST_SYMLINK_MAX {
+        fs.write_at(ino, 0, target.as_bytes())
+            .map_err(|e| WriteConfError::Ext4(format!("write {path}: {e:?}")))?;
+        return Ok(());
+    }
+
+    let mut bytes = [0u8; FAST_SYMLINK_MAX];
+    bytes[..target.len()].copy_from_slice(target.as_bytes());
+    let mut inode = fs
+        .get_inode_ref(ino)
+        .map_err(|e| WriteConfError::Ext4(format!("read inode of {path}: {e:?}")))?;
+    for (word, chunk) in inode.inode.block.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    inode.inode.set_flags(inode.inode.flags() & !EXTENTS_FLAG);
+    inode.inode.set_size(target.len() as u64);
+    fs.write_back_inode(&mut inode)
+        .map_err(|e| WriteConfError::Ext4(format!("write {path}: {e:?}")))
+}
+
+fn read_symlink_target(fs: &Ext4, ino: u32, path: &str) -> Result<String, WriteConfError> {
+    let inode = fs
+        .get_inode_ref(ino)
+        .map_err(|e| WriteConfError::Ext4(format!("readlink {path}: {e:?}")))?
+        .inode;
+    let len = inode.size() as usize;
+    let bytes = if inode.flags() & EXTENTS_FLAG == 0 && len < FAST_SYMLINK_MAX {
+        inode
+            .block
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .take(len)
+            .collect()
+    } else {
+        let mut buf = vec![0u8; len];
+        let read = fs
+            .read_at(ino, 0, &mut buf)
+            .map_err(|e| WriteConfError::Ext4(format!("readlink {path}: {e:?}")))?;
+        buf.truncate(read);
+        buf
+    };
+    String::from_utf8(bytes)
+        .map_err(|_| WriteConfError::Ext4(format!("{path}: symlink target is not UTF-8")))
+}
+
+/// Unlink a non-directory. `fuse_unlink` truncates through the extent tree even for
+/// fast symlinks and empty files, and frees the inode while other links remain.
+fn remove(fs: &Ext4, parent: u32, ino: u32, name: &str, now: u32) -> Result<(), Ext4Error> {
+    let mut dir = fs.get_inode_ref(parent)?;
+    let mut inode = fs.get_inode_ref(ino)?;
+
+    let links = inode.inode.links_count();
+    if links > 1 {
+        fs.dir_remove_entry(&mut dir, name)?;
+        inode.inode.set_links_count(links - 1);
+        return fs.write_back_inode(&mut inode);
+    }
+
+    if inode.inode.flags() & EXTENTS_FLAG != 0 && inode.inode.size() > 0 {
+        fs.truncate_inode(&mut inode, 0)?;
+    }
+    inode.inode.set_links_count(0);
+    inode.inode.set_dtime(now);
+    fs.write_back_inode(&mut inode)?;
+    fs.unlink(&mut dir, &mut inode, name)?;
+    Ok(())
+}
+
+fn type_name(kind: InodeFileType) -> &'static str {
+    match kind {
+        InodeFileType::S_IFDIR => "directory",
+        InodeFileType::S_IFLNK => "symlink",
+        InodeFileType::S_IFREG => "file",
+        _ => "special file",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_and_duplicate_entries() {
+        let ok = [
+            Entry::dir("/etc/ssh"),
+            Entry::file("/etc/hostname", "rack2-01\n"),
+            Entry::symlink("/etc/localtime", "/usr/share/zoneinfo/UTC"),
+        ];
+        assert!(check_entries(&ok).is_ok());
+
+        for bad in [
+            Entry::file("etc/hostname", ""),
+            Entry::file("/", ""),
+            Entry::file("/etc/../shadow", ""),
+            Entry::file("/etc//hostname", ""),
+            Entry::file("/etc/hostname", "").with_mode(0o100644),
+            Entry::symlink("/etc/localtime", ""),
+        ] {
+            let err = check_entries(std::slice::from_ref(&bad)).unwrap_err();
+            assert!(
+                matches!(err, WriteConfError::InvalidEntry(_)),
+                "{bad:?} accepted"
+            );
+        }
+
+        let twice = [Entry::dir("/opt/x"), Entry::dir("/opt/x")];
+        assert!(check_entries(&twice).is_err());
+    }
+
+    #[test]
+    fn normalize_resolves_dot_segments() {
+        assert_eq!(normalize("/usr/lib"), "/usr/lib");
+        assert_eq!(normalize("/lib/../usr/./lib/"), "/usr/lib");
+        assert_eq!(normalize("/../etc"), "/etc");
+    }
+}
@@ -0,0 +1,1461 @@
+//! btrfs rootfs (Armbian `ROOTFS_TYPE=btrfs`): create or overwrite one small file in the
+//! default subvolume, in userspace. The file gets an inode, an inode ref, a dir item and
+//! dir index in its parent, and its content as an inline extent, so no data block, extent
+//! tree or checksum tree entry is needed. Items are inserted into the existing leaves in
+//! place (checksums recomputed, every mirror copy of DUP/RAID1 metadata written), which
+//! keeps block addresses, generations and back references valid. A full leaf is refused
+//! rather than split, and so is a subvolume that has been snapshotted or is a snapshot,
+//! since its leaves may be shared with another tree. Validation re-parses the trees
+//! read-only: every copy of every block of the chunk, root and subvolume trees is
+//! checksummed and structurally checked, then the file, its directory entries and its
+//! parent's size are checked.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::attrs::{FileAttrs, MODE_PERM_MASK, MODE_TYPE_MASK};
+use crate::WriteConfError;
+
+/// Primary superblock offset within the partition, and the bytes its checksum covers.
+const SUPER_OFFSET: u64 = 0x10000;
+const SUPER_SIZE: usize = 4096;
+const MAGIC: &[u8; 8] = b"_BHRfS_M";
+/// Every checksum field is 32 bytes; crc32c uses the first 4.
+const CSUM_SIZE: usize = 32;
+const CSUM_TYPE_CRC32C: u16 = 0;
+/// Tree block header, leaf item header and node key pointer sizes.
+const HEADER_SIZE: usize = 101;
+const ITEM_SIZE: usize = 25;
+const KEY_PTR_SIZE: usize = 33;
+const KEY_SIZE: usize = 17;
+const MAX_LEVEL: u8 = 7;
+
+/// Superblock flags and incompat features this writer refuses.
+const SUPER_FLAG_ERROR: u64 = 1 << 2;
+const SUPER_FLAG_SEEDING: u64 = 1 << 32;
+const SUPER_FLAG_CHANGING_FSID: u64 = 1 << 35;
+const SUPER_FLAG_CHANGING_FSID_V2: u64 = 1 << 36;
+const INCOMPAT_METADATA_UUID: u64 = 1 << 10;
+const INCOMPAT_ZONED: u64 = 1 << 12;
+const INCOMPAT_EXTENT_TREE_V2: u64 = 1 << 13;
+
+/// Chunk profiles: mirrored ones are written to every stripe, striped ones are refused.
+const PROFILE_MIRRORED: u64 = (1 << 4) | (1 << 5) | (1 << 9) | (1 << 10);
+const PROFILE_STRIPED: u64 = (1 << 3) | (1 << 6) | (1 << 7) | (1 << 8);
+
+/// Object ids.
+const FS_TREE_OBJECTID: u64 = 5;
+const ROOT_TREE_DIR_OBJECTID: u64 = 6;
+const FIRST_FREE_OBJECTID: u64 = 256;
+const LAST_FREE_OBJECTID: u64 = -256i64 as u64;
+
+/// Item types.
+const INODE_ITEM: u8 = 1;
+const INODE_REF: u8 = 12;
+const DIR_ITEM: u8 = 84;
+const DIR_INDEX: u8 = 96;
+const EXTENT_DATA: u8 = 108;
+const ROOT_ITEM: u8 = 132;
+const CHUNK_ITEM: u8 = 228;
+
+/// Directory entry file types.
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const S_IFREG: u16 = 0o100000;
+
+const INODE_ITEM_SIZE: usize = 160;
+/// Root item fields: root directory, root block and transid of the last snapshot.
+const ROOT_ITEM_DIRID: usize = 168;
+const ROOT_ITEM_BYTENR: usize = 176;
+const ROOT_ITEM_LAST_SNAPSHOT: usize = 200;
+/// Directory entry header: location key, transid, data_len, name_len, type.
+const DIR_ENTRY_HEADER: usize = 30;
+/// File extent item fields before inline data.
+const INLINE_DATA_START: usize = 21;
+/// First free directory index; 0 and 1 are `.` and `..`.
+const FIRST_DIR_INDEX: u64 = 2;
+/// The kernel's default `max_inline`.
+const MAX_INLINE: usize = 2048;
+const NAME_MAX: usize = 255;
+
+/// Item key; derived ordering matches btrfs (objectid, type, offset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    objectid: u64,
+    kind: u8,
+    offset: u64,
+}
+
+impl Key {
+    fn new(objectid: u64, kind: u8, offset: u64) -> Self {
+        Self {
+            objectid,
+            kind,
+            offset,
+        }
+    }
+
+    fn parse(b: &[u8]) -> Self {
+        Self::new(le64(b, 0), b[8], le64(b, 9))
+    }
+
+    fn put(&self, b: &mut [u8]) {
+        put64(b, 0, self.objectid);
+        b[8] = self.kind;
+        put64(b, 9, self.offset);
+    }
+}
+
+/// A logical range mapped onto one or more physical copies.
+struct Chunk {
+    logical: u64,
+    len: u64,
+    /// Physical offsets of the copies, relative to the partition.
+    stripes: Vec<u64>,
+}
+
+/// Position in a tree: (block, slot) from the root down to a leaf, where the leaf slot
+/// is where the searched key is or would be inserted.
+type TreePath = Vec<(u64, usize)>;
+
+/// Callback of a tree walk, given each leaf item's key and data.
+type ItemVisitor<'a> = dyn FnMut(Key, &[u8]) -> Result<(), WriteConfError> + 'a;
+
+/// One entry of a DIR_ITEM or DIR_INDEX item.
+struct DirEntry {
+    location: Key,
+    file_type: u8,
+    name: Vec<u8>,
+}
+
+/// An open btrfs filesystem. Modified tree blocks stay in `dirty` until [`Self::commit`],
+/// so a failed edit leaves the image untouched.
+struct Btrfs {
+    file: File,
+    base: u64,
+    nodesize: usize,
+    sectorsize: usize,
+    generation: u64,
+    /// The uuid every tree block header carries.
+    metadata_uuid: [u8; 16],
+    root: u64,
+    chunk_root: u64,
+    chunks: Vec<Chunk>,
+    dirty: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Btrfs {
+    fn open(image_path: &Path, base: u64, writable: bool) -> Result<Self, WriteConfError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(image_path)?;
+        let mut sb = vec![0u8; SUPER_SIZE];
+        read_exact_at(&file, base + SUPER_OFFSET, &mut sb)?;
+        if &sb[0x40..0x48] != MAGIC {
+            return Err(btrfs_err("no btrfs superblock at 64 KiB"));
+        }
+        if le16(&sb, 0xc4) != CSUM_TYPE_CRC32C {
+            return Err(btrfs_err(format!(
+                "checksum type {} is not supported, only crc32c",
+                le16(&sb, 0xc4)
+            )));
+        }
+        if le32(&sb, 0) != crc32c(&sb[CSUM_SIZE..]) {
+            return Err(btrfs_err("superblock checksum mismatch"));
+        }
+
+        let flags = le64(&sb, 0x38);
+        let incompat = le64(&sb, 0xbc);
+        let refused = [
+            (flags & SUPER_FLAG_ERROR, "has errors recorded"),
+            (flags & SUPER_FLAG_SEEDING, "is a seed device"),
+            (
+                flags & (SUPER_FLAG_CHANGING_FSID | SUPER_FLAG_CHANGING_FSID_V2),
+                "is in the middle of an fsid change",
+            ),
+            (incompat & INCOMPAT_ZONED, "is zoned"),
+            (incompat & INCOMPAT_EXTENT_TREE_V2, "uses extent tree v2"),
+            (le64(&sb, 0x60), "has a log tree to replay"),
+        ];
+        if let Some((_, why)) = refused.iter().find(|(v, _)| *v != 0) {
+            return Err(btrfs_err(format!("filesystem {why}")));
+        }
+        if le64(&sb, 0x88) != 1 {
+            return Err(btrfs_err("multi-device filesystems are not supported"));
+        }
+
+        let nodesize = le32(&sb, 0x94) as usize;
+        let sectorsize = le32(&sb, 0x90) as usize;
+        if !(4096..=65536).contains(&nodesize) || !nodesize.is_power_of_two() {
+            return Err(btrfs_err(format!("bad nodesize {nodesize}")));
+        }
+        let mut metadata_uuid = [0u8; 16];
+        let uuid_at = if incompat & INCOMPAT_METADATA_UUID != 0 {
+            0x23b
+        } else {
+            0x20
+        };
+        metadata_uuid.copy_from_slice(&sb[uuid_at..uuid_at + 16]);
+
+        let mut fs = Self {
+            file,
+            base,
+            nodesize,
+            sectorsize,
+            generation: le64(&sb, 0x48),
+            metadata_uuid,
+            root: le64(&sb, 0x50),
+            chunk_root: le64(&sb, 0x58),
+            chunks: Vec::new(),
+            dirty: BTreeMap::new(),
+        };
+
+        // The system chunks needed to read the chunk tree are in the superblock.
+        let array_len = le32(&sb, 0xa0) as usize;
+        let array = sb
+            .get(0x32b..0x32b + array_len)
+            .ok_or_else(|| btrfs_err("bad sys_chunk_array size"))?;
+        let mut pos = 0;
+        while pos < array.len() {
+            let key = Key::parse(array.get(pos..pos + KEY_SIZE).ok_or_else(bad_chunks)?);
+            let chunk = array.get(pos + KEY_SIZE..).ok_or_else(bad_chunks)?;
+            if key.kind != CHUNK_ITEM {
+                return Err(bad_chunks());
+            }
+            let used = fs.add_chunk(key.offset, chunk)?;
+            pos += KEY_SIZE + used;
+        }
+
+        let mut chunk_items = Vec::new();
+        fs.walk(fs.chunk_root, &mut |key, data| {
+            if key.kind == CHUNK_ITEM {
+                chunk_items.push((key.offset, data.to_vec()));
+            }
+            Ok(())
+        })?;
+        for (logical, data) in chunk_items {
+            if !fs.chunks.iter().any(|c| c.logical == logical) {
+                fs.add_chunk(logical, &data)?;
+            }
+        }
+        Ok(fs)
+    }
+
+    /// Parse a chunk item; returns its size.
+    fn add_chunk(&mut self, logical: u64, b: &[u8]) -> Result<usize, WriteConfError> {
+        if b.len() < 48 {
+            return Err(bad_chunks());
+        }
+        let len = le64(b, 0);
+        let profile = le64(b, 24);
+        let num_stripes = le16(b, 44) as usize;
+        let size = 48 + num_stripes * 32;
+        if num_stripes == 0 || b.len() < size {
+            return Err(bad_chunks());
+        }
+        if profile & PROFILE_STRIPED != 0 {
+            return Err(btrfs_err(format!(
+                "chunk at {logical} uses a striped profile (RAID0/10/5/6)"
+            )));
+        }
+        if num_stripes > 1 && profile & PROFILE_MIRRORED == 0 {
+            return Err(bad_chunks());
+        }
+        let stripes = (0..num_stripes).map(|i| le64(b, 48 + i * 32 + 8)).collect();
+        self.chunks.push(Chunk {
+            logical,
+            len,
+            stripes,
+        });
+        Ok(size)
+    }
+
+    /// Physical offsets (within the partition) of every copy of a tree block.
+    fn copies(&self, logical: u64) -> Result<Vec<u64>, WriteConfError> {
+        let chunk = self
+            .chunks
+            .iter()
+            .find(|c| c.logical <= logical && logical + self.nodesize as u64 <= c.logical + c.len)
+            .ok_or_else(|| btrfs_err(format!("tree block {logical} is not in any chunk")))?;
+        Ok(chunk
+            .stripes
+            .iter()
+            .map(|s| s + (logical - chunk.logical))
+            .collect())
+    }
+
+    /// Read tree block `logical`: the pending copy if it was modified, else the first
+    /// on-disk copy that passes its checks.
+    fn read_block(&self, logical: u64) -> Result<Vec<u8>, WriteConfError> {
+        if let Some(block) = self.dirty.get(&logical) {
+            return Ok(block.clone());
+        }
+        let mut last_err = None;
+        for physical in self.copies(logical)? {
+            let mut block = vec![0u8; self.nodesize];
+            read_exact_at(&self.file, self.base + physical, &mut block)?;
+            match self.check_header(logical, &block) {
+                Ok(()) => return Ok(block),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| btrfs_err("no copies")))
+    }
+
+    /// Read every copy of tree block `logical` and require each to pass its checks.
+    fn read_block_all_copies(&self, logical: u64) -> Result<Vec<u8>, WriteConfError> {
+        let mut first = None;
+        for (i, physical) in self.copies(logical)?.into_iter().enumerate() {
+            let mut block = vec![0u8; self.nodesize];
+            read_exact_at(&self.file, self.base + physical, &mut block)?;
+            self.check_header(logical, &block)
+                .map_err(|e| btrfs_err(format!("copy {i}: {e}")))?;
+            first.get_or_insert(block);
+        }
+        first.ok_or_else(|| btrfs_err("no copies"))
+    }
+
+    fn check_header(&self, logical: u64, block: &[u8]) -> Result<(), WriteConfError> {
+        if le32(block, 0) != crc32c(&block[CSUM_SIZE..]) {
+            return Err(btrfs_err(format!(
+                "tree block {logical}: checksum mismatch"
+            )));
+        }
+        if le64(block, 48) != logical {
+            return Err(btrfs_err(format!("tree block {logical}: bytenr mismatch")));
+        }
+        if block[32..48] != self.metadata_uuid {
+            return Err(btrfs_err(format!("tree block {logical}: fsid mismatch")));
+        }
+        if block[100] > MAX_LEVEL {
+            return Err(btrfs_err(format!("tree block {logical}: bad level")));
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, logical: u64, block: Vec<u8>) {
+        self.dirty.insert(logical, block);
+    }
+
+    /// Checksum and write every modified block to all of its copies, then flush.
+    fn commit(&mut self) -> Result<(), WriteConfError> {
+        for block in self.dirty.values_mut() {
+            let csum = crc32c(&block[CSUM_SIZE..]);
+            block[..CSUM_SIZE].fill(0);
+            put32(block, 0, csum);
+        }
+        for (logical, block) in &self.dirty {
+            for physical in self.copies(*logical)? {
+                (&self.file).seek(SeekFrom::Start(self.base + physical))?;
+                (&self.file).write_all(block)?;
+            }
+        }
+        self.dirty.clear();
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Search for `key` from tree root `root`.
+    fn search(&self, root: u64, key: Key) -> Result<(TreePath, Vec<u8>), WriteConfError> {
+        let mut path = Vec::new();
+        let mut logical = root;
+        loop {
+            let block = self.read_block(logical)?;
+            let n = nritems(&block);
+            if block[100] == 0 {
+                let slot = (0..n).find(|&i| leaf_key(&block, i) >= key).unwrap_or(n);
+                path.push((logical, slot));
+                return Ok((path, block));
+            }
+            if n == 0 {
+                return Err(btrfs_err(format!("tree block {logical}: empty node")));
+            }
+            let slot = (1..n)
+                .take_while(|&i| node_key(&block, i) <= key)
+                .last()
+                .unwrap_or(0);
+            path.push((logical, slot));
+            logical = le64(&block, HEADER_SIZE + slot * KEY_PTR_SIZE + KEY_SIZE);
+        }
+    }
+
+    /// Data of the item at exactly `key`.
+    fn find(&self, root: u64, key: Key) -> Result<Option<Vec<u8>>, WriteConfError> {
+        let (path, leaf) = self.search(root, key)?;
+        let slot = path.last().unwrap().1;
+        Ok((slot < nritems(&leaf) && leaf_key(&leaf, slot) == key)
+            .then(|| leaf_data(&leaf, slot).to_vec()))
+    }
+
+    /// First item with a key at or after `key`.
+    fn seek(&self, root: u64, key: Key) -> Result<Option<(Key, Vec<u8>)>, WriteConfError> {
+        let (mut path, mut leaf) = self.search(root, key)?;
+        loop {
+            let slot = path.last().unwrap().1;
+            if slot < nritems(&leaf) {
+                return Ok(Some((
+                    leaf_key(&leaf, slot),
+                    leaf_data(&leaf, slot).to_vec(),
+                )));
+            }
+            // Past the end of this leaf: step to the next subtree on the way up.
+            path.pop();
+            let mut next = None;
+            while let Some((node, slot)) = path.pop() {
+                let block = self.read_block(node)?;
+                if slot + 1 < nritems(&block) {
+                    path.push((node, slot + 1));
+                    next = Some(le64(
+                        &block,
+                        HEADER_SIZE + (slot + 1) * KEY_PTR_SIZE + KEY_SIZE,
+                    ));
+                    break;
+                }
+            }
+            let Some(mut logical) = next else {
+                return Ok(None);
+            };
+            loop {
+                let block = self.read_block(logical)?;
+                path.push((logical, 0));
+                if block[100] == 0 {
+                    leaf = block;
+                    break;
+                }
+                logical = le64(&block, HEADER_SIZE + KEY_SIZE);
+            }
+        }
+    }
+
+    /// Largest key before `key`.
+    fn prev_key(&self, root: u64, key: Key) -> Result<Option<Key>, WriteConfError> {
+        let (mut path, leaf) = self.search(root, key)?;
+        let slot = path.last().unwrap().1;
+        if slot > 0 {
+            return Ok(Some(leaf_key(&leaf, slot - 1)));
+        }
+        // Every key in this leaf is at or after `key`: take the subtree to the left.
+        path.pop();
+        let Some(level) = path.iter().rposition(|&(_, slot)| slot > 0) else {
+            return Ok(None);
+        };
+        let (node, slot) = path[level];
+        let mut logical = le64(
+            &self.read_block(node)?,
+            HEADER_SIZE + (slot - 1) * KEY_PTR_SIZE + KEY_SIZE,
+        );
+        loop {
+            let block = self.read_block(logical)?;
+            let n = nritems(&block);
+            if n == 0 {
+                return Err(btrfs_err(format!("tree block {logical}: empty")));
+            }
+            if block[100] == 0 {
+                return Ok(Some(leaf_key(&block, n - 1)));
+            }
+            logical = le64(&block, HEADER_SIZE + (n - 1) * KEY_PTR_SIZE + KEY_SIZE);
+        }
+    }
+
+    /// Insert, replace (`data` is `Some` and the key exists) or delete (`data` is `None`)
+    /// the item at `key` in its leaf, which must have room.
+    fn set_item(
+        &mut self,
+        root: u64,
+        key: Key,
+        data: Option<Vec<u8>>,
+    ) -> Result<(), WriteConfError> {
+        let (path, leaf) = self.search(root, key)?;
+        let (logical, slot) = *path.last().unwrap();
+        let mut items: Vec<(Key, Vec<u8>)> = (0..nritems(&leaf))
+            .map(|i| (leaf_key(&leaf, i), leaf_data(&leaf, i).to_vec()))
+            .collect();
+        let exists = slot < items.len() && items[slot].0 == key;
+        match (data, exists) {
+            (Some(data), true) => items[slot].1 = data,
+            (Some(data), false) => items.insert(slot, (key, data)),
+            (None, true) => {
+                items.remove(slot);
+            }
+            (None, false) => return Ok(()),
+        }
+        if items.is_empty() && path.len() > 1 {
+            return Err(btrfs_err(format!(
+                "removing {key:?} would empty leaf {logical}"
+            )));
+        }
+
+        let mut block = leaf;
+        let room = self.nodesize - HEADER_SIZE;
+        let used: usize = items.iter().map(|(_, d)| ITEM_SIZE + d.len()).sum();
+        if used > room {
+            return Err(btrfs_err(format!(
+                "leaf {logical} has no room for {key:?} ({used} of {room} bytes); leaf splits are not supported"
+            )));
+        }
+        block[HEADER_SIZE..].fill(0);
+        put32(&mut block, 96, items.len() as u32);
+        let mut end = room;
+        for (i, (k, d)) in items.iter().enumerate() {
+            let item = HEADER_SIZE + i * ITEM_SIZE;
+            k.put(&mut block[item..]);
+            end -= d.len();
+            put32(&mut block, item + KEY_SIZE, end as u32);
+            put32(&mut block, item + KEY_SIZE + 4, d.len() as u32);
+            block[HEADER_SIZE + end..HEADER_SIZE + end + d.len()].copy_from_slice(d);
+        }
+        self.write_block(logical, block);
+
+        // A new first key must be reflected in the parents' key pointers.
+        if slot == 0 && !items.is_empty() {
+            let first = items[0].0;
+            for &(node, slot) in path[..path.len() - 1].iter().rev() {
+                let mut block = self.read_block(node)?;
+                first.put(&mut block[HEADER_SIZE + slot * KEY_PTR_SIZE..]);
+                self.write_block(node, block);
+                if slot != 0 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Visit every item of the tree at `root` in key order, checking each block.
+    fn walk(&self, root: u64, visit: &mut ItemVisitor) -> Result<(), WriteConfError> {
+        self.walk_block(root, None, None, None, visit, false)
+    }
+
+    /// [`Self::walk`] reading every copy of every block, with the structural checks the
+    /// kernel's tree checker applies: key order within and across blocks, child level and
+    /// generation matching the parent pointer, and contiguous in-bounds leaf items.
+    fn check_tree(&self, root: u64) -> Result<(), WriteConfError> {
+        self.walk_block(root, None, None, None, &mut |_, _| Ok(()), true)
+    }
+
+    fn walk_block(
+        &self,
+        logical: u64,
+        expect: Option<(Key, u8, u64)>,
+        upper: Option<Key>,
+        lower: Option<Key>,
+        visit: &mut ItemVisitor,
+        strict: bool,
+    ) -> Result<(), WriteConfError> {
+        let block = if strict {
+            self.read_block_all_copies(logical)?
+        } else {
+            self.read_block(logical)?
+        };
+        let bad = |why: String| btrfs_err(format!("tree block {logical}: {why}"));
+        let n = nritems(&block);
+        let level = block[100];
+        if let Some((first, parent_level, generation)) = expect {
+            if level + 1 != parent_level {
+                return Err(bad(format!(
+                    "level {level} under a level {parent_level} node"
+                )));
+            }
+            if le64(&block, 80) != generation {
+                return Err(bad("generation differs from the parent pointer".into()));
+            }
+            if n == 0 || key_at(&block, 0) != first {
+                return Err(bad("first key differs from the parent pointer".into()));
+            }
+        }
+        if le64(&block, 80) > self.generation {
+            return Err(bad("generation is newer than the superblock".into()));
+        }
+        let entry = if level == 0 { ITEM_SIZE } else { KEY_PTR_SIZE };
+        if HEADER_SIZE + n * entry > self.nodesize {
+            return Err(bad(format!("{n} items do not fit")));
+        }
+
+        let mut prev = lower;
+        for i in 0..n {
+            let key = key_at(&block, i);
+            if prev.is_some_and(|p| if i == 0 { key < p } else { key <= p }) {
+                return Err(bad(format!("key {i} out of order")));
+            }
+            if upper.is_some_and(|u| key >= u) {
+                return Err(bad(format!("key {i} beyond the parent's next key")));
+            }
+            prev = Some(key);
+        }
+
+        if level == 0 {
+            let mut end = self.nodesize - HEADER_SIZE;
+            for i in 0..n {
+                let item = HEADER_SIZE + i * ITEM_SIZE;
+                let offset = le32(&block, item + KEY_SIZE) as usize;
+                let size = le32(&block, item + KEY_SIZE + 4) as usize;
+                if offset + size != end || offset < n * ITEM_SIZE {
+                    return Err(bad(format!("item {i} data is out of place")));
+                }
+                end = offset;
+                visit(key_at(&block, i), leaf_data(&block, i))?;
+            }
+            return Ok(());
+        }
+
+        for i in 0..n {
+            let ptr = HEADER_SIZE + i * KEY_PTR_SIZE;
+            let next = (i + 1 < n).then(|| key_at(&block, i + 1)).or(upper);
+            self.walk_block(
+                le64(&block, ptr + KEY_SIZE),
+                Some((key_at(&block, i), level, le64(&block, ptr + KEY_SIZE + 8))),
+                next,
+                Some(key_at(&block, i)),
+                visit,
+                strict,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Id and root item of the default subvolume (`btrfs subvolume set-default`), else of
+    /// the top-level one.
+    fn default_root_item(&self) -> Result<(u64, Vec<u8>), WriteConfError> {
+        let id = self
+            .lookup(self.root, ROOT_TREE_DIR_OBJECTID, b"default")?
+            .map(|e| e.location.objectid)
+            .unwrap_or(FS_TREE_OBJECTID);
+        match self.seek(self.root, Key::new(id, ROOT_ITEM, 0))? {
+            Some((key, item))
+                if key.objectid == id && key.kind == ROOT_ITEM && item.len() >= 239 =>
+            {
+                Ok((id, item))
+            }
+            _ => Err(btrfs_err(format!("no root item for subvolume {id}"))),
+        }
+    }
+
+    /// Root block and root directory of the default subvolume.
+    fn default_subvolume(&self) -> Result<(u64, u64), WriteConfError> {
+        let (_, item) = self.default_root_item()?;
+        Ok((le64(&item, ROOT_ITEM_BYTENR), le64(&item, ROOT_ITEM_DIRID)))
+    }
+
+    /// Entry `name` in directory `dir`.
+    fn lookup(&self, root: u64, dir: u64, name: &[u8]) -> Result<Option<DirEntry>, WriteConfError> {
+        let key = Key::new(dir, DIR_ITEM, name_hash(name) as u64);
+        let Some(item) = self.find(root, key)? else {
+            return Ok(None);
+        };
+        Ok(parse_dir_entries(&item)?
+            .into_iter()
+            .find(|e| e.name == name))
+    }
+
+    /// Follow `components` from directory `dir`; each must be a directory in the same subvolume.
+    fn resolve_dir(
+        &self,
+        root: u64,
+        mut dir: u64,
+        components: &[&str],
+    ) -> Result<u64, WriteConfError> {
+        for name in components {
+            let entry = self
+                .lookup(root, dir, name.as_bytes())?
+                .ok_or_else(|| btrfs_err(format!("{name}: no such directory")))?;
+            if entry.file_type != FT_DIR || entry.location.kind != INODE_ITEM {
+                return Err(btrfs_err(format!(
+                    "{name}: not a directory in this subvolume"
+                )));
+            }
+            dir = entry.location.objectid;
+        }
+        Ok(dir)
+    }
+
+    fn inode(&self, root: u64, ino: u64) -> Result<Vec<u8>, WriteConfError> {
+        self.find(root, Key::new(ino, INODE_ITEM, 0))?
+            .filter(|i| i.len() == INODE_ITEM_SIZE)
+            .ok_or_else(|| btrfs_err(format!("inode {ino} has no inode item")))
+    }
+}
+
+/// Create or overwrite `dest_path` with `content` in the default subvolume of the btrfs
+/// filesystem at partition offset `base`. The parent directory must exist.
+pub(crate) fn write(
+    image_path: &Path,
+    base: u64,
+    dest_path: &str,
+    content: &[u8],
+    attrs: &FileAttrs,
+) -> Result<usize, WriteConfError> {
+    let (parents, name) = split_path(dest_path)?;
+    let mut fs = Btrfs::open(image_path, base, true)?;
+    let limit = MAX_INLINE.min(fs.sectorsize - 1);
+    if content.len() > limit {
+        return Err(btrfs_err(format!(
+            "{dest_path}: {} bytes is more than the {limit} bytes an inline extent holds",
+            content.len()
+        )));
+    }
+
+    // Snapshotting records its transid on both the source and the new subvolume, whose
+    // tree blocks are then shared; an in-place edit would show through in the other one.
+    let (id, item) = fs.default_root_item()?;
+    if le64(&item, ROOT_ITEM_LAST_SNAPSHOT) != 0 {
+        return Err(WriteConfError::UnsupportedImage(format!(
+            "btrfs subvolume {id} has a snapshot or is one; its shared leaves cannot be edited in place"
+        )));
+    }
+    let (root, root_dir) = fs.default_subvolume()?;
+    let parent = fs.resolve_dir(root, root_dir, &parents)?;
+    let generation = fs.generation;
+
+    let ino = match fs.lookup(root, parent, name.as_bytes())? {
+        Some(entry) => {
+            if entry.file_type != FT_REG_FILE || entry.location.kind != INODE_ITEM {
+                return Err(btrfs_err(format!(
+                    "{dest_path}: exists and is not a regular file"
+                )));
+            }
+            let ino = entry.location.objectid;
+            // Only an empty file or a single inline extent can be replaced without
+            // freeing data extents.
+            let inode = fs.inode(root, ino)?;
+            let size = le64(&inode, 16);
+            let extent = fs.find(root, Key::new(ino, EXTENT_DATA, 0))?;
+            let replaceable = match &extent {
+                None => size == 0,
+                Some(e) => e.len() > INLINE_DATA_START && e[20] == 0 && le64(e, 8) == size,
+            };
+            let more = fs
+                .seek(root, Key::new(ino, EXTENT_DATA, 1))?
+                .is_some_and(|(k, _)| k.objectid == ino && k.kind == EXTENT_DATA);
+            if !replaceable || more {
+                return Err(btrfs_err(format!(
+                    "{dest_path}: exists with data extents; only empty or inline files can be replaced"
+                )));
+            }
+            ino
+        }
+        None => {
+            let last = fs
+                .prev_key(root, Key::new(LAST_FREE_OBJECTID, u8::MAX, u64::MAX))?
+                .map(|k| k.objectid)
+                .unwrap_or(FIRST_FREE_OBJECTID);
+            let ino = last.max(FIRST_FREE_OBJECTID) + 1;
+            let index = match fs.prev_key(root, Key::new(parent, DIR_INDEX, u64::MAX))? {
+                Some(k) if k.objectid == parent && k.kind == DIR_INDEX => k.offset + 1,
+                _ => FIRST_DIR_INDEX,
+            };
+
+            let mut inode = vec![0u8; INODE_ITEM_SIZE];
+            put64(&mut inode, 0, generation);
+            put32(&mut inode, 40, 1);
+            fs.set_item(root, Key::new(ino, INODE_ITEM, 0), Some(inode))?;
+
+            let mut inode_ref = Vec::with_capacity(10 + name.len());
+            inode_ref.extend_from_slice(&index.to_le_bytes());
+            inode_ref.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            inode_ref.extend_from_slice(name.as_bytes());
+            fs.set_item(root, Key::new(ino, INODE_REF, parent), Some(inode_ref))?;
+
+            let entry = dir_entry(Key::new(ino, INODE_ITEM, 0), generation, FT_REG_FILE, name);
+            let dir_key = Key::new(parent, DIR_ITEM, name_hash(name.as_bytes()) as u64);
+            // Names with the same hash share one DIR_ITEM.
+            let mut dir_item = fs.find(root, dir_key)?.unwrap_or_default();
+            dir_item.extend_from_slice(&entry);
+            fs.set_item(root, dir_key, Some(dir_item))?;
+            fs.set_item(root, Key::new(parent, DIR_INDEX, index), Some(entry))?;
+
+            let mut dir = fs.inode(root, parent)?;
+            let (size, sequence) = (le64(&dir, 16), le64(&dir, 72));
+            put64(&mut dir, 16, size + 2 * name.len() as u64);
+            put64(&mut dir, 8, generation);
+            put64(&mut dir, 72, sequence + 1);
+            put_time(&mut dir, 124, attrs.ctime);
+            put_time(&mut dir, 136, attrs.ctime);
+            fs.set_item(root, Key::new(parent, INODE_ITEM, 0), Some(dir))?;
+            ino
+        }
+    };
+
+    let mut inode = fs.inode(root, ino)?;
+    let kind = le32(&inode, 52) as u16 & MODE_TYPE_MASK;
+    let sequence = le64(&inode, 72);
+    put64(&mut inode, 8, generation);
+    put64(&mut inode, 16, content.len() as u64);
+    put64(&mut inode, 24, content.len() as u64);
+    put32(&mut inode, 44, attrs.uid);
+    put32(&mut inode, 48, attrs.gid);
+    put32(
+        &mut inode,
+        52,
+        (if kind == 0 { S_IFREG } else { kind } | (attrs.mode & MODE_PERM_MASK)) as u32,
+    );
+    put64(&mut inode, 72, sequence + 1);
+    put_time(&mut inode, 112, attrs.atime);
+    put_time(&mut inode, 124, attrs.ctime);
+    put_time(&mut inode, 136, attrs.mtime);
+    if le64(&inode, 148) == 0 {
+        put_time(&mut inode, 148, attrs.ctime);
+    }
+    fs.set_item(root, Key::new(ino, INODE_ITEM, 0), Some(inode))?;
+
+    let extent = (!content.is_empty()).then(|| {
+        let mut e = vec![0u8; INLINE_DATA_START];
+        put64(&mut e, 0, generation);
+        put64(&mut e, 8, content.len() as u64);
+        e.extend_from_slice(content);
+        e
+    });
+    fs.set_item(root, Key::new(ino, EXTENT_DATA, 0), extent)?;
+
+    fs.commit()?;
+    Ok(content.len())
+}
+
+/// Re-open the filesystem read-only, check every copy of every block of the chunk, root
+/// and default subvolume trees, then confirm `dest_path` holds `content` with `attrs` and
+/// is linked consistently into its parent.
+pub(crate) fn validate(
+    image_path: &Path,
+    base: u64,
+    dest_path: &str,
+    content: &[u8],
+    attrs: &FileAttrs,
+) -> Result<(), WriteConfError> {
+    check(image_path, base, dest_path, content, attrs).map_err(|e| match e {
+        WriteConfError::Btrfs(m) => WriteConfError::ValidationFailed(m),
+        other => other,
+    })
+}
+
+fn check(
+    image_path: &Path,
+    base: u64,
+    dest_path: &str,
+    content: &[u8],
+    attrs: &FileAttrs,
+) -> Result<(), WriteConfError> {
+    let (parents, name) = split_path(dest_path)?;
+    let fs = Btrfs::open(image_path, base, false)?;
+    fs.check_tree(fs.chunk_root)?;
+    fs.check_tree(fs.root)?;
+    let (root, root_dir) = fs.default_subvolume()?;
+    fs.check_tree(root)?;
+
+    let fail = |why: String| btrfs_err(format!("{dest_path}: {why}"));
+    let parent = fs.resolve_dir(root, root_dir, &parents)?;
+    let entry = fs
+        .lookup(root, parent, name.as_bytes())?
+        .ok_or_else(|| fail("no dir item".into()))?;
+    let ino = entry.location.objectid;
+    if entry.file_type != FT_REG_FILE || entry.location.kind != INODE_ITEM {
+        return Err(fail("dir item is not a regular file".into()));
+    }
+
+    let inode_ref = fs
+        .find(root, Key::new(ino, INODE_REF, parent))?
+        .ok_or_else(|| fail("no inode ref".into()))?;
+    let index = le64(&inode_ref, 0);
+    let indexed = fs
+        .find(root, Key::new(parent, DIR_INDEX, index))?
+        .map(|i| parse_dir_entries(&i))
+        .transpose()?
+        .unwrap_or_default();
+    if !indexed
+        .iter()
+        .any(|e| e.location == entry.location && e.name == name.as_bytes())
+    {
+        return Err(fail(format!(
+            "dir index {index} does not match the dir item"
+        )));
+    }
+
+    // A directory's size is twice the length of its names (one dir item, one index each).
+    let mut names = 0u64;
+    let mut cur = Key::new(parent, DIR_INDEX, 0);
+    while let Some((key, item)) = fs.seek(root, cur)? {
+        if key.objectid != parent || key.kind != DIR_INDEX {
+            break;
+        }
+        names += parse_dir_entries(&item)?
+            .iter()
+            .map(|e| e.name.len() as u64)
+            .sum::<u64>();
+        if key.offset == u64::MAX {
+            break;
+        }
+        cur = Key::new(parent, DIR_INDEX, key.offset + 1);
+    }
+    let dir = fs.inode(root, parent)?;
+    if le64(&dir, 16) != 2 * names {
+        return Err(fail(format!(
+            "parent size {} does not match its entries ({})",
+            le64(&dir, 16),
+            2 * names
+        )));
+    }
+
+    let inode = fs.inode(root, ino)?;
+    let mode = le32(&inode, 52) as u16;
+    let got = (
+        mode & MODE_TYPE_MASK,
+        mode & MODE_PERM_MASK,
+        le32(&inode, 44),
+        le32(&inode, 48),
+    );
+    let want = (S_IFREG, attrs.mode & MODE_PERM_MASK, attrs.uid, attrs.gid);
+    if got != want {
+        return Err(fail(format!(
+            "type/mode/uid/gid {:o}/{:o}/{}/{} instead of {:o}/{:o}/{}/{}",
+            got.0, got.1, got.2, got.3, want.0, want.1, want.2, want.3
+        )));
+    }
+    let times = (le64(&inode, 112), le64(&inode, 124), le64(&inode, 136));
+    let want_times = (attrs.atime as u64, attrs.ctime as u64, attrs.mtime as u64);
+    if times != want_times {
+        return Err(fail(format!(
+            "atime/ctime/mtime {times:?} instead of {want_times:?}"
+        )));
+    }
+
+    let data = match fs.find(root, Key::new(ino, EXTENT_DATA, 0))? {
+        None => Vec::new(),
+        Some(e) => {
+            if e.len() < INLINE_DATA_START || e[20] != 0 || e[16] != 0 {
+                return Err(fail("extent is not an uncompressed inline extent".into()));
+            }
+            if le64(&e, 8) as usize != e.len() - INLINE_DATA_START {
+                return Err(fail("inline extent length mismatch".into()));
+            }
+            e[INLINE_DATA_START..].to_vec()
+        }
+    };
+    if data != content || le64(&inode, 16) != content.len() as u64 {
+        return Err(fail(format!(
+            "content mismatch: wrote {} bytes, read {} bytes (size {})",
+            content.len(),
+            data.len(),
+            le64(&inode, 16)
+        )));
+    }
+    Ok(())
+}
+
+/// Parent components and file name of an absolute, normalized path.
+fn split_path(path: &str) -> Result<(Vec<&str>, &str), WriteConfError> {
+    let invalid = |why: &str| WriteConfError::InvalidEntry(format!("{path}: {why}"));
+    let components: Vec<&str> = path
+        .strip_prefix('/')
+        .ok_or_else(|| invalid("path must be absolute"))?
+        .split('/')
+        .collect();
+    if components
+        .iter()
+        .any(|c| c.is_empty() || *c == "." || *c == "..")
+    {
+        return Err(invalid("path must be normalized and not the root"));
+    }
+    if components.iter().any(|c| c.len() > NAME_MAX) {
+        return Err(invalid("path component longer than 255 bytes"));
+    }
+    let (name, parents) = components.split_last().unwrap();
+    Ok((parents.to_vec(), name))
+}
+
+fn dir_entry(location: Key, transid: u64, file_type: u8, name: &str) -> Vec<u8> {
+    let mut e = vec![0u8; DIR_ENTRY_HEADER];
+    location.put(&mut e);
+    put64(&mut e, KEY_SIZE, transid);
+    e[27..29].copy_from_slice(&(name.len() as u16).to_le_bytes());
+    e[29] = file_type;
+    e.extend_from_slice(name.as_bytes());
+    e
+}
+
+fn parse_dir_entries(item: &[u8]) -> Result<Vec<DirEntry>, WriteConfError> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < item.len() {
+        let header = item
+            .get(pos..pos + DIR_ENTRY_HEADER)
+            .ok_or_else(|| btrfs_err("truncated dir item"))?;
+        let data_len = le16(header, 25) as usize;
+        let name_len = le16(header, 27) as usize;
+        let end = pos + DIR_ENTRY_HEADER + name_len + data_len;
+        if end > item.len() {
+            return Err(btrfs_err("truncated dir item"));
+        }
+        entries.push(DirEntry {
+            location: Key::parse(header),
+            file_type: header[29],
+            name: item[pos + DIR_ENTRY_HEADER..pos + DIR_ENTRY_HEADER + name_len].to_vec(),
+        });
+        pos = end;
+    }
+    Ok(entries)
+}
+
+fn nritems(block: &[u8]) -> usize {
+    le32(block, 96) as usize
+}
+
+/// Key of item or key pointer `i`, for either block level.
+fn key_at(block: &[u8], i: usize) -> Key {
+    if block[100] == 0 {
+        leaf_key(block, i)
+    } else {
+        node_key(block, i)
+    }
+}
+
+fn leaf_key(block: &[u8], i: usize) -> Key {
+    Key::parse(&block[HEADER_SIZE + i * ITEM_SIZE..])
+}
+
+fn node_key(block: &[u8], i: usize) -> Key {
+    Key::parse(&block[HEADER_SIZE + i * KEY_PTR_SIZE..])
+}
+
+fn leaf_data(block: &[u8], i: usize) -> &[u8] {
+    let item = HEADER_SIZE + i * ITEM_SIZE;
+    let offset = HEADER_SIZE + le32(block, item + KEY_SIZE) as usize;
+    let size = le32(block, item + KEY_SIZE + 4) as usize;
+    block.get(offset..offset + size).unwrap_or(&[])
+}
+
+/// Directory item key offset: crc32c of the name, seeded with `~1`, without final inversion.
+fn name_hash(name: &[u8]) -> u32 {
+    crc32c_update(!1, name)
+}
+
+/// Standard CRC-32C, as btrfs stores for blocks and the superblock.
+fn crc32c(data: &[u8]) -> u32 {
+    !crc32c_update(!0, data)
+}
+
+fn crc32c_update(mut crc: u32, data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                c = if c & 1 != 0 {
+                    (c >> 1) ^ 0x82F6_3B78
+                } else {
+                    c >> 1
+                };
+                bit += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    for &b in data {
+        crc = TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+fn read_exact_at(mut file: &File, offset: u64, buf: &mut [u8]) -> Result<(), WriteConfError> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)?;
+    Ok(())
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(b[at..at + 2].try_into().unwrap())
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn put32(b: &mut [u8], at: usize, v: u32) {
+    b[at..at + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put64(b: &mut [u8], at: usize, v: u64) {
+    b[at..at + 8].copy_from_slice(&v.to_le_bytes());
+}
+
+/// Timestamp: seconds, then nanoseconds (left at zero).
+fn put_time(b: &mut [u8], at: usize, secs: u32) {
+    put64(b, at, secs as u64);
+    put32(b, at + 8, 0);
+}
+
+fn btrfs_err(m: impl Into<String>) -> WriteConfError {
+    WriteConfError::Btrfs(m.into())
+}
+
+fn bad_chunks() -> WriteConfError {
+    btrfs_err("malformed chunk item")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const NODE: usize = 4096;
+    const GEN: u64 = 7;
+    const FSID: [u8; 16] = [0xb7; 16];
+    /// One DUP chunk: logical 1 MiB mapped to physical 1 MiB and 2 MiB.
+    const CHUNK: u64 = 1 << 20;
+    const COPIES: [u64; 2] = [1 << 20, 2 << 20];
+    const IMAGE_LEN: usize = 3 << 20;
+    const CHUNK_ROOT: u64 = CHUNK;
+    const ROOT_TREE: u64 = CHUNK + NODE as u64;
+    const FS_TREE: u64 = CHUNK + 2 * NODE as u64;
+    const TOP_LEVEL_TREE: u64 = CHUNK + 5 * NODE as u64;
+
+    type Items = Vec<(Key, Vec<u8>)>;
+
+    fn header(bytenr: u64, owner: u64, level: u8, nritems: usize) -> Vec<u8> {
+        let mut b = vec![0u8; NODE];
+        b[32..48].copy_from_slice(&FSID);
+        put64(&mut b, 48, bytenr);
+        put64(&mut b, 80, GEN);
+        put64(&mut b, 88, owner);
+        put32(&mut b, 96, nritems as u32);
+        b[100] = level;
+        b
+    }
+
+    fn leaf(bytenr: u64, owner: u64, items: &[(Key, Vec<u8>)]) -> Vec<u8> {
+        let mut b = header(bytenr, owner, 0, items.len());
+        let mut end = NODE - HEADER_SIZE;
+        for (i, (key, data)) in items.iter().enumerate() {
+            let item = HEADER_SIZE + i * ITEM_SIZE;
+            key.put(&mut b[item..]);
+            end -= data.len();
+            put32(&mut b, item + KEY_SIZE, end as u32);
+            put32(&mut b, item + KEY_SIZE + 4, data.len() as u32);
+            b[HEADER_SIZE + end..HEADER_SIZE + end + data.len()].copy_from_slice(data);
+        }
+        b
+    }
+
+    fn node(bytenr: u64, owner: u64, children: &[(Key, u64)]) -> Vec<u8> {
+        let mut b = header(bytenr, owner, 1, children.len());
+        for (i, (key, child)) in children.iter().enumerate() {
+            let ptr = HEADER_SIZE + i * KEY_PTR_SIZE;
+            key.put(&mut b[ptr..]);
+            put64(&mut b, ptr + KEY_SIZE, *child);
+            put64(&mut b, ptr + KEY_SIZE + 8, GEN);
+        }
+        b
+    }
+
+    fn place(img: &mut [u8], mut block: Vec<u8>) {
+        let logical = le64(&block, 48);
+        let csum = crc32c(&block[CSUM_SIZE..]);
+        put32(&mut block, 0, csum);
+        for copy in COPIES {
+            let at = (copy + logical - CHUNK) as usize;
+            img[at..at + NODE].copy_from_slice(&block);
+        }
+    }
+
+    fn chunk_item() -> Vec<u8> {
+        let mut c = vec![0u8; 48 + 2 * 32];
+        put64(&mut c, 0, 1 << 20);
+        put64(&mut c, 8, 2);
+        put64(&mut c, 16, 64 << 10);
+        // SYSTEM | METADATA | DUP
+        put64(&mut c, 24, (1 << 1) | (1 << 2) | (1 << 5));
+        put32(&mut c, 32, NODE as u32);
+        put32(&mut c, 36, NODE as u32);
+        put32(&mut c, 40, NODE as u32);
+        c[44..46].copy_from_slice(&2u16.to_le_bytes());
+        c[46..48].copy_from_slice(&1u16.to_le_bytes());
+        for (i, copy) in COPIES.iter().enumerate() {
+            put64(&mut c, 48 + i * 32, 1);
+            put64(&mut c, 48 + i * 32 + 8, *copy);
+        }
+        c
+    }
+
+    fn inode(mode: u16, size: u64) -> Vec<u8> {
+        let mut i = vec![0u8; INODE_ITEM_SIZE];
+        put64(&mut i, 0, GEN);
+        put64(&mut i, 8, GEN);
+        put64(&mut i, 16, size);
+        put32(&mut i, 40, 1);
+        put32(&mut i, 52, mode as u32);
+        i
+    }
+
+    fn inode_ref(index: u64, name: &str) -> Vec<u8> {
+        let mut r = index.to_le_bytes().to_vec();
+        r.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        r.extend_from_slice(name.as_bytes());
+        r
+    }
+
+    fn root_item(bytenr: u64) -> Vec<u8> {
+        let mut r = vec![0u8; 439];
+        r[..INODE_ITEM_SIZE].copy_from_slice(&inode(0o40755, 3));
+        put64(&mut r, 160, GEN);
+        put64(&mut r, 168, FIRST_FREE_OBJECTID);
+        put64(&mut r, 176, bytenr);
+        put32(&mut r, 216, 1);
+        r
+    }
+
+    /// Items of a subvolume whose root directory (256) holds the directory `/root` (257).
+    fn subvolume_items() -> (Items, Items) {
+        let root = Key::new(257, INODE_ITEM, 0);
+        let dir = vec![
+            (Key::new(256, INODE_ITEM, 0), inode(0o40755, 8)),
+            (Key::new(256, INODE_REF, 256), inode_ref(0, "..")),
+            (
+                Key::new(256, DIR_ITEM, name_hash(b"root") as u64),
+                dir_entry(root, GEN, FT_DIR, "root"),
+            ),
+            (
+                Key::new(256, DIR_INDEX, 2),
+                dir_entry(root, GEN, FT_DIR, "root"),
+            ),
+        ];
+        let home = vec![
+            (root, inode(0o40700, 0)),
+            (Key::new(257, INODE_REF, 256), inode_ref(2, "root")),
+        ];
+        (dir, home)
+    }
+
+    /// A single-device btrfs with DUP metadata. `split` puts the subvolume in two leaves
+    /// under a node; `default_subvol` makes subvolume 257 the default and leaves the
+    /// top-level tree without `/root`.
+    fn build(split: bool, default_subvol: bool) -> Vec<u8> {
+        let mut img = vec![0u8; IMAGE_LEN];
+        let chunk_key = Key::new(256, CHUNK_ITEM, CHUNK);
+        place(&mut img, leaf(CHUNK_ROOT, 3, &[(chunk_key, chunk_item())]));
+
+        let (subvol, owner) = if default_subvol {
+            (257, 257)
+        } else {
+            (FS_TREE_OBJECTID, FS_TREE_OBJECTID)
+        };
+        let mut roots = vec![(
+            Key::new(FS_TREE_OBJECTID, ROOT_ITEM, 0),
+            root_item(if default_subvol {
+                TOP_LEVEL_TREE
+            } else {
+                FS_TREE
+            }),
+        )];
+        if default_subvol {
+            let location = Key::new(subvol, ROOT_ITEM, u64::MAX);
+            roots.push((
+                Key::new(
+                    ROOT_TREE_DIR_OBJECTID,
+                    DIR_ITEM,
+                    name_hash(b"default") as u64,
+                ),
+                dir_entry(location, GEN, FT_DIR, "default"),
+            ));
+            roots.push((Key::new(subvol, ROOT_ITEM, 0), root_item(FS_TREE)));
+            let top = [(Key::new(256, INODE_ITEM, 0), inode(0o40755, 0))];
+            place(&mut img, leaf(TOP_LEVEL_TREE, FS_TREE_OBJECTID, &top));
+        }
+        place(&mut img, leaf(ROOT_TREE, 1, &roots));
+
+        let (dir, home) = subvolume_items();
+        if split {
+            let (a, b) = (FS_TREE + NODE as u64, FS_TREE + 2 * NODE as u64);
+            place(
+                &mut img,
+                node(FS_TREE, owner, &[(dir[0].0, a), (home[0].0, b)]),
+            );
+            place(&mut img, leaf(a, owner, &dir));
+            place(&mut img, leaf(b, owner, &home));
+        } else {
+            let items: Vec<_> = dir.into_iter().chain(home).collect();
+            place(&mut img, leaf(FS_TREE, owner, &items));
+        }
+
+        let sb = &mut img[SUPER_OFFSET as usize..SUPER_OFFSET as usize + SUPER_SIZE];
+        sb[0x20..0x30].copy_from_slice(&FSID);
+        put64(sb, 0x30, SUPER_OFFSET);
+        sb[0x40..0x48].copy_from_slice(MAGIC);
+        put64(sb, 0x48, GEN);
+        put64(sb, 0x50, ROOT_TREE);
+        put64(sb, 0x58, CHUNK_ROOT);
+        put64(sb, 0x70, IMAGE_LEN as u64);
+        put64(sb, 0x80, ROOT_TREE_DIR_OBJECTID);
+        put64(sb, 0x88, 1);
+        put32(sb, 0x90, NODE as u32);
+        put32(sb, 0x94, NODE as u32);
+        put32(sb, 0x98, NODE as u32);
+        let mut array = vec![0u8; KEY_SIZE];
+        chunk_key.put(&mut array);
+        array.extend_from_slice(&chunk_item());
+        put32(sb, 0xa0, array.len() as u32);
+        sb[0x32b..0x32b + array.len()].copy_from_slice(&array);
+        let csum = crc32c(&sb[CSUM_SIZE..]);
+        put32(sb, 0, csum);
+        img
+    }
+
+    fn temp_image(bytes: &[u8]) -> tempfile::NamedTempFile {
+        let mut tf = tempfile::NamedTempFile::new().unwrap();
+        tf.write_all(bytes).unwrap();
+        tf.flush().unwrap();
+        tf
+    }
+
+    fn write_and_validate(path: &Path, dest: &str, content: &[u8], attrs: &FileAttrs) {
+        write(path, 0, dest, content, attrs).unwrap();
+        validate(path, 0, dest, content, attrs).unwrap();
+    }
+
+    #[test]
+    fn crc32c_and_name_hash_match_btrfs() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        // The root tree's "default" dir item key, as `btrfs inspect-internal dump-tree` shows it.
+        assert_eq!(name_hash(b"default"), 2378154706);
+    }
+
+    #[test]
+    fn creates_and_overwrites_inline_files() {
+        for split in [false, true] {
+            let tf = temp_image(&build(split, false));
+            let path = tf.path();
+            let attrs = FileAttrs::new(0o600)
+                .with_owner(1000, 1001)
+                .with_times(1, 2, 3);
+            write_and_validate(
+                path,
+                "/root/.not_logged_in_yet",
+                b"PRESET_USER_NAME=\"pi\"\n",
+                &attrs,
+            );
+            // A second entry in the top-level directory, then shrink, empty and regrow.
+            write_and_validate(path, "/hello", b"world\n", &FileAttrs::default());
+            for content in [&b"x"[..], b"", &[b'y'; 2048]] {
+                write_and_validate(path, "/root/.not_logged_in_yet", content, &attrs);
+            }
+            validate(path, 0, "/hello", b"world\n", &FileAttrs::default()).unwrap();
+
+            let fs = Btrfs::open(path, 0, false).unwrap();
+            let (root, dir) = fs.default_subvolume().unwrap();
+            let home = fs.resolve_dir(root, dir, &["root"]).unwrap();
+            let names = ".not_logged_in_yet".len() as u64;
+            assert_eq!(le64(&fs.inode(root, home).unwrap(), 16), 2 * names);
+            let file = fs
+                .lookup(root, home, b".not_logged_in_yet")
+                .unwrap()
+                .unwrap();
+            assert_eq!(file.location.objectid, 258);
+        }
+    }
+
+    #[test]
+    fn writes_into_default_subvolume() {
+        let tf = temp_image(&build(false, true));
+        let attrs = FileAttrs::default();
+        write_and_validate(tf.path(), "/root/.not_logged_in_yet", b"x=1\n", &attrs);
+    }
+
+    #[test]
+    fn refuses_without_touching_the_image() {
+        let original = build(true, false);
+        let tf = temp_image(&original);
+        let path = tf.path();
+        let attrs = FileAttrs::default();
+        let too_big = vec![0u8; MAX_INLINE + 1];
+        for (dest, content) in [
+            ("/etc/hostname", &b"x"[..]),
+            ("/root", b"x"),
+            ("/root/big", &too_big),
+            ("relative", b"x"),
+        ] {
+            assert!(write(path, 0, dest, content, &attrs).is_err(), "{dest}");
+        }
+        assert_eq!(std::fs::read(path).unwrap(), original);
+
+        // Fill the /root leaf until an insert no longer fits.
+        let filler = vec![b'f'; 1500];
+        let err = (0..4)
+            .map(|i| write(path, 0, &format!("/root/f{i}"), &filler, &attrs))
+            .find_map(Result::err)
+            .unwrap();
+        assert!(
+            err.to_string().contains("leaf splits are not supported"),
+            "{err}"
+        );
+        validate(path, 0, "/root/f0", &filler, &attrs).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_snapshotted_subvolume() {
+        let tf = temp_image(&build(false, false));
+        let path = tf.path();
+        let mut fs = Btrfs::open(path, 0, true).unwrap();
+        let (key, root) = (Key::new(FS_TREE_OBJECTID, ROOT_ITEM, 0), fs.root);
+        let mut item = fs.find(root, key).unwrap().unwrap();
+        put64(&mut item, ROOT_ITEM_LAST_SNAPSHOT, GEN);
+        fs.set_item(root, key, Some(item)).unwrap();
+        fs.commit().unwrap();
+
+        let original = std::fs::read(path).unwrap();
+        let err = write(path, 0, "/root/a", b"a\n", &FileAttrs::default()).unwrap_err();
+        assert!(matches!(err, WriteConfError::UnsupportedImage(_)), "{err}");
+        assert_eq!(std::fs::read(path).unwrap(), original);
+    }
+
+    #[test]
+    fn validation_detects_a_corrupt_mirror_copy() {
+        let tf = temp_image(&build(false, false));
+        let path = tf.path();
+        let attrs = FileAttrs::default();
+        write_and_validate(path, "/root/a", b"a\n", &attrs);
+
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[(COPIES[1] + FS_TREE - CHUNK) as usize + NODE - 1] ^= 0xff;
+        std::fs::write(path, &bytes).unwrap();
+        let err = validate(path, 0, "/root/a", b"a\n", &attrs).unwrap_err();
+        assert!(matches!(err, WriteConfError::ValidationFailed(_)), "{err}");
+        assert!(validate(path, 0, "/root/a", b"b\n", &attrs).is_err());
+    }
+
+    #[test]
+    fn write_file_into_image_dispatches_to_btrfs() {
+        // MBR image with one Linux partition at 1 MiB holding the filesystem.
+        let start = 2048u64;
+        let fs = build(true, false);
+        let mut cur = Cursor::new(vec![0u8; (start * 512) as usize + fs.len()]);
+        let mut mbr = mbrman::MBR::new_from(&mut cur, 512, [0x12, 0x34, 0x56, 0x78]).unwrap();
+        mbr[1] = mbrman::MBRPartitionEntry {
+            boot: mbrman::BOOT_INACTIVE,
+            first_chs: mbrman::CHS::empty(),
+            sys: 0x83,
+            last_chs: mbrman::CHS::empty(),
+            starting_lba: start as u32,
+            sectors: (fs.len() / 512) as u32,
+        };
+        mbr.write_into(&mut cur).unwrap();
+        let mut bytes = cur.into_inner();
+        bytes[(start * 512) as usize..].copy_from_slice(&fs);
+        let tf = temp_image(&bytes);
+
+        let report =
+            crate::write_file_into_image(tf.path(), "/root/.not_logged_in_yet", b"a=1\n").unwrap();
+        assert!(report.validated);
+        assert_eq!(report.partition_offset, start * 512);
+        assert!(matches!(
+            crate::detect::detect_rootfs(tf.path()),
+            Err(WriteConfError::NoExt4Rootfs(_))
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-//! Partition-scheme detection and ext4 rootfs location for RAW disk images.
+//! Partition-scheme detection and ext4 rootfs / FAT boot partition location for RAW disk images.
 
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
@@ -15,6 +15,16 @@ const GPT_SIG: &[u8; 8] = b"EFI PART";
 /// Offset of the ext4 superblock within a partition, and its magic value.
 const EXT4_SB_OFFSET: u64 = 0x438;
 const EXT4_MAGIC: [u8; 2] = [0x53, 0xEF];
+/// EFI System Partition (C12A7328-F81F-11D2-BA4B-00A0C93EC93B) and Microsoft basic data
+/// (EBD0A0A2-B9E5-4433-87C0-68B6B72699C7) type GUIDs, as stored on disk.
+const ESP_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+const BASIC_DATA_GUID: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+/// MBR types for FAT: 0x0B/0x0C FAT32 (CHS/LBA), 0xEF EFI, and the FAT12/16 types.
+const MBR_FAT_TYPES: [u8; 7] = [0x0B, 0x0C, 0xEF, 0x01, 0x04, 0x06, 0x0E];
 
 /// Partition scheme of a RAW disk image.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -33,9 +43,9 @@ impl Scheme {
     }
 }
 
-/// A located rootfs partition: scheme plus its byte window in the image.
+/// A located rootfs or boot partition: scheme plus its byte window in the image.
 #[derive(Debug, Clone)]
-pub struct RootfsPartition {
+pub struct LocatedPartition {
     pub scheme: Scheme,
     pub offset: u64,
     pub len: u64,
@@ -43,22 +53,57 @@ pub struct RootfsPartition {
 
 /// Detect partition scheme (GPT if protective-MBR type 0xEE or "EFI PART" sig, else MBR) and locate the Linux
 /// ext4 rootfs: prefer a root-named/typed partition, else largest used, then gate on ext4 superblock magic.
-pub fn detect_rootfs(image_path: &Path) -> Result<RootfsPartition, WriteConfError> {
+pub fn detect_rootfs(image_path: &Path) -> Result<LocatedPartition, WriteConfError> {
+    let (part, _) = locate_rootfs(image_path)?;
+    verify_ext4(image_path, part.offset)?;
+    Ok(part)
+}
+
+/// [`detect_rootfs`] without the ext4 gate: the rootfs partition and whatever filesystem it holds.
+pub(crate) fn locate_rootfs(
+    image_path: &Path,
+) -> Result<(LocatedPartition, Filesystem), WriteConfError> {
     let (scheme, sector_size) = detect_scheme(image_path)?;
     let (offset, len) = match scheme {
         Scheme::Gpt => locate_gpt_rootfs(image_path, sector_size)?,
         Scheme::Mbr => locate_mbr_rootfs(image_path, sector_size)?,
     };
-    verify_ext4(image_path, offset)?;
-    Ok(RootfsPartition {
-        scheme,
-        offset,
-        len,
-    })
+    let fs = probe_filesystem(image_path, offset)?;
+    Ok((
+        LocatedPartition {
+            scheme,
+            offset,
+            len,
+        },
+        fs,
+    ))
+}
+
+/// Locate the FAT boot partition: the EFI System Partition or a basic-data partition on GPT,
+/// a FAT-typed entry on MBR, else any partition carrying a FAT boot sector.
+pub fn detect_fat_boot(image_path: &Path) -> Result<LocatedPartition, WriteConfError> {
+    let (scheme, sector_size) = detect_scheme(image_path)?;
+    let candidates = match scheme {
+        Scheme::Gpt => gpt_fat_candidates(image_path, sector_size)?,
+        Scheme::Mbr => mbr_fat_candidates(image_path, sector_size)?,
+    };
+    for (offset, len) in candidates {
+        if probe_filesystem(image_path, offset)? == Filesystem::Vfat {
+            return Ok(LocatedPartition {
+                scheme,
+                offset,
+                len,
+            });
+        }
+    }
+    Err(WriteConfError::NoFatPartition(format!(
+        "no {} partition carries a FAT filesystem",
+        scheme.as_str()
+    )))
 }
 
 /// Decide GPT vs MBR and recover the logical sector size from the "EFI PART" location.
-fn detect_scheme(image_path: &Path) -> Result<(Scheme, u64), WriteConfError> {
+pub(crate) fn detect_scheme(image_path: &Path) -> Result<(Scheme, u64), WriteConfError> {
     let mut f = File::open(image_path)?;
 
     // Protective-MBR first-entry type byte lives at 0x1C2.
@@ -155,41 +200,128 @@ fn locate_mbr_rootfs(image_path: &Path, sector_size: u64) -> Result<(u64, u64),
         .ok_or_else(|| WriteConfError::NoExt4Rootfs("no usable MBR partition found".into()))
 }
 
+/// Used GPT partitions, FAT-typed ones (ESP, then basic data) first.
+fn gpt_fat_candidates(
+    image_path: &Path,
+    sector_size: u64,
+) -> Result<Vec<(u64, u64)>, WriteConfError> {
+    let mut f = File::open(image_path)?;
+    let gpt = gptman::GPT::read_from(&mut f, sector_size)
+        .map_err(|e| WriteConfError::UnsupportedImage(format!("GPT parse failed: {e}")))?;
+
+    let mut parts: Vec<(u8, u64, u64)> = gpt
+        .iter()
+        .filter(|(_, p)| p.is_used())
+        .map(|(_, p)| {
+            let rank = match p.partition_type_guid {
+                ESP_GUID => 0,
+                BASIC_DATA_GUID => 1,
+                _ => 2,
+            };
+            let start = p.starting_lba * sector_size;
+            let len = (p.ending_lba - p.starting_lba + 1) * sector_size;
+            (rank, start, len)
+        })
+        .collect();
+    parts.sort_by_key(|&(rank, start, _)| (rank, start));
+    Ok(parts
+        .into_iter()
+        .map(|(_, start, len)| (start, len))
+        .collect())
+}
+
+/// Used MBR partitions, FAT-typed ones first.
+fn mbr_fat_candidates(
+    image_path: &Path,
+    sector_size: u64,
+) -> Result<Vec<(u64, u64)>, WriteConfError> {
+    let mut f = File::open(image_path)?;
+    let mbr = mbrman::MBR::read_from(&mut f, sector_size as u32)
+        .map_err(|e| WriteConfError::UnsupportedImage(format!("MBR parse failed: {e}")))?;
+
+    let mut parts: Vec<(bool, u64, u64)> = mbr
+        .iter()
+        .filter(|(_, p)| p.is_used())
+        .map(|(_, p)| {
+            let start = p.starting_lba as u64 * sector_size;
+            let len = p.sectors as u64 * sector_size;
+            (!MBR_FAT_TYPES.contains(&p.sys), start, len)
+        })
+        .collect();
+    parts.sort_by_key(|&(other, start, _)| (other, start));
+    Ok(parts
+        .into_iter()
+        .map(|(_, start, len)| (start, len))
+        .collect())
+}
+
+/// Filesystem found at a partition base by its superblock magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filesystem {
+    Ext4,
+    Btrfs,
+    F2fs,
+    Vfat,
+    Unknown,
+}
+
+impl Filesystem {
+    /// Human-readable label used in reports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Btrfs => "btrfs",
+            Filesystem::F2fs => "f2fs",
+            Filesystem::Vfat => "vfat",
+            Filesystem::Unknown => "unknown",
+        }
+    }
+}
+
 /// Confirm the ext4 superblock magic at the partition base, giving a clearer
 /// message for known non-ext4 filesystems (btrfs, f2fs).
 pub(crate) fn verify_ext4(image_path: &Path, base: u64) -> Result<(), WriteConfError> {
+    match probe_filesystem(image_path, base)? {
+        Filesystem::Ext4 => Ok(()),
+        fs @ (Filesystem::Btrfs | Filesystem::F2fs) => Err(WriteConfError::NoExt4Rootfs(format!(
+            "rootfs is {}, not ext4",
+            fs.as_str()
+        ))),
+        Filesystem::Vfat | Filesystem::Unknown => Err(WriteConfError::NoExt4Rootfs(
+            "ext4 superblock magic 0xEF53 not found at rootfs partition".into(),
+        )),
+    }
+}
+
+/// Identify the filesystem at `base` from its magic. Only a failure to open or
+/// read the ext4 superblock is an error; anything past the end is `Unknown`.
+pub(crate) fn probe_filesystem(image_path: &Path, base: u64) -> Result<Filesystem, WriteConfError> {
     let mut f = File::open(image_path)?;
     f.seek(SeekFrom::Start(base + EXT4_SB_OFFSET))?;
     let mut magic = [0u8; 2];
     f.read_exact(&mut magic)?;
     if magic == EXT4_MAGIC {
-        return Ok(());
+        return Ok(Filesystem::Ext4);
     }
 
     // btrfs magic "_BHRfS_M" at partition offset 0x10040.
-    let mut btrfs = [0u8; 8];
-    if f.seek(SeekFrom::Start(base + 0x10040)).is_ok()
-        && f.read_exact(&mut btrfs).is_ok()
-        && &btrfs == b"_BHRfS_M"
-    {
-        return Err(WriteConfError::NoExt4Rootfs(
-            "rootfs is btrfs, not ext4".into(),
-        ));
+    if magic_at(&mut f, base + 0x10040, b"_BHRfS_M") {
+        return Ok(Filesystem::Btrfs);
     }
     // f2fs magic 0xF2F52010 (LE) at partition offset 0x400.
-    let mut f2fs = [0u8; 4];
-    if f.seek(SeekFrom::Start(base + 0x400)).is_ok()
-        && f.read_exact(&mut f2fs).is_ok()
-        && f2fs == [0x10, 0x20, 0xF5, 0xF2]
-    {
-        return Err(WriteConfError::NoExt4Rootfs(
-            "rootfs is f2fs, not ext4".into(),
-        ));
+    if magic_at(&mut f, base + 0x400, &[0x10, 0x20, 0xF5, 0xF2]) {
+        return Ok(Filesystem::F2fs);
+    }
+    // FAT12/16 put their type string at 0x36, FAT32 at 0x52.
+    if magic_at(&mut f, base + 0x36, b"FAT") || magic_at(&mut f, base + 0x52, b"FAT32") {
+        return Ok(Filesystem::Vfat);
     }
+    Ok(Filesystem::Unknown)
+}
 
-    Err(WriteConfError::NoExt4Rootfs(
-        "ext4 superblock magic 0xEF53 not found at rootfs partition".into(),
-    ))
+fn magic_at(f: &mut File, pos: u64, magic: &[u8]) -> bool {
+    let mut buf = vec![0u8; magic.len()];
+    f.seek(SeekFrom::Start(pos)).is_ok() && f.read_exact(&mut buf).is_ok() && buf == magic
 }
 
 #[cfg(test)]
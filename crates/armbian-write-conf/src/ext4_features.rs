@@ -0,0 +1,322 @@
+//! ext4 superblock feature flags and the gates applied before ext4-rs writes into a
+//! filesystem. ext4-rs only understands a subset of ext4 (4 KiB blocks, extents, crc32c
+//! metadata checksums seeded from the UUID), so anything it would silently corrupt is
+//! refused up front. It also inserts directory entries linearly: `dir_index` is accepted
+//! because it only matters to directories that have grown an htree, and adding an entry
+//! to such a directory is refused by [`check_dir_insertable`].
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use armbian_ext4fs::Ext4;
+
+use crate::WriteConfError;
+
+/// Byte offset of the superblock within the filesystem.
+const SB_OFFSET: u64 = 1024;
+/// Bytes of the superblock read: up to and including the feature words.
+const SB_LEN: usize = 0x68;
+/// Block size ext4-rs is built for.
+const SUPPORTED_BLOCK_SIZE: u32 = 4096;
+
+const COMPAT_NAMES: &[(u32, &str)] = &[
+    (0x0001, "dir_prealloc"),
+    (0x0002, "imagic_inodes"),
+    (0x0004, "has_journal"),
+    (0x0008, "ext_attr"),
+    (0x0010, "resize_inode"),
+    (0x0020, "dir_index"),
+    (0x0200, "sparse_super2"),
+    (0x0400, "fast_commit"),
+    (0x0800, "stable_inodes"),
+    (0x1000, "orphan_file"),
+];
+
+const INCOMPAT_NAMES: &[(u32, &str)] = &[
+    (0x0001, "compression"),
+    (0x0002, "filetype"),
+    (INCOMPAT_RECOVER, "needs_recovery"),
+    (0x0008, "journal_dev"),
+    (0x0010, "meta_bg"),
+    (INCOMPAT_EXTENTS, "extent"),
+    (0x0080, "64bit"),
+    (0x0100, "mmp"),
+    (0x0200, "flex_bg"),
+    (0x0400, "ea_inode"),
+    (0x1000, "dirdata"),
+    (0x2000, "metadata_csum_seed"),
+    (0x4000, "large_dir"),
+    (0x8000, "inline_data"),
+    (0x1_0000, "encrypt"),
+    (0x2_0000, "casefold"),
+];
+
+const RO_COMPAT_NAMES: &[(u32, &str)] = &[
+    (0x0001, "sparse_super"),
+    (0x0002, "large_file"),
+    (0x0008, "huge_file"),
+    (0x0010, "uninit_bg"),
+    (0x0020, "dir_nlink"),
+    (0x0040, "extra_isize"),
+    (0x0100, "quota"),
+    (0x0200, "bigalloc"),
+    (0x0400, "metadata_csum"),
+    (0x0800, "replica"),
+    (0x1000, "read-only"),
+    (0x2000, "project"),
+    (0x4000, "shared_blocks"),
+    (0x8000, "verity"),
+    (RO_COMPAT_ORPHAN_PRESENT, "orphan_present"),
+];
+
+/// The journal has transactions that were never replayed.
+const INCOMPAT_RECOVER: u32 = 0x0004;
+/// Files are mapped by extent trees; ext4-rs creates nothing else.
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+/// The orphan file lists inodes still to be cleaned up.
+const RO_COMPAT_ORPHAN_PRESENT: u32 = 0x1_0000;
+/// Inode flag: the directory is hash-indexed (htree).
+const INDEX_FL: u32 = 0x1000;
+
+/// Incompatible features ext4-rs writes correctly: filetype, extent, 64bit, flex_bg,
+/// large_dir. `needs_recovery` is reported separately.
+const INCOMPAT_SUPPORTED: u32 = 0x0002 | INCOMPAT_EXTENTS | 0x0080 | 0x0200 | 0x4000;
+/// Read-only-compatible features ext4-rs keeps consistent: sparse_super, large_file,
+/// huge_file, dir_nlink, extra_isize, metadata_csum. `orphan_present` is reported
+/// separately.
+const RO_COMPAT_SUPPORTED: u32 = 0x0001 | 0x0002 | 0x0008 | 0x0020 | 0x0040 | 0x0400;
+
+/// Feature flags and block size from an ext4 superblock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ext4Features {
+    /// `s_feature_compat`: features any implementation may ignore.
+    pub compat: u32,
+    /// `s_feature_incompat`: features an implementation must understand to mount.
+    pub incompat: u32,
+    /// `s_feature_ro_compat`: features an implementation must understand to write.
+    pub ro_compat: u32,
+    /// Filesystem block size in bytes.
+    pub block_size: u32,
+}
+
+impl Ext4Features {
+    /// Read the superblock of the filesystem at `base`. The caller has already
+    /// checked the ext4 magic.
+    pub(crate) fn read(image_path: &Path, base: u64) -> Result<Self, WriteConfError> {
+        let mut f = File::open(image_path)?;
+        f.seek(SeekFrom::Start(base + SB_OFFSET))?;
+        let mut sb = [0u8; SB_LEN];
+        f.read_exact(&mut sb)?;
+        Ok(Self::parse(&sb))
+    }
+
+    fn parse(sb: &[u8; SB_LEN]) -> Self {
+        let le32 = |off: usize| u32::from_le_bytes(sb[off..off + 4].try_into().unwrap());
+        Ext4Features {
+            compat: le32(0x5C),
+            incompat: le32(0x60),
+            ro_compat: le32(0x64),
+            // s_log_block_size; anything absurd maps to 0 and is refused below.
+            block_size: 1024u32.checked_shl(le32(0x18)).unwrap_or(0),
+        }
+    }
+
+    /// Feature names as `dumpe2fs` prints them; unknown bits become `FEATURE_C12`,
+    /// `FEATURE_I31` or `FEATURE_R17`.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        names.extend(flag_names(self.compat, COMPAT_NAMES, 'C'));
+        names.extend(flag_names(self.incompat, INCOMPAT_NAMES, 'I'));
+        names.extend(flag_names(self.ro_compat, RO_COMPAT_NAMES, 'R'));
+        names
+    }
+
+    /// Refuse filesystems ext4-rs would damage: unsupported features or block size,
+    /// a journal that needs replaying, or pending orphan cleanup.
+    pub(crate) fn check_writable(&self) -> Result<(), WriteConfError> {
+        if self.incompat & INCOMPAT_RECOVER != 0 {
+            return Err(WriteConfError::UnsupportedImage(
+                "ext4 journal needs recovery (needs_recovery); mount and cleanly unmount the \
+                 filesystem or run e2fsck first"
+                    .into(),
+            ));
+        }
+        if self.ro_compat & RO_COMPAT_ORPHAN_PRESENT != 0 {
+            return Err(WriteConfError::UnsupportedImage(
+                "ext4 orphan file has pending entries (orphan_present); run e2fsck first".into(),
+            ));
+        }
+
+        let unsupported: Vec<String> = flag_names(
+            self.incompat & !INCOMPAT_SUPPORTED & !INCOMPAT_RECOVER,
+            INCOMPAT_NAMES,
+            'I',
+        )
+        .chain(flag_names(
+            self.ro_compat & !RO_COMPAT_SUPPORTED & !RO_COMPAT_ORPHAN_PRESENT,
+            RO_COMPAT_NAMES,
+            'R',
+        ))
+        .collect();
+        if !unsupported.is_empty() {
+            return Err(WriteConfError::UnsupportedImage(format!(
+                "ext4 features not supported for writing: {}",
+                unsupported.join(" ")
+            )));
+        }
+
+        if self.incompat & INCOMPAT_EXTENTS == 0 {
+            return Err(WriteConfError::UnsupportedImage(
+                "ext4 feature extent is required for writing".into(),
+            ));
+        }
+        if self.block_size != SUPPORTED_BLOCK_SIZE {
+            return Err(WriteConfError::UnsupportedImage(format!(
+                "ext4 block size {} is not supported for writing (only {SUPPORTED_BLOCK_SIZE})",
+                self.block_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Space-separated feature names, as in `dumpe2fs -h`.
+impl fmt::Display for Ext4Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.names().join(" "))
+    }
+}
+
+/// Read and gate the features of the ext4 filesystem at `base` before writing.
+pub(crate) fn check_writable(image_path: &Path, base: u64) -> Result<Ext4Features, WriteConfError> {
+    let features = Ext4Features::read(image_path, base)?;
+    features.check_writable()?;
+    Ok(features)
+}
+
+/// Refuse to add an entry to directory inode `ino` (at `path`) when it is hash-indexed:
+/// ext4-rs would append the entry to a leaf block without updating the index, so lookups
+/// through the index would not find it.
+pub(crate) fn check_dir_insertable(fs: &Ext4, ino: u32, path: &str) -> Result<(), WriteConfError> {
+    let path = if path.is_empty() { "/" } else { path };
+    let inode = fs
+        .get_inode_ref(ino)
+        .map_err(|e| WriteConfError::Ext4(format!("read inode of {path}: {e:?}")))?;
+    if inode.inode.flags() & INDEX_FL != 0 {
+        return Err(WriteConfError::UnsupportedImage(format!(
+            "{path} is a hash-indexed (dir_index) directory; adding entries to it is not supported"
+        )));
+    }
+    Ok(())
+}
+
+fn flag_names(
+    flags: u32,
+    table: &'static [(u32, &'static str)],
+    kind: char,
+) -> impl Iterator<Item = String> {
+    (0..32)
+        .map(|bit| 1u32 << bit)
+        .filter(move |b| flags & b != 0)
+        .map(move |b| match table.iter().find(|(flag, _)| *flag == b) {
+            Some((_, name)) => name.to_string(),
+            None => format!("FEATURE_{kind}{}", b.trailing_zeros()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Superblock with the feature set `mkfs.ext4` gives an Armbian rootfs.
+    fn armbian_sb() -> [u8; SB_LEN] {
+        let mut sb = [0u8; SB_LEN];
+        sb[0x18..0x1C].copy_from_slice(&2u32.to_le_bytes());
+        sb[0x5C..0x60].copy_from_slice(&0x103Cu32.to_le_bytes());
+        sb[0x60..0x64].copy_from_slice(&0x02C2u32.to_le_bytes());
+        sb[0x64..0x68].copy_from_slice(&0x046Bu32.to_le_bytes());
+        sb
+    }
+
+    fn with(mut sb: [u8; SB_LEN], off: usize, bits: u32) -> Ext4Features {
+        let word = u32::from_le_bytes(sb[off..off + 4].try_into().unwrap()) | bits;
+        sb[off..off + 4].copy_from_slice(&word.to_le_bytes());
+        Ext4Features::parse(&sb)
+    }
+
+    fn refusal(features: Ext4Features) -> String {
+        match features.check_writable() {
+            Err(WriteConfError::UnsupportedImage(m)) => m,
+            other => panic!("expected UnsupportedImage, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn armbian_features_are_writable() {
+        let features = Ext4Features::parse(&armbian_sb());
+        assert_eq!(features.block_size, 4096);
+        features.check_writable().unwrap();
+        assert_eq!(
+            features.to_string(),
+            "has_journal ext_attr resize_inode dir_index orphan_file filetype extent \
+             64bit flex_bg sparse_super large_file huge_file dir_nlink extra_isize metadata_csum"
+        );
+    }
+
+    #[test]
+    fn unsupported_features_are_refused() {
+        let m = refusal(with(armbian_sb(), 0x60, 0x8000 | 0x2_0000));
+        assert_eq!(
+            m,
+            "ext4 features not supported for writing: inline_data casefold"
+        );
+        let m = refusal(with(armbian_sb(), 0x60, 0x1_0000));
+        assert!(m.ends_with(": encrypt"), "{m}");
+        let m = refusal(with(armbian_sb(), 0x64, 0x0200));
+        assert!(m.ends_with(": bigalloc"), "{m}");
+        let m = refusal(with(armbian_sb(), 0x64, 1 << 20));
+        assert!(m.ends_with(": FEATURE_R20"), "{m}");
+
+        let m = refusal(with(armbian_sb(), 0x60, INCOMPAT_RECOVER));
+        assert!(m.starts_with("ext4 journal needs recovery"), "{m}");
+        let m = refusal(with(armbian_sb(), 0x64, RO_COMPAT_ORPHAN_PRESENT));
+        assert!(m.contains("orphan_present"), "{m}");
+
+        // Unknown compat bits are harmless.
+        with(armbian_sb(), 0x5C, 1 << 30).check_writable().unwrap();
+    }
+
+    #[test]
+    fn block_size_and_extents_are_required() {
+        let mut sb = armbian_sb();
+        sb[0x18..0x1C].copy_from_slice(&0u32.to_le_bytes());
+        let m = refusal(Ext4Features::parse(&sb));
+        assert_eq!(
+            m,
+            "ext4 block size 1024 is not supported for writing (only 4096)"
+        );
+
+        let mut sb = armbian_sb();
+        sb[0x18..0x1C].copy_from_slice(&40u32.to_le_bytes());
+        assert_eq!(Ext4Features::parse(&sb).block_size, 0);
+
+        let mut sb = armbian_sb();
+        sb[0x60..0x64].copy_from_slice(&0x0002u32.to_le_bytes());
+        let m = refusal(Ext4Features::parse(&sb));
+        assert_eq!(m, "ext4 feature extent is required for writing");
+    }
+
+    #[test]
+    fn reads_superblock_at_partition_offset() {
+        let mut tf = tempfile::NamedTempFile::new().unwrap();
+        let mut image = vec![0u8; 4096 + SB_OFFSET as usize + SB_LEN];
+        image[4096 + SB_OFFSET as usize..].copy_from_slice(&armbian_sb());
+        tf.write_all(&image).unwrap();
+
+        let features = check_writable(tf.path(), 4096).unwrap();
+        assert_eq!(features, Ext4Features::parse(&armbian_sb()));
+    }
+}
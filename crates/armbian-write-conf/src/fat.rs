@@ -0,0 +1,778 @@
+//! FAT12/16/32 boot partitions: many images keep `/boot` on FAT and a few have no ext4
+//! at all. Files are written through `fatfs` (long file names included), then read back
+//! and the allocation table checked raw: mirrored copies equal, every chain in range,
+//! terminated and claimed once, sizes matching chain lengths, long-name checksums
+//! matching their short entry, and no lost clusters.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use fatfs::{FileSystem, FsOptions};
+
+use crate::{detect, FileKind, WriteConfError, WriteConfReport};
+
+/// Directory entry attribute bits.
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LFN: u8 = 0x0F;
+/// Size of a directory entry.
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// Directory entries as (name, kind, size).
+pub(crate) type DirListing = Vec<(String, FileKind, u64)>;
+
+/// Create or overwrite `dest_path` in the image's FAT boot partition (parents are
+/// created), then validate read-only; see [`WriteConfReport`].
+/// Errors ([`WriteConfError`]) on no FAT partition, write failure, or bad validation.
+pub fn write_file_into_fat_image(
+    image_path: &Path,
+    dest_path: &str,
+    content: &[u8],
+) -> Result<WriteConfReport, WriteConfError> {
+    let part = detect::detect_fat_boot(image_path)?;
+
+    let written = write(image_path, part.offset, part.len, dest_path, content)?;
+    validate(image_path, part.offset, part.len, dest_path, content)?;
+
+    Ok(WriteConfReport {
+        scheme: part.scheme.as_str(),
+        partition_offset: part.offset,
+        partition_len: part.len,
+        dest_path: dest_path.to_string(),
+        bytes_written: written,
+        validated: true,
+        ext4_features: None,
+    })
+}
+
+/// [`write_file_into_fat_image`] for a BARE FAT image (boot sector at byte 0, no partition table).
+pub fn write_file_into_bare_fat_image(
+    image_path: &Path,
+    dest_path: &str,
+    content: &[u8],
+) -> Result<WriteConfReport, WriteConfError> {
+    if detect::probe_filesystem(image_path, 0)? != detect::Filesystem::Vfat {
+        return Err(WriteConfError::NoFatPartition(
+            "no FAT boot sector at image start".into(),
+        ));
+    }
+    let len = std::fs::metadata(image_path)?.len();
+
+    let written = write(image_path, 0, len, dest_path, content)?;
+    validate(image_path, 0, len, dest_path, content)?;
+
+    Ok(WriteConfReport {
+        scheme: "bare-fat",
+        partition_offset: 0,
+        partition_len: len,
+        dest_path: dest_path.to_string(),
+        bytes_written: written,
+        validated: true,
+        ext4_features: None,
+    })
+}
+
+/// Read `path` from the FAT boot partition of `image_path`, read-only.
+pub fn read_file_from_fat_image(image_path: &Path, path: &str) -> Result<Vec<u8>, WriteConfError> {
+    let part = detect::detect_fat_boot(image_path)?;
+    read(image_path, part.offset, part.len, path)
+}
+
+/// Byte window of the image file exposed to `fatfs` as a whole volume.
+struct PartStream {
+    file: File,
+    base: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl PartStream {
+    fn new(file: File, base: u64, len: u64) -> Self {
+        Self {
+            file,
+            base,
+            len,
+            pos: 0,
+        }
+    }
+
+    /// Bytes left in the window, capped at `want`.
+    fn room(&self, want: usize) -> usize {
+        self.len.saturating_sub(self.pos).min(want as u64) as usize
+    }
+}
+
+impl Read for PartStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.room(buf.len());
+        self.file.seek(SeekFrom::Start(self.base + self.pos))?;
+        let n = self.file.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for PartStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.room(buf.len());
+        if n == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "write past end of partition",
+            ));
+        }
+        self.file.seek(SeekFrom::Start(self.base + self.pos))?;
+        let n = self.file.write(&buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for PartStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before start of partition",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+fn mount(file: File, base: u64, len: u64) -> Result<FileSystem<PartStream>, WriteConfError> {
+    FileSystem::new(PartStream::new(file, base, len), FsOptions::new())
+        .map_err(|e| WriteConfError::Fat(format!("mount failed: {e}")))
+}
+
+/// Split an absolute path into its components; FAT has no `.`/`..` of its own to resolve.
+fn components(path: &str) -> Result<Vec<&str>, WriteConfError> {
+    let parts: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    if !path.starts_with('/') || parts.is_empty() || parts.iter().any(|c| *c == "." || *c == "..") {
+        return Err(WriteConfError::Fat(format!(
+            "{path}: expected an absolute file path"
+        )));
+    }
+    Ok(parts)
+}
+
+/// Create or truncate `dest_path` and write `content`, creating missing parents.
+pub(crate) fn write(
+    image_path: &Path,
+    base: u64,
+    len: u64,
+    dest_path: &str,
+    content: &[u8],
+) -> Result<usize, WriteConfError> {
+    let parts = components(dest_path)?;
+    let fat_err =
+        |what: &str, e: io::Error| WriteConfError::Fat(format!("{what} {dest_path}: {e}"));
+
+    let file = OpenOptions::new().read(true).write(true).open(image_path)?;
+    let fs = mount(file, base, len)?;
+    {
+        let mut dir = fs.root_dir();
+        for name in &parts[..parts.len() - 1] {
+            dir = dir
+                .create_dir(name)
+                .map_err(|e| fat_err("create parent of", e))?;
+        }
+        let mut f = dir
+            .create_file(parts[parts.len() - 1])
+            .map_err(|e| fat_err("create", e))?;
+        // create_file opens an existing file as is; drop its old clusters first.
+        f.truncate().map_err(|e| fat_err("truncate", e))?;
+        f.write_all(content).map_err(|e| fat_err("write", e))?;
+        f.flush().map_err(|e| fat_err("flush", e))?;
+    }
+    fs.unmount()
+        .map_err(|e| WriteConfError::Fat(format!("unmount failed: {e}")))?;
+    Ok(content.len())
+}
+
+pub(crate) fn read(
+    image_path: &Path,
+    base: u64,
+    len: u64,
+    path: &str,
+) -> Result<Vec<u8>, WriteConfError> {
+    let parts = components(path)?;
+    let fs = mount(File::open(image_path)?, base, len)?;
+    let mut content = Vec::new();
+    fs.root_dir()
+        .open_file(&parts.join("/"))
+        .and_then(|mut f| f.read_to_end(&mut content))
+        .map_err(|e| WriteConfError::Fat(format!("read {path}: {e}")))?;
+    Ok(content)
+}
+
+/// Entries of directory `path` as (name, kind, size), without `.` and `..`.
+pub(crate) fn read_dir(
+    image_path: &Path,
+    base: u64,
+    len: u64,
+    path: &str,
+) -> Result<DirListing, WriteConfError> {
+    let parts = components(path)?;
+    let fs = mount(File::open(image_path)?, base, len)?;
+    let fat_err = |e: io::Error| WriteConfError::Fat(format!("read_dir {path}: {e}"));
+    let dir = fs.root_dir().open_dir(&parts.join("/")).map_err(fat_err)?;
+
+    let mut entries = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry.map_err(fat_err)?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let kind = if entry.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        };
+        entries.push((name, kind, entry.len()));
+    }
+    Ok(entries)
+}
+
+/// Re-read the written file, then check the whole allocation table.
+pub(crate) fn validate(
+    image_path: &Path,
+    base: u64,
+    len: u64,
+    dest_path: &str,
+    content: &[u8],
+) -> Result<(), WriteConfError> {
+    let got = read(image_path, base, len, dest_path)
+        .map_err(|e| WriteConfError::ValidationFailed(format!("re-read {dest_path}: {e}")))?;
+    if got != content {
+        return Err(WriteConfError::ValidationFailed(format!(
+            "{dest_path} content mismatch: wrote {} bytes, read {} bytes",
+            content.len(),
+            got.len()
+        )));
+    }
+    check_consistency(image_path, base)
+}
+
+/// Geometry of a FAT volume, from its BIOS parameter block.
+struct Layout {
+    /// Bits per FAT entry: 12, 16 or 32.
+    bits: u8,
+    cluster_size: u64,
+    /// Byte offset and length of one FAT copy.
+    fat_offset: u64,
+    fat_len: u64,
+    fats: u8,
+    /// FAT32 may disable mirroring and use only the active copy.
+    active_fat: Option<u8>,
+    /// FAT12/16 fixed root directory region.
+    root_offset: u64,
+    root_len: u64,
+    /// FAT32 root directory cluster.
+    root_cluster: u32,
+    data_offset: u64,
+    clusters: u32,
+}
+
+impl Layout {
+    fn parse(bs: &[u8]) -> Result<Self, WriteConfError> {
+        let u16_at = |o: usize| u16::from_le_bytes([bs[o], bs[o + 1]]) as u64;
+        let u32_at = |o: usize| u32::from_le_bytes([bs[o], bs[o + 1], bs[o + 2], bs[o + 3]]) as u64;
+        let bad = |why: &str| WriteConfError::ValidationFailed(format!("FAT boot sector: {why}"));
+
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = bs[13] as u64;
+        if !(512..=4096).contains(&bytes_per_sector) || sectors_per_cluster == 0 {
+            return Err(bad("invalid sector or cluster size"));
+        }
+        let reserved = u16_at(14);
+        let fats = bs[16];
+        let root_entries = u16_at(17);
+        let total = match u16_at(19) {
+            0 => u32_at(32),
+            n => n,
+        };
+        let fat_sectors = match u16_at(22) {
+            0 => u32_at(36),
+            n => n,
+        };
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+        let first_data = reserved + fats as u64 * fat_sectors + root_sectors;
+        if fats == 0 || fat_sectors == 0 || total <= first_data {
+            return Err(bad("inconsistent geometry"));
+        }
+        let clusters = ((total - first_data) / sectors_per_cluster) as u32;
+
+        let bits = match clusters {
+            0..=4084 => 12,
+            4085..=65524 => 16,
+            _ => 32,
+        };
+        let ext_flags = u16_at(40);
+        let active_fat = (bits == 32 && ext_flags & 0x80 != 0).then_some((ext_flags & 0x0F) as u8);
+
+        Ok(Self {
+            bits,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            fat_offset: reserved * bytes_per_sector,
+            fat_len: fat_sectors * bytes_per_sector,
+            fats,
+            active_fat,
+            root_offset: (reserved + fats as u64 * fat_sectors) * bytes_per_sector,
+            root_len: root_sectors * bytes_per_sector,
+            root_cluster: if bits == 32 { u32_at(44) as u32 } else { 0 },
+            data_offset: first_data * bytes_per_sector,
+            clusters,
+        })
+    }
+
+    /// Values at or above this end a chain; one below it marks a bad cluster.
+    fn end_of_chain(&self) -> u32 {
+        match self.bits {
+            12 => 0xFF8,
+            16 => 0xFFF8,
+            _ => 0x0FFF_FFF8,
+        }
+    }
+
+    fn entry(&self, fat: &[u8], n: u32) -> u32 {
+        let n = n as usize;
+        match self.bits {
+            12 => {
+                let i = n * 3 / 2;
+                let v = u16::from_le_bytes([fat[i], fat[i + 1]]) as u32;
+                if n & 1 == 1 {
+                    v >> 4
+                } else {
+                    v & 0xFFF
+                }
+            }
+            16 => u16::from_le_bytes([fat[n * 2], fat[n * 2 + 1]]) as u32,
+            _ => {
+                u32::from_le_bytes([fat[n * 4], fat[n * 4 + 1], fat[n * 4 + 2], fat[n * 4 + 3]])
+                    & 0x0FFF_FFFF
+            }
+        }
+    }
+}
+
+/// Walks the directory tree, claiming every cluster it reaches.
+struct Checker<'a> {
+    image: File,
+    base: u64,
+    layout: &'a Layout,
+    fat: Vec<u8>,
+    claimed: Vec<bool>,
+}
+
+/// Check the FAT volume at `base` for the inconsistencies fsck.fat would repair.
+fn check_consistency(image_path: &Path, base: u64) -> Result<(), WriteConfError> {
+    let mut image = File::open(image_path)?;
+    let layout = Layout::parse(&read_at(&mut image, base, 512)?)?;
+
+    let fat_at = |image: &mut File, i: u8| {
+        read_at(
+            image,
+            base + layout.fat_offset + i as u64 * layout.fat_len,
+            layout.fat_len,
+        )
+    };
+    let fat = fat_at(&mut image, layout.active_fat.unwrap_or(0))?;
+    if (layout.clusters as u64 + 2) * layout.bits as u64 > layout.fat_len * 8 {
+        return Err(WriteConfError::ValidationFailed(
+            "FAT is too small for the cluster count".into(),
+        ));
+    }
+    if layout.active_fat.is_none() {
+        for i in 1..layout.fats {
+            if fat_at(&mut image, i)? != fat {
+                return Err(WriteConfError::ValidationFailed(format!(
+                    "FAT copy {i} differs from copy 0"
+                )));
+            }
+        }
+    }
+
+    let mut checker = Checker {
+        image,
+        base,
+        layout: &layout,
+        fat,
+        claimed: vec![false; layout.clusters as usize + 2],
+    };
+    let root = if layout.bits == 32 {
+        let chain = checker.chain("/", layout.root_cluster)?;
+        checker.read_chain(&chain)?
+    } else {
+        read_at(
+            &mut checker.image,
+            base + layout.root_offset,
+            layout.root_len,
+        )?
+    };
+    checker.walk("", &root)?;
+
+    let bad = layout.end_of_chain() - 1;
+    let lost = (2..layout.clusters + 2)
+        .filter(|&c| {
+            let v = layout.entry(&checker.fat, c);
+            v != 0 && v != bad && !checker.claimed[c as usize]
+        })
+        .count();
+    if lost > 0 {
+        return Err(WriteConfError::ValidationFailed(format!(
+            "{lost} allocated cluster(s) not reachable from any file"
+        )));
+    }
+    Ok(())
+}
+
+impl Checker<'_> {
+    /// Follow and claim the chain starting at `first`.
+    fn chain(&mut self, path: &str, first: u32) -> Result<Vec<u32>, WriteConfError> {
+        let fail = |why: String| WriteConfError::ValidationFailed(format!("{path}: {why}"));
+        let eoc = self.layout.end_of_chain();
+        let mut chain = Vec::new();
+        let mut c = first;
+        loop {
+            if c < 2 || c >= self.layout.clusters + 2 {
+                return Err(fail(format!("cluster {c} out of range")));
+            }
+            if std::mem::replace(&mut self.claimed[c as usize], true) {
+                return Err(fail(format!("cluster {c} is cross-linked")));
+            }
+            chain.push(c);
+            match self.layout.entry(&self.fat, c) {
+                next if next >= eoc => return Ok(chain),
+                0 => return Err(fail(format!("chain runs into free cluster after {c}"))),
+                next if next == eoc - 1 => {
+                    return Err(fail(format!("chain runs into bad cluster after {c}")))
+                }
+                next => c = next,
+            }
+        }
+    }
+
+    fn read_chain(&mut self, chain: &[u32]) -> Result<Vec<u8>, WriteConfError> {
+        let mut data = Vec::with_capacity(chain.len() * self.layout.cluster_size as usize);
+        for &c in chain {
+            let offset = self.layout.data_offset + (c as u64 - 2) * self.layout.cluster_size;
+            data.extend(read_at(
+                &mut self.image,
+                self.base + offset,
+                self.layout.cluster_size,
+            )?);
+        }
+        Ok(data)
+    }
+
+    /// Check every entry of a directory's raw contents and recurse into subdirectories.
+    fn walk(&mut self, dir: &str, entries: &[u8]) -> Result<(), WriteConfError> {
+        // Checksum and next expected sequence number of a pending long-name run.
+        let mut lfn: Option<(u8, u8)> = None;
+
+        for e in entries.chunks_exact(DIR_ENTRY_SIZE) {
+            let attr = e[11];
+            match e[0] {
+                0x00 => break,
+                0xE5 => {
+                    lfn = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if attr == ATTR_LFN {
+                let (seq, sum) = (e[0] & 0x1F, e[13]);
+                lfn = match lfn {
+                    _ if e[0] & 0x40 != 0 => Some((sum, seq)),
+                    Some((s, expected)) if s == sum && seq + 1 == expected => Some((sum, seq)),
+                    _ => return Err(orphan(dir)),
+                };
+                continue;
+            }
+
+            let name = short_name(&e[..11]);
+            match lfn.take() {
+                None => {}
+                Some((sum, 1)) if sum == sfn_checksum(&e[..11]) => {}
+                Some(_) => return Err(orphan(&format!("{dir}/{name}"))),
+            }
+            if attr & ATTR_VOLUME_ID != 0 || name == "." || name == ".." {
+                continue;
+            }
+
+            let path = format!("{dir}/{name}");
+            let hi = if self.layout.bits == 32 {
+                (u16::from_le_bytes([e[20], e[21]]) as u32) << 16
+            } else {
+                0
+            };
+            let first = hi | u16::from_le_bytes([e[26], e[27]]) as u32;
+            let size = u32::from_le_bytes([e[28], e[29], e[30], e[31]]) as u64;
+
+            if attr & ATTR_DIRECTORY != 0 {
+                let chain = self.chain(&path, first)?;
+                let contents = self.read_chain(&chain)?;
+                self.walk(&path, &contents)?;
+            } else if first == 0 {
+                if size != 0 {
+                    return Err(WriteConfError::ValidationFailed(format!(
+                        "{path}: {size} bytes but no clusters"
+                    )));
+                }
+            } else {
+                let clusters = self.chain(&path, first)?.len() as u64;
+                let cs = self.layout.cluster_size;
+                if size <= (clusters - 1) * cs || size > clusters * cs {
+                    return Err(WriteConfError::ValidationFailed(format!(
+                        "{path}: {size} bytes in {clusters} cluster(s) of {cs}"
+                    )));
+                }
+            }
+        }
+        if lfn.is_some() {
+            return Err(orphan(dir));
+        }
+        Ok(())
+    }
+}
+
+fn orphan(path: &str) -> WriteConfError {
+    WriteConfError::ValidationFailed(format!("{path}: long file name does not match its entry"))
+}
+
+/// 8.3 name as shown by tools, e.g. `ARMBIA~1.TXT`.
+fn short_name(raw: &[u8]) -> String {
+    let base = String::from_utf8_lossy(&raw[..8]).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&raw[8..11]).trim_end().to_string();
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{base}.{ext}")
+    }
+}
+
+/// Checksum of an 8.3 name, repeated in each of its long-name entries.
+fn sfn_checksum(raw: &[u8]) -> u8 {
+    raw.iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn read_at(f: &mut File, offset: u64, len: u64) -> Result<Vec<u8>, WriteConfError> {
+    let mut buf = vec![0u8; len as usize];
+    f.seek(SeekFrom::Start(offset))?;
+    f.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fatfs::{FatType, FormatVolumeOptions};
+
+    /// A bare FAT volume of `len` bytes with 512-byte clusters.
+    fn format(len: u64, fat_type: FatType) -> tempfile::NamedTempFile {
+        let tf = tempfile::NamedTempFile::new().unwrap();
+        tf.as_file().set_len(len).unwrap();
+        let options = FormatVolumeOptions::new()
+            .fat_type(fat_type)
+            .bytes_per_cluster(512)
+            .total_sectors((len / 512) as u32);
+        fatfs::format_volume(PartStream::new(tf.reopen().unwrap(), 0, len), options).unwrap();
+        tf
+    }
+
+    #[test]
+    fn writes_and_overwrites_on_fat12_16_32() {
+        for (len, fat_type) in [
+            (1 << 20, FatType::Fat12),
+            (8 << 20, FatType::Fat16),
+            (40 << 20, FatType::Fat32),
+        ] {
+            let tf = format(len, fat_type);
+            let path = tf.path();
+            check_consistency(path, 0).unwrap();
+
+            // Long names, a new parent directory, then shrinking and growing overwrites.
+            let big = vec![b'x'; 3000];
+            write_file_into_bare_fat_image(path, "/armbianEnv.txt", b"fdtfile=a.dtb\n").unwrap();
+            write_file_into_bare_fat_image(path, "/extlinux/extlinux.conf", &big).unwrap();
+            write_file_into_bare_fat_image(path, "/extlinux/extlinux.conf", b"short\n").unwrap();
+            let report =
+                write_file_into_bare_fat_image(path, "/armbianEnv.txt", &big[..1500]).unwrap();
+            assert!(report.validated);
+            assert_eq!(report.scheme, "bare-fat");
+
+            assert_eq!(
+                read(path, 0, len, "/extlinux/extlinux.conf").unwrap(),
+                b"short\n"
+            );
+            assert_eq!(read(path, 0, len, "/ARMBIANENV.TXT").unwrap(), &big[..1500]);
+        }
+    }
+
+    #[test]
+    fn detects_broken_fat() {
+        let len = 8 << 20;
+        let tf = format(len, FatType::Fat16);
+        let path = tf.path();
+        write_file_into_bare_fat_image(path, "/boot.scr", &[7u8; 2000]).unwrap();
+
+        let layout =
+            Layout::parse(&read_at(&mut File::open(path).unwrap(), 0, 512).unwrap()).unwrap();
+        let mut f = OpenOptions::new().write(true).open(path).unwrap();
+        // Mark a free cluster used in the second copy only, then in both (a lost cluster).
+        let last = layout.fat_offset + (layout.clusters as u64 + 1) * 2;
+        for copy in [1, 0] {
+            f.seek(SeekFrom::Start(last + copy * layout.fat_len))
+                .unwrap();
+            f.write_all(&[0xFF, 0xFF]).unwrap();
+            let err = check_consistency(path, 0).unwrap_err().to_string();
+            let want = if copy == 1 {
+                "differs"
+            } else {
+                "not reachable"
+            };
+            assert!(err.contains(want), "{err}");
+        }
+    }
+
+    #[test]
+    fn detects_bad_directory_entries() {
+        let len = 8 << 20;
+        let tf = format(len, FatType::Fat16);
+        let path = tf.path();
+        write_file_into_bare_fat_image(path, "/armbianEnv.txt", &[7u8; 600]).unwrap();
+
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let layout = Layout::parse(&read_at(&mut f, 0, 512).unwrap()).unwrap();
+        let root = read_at(&mut f, layout.root_offset, layout.root_len).unwrap();
+        let entries: Vec<&[u8]> = root.chunks_exact(DIR_ENTRY_SIZE).collect();
+        let lfn = entries
+            .iter()
+            .position(|e| e[11] == ATTR_LFN)
+            .expect("mixed-case name gets long-name entries") as u64;
+        // "armbianEnv.txt" needs two long-name entries before its short one.
+        let sfn = lfn + 2;
+        assert_ne!(entries[sfn as usize][11], ATTR_LFN);
+        let at = |i: u64, field: u64| layout.root_offset + i * DIR_ENTRY_SIZE as u64 + field;
+        let mut poke = |pos: u64, bytes: &[u8]| {
+            f.seek(SeekFrom::Start(pos)).unwrap();
+            f.write_all(bytes).unwrap();
+        };
+
+        // 600 bytes in two 512-byte clusters; claim 2000 instead.
+        poke(at(sfn, 28), &2000u32.to_le_bytes());
+        let err = check_consistency(path, 0).unwrap_err().to_string();
+        assert!(err.contains("2000 bytes in 2 cluster"), "{err}");
+        poke(at(sfn, 28), &600u32.to_le_bytes());
+        check_consistency(path, 0).unwrap();
+
+        let sum = entries[lfn as usize][13];
+        poke(at(lfn, 13), &[sum.wrapping_add(1)]);
+        let err = check_consistency(path, 0).unwrap_err().to_string();
+        assert!(err.contains("long file name"), "{err}");
+    }
+
+    #[test]
+    fn locates_boot_partition_on_gpt_and_mbr() {
+        const MIB: u64 = 1 << 20;
+        let tf = tempfile::NamedTempFile::new().unwrap();
+        let path = tf.path();
+        let fat_len = 2 * MIB;
+        let options = || {
+            FormatVolumeOptions::new()
+                .fat_type(FatType::Fat12)
+                .total_sectors((fat_len / 512) as u32)
+        };
+
+        // GPT: a Linux partition first, then the ESP at 3 MiB.
+        let mut disk = std::io::Cursor::new(vec![0u8; 6 * MIB as usize]);
+        let mut gpt = gptman::GPT::new_from(&mut disk, 512, [0x11; 16]).unwrap();
+        let linux_guid = [
+            0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47,
+            0x7D, 0xE4,
+        ];
+        let esp_guid = [
+            0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E,
+            0xC9, 0x3B,
+        ];
+        for (i, guid, start) in [(1, linux_guid, 2048), (2, esp_guid, 6144)] {
+            gpt[i] = gptman::GPTPartitionEntry {
+                partition_type_guid: guid,
+                unique_partition_guid: [i as u8; 16],
+                starting_lba: start,
+                ending_lba: start + fat_len / 512 - 1,
+                attribute_bits: 0,
+                partition_name: "".into(),
+            };
+        }
+        gpt.write_into(&mut disk).unwrap();
+        std::fs::write(path, disk.into_inner()).unwrap();
+        fatfs::format_volume(
+            PartStream::new(tf.reopen().unwrap(), 3 * MIB, fat_len),
+            options(),
+        )
+        .unwrap();
+
+        let report =
+            write_file_into_fat_image(path, "/armbianEnv.txt", b"overlays=uart1\n").unwrap();
+        assert_eq!(
+            (report.scheme, report.partition_offset, report.partition_len),
+            ("GPT", 3 * MIB, fat_len)
+        );
+        assert_eq!(
+            read_file_from_fat_image(path, "/armbianEnv.txt").unwrap(),
+            b"overlays=uart1\n"
+        );
+
+        // MBR: a single 0x0C partition at 1 MiB.
+        let mut mbr = vec![0u8; 4 * MIB as usize];
+        mbr[446 + 4] = 0x0C;
+        mbr[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+        mbr[446 + 12..446 + 16].copy_from_slice(&((fat_len / 512) as u32).to_le_bytes());
+        mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+        std::fs::write(path, mbr).unwrap();
+        fatfs::format_volume(
+            PartStream::new(tf.reopen().unwrap(), MIB, fat_len),
+            options(),
+        )
+        .unwrap();
+
+        let report = write_file_into_fat_image(path, "/boot.cmd", b"setenv x 1\n").unwrap();
+        assert_eq!((report.scheme, report.partition_offset), ("MBR", MIB));
+
+        // Without a FAT filesystem there is nothing to locate.
+        let mut blank = vec![0u8; MIB as usize];
+        blank[510..512].copy_from_slice(&[0x55, 0xAA]);
+        std::fs::write(path, blank).unwrap();
+        assert!(matches!(
+            write_file_into_fat_image(path, "/boot.cmd", b""),
+            Err(WriteConfError::NoFatPartition(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_relative_and_dot_paths() {
+        for bad in ["armbianEnv.txt", "/", "/boot/../x"] {
+            assert!(matches!(components(bad), Err(WriteConfError::Fat(_))));
+        }
+        assert_eq!(components("/a//b").unwrap(), ["a", "b"]);
+    }
+}
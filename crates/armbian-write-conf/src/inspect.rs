@@ -0,0 +1,329 @@
+//! Read-only inspection of a RAW disk image before it is flashed: the partition
+//! table, the filesystem on each partition, and directory listings, metadata and
+//! file contents of ext4 partitions (e.g. `/etc/armbian-release`).
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use ext4_view::{Ext4 as Ext4Ro, FileType, Metadata};
+
+use crate::detect::{self, Filesystem, Scheme};
+use crate::validate::PartReader;
+use crate::WriteConfError;
+
+/// Partition table of an image and what sits on each used partition.
+#[derive(Debug, Clone)]
+pub struct ImageLayout {
+    pub scheme: Scheme,
+    /// Logical sector size the table was read with (512, or 4096 on UFS).
+    pub sector_size: u64,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+/// One used partition-table entry.
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    /// 1-based table slot, as in `/dev/sdX<index>`; pass it to the browse calls.
+    pub index: u32,
+    /// Byte window of the partition within the image.
+    pub offset: u64,
+    pub len: u64,
+    /// GPT type GUID (e.g. `0FC63DAF-8483-4772-8E79-3D69D8477DE4`) or MBR type byte (e.g. `0x83`).
+    pub type_id: String,
+    /// GPT partition name; empty on MBR.
+    pub name: String,
+    pub filesystem: Filesystem,
+}
+
+/// Kind of an inode, as shown by a file browser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl FileKind {
+    /// Human-readable label used in reports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::File => "file",
+            FileKind::Dir => "dir",
+            FileKind::Symlink => "symlink",
+            FileKind::Other => "other",
+        }
+    }
+}
+
+/// Metadata of a path, not following a final symlink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub kind: FileKind,
+    /// Size in bytes; for a symlink, the length of its target.
+    pub len: u64,
+    /// Permission bits only (`0o7777`).
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// Target of a symlink, as stored.
+    pub symlink_target: Option<String>,
+}
+
+/// One directory entry with its metadata.
+#[derive(Debug, Clone)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub stat: FileStat,
+}
+
+/// List the used partitions of `image_path` with their detected filesystem.
+pub fn list_partitions(image_path: &Path) -> Result<ImageLayout, WriteConfError> {
+    let (scheme, sector_size) = detect::detect_scheme(image_path)?;
+    let mut partitions = match scheme {
+        Scheme::Gpt => gpt_partitions(image_path, sector_size)?,
+        Scheme::Mbr => mbr_partitions(image_path, sector_size)?,
+    };
+    for p in &mut partitions {
+        p.filesystem = detect::probe_filesystem(image_path, p.offset)?;
+    }
+    Ok(ImageLayout {
+        scheme,
+        sector_size,
+        partitions,
+    })
+}
+
+/// List directory `path` on ext4 partition `index`, sorted by name, without `.` and `..`.
+pub fn read_dir_in_partition(
+    image_path: &Path,
+    index: u32,
+    path: &str,
+) -> Result<Vec<DirEntryInfo>, WriteConfError> {
+    let fs = load(image_path, index)?;
+    let dir = fs
+        .read_dir(path)
+        .map_err(|e| WriteConfError::Ext4Read(format!("read_dir {path}: {e}")))?;
+
+    let mut entries = Vec::new();
+    for entry in dir {
+        let entry = entry.map_err(|e| WriteConfError::Ext4Read(format!("read_dir {path}: {e}")))?;
+        let name = entry.file_name().display().to_string();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let child = entry.path();
+        let md = entry
+            .metadata()
+            .map_err(|e| WriteConfError::Ext4Read(format!("stat {}: {e}", child.display())))?;
+        let stat = to_stat(&fs, &child.display().to_string(), &md)?;
+        entries.push(DirEntryInfo { name, stat });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Metadata of `path` on ext4 partition `index`.
+pub fn stat_in_partition(
+    image_path: &Path,
+    index: u32,
+    path: &str,
+) -> Result<FileStat, WriteConfError> {
+    let fs = load(image_path, index)?;
+    let md = fs
+        .symlink_metadata(path)
+        .map_err(|e| WriteConfError::Ext4Read(format!("stat {path}: {e}")))?;
+    to_stat(&fs, path, &md)
+}
+
+/// Read at most `limit` bytes of `path` on ext4 partition `index`, following
+/// symlinks. Returns the bytes read and the full length of the file.
+pub fn read_file_in_partition(
+    image_path: &Path,
+    index: u32,
+    path: &str,
+    limit: u64,
+) -> Result<(Vec<u8>, u64), WriteConfError> {
+    let fs = load(image_path, index)?;
+    let file = fs
+        .open(path)
+        .map_err(|e| WriteConfError::Ext4Read(format!("open {path}: {e}")))?;
+    let len = file.metadata().len();
+    let mut content = Vec::new();
+    file.take(limit)
+        .read_to_end(&mut content)
+        .map_err(|e| WriteConfError::Ext4Read(format!("read {path}: {e}")))?;
+    Ok((content, len))
+}
+
+/// Load partition `index` read-only, refusing anything that is not ext4.
+fn load(image_path: &Path, index: u32) -> Result<Ext4Ro, WriteConfError> {
+    let layout = list_partitions(image_path)?;
+    let part = layout
+        .partitions
+        .iter()
+        .find(|p| p.index == index)
+        .ok_or_else(|| WriteConfError::UnsupportedImage(format!("no partition {index}")))?;
+    if part.filesystem != Filesystem::Ext4 {
+        return Err(WriteConfError::Ext4Read(format!(
+            "partition {index} is {}, only ext4 can be browsed",
+            part.filesystem.as_str()
+        )));
+    }
+
+    let file = File::open(image_path)?;
+    Ext4Ro::load(Box::new(PartReader {
+        file,
+        base: part.offset,
+    }))
+    .map_err(|e| WriteConfError::Ext4Read(format!("ext4-view load failed: {e}")))
+}
+
+fn to_stat(fs: &Ext4Ro, path: &str, md: &Metadata) -> Result<FileStat, WriteConfError> {
+    let kind = match md.file_type() {
+        FileType::Regular => FileKind::File,
+        FileType::Directory => FileKind::Dir,
+        FileType::Symlink => FileKind::Symlink,
+        _ => FileKind::Other,
+    };
+    let symlink_target = if kind == FileKind::Symlink {
+        let target = fs
+            .read_link(path)
+            .map_err(|e| WriteConfError::Ext4Read(format!("read_link {path}: {e}")))?;
+        Some(target.display().to_string())
+    } else {
+        None
+    };
+    Ok(FileStat {
+        kind,
+        len: md.len(),
+        mode: md.mode() & 0o7777,
+        uid: md.uid(),
+        gid: md.gid(),
+        symlink_target,
+    })
+}
+
+fn gpt_partitions(
+    image_path: &Path,
+    sector_size: u64,
+) -> Result<Vec<PartitionInfo>, WriteConfError> {
+    let mut f = File::open(image_path)?;
+    let gpt = gptman::GPT::read_from(&mut f, sector_size)
+        .map_err(|e| WriteConfError::UnsupportedImage(format!("GPT parse failed: {e}")))?;
+
+    Ok(gpt
+        .iter()
+        .filter(|(_, p)| p.is_used())
+        .map(|(i, p)| PartitionInfo {
+            index: i,
+            offset: p.starting_lba * sector_size,
+            len: (p.ending_lba - p.starting_lba + 1) * sector_size,
+            type_id: guid_string(&p.partition_type_guid),
+            name: p.partition_name.as_str().to_string(),
+            filesystem: Filesystem::Unknown,
+        })
+        .collect())
+}
+
+fn mbr_partitions(
+    image_path: &Path,
+    sector_size: u64,
+) -> Result<Vec<PartitionInfo>, WriteConfError> {
+    let mut f = File::open(image_path)?;
+    let mbr = mbrman::MBR::read_from(&mut f, sector_size as u32)
+        .map_err(|e| WriteConfError::UnsupportedImage(format!("MBR parse failed: {e}")))?;
+
+    Ok(mbr
+        .iter()
+        .filter(|(_, p)| p.is_used())
+        .map(|(i, p)| PartitionInfo {
+            index: i as u32,
+            offset: p.starting_lba as u64 * sector_size,
+            len: p.sectors as u64 * sector_size,
+            type_id: format!("0x{:02X}", p.sys),
+            name: String::new(),
+            filesystem: Filesystem::Unknown,
+        })
+        .collect())
+}
+
+/// Format an on-disk GPT GUID; the first three fields are stored little-endian.
+fn guid_string(g: &[u8; 16]) -> String {
+    format!(
+        "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        g[3], g[2], g[1], g[0], g[5], g[4], g[7], g[6], g[8], g[9], g[10], g[11], g[12], g[13],
+        g[14], g[15]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    const LINUX_FS_GUID: [u8; 16] = [
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D,
+        0xE4,
+    ];
+
+    #[test]
+    fn lists_gpt_partitions_with_filesystems() {
+        let mut cur = Cursor::new(vec![0u8; 1024 * 1024]);
+        let mut gpt = gptman::GPT::new_from(&mut cur, 512, [0x11; 16]).unwrap();
+        let first = gpt.header.first_usable_lba;
+        for (i, name, start) in [(1, "boot", first), (2, "rootfs", first + 512)] {
+            gpt[i] = gptman::GPTPartitionEntry {
+                partition_type_guid: LINUX_FS_GUID,
+                unique_partition_guid: [0x22 + i as u8; 16],
+                starting_lba: start,
+                ending_lba: start + 511,
+                attribute_bits: 0,
+                partition_name: name.into(),
+            };
+        }
+        gpt.write_into(&mut cur).unwrap();
+        let mut bytes = cur.into_inner();
+        let boot = (first * 512) as usize;
+        bytes[boot + 0x52..boot + 0x57].copy_from_slice(b"FAT32");
+        let root = ((first + 512) * 512) as usize;
+        bytes[root + 0x438..root + 0x43A].copy_from_slice(&[0x53, 0xEF]);
+
+        let mut tf = tempfile::NamedTempFile::new().unwrap();
+        tf.write_all(&bytes).unwrap();
+        tf.flush().unwrap();
+
+        let layout = list_partitions(tf.path()).unwrap();
+        assert_eq!(layout.scheme, Scheme::Gpt);
+        assert_eq!(layout.sector_size, 512);
+        let summary: Vec<_> = layout
+            .partitions
+            .iter()
+            .map(|p| (p.index, p.name.as_str(), p.filesystem, p.offset, p.len))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, "boot", Filesystem::Vfat, first * 512, 512 * 512),
+                (
+                    2,
+                    "rootfs",
+                    Filesystem::Ext4,
+                    (first + 512) * 512,
+                    512 * 512
+                ),
+            ]
+        );
+        assert_eq!(
+            layout.partitions[0].type_id,
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+        );
+
+        // The vfat partition cannot be browsed, and slot 3 is empty.
+        let err = stat_in_partition(tf.path(), 1, "/").unwrap_err();
+        assert!(matches!(err, WriteConfError::Ext4Read(_)));
+        let err = stat_in_partition(tf.path(), 3, "/").unwrap_err();
+        assert!(matches!(err, WriteConfError::UnsupportedImage(_)));
+    }
+}
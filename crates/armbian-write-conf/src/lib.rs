@@ -1,5 +1,14 @@
 //! Write a config file into a RAW disk image's ext4 rootfs in userspace (no mount/privileges), then validate.
 //! Parses partition scheme (GPT/MBR), locates the Linux ext4 rootfs, writes via `armbian-ext4fs`, re-validates read-only with `ext4-view`.
+//! ext4 feature flags are checked first ([`Ext4Features`]); filesystems `armbian-ext4fs` can't write safely are refused.
+//! A btrfs rootfs (`ROOTFS_TYPE=btrfs`) takes single small files too, inlined into the default subvolume.
+//! [`read_file_from_image`] reads files back the same way, read-only.
+//! Mode, owner and times of written files are set from [`FileAttrs`] and checked on validation.
+//! [`write_entries_into_image`] writes a batch of files, directories and symlinks with modes and owners.
+//! [`write_file_into_fat_image`] and [`read_file_from_fat_image`] do the same on a FAT12/16/32 boot partition.
+//! [`edit_armbian_env_in_image`] edits `armbianEnv.txt` (overlays, console, kernel args) on whichever partition holds `/boot`.
+//! [`list_overlays_in_image`] lists the device-tree overlays an image ships, with their parameters.
+//! [`list_partitions`] and the `*_in_partition` calls inspect an image's partitions and browse its ext4 ones.
 
 use std::fmt;
 use std::fs::OpenOptions;
@@ -7,12 +16,41 @@ use std::io::{Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use armbian_ext4fs::{BlockDevice, Ext4, BLOCK_SIZE};
+use armbian_ext4fs::{BlockDevice, Errno, Ext4, Ext4Error, BLOCK_SIZE};
 
+mod armbian_env;
+mod attrs;
+mod batch;
+mod btrfs;
 mod detect;
+mod ext4_features;
+mod fat;
+mod inspect;
+mod overlays;
+mod read;
 mod validate;
 
-pub use detect::Scheme;
+pub use armbian_env::{
+    edit_armbian_env_in_image, read_armbian_env_from_image, ArmbianEnv, Console,
+};
+pub use attrs::FileAttrs;
+pub use batch::{
+    write_entries_into_bare_ext4_image, write_entries_into_image, BatchReport, Entry, EntryKind,
+};
+pub use detect::{Filesystem, Scheme};
+pub use ext4_features::Ext4Features;
+pub use fat::{
+    read_file_from_fat_image, write_file_into_bare_fat_image, write_file_into_fat_image,
+};
+pub use inspect::{
+    list_partitions, read_dir_in_partition, read_file_in_partition, stat_in_partition,
+    DirEntryInfo, FileKind, FileStat, ImageLayout, PartitionInfo,
+};
+pub use overlays::{list_overlays_in_image, OverlayInfo, OverlayListing};
+pub use read::read_file_from_image;
+
+/// Inode number of the root directory.
+const ROOT_INODE: u32 = 2;
 
 /// Outcome of a successful write-and-validate operation.
 #[derive(Debug, Clone)]
@@ -29,6 +67,8 @@ pub struct WriteConfReport {
     pub bytes_written: usize,
     /// True only when post-write validation fully succeeded.
     pub validated: bool,
+    /// Feature flags of the ext4 filesystem written into; `None` for btrfs and FAT.
+    pub ext4_features: Option<Ext4Features>,
 }
 
 /// Errors that can occur while writing into an image.
@@ -42,8 +82,18 @@ pub enum WriteConfError {
     NoExt4Rootfs(String),
     /// The ext4 write layer reported a failure.
     Ext4(String),
+    /// The read-only ext4 layer could not load the filesystem or read a file.
+    Ext4Read(String),
     /// Post-write validation failed (checksum/corruption/mismatch).
     ValidationFailed(String),
+    /// A batch entry was rejected before anything was written.
+    InvalidEntry(String),
+    /// No FAT boot partition was found.
+    NoFatPartition(String),
+    /// The FAT layer could not mount the filesystem, or read or write a file.
+    Fat(String),
+    /// The btrfs layer could not parse the filesystem or insert the file.
+    Btrfs(String),
 }
 
 impl fmt::Display for WriteConfError {
@@ -53,7 +103,12 @@ impl fmt::Display for WriteConfError {
             WriteConfError::UnsupportedImage(m) => write!(f, "unsupported image: {m}"),
             WriteConfError::NoExt4Rootfs(m) => write!(f, "no ext4 rootfs: {m}"),
             WriteConfError::Ext4(m) => write!(f, "ext4 write error: {m}"),
+            WriteConfError::Ext4Read(m) => write!(f, "ext4 read error: {m}"),
             WriteConfError::ValidationFailed(m) => write!(f, "validation failed: {m}"),
+            WriteConfError::InvalidEntry(m) => write!(f, "invalid entry: {m}"),
+            WriteConfError::NoFatPartition(m) => write!(f, "no FAT boot partition: {m}"),
+            WriteConfError::Fat(m) => write!(f, "FAT error: {m}"),
+            WriteConfError::Btrfs(m) => write!(f, "btrfs error: {m}"),
         }
     }
 }
@@ -71,65 +126,106 @@ impl From<std::io::Error> for WriteConfError {
 struct PartDev {
     file: Mutex<std::fs::File>,
     base: u64,
+    /// First I/O error hit by the device. ext4-rs only sees `EIO`, so the original
+    /// error is kept here and reported by [`PartDev::io_error_or`].
+    error: Mutex<Option<std::io::Error>>,
+}
+
+impl PartDev {
+    fn new(file: std::fs::File, base: u64) -> Arc<Self> {
+        Arc::new(PartDev {
+            file: Mutex::new(file),
+            base,
+            error: Mutex::new(None),
+        })
+    }
+
+    /// Load the ext4-rs filesystem from this device.
+    fn open(self: &Arc<Self>) -> Result<Ext4, WriteConfError> {
+        Ext4::open(self.clone())
+            .map_err(|e| self.io_error_or(WriteConfError::Ext4(format!("open filesystem: {e:?}"))))
+    }
+
+    /// The recorded I/O error as [`WriteConfError::Io`] if there is one, else `err`.
+    fn io_error_or(&self, err: WriteConfError) -> WriteConfError {
+        match self.error.lock().unwrap().take() {
+            Some(e) => WriteConfError::Io(e),
+            None => err,
+        }
+    }
+
+    /// Flush writes to disk before the image is reloaded for validation.
+    fn sync(&self) -> Result<(), WriteConfError> {
+        self.file.lock().unwrap().sync_all()?;
+        Ok(())
+    }
+
+    /// Record a failed I/O call and hand ext4-rs an `EIO` in its place.
+    fn check<T>(&self, result: std::io::Result<T>) -> Result<T, Ext4Error> {
+        result.map_err(|e| {
+            self.error.lock().unwrap().get_or_insert(e);
+            Ext4Error::with_message(Errno::EIO, "image I/O failed")
+        })
+    }
 }
 
 impl BlockDevice for PartDev {
-    fn read_offset(&self, offset: usize) -> Vec<u8> {
+    fn read_offset(&self, offset: usize) -> Result<Vec<u8>, Ext4Error> {
         let mut f = self.file.lock().unwrap();
-        f.seek(SeekFrom::Start(self.base + offset as u64)).unwrap();
+        self.check(f.seek(SeekFrom::Start(self.base + offset as u64)))?;
         let mut buf = vec![0u8; BLOCK_SIZE];
         let mut filled = 0;
         while filled < BLOCK_SIZE {
             match std::io::Read::read(&mut *f, &mut buf[filled..]) {
                 Ok(0) => break, // short read near EOF: leave zeros
                 Ok(n) => filled += n,
-                Err(e) => panic!("read_offset error: {e}"),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return self.check(Err(e)),
             }
         }
-        buf
+        Ok(buf)
     }
 
-    fn write_offset(&self, offset: usize, data: &[u8]) {
+    fn write_offset(&self, offset: usize, data: &[u8]) -> Result<(), Ext4Error> {
         let mut f = self.file.lock().unwrap();
-        f.seek(SeekFrom::Start(self.base + offset as u64)).unwrap();
-        f.write_all(data).unwrap();
+        self.check(f.seek(SeekFrom::Start(self.base + offset as u64)))?;
+        self.check(f.write_all(data))
     }
 }
 
-/// Write `content` to `dest_path` in the image's ext4 rootfs, then validate read-only; [`WriteConfReport`]'s `validated` is true only if it read back identically and the tree walk found no corruption.
-/// Errors ([`WriteConfError`]) on non-ext4 raw image, write failure, or bad validation.
+/// Write `content` to `dest_path` in the image's ext4 rootfs (or btrfs default subvolume, as an inline extent), then validate read-only; [`WriteConfReport`]'s `validated` is true only if it read back identically and the tree walk found no corruption.
+/// The file is root-owned 0644 ([`FileAttrs::default`]); see [`write_file_into_image_with_attrs`].
+/// Errors ([`WriteConfError`]) on a raw image without an ext4 or btrfs rootfs, write failure, or bad validation.
 pub fn write_file_into_image(
     image_path: &Path,
     dest_path: &str,
     content: &[u8],
 ) -> Result<WriteConfReport, WriteConfError> {
-    let part = detect::detect_rootfs(image_path)?;
-
-    // Open the image read+write and wrap the rootfs window for ext4-rs.
-    let file = OpenOptions::new().read(true).write(true).open(image_path)?;
-    let dev = Arc::new(PartDev {
-        file: Mutex::new(file),
-        base: part.offset,
-    });
-    let fs = Ext4::open(dev.clone());
-
-    let ino = fs
-        .ext4_file_open(dest_path, "w+")
-        .map_err(|e| WriteConfError::Ext4(format!("open {dest_path}: {e:?}")))?;
-    let written = fs
-        .ext4_file_write(ino as u64, 0, content)
-        .map_err(|e| WriteConfError::Ext4(format!("write {dest_path}: {e:?}")))?;
+    write_file_into_image_with_attrs(image_path, dest_path, content, &FileAttrs::default())
+}
 
-    // Flush to disk before reloading for validation, then release handles.
-    dev.file
-        .lock()
-        .unwrap()
-        .sync_all()
-        .map_err(WriteConfError::Io)?;
-    drop(fs);
-    drop(dev);
+/// [`write_file_into_image`] with an explicit mode, owner and times; validation also
+/// checks they read back.
+pub fn write_file_into_image_with_attrs(
+    image_path: &Path,
+    dest_path: &str,
+    content: &[u8],
+    attrs: &FileAttrs,
+) -> Result<WriteConfReport, WriteConfError> {
+    attrs.check(dest_path)?;
+    let (part, fs) = detect::locate_rootfs(image_path)?;
 
-    validate::validate(image_path, part.offset, dest_path, content)?;
+    let (written, ext4_features) = if fs == detect::Filesystem::Btrfs {
+        let written = btrfs::write(image_path, part.offset, dest_path, content, attrs)?;
+        btrfs::validate(image_path, part.offset, dest_path, content, attrs)?;
+        (written, None)
+    } else {
+        detect::verify_ext4(image_path, part.offset)?;
+        let features = ext4_features::check_writable(image_path, part.offset)?;
+        let written = write_file(image_path, part.offset, dest_path, content, attrs)?;
+        validate::validate(image_path, part.offset, dest_path, content, attrs)?;
+        (written, Some(features))
+    };
 
     Ok(WriteConfReport {
         scheme: part.scheme.as_str(),
@@ -138,6 +234,7 @@ pub fn write_file_into_image(
         dest_path: dest_path.to_string(),
         bytes_written: written,
         validated: true,
+        ext4_features,
     })
 }
 
@@ -148,34 +245,28 @@ pub fn write_file_into_bare_ext4_image(
     dest_path: &str,
     content: &[u8],
 ) -> Result<WriteConfReport, WriteConfError> {
+    write_file_into_bare_ext4_image_with_attrs(
+        image_path,
+        dest_path,
+        content,
+        &FileAttrs::default(),
+    )
+}
+
+/// [`write_file_into_bare_ext4_image`] with an explicit mode, owner and times.
+pub fn write_file_into_bare_ext4_image_with_attrs(
+    image_path: &Path,
+    dest_path: &str,
+    content: &[u8],
+    attrs: &FileAttrs,
+) -> Result<WriteConfReport, WriteConfError> {
+    attrs.check(dest_path)?;
     // The filesystem starts at file byte 0 (no partition table).
     detect::verify_ext4(image_path, 0)?;
+    let features = ext4_features::check_writable(image_path, 0)?;
 
-    // Open the image read+write; the ext4-rs window covers the whole file.
-    let file = OpenOptions::new().read(true).write(true).open(image_path)?;
-    let dev = Arc::new(PartDev {
-        file: Mutex::new(file),
-        base: 0,
-    });
-    let fs = Ext4::open(dev.clone());
-
-    let ino = fs
-        .ext4_file_open(dest_path, "w+")
-        .map_err(|e| WriteConfError::Ext4(format!("open {dest_path}: {e:?}")))?;
-    let written = fs
-        .ext4_file_write(ino as u64, 0, content)
-        .map_err(|e| WriteConfError::Ext4(format!("write {dest_path}: {e:?}")))?;
-
-    // Flush to disk before reloading for validation, then release handles.
-    dev.file
-        .lock()
-        .unwrap()
-        .sync_all()
-        .map_err(WriteConfError::Io)?;
-    drop(fs);
-    drop(dev);
-
-    validate::validate(image_path, 0, dest_path, content)?;
+    let written = write_file(image_path, 0, dest_path, content, attrs)?;
+    validate::validate(image_path, 0, dest_path, content, attrs)?;
 
     let len = std::fs::metadata(image_path)?.len();
 
@@ -186,5 +277,88 @@ pub fn write_file_into_bare_ext4_image(
         dest_path: dest_path.to_string(),
         bytes_written: written,
         validated: true,
+        ext4_features: Some(features),
     })
 }
+
+/// Write one file through ext4-rs at partition offset `base` and set its attributes.
+fn write_file(
+    image_path: &Path,
+    base: u64,
+    dest_path: &str,
+    content: &[u8],
+    attrs: &FileAttrs,
+) -> Result<usize, WriteConfError> {
+    // Open the image read+write and wrap the rootfs window for ext4-rs.
+    let file = OpenOptions::new().read(true).write(true).open(image_path)?;
+    let dev = PartDev::new(file, base);
+    let fs = dev.open()?;
+
+    check_open_insertable(&fs, dest_path).map_err(|e| dev.io_error_or(e))?;
+    let ino = fs
+        .ext4_file_open(dest_path, "w+")
+        .map_err(|e| dev.io_error_or(WriteConfError::Ext4(format!("open {dest_path}: {e:?}"))))?;
+    let written = fs
+        .ext4_file_write(ino as u64, 0, content)
+        .map_err(|e| dev.io_error_or(WriteConfError::Ext4(format!("write {dest_path}: {e:?}"))))?;
+    // ext4-rs creates new files world-writable (0777).
+    attrs.apply(&fs, ino).map_err(|e| {
+        dev.io_error_or(WriteConfError::Ext4(format!(
+            "set attributes of {dest_path}: {e:?}"
+        )))
+    })?;
+
+    // Flush to disk before reloading for validation, then release handles.
+    dev.sync()?;
+    Ok(written)
+}
+
+/// `ext4_file_open` creates a missing `dest_path`, and missing directories on the way,
+/// in its deepest existing ancestor; that directory must take new entries.
+fn check_open_insertable(fs: &Ext4, dest_path: &str) -> Result<(), WriteConfError> {
+    let mut ino = ROOT_INODE;
+    let mut path = String::new();
+    for name in dest_path.split('/').filter(|c| !c.is_empty()) {
+        match fs.fuse_lookup(ino as u64, name) {
+            Ok(attr) => ino = attr.ino as u32,
+            Err(e) if e.error() == Errno::ENOENT => {
+                return ext4_features::check_dir_insertable(fs, ino, &path);
+            }
+            Err(e) => {
+                return Err(WriteConfError::Ext4(format!(
+                    "look up {path}/{name}: {e:?}"
+                )))
+            }
+        }
+        path = format!("{path}/{name}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_dev_zero_fills_short_reads_and_reports_io_errors_once() {
+        let tf = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tf.path(), [0xAAu8; 100]).unwrap();
+
+        // Short read at EOF: zero-filled, not an error.
+        let dev = PartDev::new(std::fs::File::open(tf.path()).unwrap(), 0);
+        let block = dev.read_offset(0).unwrap();
+        assert_eq!(block.len(), BLOCK_SIZE);
+        assert_eq!(&block[..100], &[0xAA; 100]);
+        assert!(block[100..].iter().all(|&b| b == 0));
+
+        // Writing through a read-only handle is EIO for ext4-rs, Io for the caller.
+        let err = dev.write_offset(0, &[0; 16]).unwrap_err();
+        assert_eq!(err.error(), Errno::EIO);
+        let err = dev.io_error_or(WriteConfError::Ext4("unused".into()));
+        assert!(matches!(err, WriteConfError::Io(_)), "{err:?}");
+
+        // The recorded error is reported once.
+        let err = dev.io_error_or(WriteConfError::Ext4("fallback".into()));
+        assert!(matches!(err, WriteConfError::Ext4(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,383 @@
+//! Device-tree overlays shipped in an image: the `.dtbo` files under
+//! `/boot/dtb/<vendor>/overlay/` (or `/boot/dtb/overlay/` on 32-bit boards), named the way
+//! `overlays=` in `armbianEnv.txt` expects them, with the parameters each declares in its
+//! `__overrides__` node. Read-only, on the ext4 or FAT partition holding `/boot`.
+
+use std::fs::File;
+use std::path::Path;
+
+use ext4_view::{Ext4 as Ext4Ro, FileType};
+
+use crate::detect::Filesystem;
+use crate::fat::DirListing;
+use crate::validate::PartReader;
+use crate::{fat, inspect, read_armbian_env_from_image, FileKind, WriteConfError};
+
+/// Where the dtb directory sits on an ext4 partition: the rootfs' `/boot`, or the top of a boot partition.
+const EXT4_DTB_DIRS: [&str; 2] = ["/boot/dtb", "/dtb"];
+/// Where the dtb directory sits on a FAT boot partition.
+const FAT_DTB_DIR: &str = "/dtb";
+/// Overlay directory name, directly under the dtb directory or under a vendor directory.
+const OVERLAY_DIR: &str = "overlay";
+const DTBO_SUFFIX: &str = ".dtbo";
+
+/// Flattened device tree header magic and structure block tokens.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/// Node whose properties are an overlay's parameters.
+const OVERRIDES_NODE: &[u8] = b"__overrides__";
+
+/// Overlays found in an image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverlayListing {
+    /// `overlay_prefix` from `armbianEnv.txt`, stripped from file names to get overlay names.
+    pub prefix: Option<String>,
+    /// Byte offset of the partition holding the dtb directory; `None` when no overlays were found.
+    pub partition_offset: Option<u64>,
+    /// Sorted by path.
+    pub overlays: Vec<OverlayInfo>,
+}
+
+/// One `.dtbo` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayInfo {
+    /// Name to list in `overlays=`, e.g. `uart1` for `rockchip-uart1.dtbo` with prefix `rockchip`.
+    pub name: String,
+    /// Path inside the partition, e.g. `/boot/dtb/rockchip/overlay/rockchip-uart1.dtbo`.
+    pub path: String,
+    pub size: u64,
+    /// Property names of the `__overrides__` node, in file order; empty when it has none.
+    pub params: Vec<String>,
+}
+
+/// A partition opened read-only for browsing.
+enum Volume {
+    Ext4(Ext4Ro),
+    Fat { offset: u64, len: u64 },
+}
+
+impl Volume {
+    /// Entries of `path`, or `None` when it is not a directory.
+    fn read_dir(
+        &self,
+        image_path: &Path,
+        path: &str,
+    ) -> Result<Option<DirListing>, WriteConfError> {
+        match self {
+            Volume::Ext4(fs) => {
+                match fs.metadata(path) {
+                    Ok(md) if md.file_type() == FileType::Directory => {}
+                    _ => return Ok(None),
+                }
+                let err = |e| WriteConfError::Ext4Read(format!("read_dir {path}: {e}"));
+                let mut entries = Vec::new();
+                for entry in fs.read_dir(path).map_err(err)? {
+                    let name = entry.map_err(err)?.file_name().display().to_string();
+                    if name == "." || name == ".." {
+                        continue;
+                    }
+                    // Follow symlinks, e.g. an overlay linked from another kernel's directory.
+                    let Ok(md) = fs.metadata(format!("{path}/{name}").as_str()) else {
+                        continue;
+                    };
+                    let kind = match md.file_type() {
+                        FileType::Regular => FileKind::File,
+                        FileType::Directory => FileKind::Dir,
+                        _ => FileKind::Other,
+                    };
+                    entries.push((name, kind, md.len()));
+                }
+                Ok(Some(entries))
+            }
+            Volume::Fat { offset, len } => match fat::read_dir(image_path, *offset, *len, path) {
+                Ok(entries) => Ok(Some(entries)),
+                Err(WriteConfError::Fat(_)) => Ok(None),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn read(&self, image_path: &Path, path: &str) -> Result<Vec<u8>, WriteConfError> {
+        match self {
+            Volume::Ext4(fs) => fs
+                .read(path)
+                .map_err(|e| WriteConfError::Ext4Read(format!("read {path}: {e}"))),
+            Volume::Fat { offset, len } => fat::read(image_path, *offset, *len, path),
+        }
+    }
+}
+
+/// List the device-tree overlays an image ships. The first partition, in table order,
+/// with a dtb directory is used; an image without one yields an empty listing.
+/// Errors ([`WriteConfError`]) on an unreadable partition table or filesystem.
+pub fn list_overlays_in_image(image_path: &Path) -> Result<OverlayListing, WriteConfError> {
+    let prefix = match read_armbian_env_from_image(image_path) {
+        Ok(env) => env
+            .get("overlay_prefix")
+            .filter(|p| !p.is_empty())
+            .map(str::to_string),
+        Err(WriteConfError::UnsupportedImage(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let layout = inspect::list_partitions(image_path)?;
+    for p in &layout.partitions {
+        let (volume, dtb_dirs) = match p.filesystem {
+            Filesystem::Ext4 => {
+                let fs = Ext4Ro::load(Box::new(PartReader {
+                    file: File::open(image_path)?,
+                    base: p.offset,
+                }))
+                .map_err(|e| WriteConfError::Ext4Read(format!("ext4-view load failed: {e}")))?;
+                (Volume::Ext4(fs), &EXT4_DTB_DIRS[..])
+            }
+            Filesystem::Vfat => (
+                Volume::Fat {
+                    offset: p.offset,
+                    len: p.len,
+                },
+                &[FAT_DTB_DIR][..],
+            ),
+            _ => continue,
+        };
+
+        for dtb_dir in dtb_dirs {
+            let Some(entries) = volume.read_dir(image_path, dtb_dir)? else {
+                continue;
+            };
+            let mut overlay_dirs = Vec::new();
+            for (name, kind, _) in entries {
+                if kind != FileKind::Dir {
+                    continue;
+                }
+                if name == OVERLAY_DIR {
+                    overlay_dirs.push(format!("{dtb_dir}/{OVERLAY_DIR}"));
+                } else {
+                    overlay_dirs.push(format!("{dtb_dir}/{name}/{OVERLAY_DIR}"));
+                }
+            }
+
+            let mut overlays = Vec::new();
+            for dir in overlay_dirs {
+                let Some(entries) = volume.read_dir(image_path, &dir)? else {
+                    continue;
+                };
+                for (file_name, kind, size) in entries {
+                    let Some(stem) = file_name.strip_suffix(DTBO_SUFFIX) else {
+                        continue;
+                    };
+                    if kind != FileKind::File {
+                        continue;
+                    }
+                    let path = format!("{dir}/{file_name}");
+                    let blob = volume.read(image_path, &path)?;
+                    overlays.push(OverlayInfo {
+                        name: overlay_name(stem, prefix.as_deref()),
+                        path,
+                        size,
+                        params: overrides(&blob).unwrap_or_default(),
+                    });
+                }
+            }
+            overlays.sort_by(|a, b| a.path.cmp(&b.path));
+            return Ok(OverlayListing {
+                prefix,
+                partition_offset: Some(p.offset),
+                overlays,
+            });
+        }
+    }
+
+    Ok(OverlayListing {
+        prefix,
+        ..Default::default()
+    })
+}
+
+/// `rockchip-uart1` with prefix `rockchip` is listed as `uart1` in `overlays=`.
+fn overlay_name(stem: &str, prefix: Option<&str>) -> String {
+    prefix
+        .and_then(|p| stem.strip_prefix(p))
+        .and_then(|rest| rest.strip_prefix('-'))
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(stem)
+        .to_string()
+}
+
+/// Property names of the root's `__overrides__` child in a flattened device tree, or
+/// `None` when `blob` is not a well-formed one.
+fn overrides(blob: &[u8]) -> Option<Vec<String>> {
+    let be32 = |at: usize| -> Option<u32> {
+        let bytes = blob.get(at..at.checked_add(4)?)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    };
+    let cstr = |at: usize| -> Option<&[u8]> {
+        let rest = blob.get(at..)?;
+        Some(&rest[..rest.iter().position(|&b| b == 0)?])
+    };
+
+    if be32(0)? != FDT_MAGIC {
+        return None;
+    }
+    let struct_off = be32(8)? as usize;
+    let strings_off = be32(12)? as usize;
+
+    let mut params = Vec::new();
+    let mut pos = struct_off;
+    let mut depth = 0usize;
+    // Depth of the `__overrides__` node while inside it.
+    let mut in_overrides = None;
+    loop {
+        let token = be32(pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(pos)?;
+                pos += (name.len() + 1).next_multiple_of(4);
+                depth += 1;
+                if depth == 2 && name == OVERRIDES_NODE {
+                    in_overrides = Some(depth);
+                }
+            }
+            FDT_END_NODE => {
+                if in_overrides == Some(depth) {
+                    in_overrides = None;
+                }
+                depth = depth.checked_sub(1)?;
+            }
+            FDT_PROP => {
+                let len = be32(pos)? as usize;
+                let name_off = be32(pos + 4)? as usize;
+                pos += 8 + len.next_multiple_of(4);
+                if in_overrides == Some(depth) {
+                    let name = cstr(strings_off.checked_add(name_off)?)?;
+                    params.push(String::from_utf8_lossy(name).into_owned());
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => return Some(params),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal flattened device tree builder: nodes and properties in order.
+    #[derive(Default)]
+    struct Fdt {
+        structure: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Fdt {
+        fn token(&mut self, token: u32) -> &mut Self {
+            self.structure.extend_from_slice(&token.to_be_bytes());
+            self
+        }
+
+        fn padded(&mut self, bytes: &[u8]) {
+            self.structure.extend_from_slice(bytes);
+            let pad = bytes.len().next_multiple_of(4) - bytes.len();
+            self.structure.extend(std::iter::repeat_n(0, pad));
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.token(FDT_BEGIN_NODE);
+            self.padded(format!("{name}\0").as_bytes());
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.token(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_off = self.strings.len() as u32;
+            self.strings
+                .extend_from_slice(format!("{name}\0").as_bytes());
+            self.token(FDT_PROP);
+            self.structure
+                .extend_from_slice(&(value.len() as u32).to_be_bytes());
+            self.structure.extend_from_slice(&name_off.to_be_bytes());
+            self.padded(value);
+            self
+        }
+
+        fn build(&mut self) -> Vec<u8> {
+            self.token(FDT_END);
+            let header_len = 40u32;
+            let struct_len = self.structure.len() as u32;
+            let mut blob = Vec::new();
+            for word in [
+                FDT_MAGIC,
+                header_len + struct_len + self.strings.len() as u32,
+                header_len,
+                header_len + struct_len,
+                header_len,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                struct_len,
+            ] {
+                blob.extend_from_slice(&word.to_be_bytes());
+            }
+            blob.extend_from_slice(&self.structure);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    #[test]
+    fn reads_overrides_of_root_node_only() {
+        let blob = Fdt::default()
+            .begin("")
+            .prop("compatible", b"rockchip,rk3588\0")
+            .begin("fragment@0")
+            .prop("target", &[0, 0, 0, 1])
+            .begin("__overrides__")
+            .prop("nested", b"\0")
+            .end()
+            .end()
+            .token(FDT_NOP)
+            .begin("__overrides__")
+            .prop("baudrate", b"&uart1:current-speed:0\0")
+            .prop("pins", b"&uart1_pins:rockchip,pins:0\0")
+            .end()
+            .begin("__fixups__")
+            .prop("uart1", b"/fragment@0:target:0\0")
+            .end()
+            .end()
+            .build();
+        assert_eq!(overrides(&blob).unwrap(), ["baudrate", "pins"]);
+
+        let plain = Fdt::default().begin("").prop("a", b"").end().build();
+        assert_eq!(overrides(&plain).unwrap(), Vec::<String>::new());
+
+        assert_eq!(overrides(b"not a device tree"), None);
+        assert_eq!(overrides(&blob[..blob.len() / 2]), None);
+    }
+
+    #[test]
+    fn strips_prefix_from_names() {
+        assert_eq!(overlay_name("rockchip-uart1", Some("rockchip")), "uart1");
+        assert_eq!(overlay_name("rk3588-i2c7-m3", Some("rk3588")), "i2c7-m3");
+        assert_eq!(
+            overlay_name("sun50i-h6-spi", Some("rockchip")),
+            "sun50i-h6-spi"
+        );
+        assert_eq!(
+            overlay_name("rockchipuart1", Some("rockchip")),
+            "rockchipuart1"
+        );
+        assert_eq!(overlay_name("rockchip-", Some("rockchip")), "rockchip-");
+        assert_eq!(overlay_name("w1-gpio", None), "w1-gpio");
+    }
+}
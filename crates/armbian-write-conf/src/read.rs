@@ -0,0 +1,26 @@
+//! Read-only access to files in a RAW disk's ext4 rootfs, for identifying what is
+//! already installed on a card without mounting it.
+
+use std::fs::File;
+use std::path::Path;
+
+use ext4_view::Ext4 as Ext4Ro;
+
+use crate::validate::PartReader;
+use crate::{detect, WriteConfError};
+
+/// Read `path` from the ext4 rootfs of `image_path` (an image file or a whole-disk
+/// block device). Errors ([`WriteConfError`]) on no ext4 rootfs or a missing file.
+pub fn read_file_from_image(image_path: &Path, path: &str) -> Result<Vec<u8>, WriteConfError> {
+    let part = detect::detect_rootfs(image_path)?;
+
+    let file = File::open(image_path)?;
+    let fs = Ext4Ro::load(Box::new(PartReader {
+        file,
+        base: part.offset,
+    }))
+    .map_err(|e| WriteConfError::Ext4Read(format!("ext4-view load failed: {e}")))?;
+
+    fs.read(path)
+        .map_err(|e| WriteConfError::Ext4Read(format!("read {path}: {e}")))
+}
@@ -1,18 +1,22 @@
 //! Read-only validation of an ext4 rootfs after a write, using ext4-view (verifies inode + block-group-descriptor
 //! checksums on access), so reading the dest file back and walking the whole tree acts as an e2fsck proxy.
+//! Mode and owner are checked through ext4-view metadata; it has no timestamps, so those come from ext4-rs.
 
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 
-use ext4_view::{Ext4 as Ext4Ro, Ext4Read};
+use ext4_view::{Ext4 as Ext4Ro, Ext4Read, Metadata};
 
-use crate::WriteConfError;
+use crate::{Entry, EntryKind, FileAttrs, PartDev, WriteConfError};
+
+/// Inode number of the root directory.
+const ROOT_INODE: u32 = 2;
 
 /// ext4-view reader over a partition window of the image file.
-struct PartReader {
-    file: File,
-    base: u64,
+pub(crate) struct PartReader {
+    pub(crate) file: File,
+    pub(crate) base: u64,
 }
 
 impl Ext4Read for PartReader {
@@ -27,17 +31,16 @@ impl Ext4Read for PartReader {
     }
 }
 
-/// Reload the rootfs read-only, confirm the dest file matches `content`, and
-/// walk the whole tree so any checksum/corruption error surfaces.
+/// Reload the rootfs read-only, confirm the dest file matches `content` and `attrs`,
+/// and walk the whole tree so any checksum/corruption error surfaces.
 pub fn validate(
     image_path: &Path,
     base: u64,
     dest_path: &str,
     content: &[u8],
+    attrs: &FileAttrs,
 ) -> Result<(), WriteConfError> {
-    let file = File::open(image_path)?;
-    let fs = Ext4Ro::load(Box::new(PartReader { file, base }))
-        .map_err(|e| WriteConfError::ValidationFailed(format!("ext4-view load failed: {e}")))?;
+    let fs = load(image_path, base)?;
 
     // The written file must read back byte-for-byte.
     let got = fs
@@ -50,12 +53,150 @@ pub fn validate(
             got.len()
         )));
     }
+    let md = fs
+        .symlink_metadata(dest_path)
+        .map_err(|e| WriteConfError::ValidationFailed(format!("stat {dest_path}: {e}")))?;
+    check_owner_and_mode(dest_path, &md, attrs)?;
+    check_times(&fs, image_path, base, &[(dest_path, attrs)])?;
 
     // Full tree walk forces checksum validation across every inode.
     walk(&fs, "/")?;
     Ok(())
 }
 
+/// Batch counterpart of [`validate`]: every entry must be there with the right type,
+/// file content or symlink target and attributes, then the tree is walked once.
+pub fn validate_entries(
+    image_path: &Path,
+    base: u64,
+    entries: &[Entry],
+) -> Result<(), WriteConfError> {
+    let fs = load(image_path, base)?;
+
+    for entry in entries {
+        let path = entry.path.as_str();
+        let md = fs
+            .symlink_metadata(path)
+            .map_err(|e| WriteConfError::ValidationFailed(format!("stat {path}: {e}")))?;
+        match &entry.kind {
+            EntryKind::File(content) => {
+                let got = fs.read(path).map_err(|e| {
+                    WriteConfError::ValidationFailed(format!("re-read {path}: {e}"))
+                })?;
+                if md.is_dir() || md.is_symlink() || got != *content {
+                    return Err(WriteConfError::ValidationFailed(format!(
+                        "{path} content mismatch: wrote {} bytes, read {} bytes",
+                        content.len(),
+                        got.len()
+                    )));
+                }
+            }
+            EntryKind::Dir if !md.is_dir() => {
+                return Err(WriteConfError::ValidationFailed(format!(
+                    "{path} is not a directory"
+                )));
+            }
+            EntryKind::Dir => {}
+            EntryKind::Symlink(target) => {
+                let got = fs.read_link(path).map_err(|e| {
+                    WriteConfError::ValidationFailed(format!("readlink {path}: {e}"))
+                })?;
+                let got = got.display().to_string();
+                if got != *target {
+                    return Err(WriteConfError::ValidationFailed(format!(
+                        "{path} points to {got}, expected {target}"
+                    )));
+                }
+            }
+        }
+        check_owner_and_mode(path, &md, &entry.attrs)?;
+    }
+    let times: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.path.as_str(), &entry.attrs))
+        .collect();
+    check_times(&fs, image_path, base, &times)?;
+
+    walk(&fs, "/")?;
+    Ok(())
+}
+
+fn check_owner_and_mode(
+    path: &str,
+    md: &Metadata,
+    attrs: &FileAttrs,
+) -> Result<(), WriteConfError> {
+    let got = (md.mode(), md.uid(), md.gid());
+    let want = (attrs.mode, attrs.uid, attrs.gid);
+    if got != want {
+        return Err(WriteConfError::ValidationFailed(format!(
+            "{path} mode/uid/gid read back as {:o} {}:{}, expected {:o} {}:{}",
+            got.0, got.1, got.2, want.0, want.1, want.2
+        )));
+    }
+    Ok(())
+}
+
+/// Compare atime/mtime/ctime of each path on a read-only ext4-rs handle. Parents are
+/// canonicalized by ext4-view first, since ext4-rs lookups don't follow symlinks.
+fn check_times(
+    fs: &Ext4Ro,
+    image_path: &Path,
+    base: u64,
+    files: &[(&str, &FileAttrs)],
+) -> Result<(), WriteConfError> {
+    let file = File::open(image_path)?;
+    let dev = PartDev::new(file, base);
+    let raw = dev.open()?;
+
+    for (path, attrs) in files {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let parent = match parent {
+            "" => String::new(),
+            parent => fs
+                .canonicalize(parent)
+                .map_err(|e| WriteConfError::ValidationFailed(format!("resolve {parent}: {e}")))?
+                .display()
+                .to_string(),
+        };
+
+        let mut ino = ROOT_INODE;
+        for component in parent.split('/').filter(|c| !c.is_empty()).chain([name]) {
+            ino = raw
+                .fuse_lookup(ino as u64, component)
+                .map_err(|e| {
+                    dev.io_error_or(WriteConfError::ValidationFailed(format!(
+                        "lookup {path}: {e:?}"
+                    )))
+                })?
+                .ino as u32;
+        }
+
+        let inode = raw
+            .get_inode_ref(ino)
+            .map_err(|e| {
+                dev.io_error_or(WriteConfError::ValidationFailed(format!(
+                    "read inode of {path}: {e:?}"
+                )))
+            })?
+            .inode;
+        let got = (inode.atime(), inode.mtime(), inode.ctime());
+        if got != (attrs.atime, attrs.mtime, attrs.ctime) {
+            return Err(WriteConfError::ValidationFailed(format!(
+                "{path} times read back as {got:?}, expected {:?}",
+                (attrs.atime, attrs.mtime, attrs.ctime)
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn load(image_path: &Path, base: u64) -> Result<Ext4Ro, WriteConfError> {
+    let file = File::open(image_path)?;
+    Ext4Ro::load(Box::new(PartReader { file, base }))
+        .map_err(|e| WriteConfError::ValidationFailed(format!("ext4-view load failed: {e}")))
+}
+
 /// Recursively read every directory and file, propagating the first error.
 fn walk(fs: &Ext4Ro, path: &str) -> Result<(), WriteConfError> {
     let rd = fs
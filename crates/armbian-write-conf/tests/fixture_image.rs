@@ -0,0 +1,241 @@
+//! Integration tests on `fixtures/armbian-mini.img.gz`: a small MBR image whose ext4 rootfs is laid
+//! out like Armbian's (merged /usr, /boot/armbianEnv.txt, one overlay, a hash-indexed
+//! /usr/share/zoneinfo). Every write goes to a fresh
+//! unpacked copy and is read back through the public API. See `fixtures/make-armbian-mini.sh`.
+
+use std::fs::File;
+use std::io;
+
+use armbian_write_conf::{
+    edit_armbian_env_in_image, list_overlays_in_image, list_partitions,
+    read_armbian_env_from_image, read_dir_in_partition, read_file_from_image,
+    read_file_in_partition, stat_in_partition, write_entries_into_image, write_file_into_image,
+    write_file_into_image_with_attrs, Entry, FileAttrs, FileKind, Filesystem, OverlayInfo,
+    OverlayListing, Scheme, WriteConfError,
+};
+use flate2::read::GzDecoder;
+use tempfile::NamedTempFile;
+
+const FIXTURE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/armbian-mini.img.gz"
+);
+
+/// Unpack the fixture into a temp file the test may modify.
+fn fixture() -> NamedTempFile {
+    let mut compressed = GzDecoder::new(File::open(FIXTURE).expect("open fixture"));
+    let mut image = NamedTempFile::new().unwrap();
+    io::copy(&mut compressed, &mut image).expect("unpack fixture");
+    image
+}
+
+#[test]
+fn written_file_reads_back() {
+    let image = fixture();
+    let content = b"PRESET_NET_CHANGE_DEFAULTS=\"1\"\n";
+
+    let report = write_file_into_image(image.path(), "/root/.not_logged_in_yet", content).unwrap();
+    assert_eq!(report.scheme, "MBR");
+    assert_eq!(report.partition_offset, 1 << 20);
+    assert_eq!(report.bytes_written, content.len());
+    assert!(report.validated);
+
+    let read = read_file_from_image(image.path(), "/root/.not_logged_in_yet").unwrap();
+    assert_eq!(read, content);
+    // Existing files are left alone.
+    let hostname = read_file_from_image(image.path(), "/etc/hostname").unwrap();
+    assert_eq!(hostname, b"armbian\n");
+}
+
+#[test]
+fn written_attrs_read_back() {
+    let image = fixture();
+    // uid above 65535 exercises the high 16 bits of the inode fields.
+    let attrs = FileAttrs::new(0o440)
+        .with_owner(70000, 70001)
+        .with_mtime(1_700_000_000);
+
+    let report = write_file_into_image_with_attrs(
+        image.path(),
+        "/etc/sudoers.d/lab",
+        b"lab ALL=(ALL) ALL\n",
+        &attrs,
+    )
+    .unwrap();
+    assert!(report.validated);
+
+    let stat = stat_in_partition(image.path(), 1, "/etc/sudoers.d/lab").unwrap();
+    assert_eq!(stat.kind, FileKind::File);
+    assert_eq!(stat.len, 18);
+    assert_eq!((stat.mode, stat.uid, stat.gid), (0o440, 70000, 70001));
+    // The created parent gets the default directory attributes.
+    let parent = stat_in_partition(image.path(), 1, "/etc/sudoers.d").unwrap();
+    assert_eq!(parent.kind, FileKind::Dir);
+    assert_eq!((parent.uid, parent.gid), (0, 0));
+}
+
+#[test]
+fn batch_creates_parents_and_follows_lib_symlink() {
+    let image = fixture();
+    let entries = [
+        Entry::dir("/root/.ssh").with_mode(0o700),
+        Entry::file("/root/.ssh/authorized_keys", "ssh-ed25519 AAAA test\n").with_mode(0o600),
+        Entry::file("/etc/hostname", "rack2-01\n"),
+        Entry::file("/lib/systemd/system/first-boot.service", "[Unit]\n"),
+        Entry::file("/opt/lab/bin/setup.sh", "#!/bin/sh\n")
+            .with_mode(0o755)
+            .with_owner(1000, 1000),
+        Entry::symlink("/etc/localtime", "/usr/share/zoneinfo/UTC"),
+    ];
+
+    let report = write_entries_into_image(image.path(), &entries).unwrap();
+    assert_eq!(report.entries, entries.len());
+    assert!(report.validated);
+
+    let read = |path| read_file_from_image(image.path(), path).unwrap();
+    assert_eq!(
+        read("/root/.ssh/authorized_keys"),
+        b"ssh-ed25519 AAAA test\n"
+    );
+    assert_eq!(read("/etc/hostname"), b"rack2-01\n");
+    assert_eq!(read("/opt/lab/bin/setup.sh"), b"#!/bin/sh\n");
+    // Written through /lib -> usr/lib, so it is found under both paths.
+    assert_eq!(
+        read("/usr/lib/systemd/system/first-boot.service"),
+        b"[Unit]\n"
+    );
+    assert_eq!(read("/lib/systemd/system/first-boot.service"), b"[Unit]\n");
+}
+
+#[test]
+fn inspect_lists_partitions_and_browses_rootfs() {
+    let image = fixture();
+    let path = image.path();
+
+    let layout = list_partitions(path).unwrap();
+    assert_eq!(layout.scheme, Scheme::Mbr);
+    assert_eq!(layout.partitions.len(), 1);
+    let root = &layout.partitions[0];
+    assert_eq!((root.index, root.offset, root.len), (1, 1 << 20, 15 << 20));
+    assert_eq!(root.type_id, "0x83");
+    assert_eq!(root.filesystem, Filesystem::Ext4);
+
+    let entries = read_dir_in_partition(path, 1, "/").unwrap();
+    let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["boot", "etc", "lib", "lost+found", "root", "usr"]);
+    let lib = &entries[2].stat;
+    assert_eq!(lib.kind, FileKind::Symlink);
+    assert_eq!(lib.symlink_target.as_deref(), Some("usr/lib"));
+    assert_eq!(entries[4].stat.mode, 0o700);
+
+    let release =
+        b"BOARD=fixture\nBOARD_NAME=\"Fixture\"\nBOARDFAMILY=rockchip64\nVERSION=26.05.0\n";
+    let stat = stat_in_partition(path, 1, "/etc/armbian-release").unwrap();
+    assert_eq!(stat.kind, FileKind::File);
+    assert_eq!(stat.len, release.len() as u64);
+    let (content, len) = read_file_in_partition(path, 1, "/etc/armbian-release", 4096).unwrap();
+    assert_eq!((content.as_slice(), len), (&release[..], stat.len));
+    let (head, len) = read_file_in_partition(path, 1, "/etc/armbian-release", 5).unwrap();
+    assert_eq!((head.as_slice(), len), (&b"BOARD"[..], stat.len));
+
+    assert!(stat_in_partition(path, 1, "/etc/missing").is_err());
+}
+
+#[test]
+fn armbian_env_edit_on_ext4_reads_back() {
+    let image = fixture();
+    let path = image.path();
+
+    let report = edit_armbian_env_in_image(path, |env| {
+        env.add_overlay("i2c7")?;
+        env.set_verbosity(7)?;
+        env.set_extraargs("net.ifnames=0")
+    })
+    .unwrap();
+    assert_eq!(report.dest_path, "/boot/armbianEnv.txt");
+    assert_eq!(report.partition_offset, 1 << 20);
+    assert!(report.ext4_features.is_some());
+    assert!(report.validated);
+
+    // Keys are edited in place; new ones are appended.
+    let text = read_file_from_image(path, "/boot/armbianEnv.txt").unwrap();
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "verbosity=7\nbootlogo=false\noverlay_prefix=rockchip\n\
+         fdtfile=rockchip/rk3588-fixture.dtb\noverlays=uart1 i2c7\nextraargs=net.ifnames=0\n"
+    );
+
+    // Shrinking the file back is validated too.
+    edit_armbian_env_in_image(path, |env| {
+        env.remove_overlay("i2c7")?;
+        env.remove_overlay("uart1")?;
+        env.remove("extraargs");
+        Ok(())
+    })
+    .unwrap();
+    let env = read_armbian_env_from_image(path).unwrap();
+    assert!(env.overlays().is_empty());
+    assert_eq!(env.get("extraargs"), None);
+    assert_eq!(env.get("fdtfile"), Some("rockchip/rk3588-fixture.dtb"));
+    let stat = stat_in_partition(path, 1, "/boot/armbianEnv.txt").unwrap();
+    assert_eq!((stat.uid, stat.gid), (0, 0));
+}
+
+#[test]
+fn overlays_are_listed_with_their_params() {
+    let image = fixture();
+
+    let listing = list_overlays_in_image(image.path()).unwrap();
+    assert_eq!(
+        listing,
+        OverlayListing {
+            prefix: Some("rockchip".to_string()),
+            partition_offset: Some(1 << 20),
+            overlays: vec![OverlayInfo {
+                name: "uart1".to_string(),
+                path: "/boot/dtb/rockchip/overlay/rockchip-uart1.dtbo".to_string(),
+                size: 121,
+                params: vec!["baudrate".to_string()],
+            }],
+        }
+    );
+}
+
+#[test]
+fn hash_indexed_directories_only_take_overwrites() {
+    let image = fixture();
+    let path = image.path();
+    let original = std::fs::read(path).unwrap();
+
+    let refused = |result: Result<_, WriteConfError>| match result {
+        Err(WriteConfError::UnsupportedImage(m)) => {
+            assert!(m.contains("/usr/share/zoneinfo"), "{m}")
+        }
+        other => panic!("expected UnsupportedImage, got {other:?}"),
+    };
+    refused(write_file_into_image(path, "/usr/share/zoneinfo/UTC", b"TZif\n").map(|_| ()));
+    refused(write_file_into_image(path, "/usr/share/zoneinfo/posix/UTC", b"TZif\n").map(|_| ()));
+    refused(
+        write_entries_into_image(
+            path,
+            &[Entry::symlink("/usr/share/zoneinfo/UTC", "Etc/UTC")],
+        )
+        .map(|_| ()),
+    );
+    refused(
+        write_entries_into_image(
+            path,
+            &[Entry::symlink(
+                "/usr/share/zoneinfo/Antarctica_Station_7",
+                "UTC",
+            )],
+        )
+        .map(|_| ()),
+    );
+    assert_eq!(std::fs::read(path).unwrap(), original);
+
+    // Replacing the content of an existing file leaves the directory alone.
+    let zone = "/usr/share/zoneinfo/Antarctica_Station_7";
+    write_file_into_image(path, zone, b"TZif2\n").unwrap();
+    assert_eq!(read_file_from_image(path, zone).unwrap(), b"TZif2\n");
+}
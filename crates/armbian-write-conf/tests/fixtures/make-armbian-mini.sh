@@ -0,0 +1,62 @@
+#!/bin/sh
+# Regenerate armbian-mini.img.gz: a 16 MiB MBR image with one ext4 rootfs laid
+# out like an Armbian image (merged /usr, /boot/armbianEnv.txt, an overlay, a
+# hash-indexed directory). Needs mke2fs >= 1.43 (-d), e2fsck, python3 and gzip.
+set -eu
+
+out="$(cd "$(dirname "$0")" && pwd)/armbian-mini.img.gz"
+work="$(mktemp -d)"
+trap 'rm -rf "$work"' EXIT
+root="$work/root"
+
+mkdir -p "$root/etc" "$root/root" "$root/usr/lib/systemd/system" \
+    "$root/usr/share/zoneinfo" "$root/boot/dtb/rockchip/overlay"
+ln -s usr/lib "$root/lib"
+chmod 700 "$root/root"
+printf 'armbian\n' > "$root/etc/hostname"
+printf 'BOARD=fixture\nBOARD_NAME="Fixture"\nBOARDFAMILY=rockchip64\nVERSION=26.05.0\n' \
+    > "$root/etc/armbian-release"
+# Two blocks of entries, so e2fsck -D below gives the directory an htree index.
+for i in $(seq 1 200); do
+    printf 'TZif\n' > "$root/usr/share/zoneinfo/Antarctica_Station_$i"
+done
+printf 'verbosity=1\nbootlogo=false\noverlay_prefix=rockchip\nfdtfile=rockchip/rk3588-fixture.dtb\noverlays=uart1\n' \
+    > "$root/boot/armbianEnv.txt"
+
+# Smallest valid overlay: a root node with an __overrides__ node declaring `baudrate`.
+python3 - "$root/boot/dtb/rockchip/overlay/rockchip-uart1.dtbo" <<'PY'
+import struct, sys
+strings = b"baudrate\0"
+dt = b""
+dt += struct.pack(">I", 1) + b"\0\0\0\0"
+dt += struct.pack(">I", 1) + b"__overrides__\0\0\0"
+dt += struct.pack(">III", 3, 4, 0) + b"\0\0\0\0"
+dt += struct.pack(">I", 2) * 2 + struct.pack(">I", 9)
+off_struct = 40 + 16
+off_strings = off_struct + len(dt)
+total = off_strings + len(strings)
+header = struct.pack(">10I", 0xd00dfeed, total, off_struct, off_strings, 40, 17, 16, 0,
+                     len(strings), len(dt))
+open(sys.argv[1], "wb").write(header + b"\0" * 16 + dt + strings)
+PY
+
+img="$work/armbian-mini.img"
+truncate -s 16M "$img"
+# MBR with one Linux partition from 1 MiB to the end.
+python3 - "$img" <<'PY'
+import struct, sys
+entry = struct.pack("<B3sB3sII", 0, b"\0\0\0", 0x83, b"\0\0\0", 2048, 30720)
+with open(sys.argv[1], "r+b") as f:
+    f.seek(446)
+    f.write(entry)
+    f.seek(510)
+    f.write(b"\x55\xaa")
+PY
+truncate -s 15M "$work/rootfs.ext4"
+# Small block groups, so the tiny filesystem still has several like a real rootfs.
+mke2fs -q -t ext4 -b 4096 -g 1024 -L armbi_root -U 3a4b5c6d-0000-4000-8000-000000000001 \
+    -E root_owner=0:0 -d "$root" "$work/rootfs.ext4"
+# Exit status 1 means directories were optimized.
+e2fsck -fyD "$work/rootfs.ext4" >/dev/null 2>&1 || [ $? -eq 1 ]
+dd if="$work/rootfs.ext4" of="$img" bs=1M seek=1 conv=notrunc status=none
+gzip -9n < "$img" > "$out"
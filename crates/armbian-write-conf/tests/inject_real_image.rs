@@ -1,40 +1,40 @@
-//! Integration test against a real Armbian RAW image at ARMBIAN_TEST_IMAGE (with a default); if absent it prints a
-//! skip notice and passes so CI without the large image doesn't fail. The write always targets a temp copy.
+//! Integration test against a real Armbian RAW image at ARMBIAN_TEST_IMAGE; when unset it prints a skip
+//! notice and passes so CI without the large image doesn't fail. The write always targets a temp copy.
+//! `fixture_image.rs` covers the same paths on a small image that always runs.
 
 use std::env;
 use std::path::{Path, PathBuf};
 
-use armbian_write_conf::write_file_into_image;
+use armbian_write_conf::{
+    edit_armbian_env_in_image, list_overlays_in_image, list_partitions, read_dir_in_partition,
+    read_file_in_partition, stat_in_partition, write_entries_into_image, write_file_into_image,
+    write_file_into_image_with_attrs, Entry, EntryKind, FileAttrs, FileKind, Filesystem,
+};
 
-const DEFAULT_IMAGE: &str = "/Users/danielebriguglio/Downloads/Armbian-unofficial_26.05.0-trunk_Nanopi-r76s_trixie_edge_7.0.10_minimal.img";
 const DEST: &str = "/root/.not_logged_in_yet";
 const CONTENT: &[u8] = b"PRESET_NET_CHANGE_DEFAULTS=\"1\"\n";
 
 #[test]
 fn inject_into_real_image() {
-    let image = env::var("ARMBIAN_TEST_IMAGE").unwrap_or_else(|_| DEFAULT_IMAGE.to_string());
-    let src = Path::new(&image);
-
-    if !src.exists() {
-        eprintln!("SKIP: test image not found at {image} (set ARMBIAN_TEST_IMAGE to run)");
-        return;
-    }
+    let Some(image) = real_image() else { return };
+    let src = image.as_path();
 
     // Copy to a temp file so the original image is never modified.
-    let tmp = temp_copy(src);
+    let tmp = temp_copy(src, "single");
     eprintln!("Operating on copy: {}", tmp.display());
 
     let report = write_file_into_image(&tmp, DEST, CONTENT)
         .unwrap_or_else(|e| panic!("write_file_into_image failed: {e}"));
 
     eprintln!(
-        "scheme={} offset={} len={} dest={} bytes={} validated={}",
+        "scheme={} offset={} len={} dest={} bytes={} validated={} features={:?}",
         report.scheme,
         report.partition_offset,
         report.partition_len,
         report.dest_path,
         report.bytes_written,
-        report.validated
+        report.validated,
+        report.ext4_features.map(|f| f.to_string())
     );
 
     assert_eq!(report.bytes_written, CONTENT.len(), "byte count mismatch");
@@ -43,11 +43,165 @@ fn inject_into_real_image() {
     let _ = std::fs::remove_file(&tmp);
 }
 
-/// Copy `src` into the OS temp dir with a unique name; panics on failure.
-fn temp_copy(src: &Path) -> PathBuf {
+#[test]
+fn inject_with_attrs_into_real_image() {
+    let Some(image) = real_image() else { return };
+    let src = image.as_path();
+
+    let tmp = temp_copy(src, "attrs");
+    // uid above 65535 exercises the high 16 bits; validation reads all of it back.
+    let attrs = FileAttrs::new(0o440).with_owner(70000, 70001).with_times(
+        1_600_000_000,
+        1_700_000_000,
+        1_700_000_001,
+    );
+
+    let report = write_file_into_image_with_attrs(&tmp, DEST, CONTENT, &attrs)
+        .unwrap_or_else(|e| panic!("write_file_into_image_with_attrs failed: {e}"));
+    assert!(report.validated, "report.validated must be true");
+
+    let _ = std::fs::remove_file(&tmp);
+}
+
+#[test]
+fn inject_batch_into_real_image() {
+    let Some(image) = real_image() else { return };
+    let src = image.as_path();
+
+    let tmp = temp_copy(src, "batch");
+    let entries = [
+        Entry::dir("/root/.ssh").with_mode(0o700),
+        Entry::file("/root/.ssh/authorized_keys", "ssh-ed25519 AAAA test\n").with_mode(0o600),
+        Entry::file("/etc/hostname", "rack2-01\n"),
+        // Parents are created, and /lib -> usr/lib is followed on merged-/usr images.
+        Entry::file("/lib/systemd/system/first-boot.service", "[Unit]\n"),
+        Entry::file("/opt/lab/bin/setup.sh", "#!/bin/sh\n")
+            .with_mode(0o755)
+            .with_owner(1000, 1000),
+        Entry::symlink("/etc/localtime", "/usr/share/zoneinfo/UTC"),
+    ];
+
+    let report = write_entries_into_image(&tmp, &entries)
+        .unwrap_or_else(|e| panic!("write_entries_into_image failed: {e}"));
+
+    assert_eq!(report.entries, entries.len());
+    let content_len: usize = entries
+        .iter()
+        .map(|e| match &e.kind {
+            EntryKind::File(content) => content.len(),
+            _ => 0,
+        })
+        .sum();
+    assert_eq!(report.bytes_written, content_len, "byte count mismatch");
+    assert!(report.validated, "report.validated must be true");
+
+    let _ = std::fs::remove_file(&tmp);
+}
+
+#[test]
+fn edit_armbian_env_in_real_image() {
+    let Some(image) = real_image() else { return };
+    let src = image.as_path();
+
+    let tmp = temp_copy(src, "armbianenv");
+    // Growing then shrinking the file; each write is validated.
+    for overlays in [&["uart1", "i2c7", "spi-spidev"][..], &[]] {
+        let report = edit_armbian_env_in_image(&tmp, |env| {
+            env.remove("overlays");
+            for overlay in overlays {
+                env.add_overlay(overlay)?;
+            }
+            env.set_verbosity(7)?;
+            env.set_extraargs("net.ifnames=0")
+        })
+        .unwrap_or_else(|e| panic!("edit_armbian_env_in_image failed: {e}"));
+        assert!(report.validated, "report.validated must be true");
+    }
+
+    let _ = std::fs::remove_file(&tmp);
+}
+
+#[test]
+fn list_overlays_in_real_image() {
+    let Some(image) = real_image() else { return };
+    let src = image.as_path();
+
+    let listing = list_overlays_in_image(src)
+        .unwrap_or_else(|e| panic!("list_overlays_in_image failed: {e}"));
+    eprintln!(
+        "prefix={:?} overlays={}",
+        listing.prefix,
+        listing.overlays.len()
+    );
+    for overlay in &listing.overlays {
+        eprintln!(
+            "{} {} ({} bytes) params={:?}",
+            overlay.name, overlay.path, overlay.size, overlay.params
+        );
+        assert!(overlay.path.ends_with(".dtbo"));
+        assert!(!overlay.name.is_empty());
+    }
+}
+
+/// The image named by ARMBIAN_TEST_IMAGE, or `None` after a skip notice.
+fn real_image() -> Option<PathBuf> {
+    let Ok(image) = env::var("ARMBIAN_TEST_IMAGE") else {
+        eprintln!("SKIP: set ARMBIAN_TEST_IMAGE to a RAW Armbian image to run");
+        return None;
+    };
+    let path = PathBuf::from(&image);
+    if !path.exists() {
+        eprintln!("SKIP: test image not found at {image}");
+        return None;
+    }
+    Some(path)
+}
+
+/// Copy `src` into the OS temp dir with a name unique per test; panics on failure.
+fn temp_copy(src: &Path, tag: &str) -> PathBuf {
     let mut dst = env::temp_dir();
     let pid = std::process::id();
-    dst.push(format!("awc_test_{pid}.img"));
+    dst.push(format!("awc_test_{pid}_{tag}.img"));
     std::fs::copy(src, &dst).expect("copy image to temp");
     dst
 }
+
+#[test]
+fn inspect_real_image() {
+    let Some(image) = real_image() else { return };
+    let src = image.as_path();
+
+    let layout = list_partitions(src).unwrap_or_else(|e| panic!("list_partitions failed: {e}"));
+    for p in &layout.partitions {
+        eprintln!(
+            "#{} type={} name={:?} offset={} len={} fs={}",
+            p.index,
+            p.type_id,
+            p.name,
+            p.offset,
+            p.len,
+            p.filesystem.as_str()
+        );
+    }
+    let root = layout
+        .partitions
+        .iter()
+        .rev()
+        .find(|p| p.filesystem == Filesystem::Ext4)
+        .expect("an ext4 partition");
+
+    let entries = read_dir_in_partition(src, root.index, "/")
+        .unwrap_or_else(|e| panic!("read_dir_in_partition failed: {e}"));
+    let etc = entries.iter().find(|e| e.name == "etc").expect("/etc");
+    assert_eq!(etc.stat.kind, FileKind::Dir);
+    assert!(entries.iter().all(|e| e.name != "." && e.name != ".."));
+
+    let stat = stat_in_partition(src, root.index, "/etc/armbian-release")
+        .unwrap_or_else(|e| panic!("stat_in_partition failed: {e}"));
+    let (content, len) = read_file_in_partition(src, root.index, "/etc/armbian-release", 4096)
+        .unwrap_or_else(|e| panic!("read_file_in_partition failed: {e}"));
+    assert_eq!(len, stat.len);
+    assert_eq!(content.len() as u64, len);
+    let (head, _) = read_file_in_partition(src, root.index, "/etc/armbian-release", 1).unwrap();
+    assert_eq!(head.len(), len.min(1) as usize);
+}
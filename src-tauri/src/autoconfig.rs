@@ -1,18 +1,26 @@
 //! Armbian first-boot autoconfig: render a preset (mirrors client-side AutoconfigConfig) and inject it.
-//! [`inject_into_image`] writes it to `/root/.not_logged_in_yet` in the image's ext4 rootfs, consumed on first boot. See https://docs.armbian.com/User-Guide_Autoconfig/.
+//! [`inject_into_image`] writes it to `/root/.not_logged_in_yet` in the image's ext4 or btrfs rootfs, consumed on first boot. See https://docs.armbian.com/User-Guide_Autoconfig/.
+//! [`apply_boot_env`] applies the boot settings (overlays, console, kernel args) to `armbianEnv.txt`.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use armbian_write_conf::{write_file_into_bare_ext4_image, write_file_into_image, WriteConfError};
+use armbian_write_conf::{
+    edit_armbian_env_in_image, write_file_into_bare_ext4_image_with_attrs,
+    write_file_into_image_with_attrs, ArmbianEnv, Console, FileAttrs, WriteConfError,
+};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
+use crate::utils::app_cache_dir;
 use crate::{log_error, log_info};
 
 /// Destination of the first-boot preset file inside the rootfs.
 const PRESET_DEST_PATH: &str = "/root/.not_logged_in_yet";
+/// Root-only: the preset carries the root password and Wi-Fi key.
+const PRESET_MODE: u16 = 0o600;
 
 /// Login shell choices offered for the first user.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum UserShell {
     Bash,
@@ -29,9 +37,28 @@ impl UserShell {
     }
 }
 
+/// Where the boot script sends the kernel console (`console=` in armbianEnv.txt).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootConsole {
+    Serial,
+    Display,
+    Both,
+}
+
+impl From<BootConsole> for Console {
+    fn from(console: BootConsole) -> Self {
+        match console {
+            BootConsole::Serial => Console::Serial,
+            BootConsole::Display => Console::Display,
+            BootConsole::Both => Console::Both,
+        }
+    }
+}
+
 /// First-boot autoconfig model. All fields optional; only set/non-empty fields
 /// are emitted into the preset. Mirrors the TS `AutoconfigConfig` type.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AutoconfigConfig {
     pub apply_network: Option<bool>,
@@ -60,6 +87,15 @@ pub struct AutoconfigConfig {
     pub user_real_name: Option<String>,
 
     pub remote_config_url: Option<String>,
+
+    // Boot settings, written to armbianEnv.txt rather than the preset.
+    pub add_overlays: Option<Vec<String>>,
+    pub remove_overlays: Option<Vec<String>>,
+    pub boot_verbosity: Option<u8>,
+    pub boot_console: Option<BootConsole>,
+    pub extra_args: Option<String>,
+    pub fdtfile: Option<String>,
+    pub rootdev: Option<String>,
 }
 
 /// Quote a value for a bash-sourced file: wrap in double quotes and escape the
@@ -176,7 +212,7 @@ pub fn render_preset(config: &AutoconfigConfig) -> String {
     out
 }
 
-/// Render the preset and write it into the image's ext4 rootfs; `image_path` must be a raw (decompressed) image
+/// Render the preset and write it into the image's ext4 or btrfs rootfs; `image_path` must be a raw (decompressed) image
 /// that will be mutated. Never logs secret values (password/wifi key).
 pub fn inject_into_image(
     image_path: &Path,
@@ -190,7 +226,12 @@ pub fn inject_into_image(
         image_path.display()
     );
 
-    match write_file_into_image(image_path, PRESET_DEST_PATH, preset.as_bytes()) {
+    match write_file_into_image_with_attrs(
+        image_path,
+        PRESET_DEST_PATH,
+        preset.as_bytes(),
+        &FileAttrs::new(PRESET_MODE),
+    ) {
         Ok(report) => {
             log_info!(
                 "autoconfig",
@@ -209,6 +250,170 @@ pub fn inject_into_image(
     }
 }
 
+/// True when the profile changes anything in armbianEnv.txt.
+pub fn has_boot_env_edits(config: &AutoconfigConfig) -> bool {
+    config.add_overlays.as_ref().is_some_and(|v| !v.is_empty())
+        || config
+            .remove_overlays
+            .as_ref()
+            .is_some_and(|v| !v.is_empty())
+        || config.boot_verbosity.is_some()
+        || config.boot_console.is_some()
+        || non_empty(&config.extra_args).is_some()
+        || non_empty(&config.fdtfile).is_some()
+        || non_empty(&config.rootdev).is_some()
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|s| !s.is_empty())
+}
+
+/// Apply the profile's boot settings to an armbianEnv.txt; empty strings are skipped.
+fn edit_boot_env(env: &mut ArmbianEnv, config: &AutoconfigConfig) -> Result<(), WriteConfError> {
+    for overlay in config.remove_overlays.iter().flatten() {
+        env.remove_overlay(overlay)?;
+    }
+    for overlay in config.add_overlays.iter().flatten() {
+        env.add_overlay(overlay)?;
+    }
+    if let Some(level) = config.boot_verbosity {
+        env.set_verbosity(level)?;
+    }
+    if let Some(console) = config.boot_console {
+        env.set_console(console.into())?;
+    }
+    if let Some(args) = non_empty(&config.extra_args) {
+        env.set_extraargs(args)?;
+    }
+    if let Some(fdtfile) = non_empty(&config.fdtfile) {
+        env.set_fdtfile(fdtfile)?;
+    }
+    if let Some(rootdev) = non_empty(&config.rootdev) {
+        env.set_rootdev(rootdev)?;
+    }
+    Ok(())
+}
+
+/// The boot settings as `key=value` lines, in the order [`edit_boot_env`] applies them.
+fn render_boot_settings(config: &AutoconfigConfig) -> String {
+    let mut out = String::new();
+    for overlay in config.remove_overlays.iter().flatten() {
+        out.push_str(&format!("remove_overlay={}\n", overlay));
+    }
+    for overlay in config.add_overlays.iter().flatten() {
+        out.push_str(&format!("add_overlay={}\n", overlay));
+    }
+    if let Some(level) = config.boot_verbosity {
+        out.push_str(&format!("verbosity={}\n", level));
+    }
+    if let Some(console) = config.boot_console {
+        out.push_str(&format!("console={:?}\n", console));
+    }
+    for (key, value) in [
+        ("extraargs", &config.extra_args),
+        ("fdtfile", &config.fdtfile),
+        ("rootdev", &config.rootdev),
+    ] {
+        if let Some(value) = non_empty(value) {
+            out.push_str(&format!("{}={}\n", key, value));
+        }
+    }
+    out
+}
+
+/// SHA256 of everything the profile writes into an image, the preset and the boot
+/// settings. Tells profiles apart (flash checkpoints) without keeping their secrets.
+pub fn profile_hash(config: &AutoconfigConfig) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(render_preset(config));
+    // The preset is quoted shell and never holds a NUL, so the two parts cannot blur.
+    hasher.update([0u8]);
+    hasher.update(render_boot_settings(config));
+    hex::encode(hasher.finalize())
+}
+
+/// Write the profile's boot settings into armbianEnv.txt on whichever partition holds `/boot`;
+/// a no-op when the profile has none. `image_path` must be a raw image that will be mutated.
+pub fn apply_boot_env(image_path: &Path, config: &AutoconfigConfig) -> Result<(), WriteConfError> {
+    if !has_boot_env_edits(config) {
+        return Ok(());
+    }
+
+    match edit_armbian_env_in_image(image_path, |env| edit_boot_env(env, config)) {
+        Ok(report) => {
+            log_info!(
+                "autoconfig",
+                "Boot settings written to {} at offset {} ({} bytes, validated: {})",
+                report.dest_path,
+                report.partition_offset,
+                report.bytes_written,
+                report.validated
+            );
+            Ok(())
+        }
+        Err(e) => {
+            log_error!("autoconfig", "Failed to apply boot settings: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Copy the decompressed image to a per-flash temp file and inject the autoconfig preset and boot settings
+/// into the copy. Aborts (deleting the copy) if the image has no writable ext4 or btrfs rootfs, since a profile was requested.
+pub fn prepare_injected_copy(source: &Path, config: &AutoconfigConfig) -> Result<PathBuf, String> {
+    let temp_dir = app_cache_dir().join("autoconfig-temp");
+    std::fs::create_dir_all(&temp_dir)
+        .map_err(|e| format!("Failed to create autoconfig temp directory: {}", e))?;
+
+    // Unique per-flash name to avoid collisions across concurrent/repeat flashes.
+    let stem = source
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "image.img".to_string());
+    let unique = format!(
+        "{}.{}.{}",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+        stem
+    );
+    let copy_path = temp_dir.join(unique);
+
+    log_info!(
+        "autoconfig",
+        "Copying image for autoconfig injection: {} -> {}",
+        source.display(),
+        copy_path.display()
+    );
+    std::fs::copy(source, &copy_path)
+        .map_err(|e| format!("Failed to copy image for autoconfig: {}", e))?;
+
+    if let Err(e) = inject_into_image(&copy_path, config) {
+        // Clean up the half-prepared copy before bubbling up.
+        let _ = std::fs::remove_file(&copy_path);
+        let message = match e {
+            WriteConfError::UnsupportedImage(_) | WriteConfError::NoExt4Rootfs(_) => format!(
+                "This image does not have a writable ext4 or btrfs root filesystem, so the selected autoconfig profile cannot be applied: {}",
+                e
+            ),
+            other => format!("Failed to apply autoconfig profile: {}", other),
+        };
+        return Err(message);
+    }
+
+    if let Err(e) = apply_boot_env(&copy_path, config) {
+        let _ = std::fs::remove_file(&copy_path);
+        return Err(format!(
+            "Failed to apply the boot settings of the autoconfig profile: {}",
+            e
+        ));
+    }
+
+    Ok(copy_path)
+}
+
 /// Render the preset and write it into a BARE ext4 image (no partition table; e.g. Armbian QDL `disk-sdcard.img.root`);
 /// `image_path` must be a flat ext4 filesystem that will be mutated. Never logs secret values (password/wifi key).
 pub fn inject_into_bare_ext4_image(
@@ -223,7 +428,12 @@ pub fn inject_into_bare_ext4_image(
         image_path.display()
     );
 
-    match write_file_into_bare_ext4_image(image_path, PRESET_DEST_PATH, preset.as_bytes()) {
+    match write_file_into_bare_ext4_image_with_attrs(
+        image_path,
+        PRESET_DEST_PATH,
+        preset.as_bytes(),
+        &FileAttrs::new(PRESET_MODE),
+    ) {
         Ok(report) => {
             log_info!(
                 "autoconfig",
@@ -270,6 +480,13 @@ mod tests {
             user_shell: None,
             user_real_name: None,
             remote_config_url: None,
+            add_overlays: None,
+            remove_overlays: None,
+            boot_verbosity: None,
+            boot_console: None,
+            extra_args: None,
+            fdtfile: None,
+            rootdev: None,
         }
     }
 
@@ -321,6 +538,57 @@ mod tests {
         assert!(render_preset(&c).contains("PRESET_USER_SHELL=\"zsh\"\n"));
     }
 
+    #[test]
+    fn boot_settings_edit_armbian_env_only() {
+        let mut c = empty();
+        assert!(!has_boot_env_edits(&c));
+        c.add_overlays = Some(vec!["uart1".to_string(), "i2c7".to_string()]);
+        c.remove_overlays = Some(vec!["spi-spidev".to_string()]);
+        c.boot_console = Some(BootConsole::Serial);
+        c.extra_args = Some(String::new());
+        c.rootdev = Some("/dev/mmcblk0p2".to_string());
+        assert!(has_boot_env_edits(&c));
+        // Boot settings never leak into the first-boot preset.
+        assert_eq!(render_preset(&c), "");
+
+        let mut env = ArmbianEnv::parse("verbosity=1\nconsole=both\noverlays=spi-spidev\n");
+        edit_boot_env(&mut env, &c).unwrap();
+        assert_eq!(
+            env.to_string(),
+            "verbosity=1\nconsole=serial\noverlays=uart1 i2c7\nrootdev=/dev/mmcblk0p2\n"
+        );
+
+        c.boot_verbosity = Some(8);
+        assert!(matches!(
+            edit_boot_env(&mut env, &c),
+            Err(WriteConfError::InvalidEntry(_))
+        ));
+    }
+
+    #[test]
+    fn profile_hash_covers_boot_settings() {
+        let mut c = empty();
+        let base = profile_hash(&c);
+        assert_eq!(profile_hash(&empty()), base);
+
+        c.add_overlays = Some(vec!["uart1".to_string()]);
+        let with_overlay = profile_hash(&c);
+        assert_ne!(with_overlay, base);
+
+        // Adding and removing the same overlay write different images.
+        c.add_overlays = None;
+        c.remove_overlays = Some(vec!["uart1".to_string()]);
+        assert_ne!(profile_hash(&c), with_overlay);
+
+        let mut console = empty();
+        console.boot_console = Some(BootConsole::Serial);
+        let mut rootdev = empty();
+        rootdev.rootdev = Some("/dev/mmcblk0p2".to_string());
+        assert_ne!(profile_hash(&console), base);
+        assert_ne!(profile_hash(&rootdev), base);
+        assert_ne!(profile_hash(&console), profile_hash(&rootdev));
+    }
+
     #[test]
     fn empty_string_is_skipped() {
         let mut c = empty();
@@ -0,0 +1,113 @@
+//! Minimal argument parser for the CLI: a subcommand, positionals, `--flag`
+//! switches and `--option value` / `--option=value` pairs.
+
+use std::collections::{HashMap, HashSet};
+
+/// Options that never take a value.
+const FLAGS: &[&str] = &[
+    "allow-missing-sha",
+    "allow-system",
+    "debug",
+    "delta",
+    "dry-run",
+    "help",
+    "quiet",
+    "ufs",
+    "verify",
+];
+
+/// Parsed command line, minus the leading `cli`.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub command: String,
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut iter = args.into_iter();
+
+        while let Some(arg) = iter.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                if parsed.command.is_empty() {
+                    parsed.command = arg;
+                } else {
+                    parsed.positional.push(arg);
+                }
+                continue;
+            };
+
+            if let Some((key, value)) = name.split_once('=') {
+                parsed.options.insert(key.to_string(), value.to_string());
+            } else if FLAGS.contains(&name) {
+                parsed.flags.insert(name.to_string());
+            } else {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("[USAGE] --{} needs a value", name))?;
+                parsed.options.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        self.positional.get(index).map(String::as_str)
+    }
+
+    /// Value given either as `--name value` or as the positional at `index`.
+    pub fn required(&self, name: &str, index: usize) -> Result<&str, String> {
+        self.option(name)
+            .or_else(|| self.positional(index))
+            .ok_or_else(|| format!("[USAGE] {} requires --{}", self.command, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Args {
+        Args::parse(line.split_whitespace().map(str::to_string)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_flash_command() {
+        let args = parse("flash --image a.img --device=/dev/sdb --verify --autoconfig preset.json");
+        assert_eq!(args.command, "flash");
+        assert_eq!(args.option("image"), Some("a.img"));
+        assert_eq!(args.option("device"), Some("/dev/sdb"));
+        assert_eq!(args.option("autoconfig"), Some("preset.json"));
+        assert!(args.flag("verify"));
+        assert!(!args.flag("delta"));
+    }
+
+    #[test]
+    fn test_required_falls_back_to_positional() {
+        let args = parse("images orangepi5 --stability stable");
+        assert_eq!(args.required("board", 0), Ok("orangepi5"));
+        assert_eq!(args.option("stability"), Some("stable"));
+        assert!(args
+            .required("image", 1)
+            .unwrap_err()
+            .starts_with("[USAGE]"));
+    }
+
+    #[test]
+    fn test_missing_option_value() {
+        let err = Args::parse(["flash".to_string(), "--device".to_string()]).unwrap_err();
+        assert_eq!(err, "[USAGE] --device needs a value");
+    }
+}
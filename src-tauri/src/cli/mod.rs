@@ -0,0 +1,702 @@
+//! Headless mode: `armbian-imager cli <command>` drives the same images, download,
+//! decompress, flash, autoconfig and QDL code as the GUI, for CI and provisioning
+//! scripts. Output is JSON lines on stdout (see [`output`]); the exit code tells
+//! the failure class apart.
+
+mod args;
+mod output;
+mod run;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::autoconfig::AutoconfigConfig;
+use crate::decompress::{decompress_local_file, needs_decompression};
+use crate::devices::{self, policy, BlockDevice};
+use crate::download::{self, DownloadState, ExpectedSha};
+use crate::flash::multi::{self, DeviceFlashResult, FlashTarget};
+use crate::flash::{self, FlashOptions, FlashState};
+use crate::history::{self, FlashContext, ImageHasher, TargetInfo};
+use crate::images::{fetch_boards, map_board, ImageInfo};
+use crate::jobs::{Job, JobKind, JobManager, JobProgressState};
+use crate::utils::{images_dir, qdl_temp_dir};
+use crate::{commands, logging, qdl};
+use crate::{log_error, log_info, log_warn};
+
+use args::Args;
+use output::{exit, ProgressReporter};
+
+const MODULE: &str = "cli";
+
+const USAGE: &str = "\
+Usage: armbian-imager cli <command> [options]
+
+Commands:
+  boards                              List boards
+  images <board>                      List images for a board
+        [--stability S] [--kernel K] [--variant V] [--preapp P]
+  devices                             List block devices (device policy applied)
+  qdl-devices                         List devices in Qualcomm EDL mode
+  download <url> [--sha-url URL]      Download and decompress an image into the cache
+  download --board B [image filters]  Download the first (promoted) image for a board
+        [--allow-missing-sha]
+  decompress <file>                   Decompress a .xz/.gz/.bz2/.zst image
+  flash --image F --device D[,D...]   Write an image (compressed images are decompressed);
+                                      several devices are flashed in parallel
+        [--verify] [--delta] [--autoconfig preset.json] [--allow-system]
+  verify --image F --device D         Compare a device with an image
+  qdl-flash <archive.tar>             Flash a QDL archive to a device in EDL mode
+        [--serial S] [--autoconfig preset.json]
+  qdl-flash <image> --ufs --board B   Flash a raw image to UFS via Firehose
+        [--soc S] [--serial S] [--autoconfig preset.json]
+  run <manifest.json>                 Provision from a manifest: select and download the
+                                      image, flash the matching devices, run post-flash steps
+        [--dry-run] [--allow-missing-sha]
+  validate <manifest.json>            Check a manifest (secret references are not read)
+
+Global options:
+  --quiet   No log output on stderr
+  --debug   Debug-level logging
+
+Every stdout line is a JSON object: {\"type\": \"progress\" | \"result\" | \"error\", ...}.
+Exit codes: 0 success, 1 failure, 2 usage, 3 download, 4 verification,
+5 device, 6 denied by device policy or authorization, 130 interrupted.
+";
+
+/// Run the CLI when the first argument is `cli`, returning the exit code;
+/// `None` means start the GUI.
+pub fn run() -> Option<i32> {
+    let mut argv = std::env::args().skip(1).peekable();
+    if argv.peek().map(String::as_str) != Some("cli") {
+        return None;
+    }
+    argv.next();
+    attach_console();
+
+    let args = match Args::parse(argv) {
+        Ok(args) => args,
+        Err(e) => return Some(output::error("cli", &e)),
+    };
+
+    if args.flag("quiet") {
+        logging::set_console_output(false);
+    }
+    if args.flag("debug") {
+        logging::set_log_level(true);
+    }
+
+    if args.command.is_empty() || args.command == "help" || args.flag("help") {
+        print!("{}", USAGE);
+        return Some(exit::SUCCESS);
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            return Some(output::error(
+                &args.command,
+                &format!("Failed to start runtime: {}", e),
+            ))
+        }
+    };
+
+    log_info!(MODULE, "Running `{}`", args.command);
+    let code = match runtime.block_on(dispatch(&args)) {
+        Ok(data) => {
+            output::result(&args.command, data);
+            exit::SUCCESS
+        }
+        Err(e) => {
+            log_error!(MODULE, "`{}` failed: {}", args.command, e);
+            output::error(&args.command, &e)
+        }
+    };
+    Some(code)
+}
+
+async fn dispatch(args: &Args) -> Result<Value, String> {
+    match args.command.as_str() {
+        "boards" => boards().await,
+        "images" => images(args).await,
+        "devices" => list_devices(),
+        "qdl-devices" => to_json(qdl::detect::get_qdl_devices()?),
+        "download" => download_cmd(args).await,
+        "decompress" => decompress_cmd(args).await,
+        "flash" => flash_cmd(args).await,
+        "verify" => verify_cmd(args).await,
+        "qdl-flash" => qdl_flash_cmd(args).await,
+        "run" => run::run_cmd(args).await,
+        "validate" => run::validate_cmd(args),
+        other => Err(format!(
+            "[USAGE] Unknown command '{}', see `armbian-imager cli help`",
+            other
+        )),
+    }
+}
+
+async fn boards() -> Result<Value, String> {
+    let boards: Vec<_> = fetch_boards().await?.iter().map(map_board).collect();
+    to_json(boards)
+}
+
+async fn images(args: &Args) -> Result<Value, String> {
+    let board = args.required("board", 0)?;
+    to_json(fetch_images(args, board).await?)
+}
+
+/// Board images with the GUI's filters.
+async fn fetch_images(args: &Args, board: &str) -> Result<Vec<ImageInfo>, String> {
+    let owned = |name: &str| args.option(name).map(str::to_string);
+    commands::board_queries::get_images_for_board(
+        board.to_string(),
+        owned("preapp"),
+        owned("kernel"),
+        owned("variant"),
+        owned("stability"),
+    )
+    .await
+}
+
+fn list_devices() -> Result<Value, String> {
+    let devices = devices::get_block_devices()?;
+    to_json(policy::reload(None).apply(devices))
+}
+
+async fn download_cmd(args: &Args) -> Result<Value, String> {
+    let (url, sha_url) = match args.option("url").or_else(|| args.positional(0)) {
+        Some(url) => (url.to_string(), args.option("sha-url").map(str::to_string)),
+        None => {
+            let board = args.required("board", 0)?;
+            let images = fetch_images(args, board).await?;
+            let image = images
+                .iter()
+                .find(|img| img.promoted)
+                .or_else(|| images.first())
+                .ok_or_else(|| format!("No image matches the filters for {}", board))?;
+            log_info!(MODULE, "Selected {} for {}", image.direct_url, board);
+            (image.direct_url.clone(), image.sha_url.clone())
+        }
+    };
+
+    let path = download_to_cache(
+        &url,
+        sha_url.as_deref().map(ExpectedSha::Url),
+        args.flag("allow-missing-sha"),
+    )
+    .await?;
+
+    Ok(json!({ "url": url, "path": path }))
+}
+
+/// Download (and decompress) an image into the cache, reporting progress.
+/// `allow_missing_sha` keeps going when the checksum cannot be fetched.
+async fn download_to_cache(
+    url: &str,
+    sha: Option<ExpectedSha<'_>>,
+    allow_missing_sha: bool,
+) -> Result<PathBuf, String> {
+    let state = Arc::new(DownloadState::new());
+    let _cancel = CancelOnInterrupt::new({
+        let state = state.clone();
+        move || state.is_cancelled.store(true, Ordering::SeqCst)
+    });
+    let progress = ProgressReporter::start({
+        let state = state.clone();
+        move || output::download_progress(&state)
+    });
+
+    let mut result = download::download_image(url, sha, &images_dir(), state.clone()).await;
+    if let Err(e) = &result {
+        if e.contains("[SHA_UNAVAILABLE]") && allow_missing_sha {
+            log_warn!(MODULE, "Checksum unavailable, continuing: {}", e);
+            result = download::continue_without_sha(state.clone(), &images_dir()).await;
+        } else {
+            download::cleanup_pending_download(state.clone()).await;
+        }
+    }
+    progress.stop();
+    result.map_err(|e| {
+        let cancelled = state.is_cancelled.load(Ordering::SeqCst);
+        output::tag_error(e, cancelled, Some("DOWNLOAD_FAILED"))
+    })
+}
+
+async fn decompress_cmd(args: &Args) -> Result<Value, String> {
+    let input = PathBuf::from(args.required("image", 0)?);
+    let (path, _) = decompressed(&input).await?;
+    Ok(json!({ "path": path }))
+}
+
+async fn flash_cmd(args: &Args) -> Result<Value, String> {
+    let image = PathBuf::from(args.required("image", 0)?);
+    // `--device a,b,c` flashes all of them in parallel from one read of the image.
+    let device_paths: Vec<String> = args
+        .required("device", 1)?
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect();
+    if device_paths.is_empty() {
+        return Err("[USAGE] flash requires --device".to_string());
+    }
+    let verify = args.flag("verify");
+    let delta = args.flag("delta");
+
+    let policy = policy::reload(None);
+    for device_path in &device_paths {
+        let device = find_device(device_path)?;
+        if device.is_read_only {
+            return Err(format!(
+                "[DEVICE_READ_ONLY] {} is write-protected",
+                device_path
+            ));
+        }
+        if device.is_system && !args.flag("allow-system") {
+            return Err(format!(
+                "[DEVICE_SYSTEM] {} is a system disk, pass --allow-system to flash it anyway",
+                device_path
+            ));
+        }
+        policy.authorize_flash(device_path)?;
+    }
+
+    let autoconfig = args.option("autoconfig").map(read_autoconfig).transpose()?;
+    for device_path in &device_paths {
+        if !flash::request_authorization(device_path)? {
+            return Err(format!(
+                "[AUTH_DENIED] Authorization to flash {} was denied",
+                device_path
+            ));
+        }
+    }
+
+    let context = FlashContext {
+        board: None,
+        profile_name: profile_name(args),
+    };
+    let mut history = FlashHistory::new(&image, context, verify);
+    let (flash_path, temps) = prepare_image(&image, autoconfig).await?;
+    let options = FlashOptions {
+        verify,
+        delta,
+        ..Default::default()
+    };
+
+    let result = if let [device_path] = device_paths.as_slice() {
+        flash_one(&flash_path, device_path, options, &mut history)
+            .await
+            .map(|state| {
+                json!({
+                    "image": image,
+                    "device": device_path,
+                    "bytes": state.total_bytes.load(Ordering::SeqCst),
+                    "verified": verify,
+                    "skipped_bytes": state.skipped_bytes.load(Ordering::SeqCst),
+                })
+            })
+    } else {
+        flash_several(&flash_path, &device_paths, options, &mut history)
+            .await
+            .map(|devices| json!({ "image": image, "devices": devices }))
+    };
+
+    history.record().await;
+    remove_temps(temps);
+    result
+}
+
+/// The file to write for `image`: decompressed, then with the autoconfig preset
+/// injected. Temp files made on the way are returned for [`remove_temps`].
+async fn prepare_image(
+    image: &Path,
+    autoconfig: Option<AutoconfigConfig>,
+) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    let (raw, decompressed_copy) = decompressed(image).await?;
+    let mut temps: Vec<PathBuf> = decompressed_copy.into_iter().collect();
+    let Some(config) = autoconfig else {
+        return Ok((raw, temps));
+    };
+
+    let injected = tokio::task::spawn_blocking(move || {
+        crate::autoconfig::prepare_injected_copy(&raw, &config)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+    match injected {
+        Ok(copy) => {
+            temps.push(copy.clone());
+            Ok((copy, temps))
+        }
+        Err(e) => {
+            remove_temps(temps);
+            Err(e)
+        }
+    }
+}
+
+fn remove_temps(temps: Vec<PathBuf>) {
+    for temp in temps {
+        let _ = std::fs::remove_file(temp);
+    }
+}
+
+async fn flash_one(
+    image: &Path,
+    device_path: &str,
+    options: FlashOptions,
+    history: &mut FlashHistory,
+) -> Result<Arc<FlashState>, String> {
+    let state = Arc::new(FlashState::new());
+    let job = history
+        .begin(JobKind::Flash, TargetInfo::block(device_path), &state)
+        .await;
+    let _cancel = cancel_job_on_interrupt(&job);
+    let progress = ProgressReporter::start({
+        let state = state.clone();
+        move || output::flash_progress(&state)
+    });
+    let result = flash::flash_image(image, device_path, state.clone(), options).await;
+    progress.stop();
+    job.finish(&result);
+    result
+        .map_err(|e| tag_flash_error(e, &state))
+        .map(|_| state)
+}
+
+/// Flash every device at once. One failing device does not stop the others, but
+/// the command fails (with the first error) unless all of them succeeded; the
+/// per-device outcome is printed as a result line either way.
+async fn flash_several(
+    image: &Path,
+    device_paths: &[String],
+    options: FlashOptions,
+    history: &mut FlashHistory,
+) -> Result<Value, String> {
+    let results = flash_targets(image, device_paths, options, history).await;
+    let devices = serde_json::to_value(&results).map_err(|e| e.to_string())?;
+    match results.iter().find_map(|r| r.error.as_ref()) {
+        None => Ok(devices),
+        Some(first) => {
+            output::result("flash", json!({ "devices": devices }));
+            let failed = results.iter().filter(|r| !r.success).count();
+            Err(output::tag_error(
+                format!("{} of {} devices failed: {}", failed, results.len(), first),
+                false,
+                output::leading_tag(first),
+            ))
+        }
+    }
+}
+
+/// Flash `image` to every device in parallel, reporting progress for all of them.
+async fn flash_targets(
+    image: &Path,
+    device_paths: &[String],
+    options: FlashOptions,
+    history: &mut FlashHistory,
+) -> Vec<DeviceFlashResult> {
+    let targets: Vec<Arc<FlashTarget>> = device_paths
+        .iter()
+        .map(|path| Arc::new(FlashTarget::new(path)))
+        .collect();
+    let devices = devices::get_block_devices().unwrap_or_default();
+    let mut jobs = Vec::with_capacity(targets.len());
+    for target in &targets {
+        let device = TargetInfo::from_devices(&devices, &target.device_path);
+        jobs.push(history.begin(JobKind::Flash, device, &target.state).await);
+    }
+
+    let _cancel = CancelOnInterrupt::new({
+        let jobs = jobs.clone();
+        move || {
+            for job in &jobs {
+                job.cancel();
+            }
+        }
+    });
+    let progress = ProgressReporter::start({
+        let targets = targets.clone();
+        move || {
+            let devices: Vec<Value> = targets
+                .iter()
+                .map(|target| {
+                    let mut value = output::flash_progress(&target.state);
+                    value["device"] = json!(target.device_path);
+                    value["finished"] = json!(target.finished.load(Ordering::SeqCst));
+                    value
+                })
+                .collect();
+            json!({ "type": "progress", "stage": "multi", "devices": devices })
+        }
+    });
+    let mut results = multi::flash_many(image, &targets, options).await;
+    progress.stop();
+    for ((result, target), job) in results.iter_mut().zip(&targets).zip(&jobs) {
+        job.finish(&result.error.clone().map_or(Ok(()), Err));
+        result.error = result
+            .error
+            .take()
+            .map(|e| tag_flash_error(e, &target.state));
+    }
+    results
+}
+
+/// Tag a write or verify error by how far the flash got, so the exit code tells
+/// a verification mismatch and an interruption apart from other failures.
+fn tag_flash_error(error: String, state: &FlashState) -> String {
+    let cancelled = state.is_cancelled.load(Ordering::SeqCst);
+    let verifying = state.is_verifying.load(Ordering::SeqCst);
+    output::tag_error(error, cancelled, verifying.then_some("VERIFY_FAILED"))
+}
+
+async fn verify_cmd(args: &Args) -> Result<Value, String> {
+    let image = PathBuf::from(args.required("image", 0)?);
+    let device_path = args.required("device", 1)?.to_string();
+    find_device(&device_path)?;
+
+    let (raw, decompressed_copy) = decompressed(&image).await?;
+    let state = Arc::new(FlashState::new());
+    let result = {
+        let _cancel = cancel_flash_on_interrupt(&state);
+        let progress = ProgressReporter::start({
+            let state = state.clone();
+            move || output::flash_progress(&state)
+        });
+        let (raw, device, verify_state) = (raw.clone(), device_path.clone(), state.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            flash::verify::verify_device(&raw, &device, verify_state)
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r);
+        progress.stop();
+        result.map_err(|e| {
+            let cancelled = state.is_cancelled.load(Ordering::SeqCst);
+            output::tag_error(e, cancelled, Some("VERIFY_FAILED"))
+        })
+    };
+
+    if let Some(temp) = decompressed_copy {
+        let _ = std::fs::remove_file(temp);
+    }
+    result?;
+
+    Ok(json!({
+        "image": image,
+        "device": device_path,
+        "bytes": state.verified_bytes.load(Ordering::SeqCst),
+    }))
+}
+
+async fn qdl_flash_cmd(args: &Args) -> Result<Value, String> {
+    let image = PathBuf::from(args.required("image", 0)?);
+    let serial = args.option("serial").map(str::to_string);
+    let autoconfig = args.option("autoconfig").map(read_autoconfig).transpose()?;
+    let state = Arc::new(FlashState::new());
+    let target = TargetInfo::edl(serial.as_deref());
+    let context = FlashContext {
+        board: args.option("board").map(str::to_string),
+        profile_name: profile_name(args),
+    };
+    let mut history = FlashHistory::new(&image, context, false);
+
+    let extract_dir = qdl_temp_dir();
+    let job: Box<dyn FnOnce() -> Result<(), String> + Send> = if args.flag("ufs") {
+        let board = args.required("board", 1)?.to_string();
+        let soc = args.option("soc").unwrap_or_default().to_string();
+        let loader = qdl::loader::ensure_loader(&soc, &board).await?;
+        let provision = qdl::provision::ensure_provision_xml(&soc, &board).await;
+        if let qdl::provision::ProvisionSource::Unavailable(reason) = &provision {
+            log_warn!(MODULE, "Provision XML unavailable: {}", reason);
+        }
+        let (image, state) = (image.clone(), state.clone());
+        Box::new(move || {
+            qdl::flash::qdl_flash_ufs(&image, &loader, serial, autoconfig, provision, state)
+        })
+    } else {
+        let flash_dir = qdl::extract::extract_qdl_archive(&image, &extract_dir)?;
+        let state = state.clone();
+        Box::new(move || qdl::flash::qdl_flash(&flash_dir, serial, autoconfig, state))
+    };
+
+    let result = {
+        let history_job = history.begin(JobKind::QdlFlash, target, &state).await;
+        let _cancel = cancel_job_on_interrupt(&history_job);
+        let progress = ProgressReporter::start({
+            let state = state.clone();
+            move || output::flash_progress(&state)
+        });
+        // qdlrs is synchronous, so run the flash off the async runtime.
+        let result = tokio::task::spawn_blocking(job)
+            .await
+            .map_err(|e| format!("[QDL_ERROR] {}", e))
+            .and_then(|r| r);
+        progress.stop();
+        history_job.finish(&result);
+        result.map_err(|e| output::tag_error(e, state.is_cancelled.load(Ordering::SeqCst), None))
+    };
+
+    history.record().await;
+    qdl::extract::cleanup_extraction(&extract_dir);
+    result?;
+
+    Ok(json!({ "image": image, "storage": if args.flag("ufs") { "ufs" } else { "emmc" } }))
+}
+
+/// The CLI's flashes in the flash history: as in the GUI, every device is a job of
+/// its own and gets one record.
+struct FlashHistory {
+    jobs: JobManager,
+    flashes: Vec<(Arc<Job>, TargetInfo)>,
+    image: ImageHasher,
+    context: FlashContext,
+    verify: bool,
+}
+
+impl FlashHistory {
+    fn new(image: &Path, context: FlashContext, verify: bool) -> Self {
+        Self {
+            jobs: JobManager::default(),
+            flashes: Vec::new(),
+            image: ImageHasher::new(image),
+            context,
+            verify,
+        }
+    }
+
+    /// A running job for the flash of `target`, which is about to write. Finish it
+    /// with [`Job::finish`].
+    async fn begin(
+        &mut self,
+        kind: JobKind,
+        target: TargetInfo,
+        state: &Arc<FlashState>,
+    ) -> Arc<Job> {
+        self.image.start();
+        let progress = JobProgressState::Flash(state.clone());
+        let job = self.jobs.create(kind, &target.path, progress, None);
+        // Only this command's flashes use the manager, one job per target, so
+        // nothing is waited for; the guard is not needed past the start time.
+        let _ = self.jobs.begin(&job).await;
+        self.flashes.push((job.clone(), target));
+        job
+    }
+
+    /// Record every flash begun, once all of them are finished.
+    async fn record(self) {
+        let image = self.image.finish().await;
+        for (job, target) in self.flashes {
+            history::record_job(&job, &image, target, &self.context, self.verify);
+        }
+    }
+}
+
+/// Profile name for the flash history: the `--autoconfig` preset's file name.
+fn profile_name(args: &Args) -> Option<String> {
+    let path = Path::new(args.option("autoconfig")?);
+    path.file_stem().map(|s| s.to_string_lossy().to_string())
+}
+
+/// The target as currently enumerated, or a `[DEVICE_NOT_FOUND]` error.
+fn find_device(device_path: &str) -> Result<BlockDevice, String> {
+    devices::get_block_devices()?
+        .into_iter()
+        .find(|d| d.path == device_path)
+        .ok_or_else(|| format!("[DEVICE_NOT_FOUND] {} is not a flashable disk", device_path))
+}
+
+/// A raw image for `input`: the file itself, or a decompressed temp copy which
+/// the caller should delete once done (second value).
+async fn decompressed(input: &Path) -> Result<(PathBuf, Option<PathBuf>), String> {
+    if !input.exists() {
+        return Err(format!("[USAGE] {} does not exist", input.display()));
+    }
+    if !needs_decompression(input) {
+        return Ok((input.to_path_buf(), None));
+    }
+
+    let state = Arc::new(DownloadState::new());
+    let _cancel = CancelOnInterrupt::new({
+        let state = state.clone();
+        move || state.is_cancelled.store(true, Ordering::SeqCst)
+    });
+    let progress = ProgressReporter::start({
+        let state = state.clone();
+        move || output::download_progress(&state)
+    });
+
+    let input = input.to_path_buf();
+    let result = tokio::task::spawn_blocking({
+        let state = state.clone();
+        move || decompress_local_file(&input, &state)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+    progress.stop();
+
+    let path = result
+        .map_err(|e| output::tag_error(e, state.is_cancelled.load(Ordering::SeqCst), None))?;
+    Ok((path.clone(), Some(path)))
+}
+
+/// Autoconfig preset in the GUI's profile format (camelCase keys).
+fn read_autoconfig(path: &str) -> Result<AutoconfigConfig, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("[USAGE] Cannot read autoconfig file {}: {}", path, e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("[USAGE] Invalid autoconfig file {}: {}", path, e))
+}
+
+fn to_json<T: serde::Serialize>(value: T) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+fn cancel_flash_on_interrupt(state: &Arc<FlashState>) -> CancelOnInterrupt {
+    let state = state.clone();
+    CancelOnInterrupt::new(move || state.is_cancelled.store(true, Ordering::SeqCst))
+}
+
+fn cancel_job_on_interrupt(job: &Arc<Job>) -> CancelOnInterrupt {
+    let job = job.clone();
+    CancelOnInterrupt::new(move || job.cancel())
+}
+
+/// Runs `cancel` on Ctrl-C while alive, so the operation stops at its next
+/// chunk and cleans up instead of leaving a half-written temp file.
+struct CancelOnInterrupt(tokio::task::JoinHandle<()>);
+
+impl CancelOnInterrupt {
+    fn new(cancel: impl FnOnce() + Send + 'static) -> Self {
+        Self(tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                log_warn!(MODULE, "Interrupted, cancelling");
+                cancel();
+            }
+        }))
+    }
+}
+
+impl Drop for CancelOnInterrupt {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Release builds use the GUI subsystem on Windows; reattach to the calling
+/// console so stdout reaches the script.
+#[cfg(target_os = "windows")]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_console() {}
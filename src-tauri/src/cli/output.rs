@@ -0,0 +1,299 @@
+//! JSON-lines output and exit codes. Every line on stdout is one JSON object with a
+//! `type` of `progress`, `result` or `error`; logs stay on stderr and in the log file.
+
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::download::DownloadState;
+use crate::flash::FlashState;
+
+/// Process exit codes, stable for scripts.
+pub mod exit {
+    pub const SUCCESS: i32 = 0;
+    /// Anything not covered below
+    pub const FAILURE: i32 = 1;
+    /// Unknown subcommand, missing or invalid argument, invalid manifest
+    pub const USAGE: i32 = 2;
+    /// Download failed or the checksum did not match
+    pub const DOWNLOAD: i32 = 3;
+    /// The device does not match the image after writing
+    pub const VERIFY: i32 = 4;
+    /// Device missing, busy, removed, protected or failing (block or EDL)
+    pub const DEVICE: i32 = 5;
+    /// The device policy forbids flashing the target, or authorization was denied
+    pub const POLICY: i32 = 6;
+    /// Interrupted with Ctrl-C
+    pub const CANCELLED: i32 = 130;
+}
+
+/// Print one JSON line.
+pub fn emit(value: &Value) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", value);
+    let _ = stdout.flush();
+}
+
+pub fn result(command: &str, data: Value) {
+    emit(&json!({ "type": "result", "command": command, "data": data }));
+}
+
+/// Report a failed command and return the exit code for it.
+pub fn error(command: &str, message: &str) -> i32 {
+    let code = exit_code(message);
+    emit(&json!({
+        "type": "error",
+        "command": command,
+        "code": error_tag(message),
+        "message": message,
+        "exit_code": code,
+    }));
+    code
+}
+
+/// Map a backend error string to an exit code by its leading `[TAG]` only; the
+/// wording after it is for people and may change.
+pub fn exit_code(message: &str) -> i32 {
+    match leading_tag(message) {
+        Some("USAGE" | "MANIFEST_INVALID" | "MANIFEST_NO_IMAGE") => exit::USAGE,
+        Some("POLICY_DENIED" | "AUTH_DENIED") => exit::POLICY,
+        Some("CANCELLED" | "QDL_CANCELLED") => exit::CANCELLED,
+        Some("DOWNLOAD_FAILED" | "SHA_UNAVAILABLE") => exit::DOWNLOAD,
+        Some("VERIFY_FAILED") => exit::VERIFY,
+        Some("WRITE_FAILED") => exit::DEVICE,
+        Some(tag) if tag.starts_with("DEVICE_") || tag.starts_with("QDL_") => exit::DEVICE,
+        _ => exit::FAILURE,
+    }
+}
+
+/// The error's leading `[TAG]` (without any `:detail`), or `ERROR`.
+fn error_tag(message: &str) -> &str {
+    leading_tag(message).unwrap_or("ERROR")
+}
+
+/// The error's leading `[TAG]` without any `:detail`.
+pub fn leading_tag(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix('[')?;
+    let tag = &rest[..rest.find([']', ':'])?];
+    (!tag.is_empty() && tag.chars().all(|c| c.is_ascii_uppercase() || c == '_')).then_some(tag)
+}
+
+/// Tag an error from a stage that does not tag its own: `[CANCELLED]` when it was
+/// interrupted, else `tag` if given. Errors that already have a tag are kept.
+pub fn tag_error(message: String, cancelled: bool, tag: Option<&str>) -> String {
+    if leading_tag(&message).is_some() {
+        return message;
+    }
+    match (cancelled, tag) {
+        (true, _) => format!("[CANCELLED] {}", message),
+        (false, Some(tag)) => format!("[{}] {}", tag, message),
+        (false, None) => message,
+    }
+}
+
+fn progress(stage: &str, bytes: u64, total: u64) -> Value {
+    let percent = if total > 0 {
+        (bytes as f64 * 1000.0 / total as f64).round() / 10.0
+    } else {
+        0.0
+    };
+    json!({
+        "type": "progress",
+        "stage": stage,
+        "bytes": bytes,
+        "total": total,
+        "percent": percent,
+    })
+}
+
+pub fn download_progress(state: &DownloadState) -> Value {
+    let stage = if state.is_decompressing.load(Ordering::SeqCst) {
+        "decompress"
+    } else if state.is_verifying_sha.load(Ordering::SeqCst) {
+        "sha"
+    } else {
+        "download"
+    };
+    progress(
+        stage,
+        state.downloaded_bytes.load(Ordering::SeqCst),
+        state.total_bytes.load(Ordering::SeqCst),
+    )
+}
+
+pub fn flash_progress(state: &FlashState) -> Value {
+    let total = state.total_bytes.load(Ordering::SeqCst);
+
+    if state.qdl.is_active.load(Ordering::SeqCst) {
+        let mut value = progress("qdl", state.written_bytes.load(Ordering::SeqCst), total);
+        value["qdl_stage"] = json!(state
+            .qdl
+            .stage
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone());
+        value["partitions"] = json!(state.qdl.partitions_written.load(Ordering::SeqCst));
+        value["partitions_total"] = json!(state.qdl.partitions_total.load(Ordering::SeqCst));
+        return value;
+    }
+
+    if state.is_verifying.load(Ordering::SeqCst) {
+        progress("verify", state.verified_bytes.load(Ordering::SeqCst), total)
+    } else {
+        progress("write", state.written_bytes.load(Ordering::SeqCst), total)
+    }
+}
+
+/// Emit `snapshot()` every progress interval while it changes, until the
+/// returned reporter is stopped.
+pub struct ProgressReporter {
+    task: tokio::task::JoinHandle<()>,
+    snapshot: Arc<dyn Fn() -> Value + Send + Sync>,
+}
+
+impl ProgressReporter {
+    pub fn start(snapshot: impl Fn() -> Value + Send + Sync + 'static) -> Self {
+        let snapshot: Arc<dyn Fn() -> Value + Send + Sync> = Arc::new(snapshot);
+        let poll = snapshot.clone();
+        let task = tokio::spawn(async move {
+            let mut last = Value::Null;
+            loop {
+                let current = poll();
+                if current != last {
+                    emit(&current);
+                    last = current;
+                }
+                tokio::time::sleep(Duration::from_millis(config::cli::PROGRESS_INTERVAL_MS)).await;
+            }
+        });
+        Self { task, snapshot }
+    }
+
+    /// Stop polling and print the final state, so a finished stage reads 100%.
+    pub fn stop(self) {
+        self.task.abort();
+        emit(&(self.snapshot)());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_and_manifest_errors_exit_with_usage() {
+        assert_eq!(exit_code("[USAGE] flash requires --device"), exit::USAGE);
+        assert_eq!(
+            exit_code("[MANIFEST_INVALID] lab.json:image.sha256: must be 64 hex characters"),
+            exit::USAGE
+        );
+        assert_eq!(
+            exit_code("[MANIFEST_NO_IMAGE] No flashable image for rock-5b matches"),
+            exit::USAGE
+        );
+    }
+
+    #[test]
+    fn policy_and_authorization_denials_exit_with_policy() {
+        assert_eq!(exit_code("[POLICY_DENIED] Lab backup disk"), exit::POLICY);
+        assert_eq!(
+            exit_code("[AUTH_DENIED] Flash cancelled: authorization was denied"),
+            exit::POLICY
+        );
+    }
+
+    #[test]
+    fn cancellation_exits_with_130() {
+        assert_eq!(
+            exit_code("[CANCELLED] Verification cancelled"),
+            exit::CANCELLED
+        );
+        assert_eq!(
+            exit_code("[QDL_CANCELLED] Operation cancelled by user"),
+            exit::CANCELLED
+        );
+    }
+
+    #[test]
+    fn download_and_verify_failures_have_their_own_codes() {
+        assert_eq!(
+            exit_code("[DOWNLOAD_FAILED] SHA256 verification failed: SHA256 mismatch"),
+            exit::DOWNLOAD
+        );
+        assert_eq!(
+            exit_code("[SHA_UNAVAILABLE] No checksum published"),
+            exit::DOWNLOAD
+        );
+        assert_eq!(
+            exit_code("[VERIFY_FAILED] Verification failed: data mismatch at byte 4096"),
+            exit::VERIFY
+        );
+    }
+
+    #[test]
+    fn device_errors_exit_with_device() {
+        assert_eq!(exit_code("[DEVICE_BUSY] /dev/sdb is in use"), exit::DEVICE);
+        assert_eq!(exit_code("[WRITE_FAILED:1048576] EIO"), exit::DEVICE);
+        assert_eq!(
+            exit_code("[QDL_DISCONNECTED] Device disconnected"),
+            exit::DEVICE
+        );
+    }
+
+    #[test]
+    fn wording_without_a_leading_tag_is_a_plain_failure() {
+        assert_eq!(
+            exit_code("Flash cancelled: authorization was denied"),
+            exit::FAILURE
+        );
+        assert_eq!(exit_code("SHA256 verification failed"), exit::FAILURE);
+        assert_eq!(exit_code("Download failed: 404"), exit::FAILURE);
+        assert_eq!(
+            exit_code("2 of 3 devices failed: [DEVICE_BUSY] /dev/sdb"),
+            exit::FAILURE
+        );
+        assert_eq!(exit_code("[sic] Failed to read image"), exit::FAILURE);
+    }
+
+    #[test]
+    fn error_tag_is_the_leading_tag() {
+        assert_eq!(error_tag("[WRITE_FAILED:1048576] EIO"), "WRITE_FAILED");
+        assert_eq!(
+            error_tag("[DOWNLOAD_FAILED] SHA256 verification failed: [SHA_UNAVAILABLE] 404"),
+            "DOWNLOAD_FAILED"
+        );
+        assert_eq!(error_tag("Failed to read image metadata"), "ERROR");
+    }
+
+    #[test]
+    fn tag_error_keeps_existing_tags() {
+        assert_eq!(
+            tag_error(
+                "Download failed: 404".into(),
+                false,
+                Some("DOWNLOAD_FAILED")
+            ),
+            "[DOWNLOAD_FAILED] Download failed: 404"
+        );
+        assert_eq!(
+            tag_error("Download cancelled".into(), true, Some("DOWNLOAD_FAILED")),
+            "[CANCELLED] Download cancelled"
+        );
+        assert_eq!(
+            tag_error("[DEVICE_REMOVED] gone".into(), true, Some("VERIFY_FAILED")),
+            "[DEVICE_REMOVED] gone"
+        );
+        assert_eq!(tag_error("Failed".into(), false, None), "Failed");
+    }
+
+    #[test]
+    fn test_progress_percent() {
+        let value = progress("write", 1, 3);
+        assert_eq!(value["percent"], json!(33.3));
+        assert_eq!(progress("download", 5, 0)["percent"], json!(0.0));
+    }
+}
@@ -0,0 +1,209 @@
+//! `run` and `validate`: carry out a provisioning manifest (see [`crate::manifest`])
+//! with the same download and flash steps as the other commands, ending with one
+//! report of what was flashed where.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use super::args::Args;
+use super::{
+    download_to_cache, flash_targets, output, prepare_image, remove_temps, FlashHistory, MODULE,
+};
+use crate::devices::{self, policy};
+use crate::flash::{self, FlashOptions};
+use crate::history::FlashContext;
+use crate::manifest::{self, Manifest, PostFlashStep, ResolvedImage};
+use crate::{log_info, log_warn};
+
+pub(super) fn validate_cmd(args: &Args) -> Result<Value, String> {
+    let path = PathBuf::from(args.required("manifest", 0)?);
+    let manifest = manifest::load(&path)?;
+    Ok(json!({
+        "manifest": path,
+        "name": manifest.name,
+        "board": manifest.board,
+        "valid": true,
+    }))
+}
+
+pub(super) async fn run_cmd(args: &Args) -> Result<Value, String> {
+    let path = PathBuf::from(args.required("manifest", 0)?);
+    let manifest = manifest::load(&path)?;
+    let image = manifest.resolve_image().await?;
+    let device_paths: Vec<String> = manifest
+        .select_devices(devices::get_block_devices()?)?
+        .into_iter()
+        .map(|d| d.path)
+        .collect();
+
+    // The station's own device policy still has the last word.
+    let station_policy = policy::reload(None);
+    for device_path in &device_paths {
+        station_policy.authorize_flash(device_path)?;
+    }
+
+    let mut report = json!({
+        "manifest": path,
+        "name": manifest.name,
+        "board": manifest.board,
+        "image": image_summary(&image),
+        "devices": device_paths,
+        "verify": manifest.verify,
+        "delta": manifest.delta,
+    });
+    if args.flag("dry-run") {
+        return Ok(report);
+    }
+
+    for device_path in &device_paths {
+        if !flash::request_authorization(device_path)? {
+            return Err(format!(
+                "[AUTH_DENIED] Authorization to flash {} was denied",
+                device_path
+            ));
+        }
+    }
+
+    let downloaded = download_to_cache(
+        &image.url,
+        image.expected_sha(),
+        args.flag("allow-missing-sha"),
+    )
+    .await?;
+    // Secrets are read only now, right before they are written into the image.
+    let autoconfig = manifest.autoconfig_config()?;
+    // The manifest names the profile it carries.
+    let context = FlashContext {
+        board: Some(manifest.board.clone()),
+        profile_name: autoconfig.as_ref().and(manifest.name.clone()),
+    };
+    let mut history = FlashHistory::new(&downloaded, context, manifest.verify);
+    let (flash_path, temps) = prepare_image(&downloaded, autoconfig).await?;
+    let options = FlashOptions {
+        verify: manifest.verify,
+        delta: manifest.delta,
+        ..Default::default()
+    };
+    let results = flash_targets(&flash_path, &device_paths, options, &mut history).await;
+    history.record().await;
+    remove_temps(temps);
+
+    let mut failures = Vec::new();
+    // The exit code follows the first flash failure's tag.
+    let mut first_tag = None;
+    let mut devices = Vec::new();
+    for result in results {
+        let mut entry = serde_json::to_value(&result).map_err(|e| e.to_string())?;
+        match &result.error {
+            Some(e) => {
+                first_tag = first_tag.or_else(|| output::leading_tag(e).map(str::to_string));
+                failures.push(format!("{}: {}", result.device_path, e));
+            }
+            None => {
+                let steps = run_steps(&manifest, &result.device_path, &downloaded).await;
+                if let Some(failed) = steps.iter().find(|s| s["success"] == json!(false)) {
+                    failures.push(format!(
+                        "{}: post-flash {} failed: {}",
+                        result.device_path,
+                        failed["step"].as_str().unwrap_or_default(),
+                        failed["error"].as_str().unwrap_or_default()
+                    ));
+                }
+                entry["steps"] = json!(steps);
+            }
+        }
+        devices.push(entry);
+    }
+    report["devices"] = json!(devices);
+    report["image"]["path"] = json!(downloaded);
+
+    if failures.is_empty() {
+        log_info!(MODULE, "Manifest {} done", path.display());
+        return Ok(report);
+    }
+    output::result("run", report);
+    Err(output::tag_error(
+        format!(
+            "{} of {} devices failed: {}",
+            failures.len(),
+            devices.len(),
+            failures.join("; ")
+        ),
+        false,
+        first_tag.as_deref(),
+    ))
+}
+
+fn image_summary(image: &ResolvedImage) -> Value {
+    let mut summary = json!({ "url": image.url, "sha256": image.sha256 });
+    if let Some(info) = &image.info {
+        summary["release"] = json!(info.release);
+        summary["distribution"] = json!(info.distro_release);
+        summary["branch"] = json!(info.kernel_branch);
+        summary["variant"] = json!(info.image_variant);
+    }
+    summary
+}
+
+/// Run the post-flash steps for one device in order, stopping at the first failure.
+async fn run_steps(manifest: &Manifest, device_path: &str, image: &Path) -> Vec<Value> {
+    let mut outcomes = Vec::new();
+    for step in &manifest.post_flash {
+        let result = run_step(step, device_path, image).await;
+        if let Err(e) = &result {
+            log_warn!(
+                MODULE,
+                "Post-flash {} on {} failed: {}",
+                step.name(),
+                device_path,
+                e
+            );
+        }
+        outcomes.push(json!({
+            "step": step.name(),
+            "success": result.is_ok(),
+            "error": result.as_ref().err(),
+        }));
+        if result.is_err() {
+            break;
+        }
+    }
+    outcomes
+}
+
+async fn run_step(step: &PostFlashStep, device_path: &str, image: &Path) -> Result<(), String> {
+    let (run, timeout_secs) = match step {
+        PostFlashStep::Unmount => return flash::unmount_device(device_path),
+        PostFlashStep::Command { run, timeout_secs } => (run, *timeout_secs),
+    };
+
+    let program = &run[0];
+    log_info!(MODULE, "Running {} for {}", program, device_path);
+    // stdout carries the JSON lines, so the program's output goes to stderr.
+    let mut child = tokio::process::Command::new(program)
+        .args(&run[1..])
+        .env("IMAGER_DEVICE", device_path)
+        .env("IMAGER_IMAGE", image)
+        .stdin(Stdio::null())
+        .stdout(std::io::stderr())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Cannot run {}: {}", program, e))?;
+
+    let status = match timeout_secs {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), child.wait())
+            .await
+            .map_err(|_| format!("{} timed out after {}s", program, secs))?,
+        None => child.wait().await,
+    }
+    .map_err(|e| format!("{} failed: {}", program, e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", program, status))
+    }
+}
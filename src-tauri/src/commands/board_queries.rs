@@ -4,10 +4,10 @@ use std::collections::HashSet;
 use std::sync::Mutex;
 
 use once_cell::sync::Lazy;
-use tauri::State;
+use tauri::{AppHandle, State};
 
 use crate::config;
-use crate::devices::{get_block_devices as devices_get_block_devices, BlockDevice};
+use crate::devices::{get_block_devices as devices_get_block_devices, policy, BlockDevice};
 use crate::images::{
     fetch_boards, fetch_images_for_board, fetch_vendors, map_board, map_images, ApiVendor,
     BoardInfo, ImageInfo,
@@ -127,13 +127,14 @@ pub async fn get_vendors(state: State<'_, AppState>) -> Result<Vec<ApiVendor>, S
     Ok(vendors)
 }
 
-/// Get available block devices
+/// Get available block devices, with the device policy applied
 #[tauri::command]
-pub async fn get_block_devices() -> Result<Vec<BlockDevice>, String> {
+pub async fn get_block_devices(app: AppHandle) -> Result<Vec<BlockDevice>, String> {
     let devices = devices_get_block_devices().map_err(|e| {
         log_error!("board_queries", "Failed to get block devices: {}", e);
         e
     })?;
+    let devices = policy::load(&app).apply(devices);
 
     // Log only when the device set changes, to avoid flooding the polling loop.
     let current_paths: HashSet<String> = devices.iter().map(|d| d.path.clone()).collect();
@@ -1,17 +1,30 @@
 //! Selection and processing of user-provided custom images.
 
+use armbian_write_conf::Filesystem;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tauri::State;
 
+use crate::config;
 use crate::decompress::{decompress_local_file, needs_decompression};
+use crate::devices::ArmbianReleaseInfo;
+use crate::download::DownloadState;
 use crate::images::{fetch_boards, map_board, BoardInfo};
+use crate::jobs::{JobKind, JobProgressState};
 use crate::qdl::extract::open_tar_reader;
-use crate::utils::{custom_decompress_dir, normalize_slug, parse_armbian_filename};
+use crate::utils::{
+    custom_decompress_dir, match_fdtfile_to_slug, normalize_slug, parse_armbian_env_fdtfile,
+    parse_armbian_filename,
+};
 use crate::{log_debug, log_error, log_info};
 
 use super::state::AppState;
 
+const RELEASE_FILE: &str = "/etc/armbian-release";
+/// armbianEnv.txt in the rootfs, or at the top of a separate /boot partition.
+const ARMBIAN_ENV_FILES: [&str; 2] = ["/boot/armbianEnv.txt", "/armbianEnv.txt"];
+
 /// Custom image info returned when user selects a local file
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CustomImageInfo {
@@ -38,21 +51,29 @@ pub async fn check_needs_decompression(image_path: String) -> Result<bool, Strin
 #[tauri::command]
 pub async fn decompress_custom_image(
     image_path: String,
+    client_ref: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
     log_info!("custom_image", "Starting decompression: {}", image_path);
     let path = PathBuf::from(&image_path);
-    let download_state = state.download_state.clone();
-
-    download_state.reset();
+    let download_state = Arc::new(DownloadState::new());
+    let job = state.jobs.create(
+        JobKind::Decompress,
+        &image_path,
+        JobProgressState::Download(download_state.clone()),
+        client_ref,
+    );
 
     // Decompression is CPU-bound, so run it off the async runtime.
-    let result = tokio::task::spawn_blocking(move || decompress_local_file(&path, &download_state))
-        .await
-        .map_err(|e| {
-            log_error!("custom_image", "Decompression task failed: {}", e);
-            format!("Task failed: {}", e)
-        })?;
+    let decompress = async {
+        tokio::task::spawn_blocking(move || decompress_local_file(&path, &download_state))
+            .await
+            .map_err(|e| {
+                log_error!("custom_image", "Decompression task failed: {}", e);
+                format!("Task failed: {}", e)
+            })?
+    };
+    let result = state.jobs.run(&job, decompress).await;
 
     match &result {
         Ok(path) => {
@@ -267,82 +288,196 @@ pub async fn detect_board_from_filename(
         filename
     );
 
-    let path = PathBuf::from(&filename);
-    let filename_only = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or("Invalid filename")?;
+    let Some(slug) = slug_from_filename(&filename)? else {
+        return Ok(None);
+    };
+    let boards = load_boards(&state).await?;
+    Ok(find_board(&boards, &slug, "filename"))
+}
 
-    let parsed = match parse_armbian_filename(filename_only) {
-        Some(info) => info,
-        None => {
+/// Detect the board for a custom image from its contents: BOARD in /etc/armbian-release,
+/// then the fdtfile in armbianEnv.txt, with the filename as the last resort. Compressed
+/// images can't be read in place and go straight to the filename.
+#[tauri::command]
+pub async fn detect_board_from_image(
+    image_path: String,
+    state: State<'_, AppState>,
+) -> Result<Option<BoardInfo>, String> {
+    log_debug!(
+        "custom_image",
+        "Starting board detection from image contents: {}",
+        image_path
+    );
+
+    let path = PathBuf::from(&image_path);
+    let identity = if needs_decompression(&path) {
+        ImageIdentity::default()
+    } else {
+        tokio::task::spawn_blocking(move || read_image_identity(&path))
+            .await
+            .map_err(|e| e.to_string())?
+    };
+
+    let boards = load_boards(&state).await?;
+
+    if let Some(release) = &identity.release {
+        log_debug!(
+            "custom_image",
+            "armbian-release: BOARD={} BRANCH={} VERSION={} LINUXFAMILY={}",
+            release.board,
+            release.branch,
+            release.version,
+            release.linux_family
+        );
+        if let Some(board) = find_board(&boards, &normalize_slug(&release.board), "armbian-release")
+        {
+            return Ok(Some(board));
+        }
+    }
+
+    if let Some(fdtfile) = &identity.fdtfile {
+        log_debug!("custom_image", "armbianEnv.txt fdtfile: {}", fdtfile);
+        let slug = match_fdtfile_to_slug(fdtfile, boards.iter().map(|b| b.slug.as_str()))
+            .map(str::to_string);
+        if let Some(slug) = slug {
+            return Ok(find_board(&boards, &slug, "fdtfile"));
+        }
+    }
+
+    match slug_from_filename(&image_path)? {
+        Some(slug) => Ok(find_board(&boards, &slug, "filename")),
+        None => Ok(None),
+    }
+}
+
+/// What a RAW image says about itself, read without mounting
+#[derive(Debug, Default)]
+struct ImageIdentity {
+    release: Option<ArmbianReleaseInfo>,
+    fdtfile: Option<String>,
+}
+
+fn read_image_identity(path: &Path) -> ImageIdentity {
+    let release = match armbian_write_conf::read_file_from_image(path, RELEASE_FILE) {
+        Ok(bytes) => ArmbianReleaseInfo::parse(&String::from_utf8_lossy(&bytes)),
+        Err(e) => {
             log_debug!(
                 "custom_image",
-                "Not an Armbian image or invalid format: {}",
-                filename_only
+                "No armbian-release in {}: {}",
+                path.display(),
+                e
             );
-            return Ok(None);
+            None
+        }
+    };
+    ImageIdentity {
+        release,
+        fdtfile: read_fdtfile(path),
+    }
+}
+
+/// The fdtfile from armbianEnv.txt, which lives in /boot of the rootfs or at the top
+/// of a separate ext4 or FAT boot partition.
+fn read_fdtfile(path: &Path) -> Option<String> {
+    let layout = match armbian_write_conf::list_partitions(path) {
+        Ok(layout) => layout,
+        Err(e) => {
+            log_debug!("custom_image", "No partitions in {}: {}", path.display(), e);
+            return None;
         }
     };
 
+    let from_ext4 = layout
+        .partitions
+        .iter()
+        .filter(|p| p.filesystem == Filesystem::Ext4)
+        .flat_map(|p| ARMBIAN_ENV_FILES.iter().map(move |file| (p.index, *file)))
+        .find_map(|(index, file)| {
+            let (bytes, _) = armbian_write_conf::read_file_in_partition(
+                path,
+                index,
+                file,
+                config::inspect::MAX_READ_BYTES,
+            )
+            .ok()?;
+            parse_armbian_env_fdtfile(&String::from_utf8_lossy(&bytes))
+        });
+
+    from_ext4.or_else(|| {
+        let bytes =
+            armbian_write_conf::read_file_from_fat_image(path, ARMBIAN_ENV_FILES[1]).ok()?;
+        parse_armbian_env_fdtfile(&String::from_utf8_lossy(&bytes))
+    })
+}
+
+/// Board slug from an Armbian filename; None when the name isn't one.
+fn slug_from_filename(filename: &str) -> Result<Option<String>, String> {
+    let path = PathBuf::from(filename);
+    let filename_only = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid filename")?;
+
+    let Some(parsed) = parse_armbian_filename(filename_only) else {
+        log_debug!(
+            "custom_image",
+            "Not an Armbian image or invalid format: {}",
+            filename_only
+        );
+        return Ok(None);
+    };
+
     log_debug!(
         "custom_image",
         "Extracted board slug from filename: {}",
         parsed.board_slug
     );
+    Ok(Some(normalize_slug(&parsed.board_slug)))
+}
 
-    let normalized_slug = normalize_slug(&parsed.board_slug);
-    log_debug!("custom_image", "Normalized board slug: {}", normalized_slug);
-
-    // Auto-load board data on first use; double-checked to avoid a redundant fetch.
-    {
-        let needs_loading = {
-            let boards_guard = state.boards.lock().await;
-            boards_guard.is_none()
-        };
+/// The API board list, fetched on first use; double-checked to avoid a redundant fetch.
+async fn load_boards(state: &AppState) -> Result<Vec<BoardInfo>, String> {
+    let needs_loading = {
+        let boards_guard = state.boards.lock().await;
+        boards_guard.is_none()
+    };
 
-        if needs_loading {
-            log_debug!("custom_image", "Board data not cached, fetching from API");
-            let api_boards = fetch_boards().await.map_err(|e| {
-                log_error!("custom_image", "Failed to fetch board data: {}", e);
-                format!("Failed to fetch board data: {}", e)
-            })?;
+    if needs_loading {
+        log_debug!("custom_image", "Board data not cached, fetching from API");
+        let api_boards = fetch_boards().await.map_err(|e| {
+            log_error!("custom_image", "Failed to fetch board data: {}", e);
+            format!("Failed to fetch board data: {}", e)
+        })?;
 
-            let mut boards_guard = state.boards.lock().await;
-            if boards_guard.is_none() {
-                *boards_guard = Some(api_boards);
-            }
+        let mut boards_guard = state.boards.lock().await;
+        if boards_guard.is_none() {
+            *boards_guard = Some(api_boards);
         }
     }
 
-    let matching_board = {
-        let boards_guard = state.boards.lock().await;
-        let api_boards = boards_guard.as_ref().ok_or("Boards not loaded")?;
+    let boards_guard = state.boards.lock().await;
+    let api_boards = boards_guard.as_ref().ok_or("Boards not loaded")?;
+    let boards: Vec<BoardInfo> = api_boards.iter().map(map_board).collect();
+    log_debug!("custom_image", "Found {} boards in database", boards.len());
+    Ok(boards)
+}
 
-        let boards: Vec<BoardInfo> = api_boards.iter().map(map_board).collect();
-        log_debug!("custom_image", "Found {} boards in database", boards.len());
-
-        boards
-            .into_iter()
-            .find(|board| board.slug == normalized_slug)
-    };
+fn find_board(boards: &[BoardInfo], slug: &str, source: &str) -> Option<BoardInfo> {
+    let matching_board = boards.iter().find(|board| board.slug == slug).cloned();
 
     if let Some(ref board) = matching_board {
         log_info!(
             "custom_image",
-            "Detected board: {} (slug: {})",
+            "Detected board from {}: {} (slug: {})",
+            source,
             board.name,
             board.slug
         );
     } else {
-        log_info!(
-            "custom_image",
-            "Board not found in database: {}",
-            normalized_slug
-        );
+        log_info!("custom_image", "Board not found in database: {}", slug);
     }
 
-    Ok(matching_board)
+    matching_board
 }
 
 #[cfg(test)]
@@ -0,0 +1,31 @@
+//! Flash history queries, deletion and report export.
+
+use crate::history::{self, FlashRecord, HistoryFilter, ReportFormat};
+use crate::log_info;
+
+/// Recorded flashes matching `filter`, newest first
+#[tauri::command]
+pub async fn get_flash_history(filter: Option<HistoryFilter>) -> Result<Vec<FlashRecord>, String> {
+    let filter = filter.unwrap_or_default();
+    tokio::task::spawn_blocking(move || history::query(&filter))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Delete history records by ID, returning how many were removed
+#[tauri::command]
+pub async fn delete_flash_history(ids: Vec<String>) -> Result<usize, String> {
+    let removed = tokio::task::spawn_blocking(move || history::delete(&ids))
+        .await
+        .map_err(|e| e.to_string())??;
+    log_info!("history", "Deleted {} history record(s)", removed);
+    Ok(removed)
+}
+
+/// One flash as a standalone JSON or CSV report, for the caller to save
+#[tauri::command]
+pub async fn export_flash_report(id: String, format: ReportFormat) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || history::export(&id, format))
+        .await
+        .map_err(|e| e.to_string())?
+}
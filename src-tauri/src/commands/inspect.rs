@@ -0,0 +1,264 @@
+//! Read-only inspection of an image before flashing: partition table, a file
+//! browser over its ext4 partitions, and the device-tree overlays it ships.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use armbian_write_conf::{self as awc, WriteConfError};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::config;
+use crate::log_debug;
+use crate::utils::image_sha256;
+
+/// Overlay listings by image SHA256, so the picker does not rescan an image
+static OVERLAY_CACHE: Lazy<Mutex<HashMap<String, OverlayListing>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Partition table of an image
+#[derive(Debug, Serialize)]
+pub struct ImageLayout {
+    /// "GPT" or "MBR"
+    pub scheme: &'static str,
+    pub sector_size: u64,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+/// One used partition and the filesystem detected on it
+#[derive(Debug, Serialize)]
+pub struct PartitionInfo {
+    /// 1-based table slot, passed back to the browse commands
+    pub index: u32,
+    pub offset: u64,
+    pub size: u64,
+    /// GPT type GUID or MBR type byte ("0x83")
+    pub type_id: String,
+    /// GPT partition name, empty on MBR
+    pub name: String,
+    /// "ext4", "btrfs", "f2fs", "vfat" or "unknown"
+    pub filesystem: &'static str,
+}
+
+/// Metadata of a path inside a partition; symlinks are not followed
+#[derive(Debug, Serialize)]
+pub struct FileStat {
+    /// "file", "dir", "symlink" or "other"
+    pub kind: &'static str,
+    pub size: u64,
+    /// Permission bits
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub symlink_target: Option<String>,
+}
+
+/// One directory entry with its metadata
+#[derive(Debug, Serialize)]
+pub struct DirEntry {
+    pub name: String,
+    #[serde(flatten)]
+    pub stat: FileStat,
+}
+
+/// File contents for display, cut at `config::inspect::MAX_READ_BYTES`
+#[derive(Debug, Serialize)]
+pub struct FileContent {
+    /// UTF-8 text, with invalid sequences replaced
+    pub content: String,
+    /// Full size of the file
+    pub size: u64,
+    pub truncated: bool,
+    /// True when the file is not valid UTF-8 and the text is only a preview
+    pub binary: bool,
+}
+
+/// Device-tree overlays shipped in an image
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlayListing {
+    /// `overlay_prefix` from armbianEnv.txt, if set
+    pub prefix: Option<String>,
+    pub overlays: Vec<OverlayInfo>,
+}
+
+/// One `.dtbo` file
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlayInfo {
+    /// Name to list in `overlays=`, without the prefix
+    pub name: String,
+    /// Path inside its partition
+    pub path: String,
+    pub size: u64,
+    /// Parameters declared in `__overrides__`
+    pub params: Vec<String>,
+}
+
+impl From<awc::FileStat> for FileStat {
+    fn from(stat: awc::FileStat) -> Self {
+        Self {
+            kind: stat.kind.as_str(),
+            size: stat.len,
+            mode: stat.mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            symlink_target: stat.symlink_target,
+        }
+    }
+}
+
+fn inspect_error(e: WriteConfError) -> String {
+    format!("[INSPECT_FAILED] {}", e)
+}
+
+/// Partitions of an image file with their detected filesystems
+#[tauri::command]
+pub async fn inspect_image_partitions(image_path: String) -> Result<ImageLayout, String> {
+    log_debug!("inspect", "Listing partitions of {}", image_path);
+    let layout =
+        tokio::task::spawn_blocking(move || awc::list_partitions(&PathBuf::from(image_path)))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(inspect_error)?;
+
+    Ok(ImageLayout {
+        scheme: layout.scheme.as_str(),
+        sector_size: layout.sector_size,
+        partitions: layout
+            .partitions
+            .into_iter()
+            .map(|p| PartitionInfo {
+                index: p.index,
+                offset: p.offset,
+                size: p.len,
+                type_id: p.type_id,
+                name: p.name,
+                filesystem: p.filesystem.as_str(),
+            })
+            .collect(),
+    })
+}
+
+/// Entries of a directory in an ext4 partition, sorted by name
+#[tauri::command]
+pub async fn inspect_read_dir(
+    image_path: String,
+    partition: u32,
+    path: String,
+) -> Result<Vec<DirEntry>, String> {
+    let entries = tokio::task::spawn_blocking(move || {
+        awc::read_dir_in_partition(&PathBuf::from(image_path), partition, &path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(inspect_error)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|e| DirEntry {
+            name: e.name,
+            stat: e.stat.into(),
+        })
+        .collect())
+}
+
+/// Metadata of a path in an ext4 partition
+#[tauri::command]
+pub async fn inspect_stat(
+    image_path: String,
+    partition: u32,
+    path: String,
+) -> Result<FileStat, String> {
+    tokio::task::spawn_blocking(move || {
+        awc::stat_in_partition(&PathBuf::from(image_path), partition, &path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map(FileStat::from)
+    .map_err(inspect_error)
+}
+
+/// Contents of a file in an ext4 partition, e.g. `/etc/armbian-release`
+#[tauri::command]
+pub async fn inspect_read_file(
+    image_path: String,
+    partition: u32,
+    path: String,
+) -> Result<FileContent, String> {
+    let (bytes, size) = tokio::task::spawn_blocking(move || {
+        awc::read_file_in_partition(
+            &PathBuf::from(image_path),
+            partition,
+            &path,
+            config::inspect::MAX_READ_BYTES,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(inspect_error)?;
+    Ok(to_content(bytes, size))
+}
+
+/// Device-tree overlays an image ships, for the overlay picker; cached per image SHA256
+#[tauri::command]
+pub async fn inspect_image_overlays(image_path: String) -> Result<OverlayListing, String> {
+    tokio::task::spawn_blocking(move || list_overlays(&PathBuf::from(image_path)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn list_overlays(image_path: &Path) -> Result<OverlayListing, String> {
+    let sha = image_sha256(image_path).ok_or_else(|| {
+        format!(
+            "[INSPECT_FAILED] cannot read image {}",
+            image_path.display()
+        )
+    })?;
+    let cached = OVERLAY_CACHE
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .get(&sha)
+        .cloned();
+    if let Some(listing) = cached {
+        return Ok(listing);
+    }
+
+    log_debug!("inspect", "Listing overlays of {}", image_path.display());
+    let listing = awc::list_overlays_in_image(image_path).map_err(inspect_error)?;
+    let listing = OverlayListing {
+        prefix: listing.prefix,
+        overlays: listing
+            .overlays
+            .into_iter()
+            .map(|o| OverlayInfo {
+                name: o.name,
+                path: o.path,
+                size: o.size,
+                params: o.params,
+            })
+            .collect(),
+    };
+    OVERLAY_CACHE
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .insert(sha, listing.clone());
+    Ok(listing)
+}
+
+fn to_content(bytes: Vec<u8>, size: u64) -> FileContent {
+    let truncated = (bytes.len() as u64) < size;
+    let (content, binary) = match String::from_utf8(bytes) {
+        Ok(text) => (text, false),
+        // A cut in the middle of a multi-byte character is still text.
+        Err(e) => {
+            let cut = truncated && e.utf8_error().error_len().is_none();
+            (String::from_utf8_lossy(e.as_bytes()).into_owned(), !cut)
+        }
+    };
+    FileContent {
+        content,
+        size,
+        truncated,
+        binary,
+    }
+}
@@ -0,0 +1,81 @@
+//! Job listing, inspection and cancellation by ID.
+
+use serde::Serialize;
+use tauri::State;
+
+use crate::jobs::{Job, JobKind, JobProgressState, JobRecord};
+use crate::log_info;
+
+use super::progress::{download_progress, flash_progress, DownloadProgress, FlashProgress};
+use super::state::AppState;
+
+/// A job with its lifecycle and current progress
+#[derive(Debug, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub kind: JobKind,
+    /// Device path, URL or file the job works on
+    pub target: String,
+    /// Unix time in milliseconds
+    pub created_at: u64,
+    #[serde(flatten)]
+    pub record: JobRecord,
+    pub progress: JobProgress,
+}
+
+/// Download progress for download/decompress jobs, flash progress for flash jobs
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum JobProgress {
+    Download(DownloadProgress),
+    Flash(FlashProgress),
+}
+
+async fn job_info(job: &Job) -> JobInfo {
+    let progress = match &job.progress {
+        JobProgressState::Download(state) => JobProgress::Download(download_progress(state).await),
+        JobProgressState::Flash(state) => JobProgress::Flash(flash_progress(state).await),
+    };
+    JobInfo {
+        id: job.id,
+        kind: job.kind,
+        target: job.target.clone(),
+        created_at: job.created_at,
+        record: job.record(),
+        progress,
+    }
+}
+
+/// All known jobs, oldest first: unfinished ones and the most recent finished ones
+#[tauri::command]
+pub async fn list_jobs(state: State<'_, AppState>) -> Result<Vec<JobInfo>, String> {
+    let mut jobs = Vec::new();
+    for job in state.jobs.list() {
+        jobs.push(job_info(&job).await);
+    }
+    Ok(jobs)
+}
+
+/// One job by ID
+#[tauri::command]
+pub async fn get_job(id: u64, state: State<'_, AppState>) -> Result<JobInfo, String> {
+    let job = state
+        .jobs
+        .get(id)
+        .ok_or_else(|| format!("[JOB_NOT_FOUND] {}", id))?;
+    Ok(job_info(&job).await)
+}
+
+/// Cancel a queued or running job. Cancelling a finished job does nothing.
+#[tauri::command]
+pub async fn cancel_job(id: u64, state: State<'_, AppState>) -> Result<(), String> {
+    let job = state
+        .jobs
+        .get(id)
+        .ok_or_else(|| format!("[JOB_NOT_FOUND] {}", id))?;
+    if !job.status().is_finished() {
+        log_info!("jobs", "Cancelling job {}", id);
+        job.cancel();
+    }
+    Ok(())
+}
@@ -2,6 +2,9 @@
 
 pub mod board_queries;
 pub mod custom_image;
+pub mod history;
+pub mod inspect;
+pub mod jobs;
 pub mod operations;
 pub mod progress;
 pub mod qdl_operations;
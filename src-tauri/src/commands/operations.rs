@@ -1,15 +1,20 @@
 //! Download and flash operations.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use tauri::{AppHandle, State};
 use tauri_plugin_store::StoreExt;
 
-use armbian_write_conf::WriteConfError;
-
 use crate::autoconfig::AutoconfigConfig;
-use crate::download::download_image as do_download;
-use crate::flash::{flash_image as do_flash, request_authorization};
-use crate::utils::{app_cache_dir, images_dir, validate_cache_path};
+use crate::devices::{check_flash_target, get_block_devices, policy, watcher};
+use crate::download::{download_image as do_download, DownloadState, ExpectedSha};
+use crate::flash::checkpoint::{self, ImageIdentity, ResumableFlash};
+use crate::flash::multi::{flash_many, DeviceFlashResult, FlashTarget};
+use crate::flash::{flash_image as do_flash, request_authorization, FlashOptions, FlashState};
+use crate::history::{self, FlashContext, FlashedImage, ImageHasher, TargetInfo};
+use crate::jobs::{JobKind, JobProgressState};
+use crate::utils::{app_cache_dir, emit_phase, images_dir, phase, validate_cache_path};
 use crate::{log_debug, log_error, log_info, log_warn};
 
 use super::state::AppState;
@@ -48,11 +53,13 @@ pub async fn request_write_authorization(device_path: String) -> Result<bool, St
     result
 }
 
-/// Start downloading an image
+/// Start downloading an image. `client_ref` tags the job so the UI can pick out its
+/// events (see [`crate::jobs::JOB_QUEUED_EVENT`]).
 #[tauri::command]
 pub async fn download_image(
     file_url: String,
     sha_url: Option<String>,
+    client_ref: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
     log_info!("operations", "Starting download: {}", file_url);
@@ -67,8 +74,25 @@ pub async fn download_image(
     }
     let download_dir = images_dir();
 
-    let download_state = state.download_state.clone();
-    let result = do_download(&file_url, sha_url.as_deref(), &download_dir, download_state).await;
+    let download_state = Arc::new(DownloadState::new());
+    let job = state.jobs.create(
+        JobKind::Download,
+        &file_url,
+        JobProgressState::Download(download_state.clone()),
+        client_ref,
+    );
+    let result = state
+        .jobs
+        .run(
+            &job,
+            do_download(
+                &file_url,
+                sha_url.as_deref().map(ExpectedSha::Url),
+                &download_dir,
+                download_state,
+            ),
+        )
+        .await;
 
     match &result {
         Ok(path) => {
@@ -84,22 +108,36 @@ pub async fn download_image(
 
 /// Start flashing an image to a device. With `autoconfig` Some, injects the Armbian first-boot preset
 /// into a per-flash copy (original never mutated) and flashes that; None flashes the original directly.
+/// `resume` continues an interrupted flash from its checkpoint (see [`find_resumable_flash`]),
+/// never one with `autoconfig`, whose copy is rebuilt and so differs on every attempt;
+/// `delta` rewrites only the chunks that differ from what is already on the card.
+/// `context` (board, profile name) only goes into the flash history; `client_ref` tags
+/// the job as in [`download_image`].
+/// Missing, write-protected and (unless allowed in the settings) system disks are refused.
 #[tauri::command]
 pub async fn flash_image(
     image_path: String,
     device_path: String,
     verify: bool,
     autoconfig: Option<AutoconfigConfig>,
+    resume: Option<bool>,
+    delta: Option<bool>,
+    context: Option<FlashContext>,
+    client_ref: Option<String>,
     state: State<'_, AppState>,
-    _app: AppHandle,
+    app: AppHandle,
 ) -> Result<(), String> {
+    let resume = resume.unwrap_or(false);
+    let delta = delta.unwrap_or(false);
     log_info!(
         "operations",
-        "Starting flash: {} -> {} (verify: {}, autoconfig: {})",
+        "Starting flash: {} -> {} (verify: {}, autoconfig: {}, resume: {}, delta: {})",
         image_path,
         device_path,
         verify,
-        autoconfig.is_some()
+        autoconfig.is_some(),
+        resume,
+        delta
     );
     log_debug!(
         "operations",
@@ -113,26 +151,282 @@ pub async fn flash_image(
     );
     log_debug!("operations", "Verification enabled: {}", verify);
 
-    let path = PathBuf::from(&image_path);
-    let flash_state = state.flash_state.clone();
+    // Register the job before policy/copy/unmount work, so its job-queued event
+    // reaches the frontend before any progress or error of this flash.
+    let flash_state = Arc::new(FlashState::new());
+    let job = state.jobs.create(
+        JobKind::Flash,
+        &device_path,
+        JobProgressState::Flash(flash_state.clone()),
+        client_ref,
+    );
+    // Read the device identity now: after the flash it may be gone or re-enumerated.
+    let target = TargetInfo::block(&device_path);
+    let mut image = ImageHasher::new(Path::new(&image_path));
+    let flash = async {
+        let devices = get_block_devices()?;
+        check_flash_target(&devices, &device_path, allow_system_devices(&app))?;
+        policy::load(&app).authorize_flash(&device_path)?;
+
+        let path = PathBuf::from(&image_path);
+
+        // The injected copy gets fresh timestamps on every attempt, so the bytes already
+        // on the card need not match it. The checkpoint is still written: its profile
+        // hash keeps a plain flash of the same image from resuming over this one.
+        if resume && autoconfig.is_some() {
+            return Err(
+                "[RESUME_UNAVAILABLE] A flash with an autoconfig profile cannot be resumed"
+                    .to_string(),
+            );
+        }
 
-    // Reset shared progress before auth/copy/unmount: frontend polls on invoke, so without an early
-    // reset it reads the previous flash's stale state (is_verifying=true, verified=100%) and latches onto it.
-    flash_state.reset();
+        // Only the Linux writer knows its durable sync points, so only it checkpoints.
+        let checkpoint = if cfg!(target_os = "linux") {
+            let profile_hash = autoconfig.as_ref().map(crate::autoconfig::profile_hash);
+            checkpoint::prepare(&path, profile_hash, &device_path, resume)?
+        } else if resume {
+            return Err(
+                "[RESUME_UNAVAILABLE] Resuming a flash is only supported on Linux".to_string(),
+            );
+        } else {
+            None
+        };
 
-    // With a profile selected, flash a temp copy with the preset injected so the
-    // shared cached/decompressed image stays pristine.
-    let (flash_path, temp_copy) = match autoconfig {
-        Some(config) => {
-            let copy = prepare_autoconfig_copy(&path, &config)?;
-            (copy.clone(), Some(copy))
+        image.start();
+        // With a profile selected, flash a temp copy with the preset injected so the
+        // shared cached/decompressed image stays pristine.
+        let (flash_path, temp_copy) = match autoconfig {
+            Some(config) => {
+                emit_phase(job.id, phase::INJECT);
+                let copy = crate::autoconfig::prepare_injected_copy(&path, &config)?;
+                (copy.clone(), Some(copy))
+            }
+            None => (path, None),
+        };
+
+        // Bad-region probing writes extra data to a failing card, so it is opt-in.
+        let diagnose = match app.store("settings.json") {
+            Ok(store) => store
+                .get("flash_diagnostics")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            Err(_) => false,
+        };
+
+        let options = FlashOptions {
+            verify,
+            checkpoint,
+            diagnose,
+            delta,
+            ..Default::default()
+        };
+        let target_watch = watcher::watch_flash_target(&device_path, flash_state.clone());
+        let result = do_flash(&flash_path, &device_path, flash_state.clone(), options).await;
+        drop(target_watch);
+
+        // A write into a vanished device surfaces as an I/O error; report the real cause.
+        let result = match result {
+            Err(_) if flash_state.target_removed.load(Ordering::SeqCst) => {
+                Err(crate::flash::device_removed_err())
+            }
+            other => other,
+        };
+
+        // Always remove the temp copy, regardless of flash outcome.
+        if let Some(copy) = temp_copy {
+            if let Err(e) = std::fs::remove_file(&copy) {
+                log_warn!(
+                    "operations",
+                    "Failed to remove autoconfig temp copy {}: {}",
+                    copy.display(),
+                    e
+                );
+            }
+        }
+
+        match &result {
+            Ok(_) => {
+                log_info!("operations", "Flash completed successfully");
+            }
+            Err(e) => {
+                log_error!("operations", "Flash failed: {}", e);
+            }
         }
+
+        result
+    };
+    let result = state.jobs.run(&job, flash).await;
+    history::record_job(
+        &job,
+        &image.finish().await,
+        target,
+        &context.unwrap_or_default(),
+        verify,
+    );
+    result
+}
+
+/// Flash one image to several devices at once. The image is read once and shared;
+/// each device is a flash job of its own, with its own progress events, `cancel_job`
+/// and result, and a failing device does not stop the others. Targets are checked
+/// like in [`flash_image`].
+#[tauri::command]
+pub async fn flash_image_multi(
+    image_path: String,
+    device_paths: Vec<String>,
+    verify: bool,
+    autoconfig: Option<AutoconfigConfig>,
+    delta: Option<bool>,
+    context: Option<FlashContext>,
+    client_ref: Option<String>,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Vec<DeviceFlashResult>, String> {
+    let mut unique = Vec::with_capacity(device_paths.len());
+    for path in device_paths {
+        if !unique.contains(&path) {
+            unique.push(path);
+        }
+    }
+    let device_paths = unique;
+    if device_paths.is_empty() {
+        return Err("No devices selected".to_string());
+    }
+    let delta = delta.unwrap_or(false);
+    log_info!(
+        "operations",
+        "Starting multi-device flash: {} -> {} (verify: {}, autoconfig: {}, delta: {})",
+        image_path,
+        device_paths.join(", "),
+        verify,
+        autoconfig.is_some(),
+        delta
+    );
+
+    let targets: Vec<Arc<FlashTarget>> = device_paths
+        .iter()
+        .map(|path| Arc::new(FlashTarget::new(path)))
+        .collect();
+    // Register the jobs before the copy/unmount work, like a single flash does.
+    let jobs: Vec<_> = targets
+        .iter()
+        .map(|target| {
+            let progress = JobProgressState::Flash(target.state.clone());
+            state.jobs.create(
+                JobKind::Flash,
+                &target.device_path,
+                progress,
+                client_ref.clone(),
+            )
+        })
+        .collect();
+    let finish_job = |device_path: &str, result: Result<(), String>| {
+        if let Some(job) = jobs.iter().find(|job| job.target == device_path) {
+            job.finish(&result);
+        }
+    };
+    let devices = get_block_devices().unwrap_or_default();
+    let context = context.unwrap_or_default();
+    let mut image = ImageHasher::new(Path::new(&image_path));
+    let record_jobs = |image: &FlashedImage| {
+        for job in &jobs {
+            let target = TargetInfo::from_devices(&devices, &job.target);
+            history::record_job(job, image, target, &context, verify);
+        }
+    };
+
+    let mut skipped = Vec::new();
+    let mut skip = |target: &FlashTarget, e: String| {
+        if let Ok(mut error) = target.state.error.try_lock() {
+            *error = Some(e.clone());
+        }
+        target.finished.store(true, Ordering::SeqCst);
+        finish_job(&target.device_path, Err(e.clone()));
+        skipped.push(DeviceFlashResult {
+            device_path: target.device_path.clone(),
+            success: false,
+            verified: false,
+            error: Some(e),
+        });
+    };
+
+    // Denied devices fail straight away; the rest of the batch still runs.
+    let policy = policy::load(&app);
+    let allow_system = allow_system_devices(&app);
+    let mut permitted = Vec::new();
+    for target in &targets {
+        let checked = check_flash_target(&devices, &target.device_path, allow_system)
+            .and_then(|()| policy.authorize_flash(&target.device_path));
+        match checked {
+            Ok(()) => permitted.push(target.clone()),
+            Err(e) => skip(target, e),
+        }
+    }
+
+    // Wait for earlier jobs on these devices like a single flash does. Taking them
+    // in path order keeps two overlapping batches from waiting on each other.
+    permitted.sort_by(|a, b| a.device_path.cmp(&b.device_path));
+    let mut held = Vec::with_capacity(permitted.len());
+    let mut allowed: Vec<Arc<FlashTarget>> = Vec::with_capacity(permitted.len());
+    for target in permitted {
+        let Some(job) = jobs.iter().find(|job| job.target == target.device_path) else {
+            continue;
+        };
+        match state.jobs.begin(job).await {
+            Ok(guard) => {
+                held.push(guard);
+                allowed.push(target);
+            }
+            Err(e) => skip(&target, e),
+        }
+    }
+
+    let path = PathBuf::from(&image_path);
+    if !allowed.is_empty() {
+        image.start();
+    }
+    if autoconfig.is_some() {
+        for job in &jobs {
+            emit_phase(job.id, phase::INJECT);
+        }
+    }
+    let (flash_path, temp_copy) = match autoconfig {
+        Some(config) => match crate::autoconfig::prepare_injected_copy(&path, &config) {
+            Ok(copy) => (copy.clone(), Some(copy)),
+            Err(e) => {
+                for target in &allowed {
+                    finish_job(&target.device_path, Err(e.clone()));
+                }
+                record_jobs(&image.finish().await);
+                return Err(e);
+            }
+        },
         None => (path, None),
     };
 
-    let result = do_flash(&flash_path, &device_path, flash_state, verify).await;
+    let options = FlashOptions {
+        verify,
+        delta,
+        ..Default::default()
+    };
+    let target_watches: Vec<_> = allowed
+        .iter()
+        .map(|target| watcher::watch_flash_target(&target.device_path, target.state.clone()))
+        .collect();
+    let flashed = if allowed.is_empty() {
+        Vec::new()
+    } else {
+        flash_many(&flash_path, &allowed, options).await
+    };
+    drop(target_watches);
+    for result in &flashed {
+        finish_job(
+            &result.device_path,
+            result.error.clone().map_or(Ok(()), Err),
+        );
+    }
+    record_jobs(&image.finish().await);
 
-    // Always remove the temp copy, regardless of flash outcome.
     if let Some(copy) = temp_copy {
         if let Err(e) = std::fs::remove_file(&copy) {
             log_warn!(
@@ -144,67 +438,57 @@ pub async fn flash_image(
         }
     }
 
-    match &result {
-        Ok(_) => {
-            log_info!("operations", "Flash completed successfully");
-        }
-        Err(e) => {
-            log_error!("operations", "Flash failed: {}", e);
-        }
-    }
+    // Report in the order the devices were selected.
+    let mut results: Vec<DeviceFlashResult> = flashed.into_iter().chain(skipped).collect();
+    results.sort_by_key(|r| device_paths.iter().position(|p| *p == r.device_path));
+    drop(held);
+    Ok(results)
+}
 
-    result
+/// The `allow_system_devices` setting: whether system disks may be flashed.
+fn allow_system_devices(app: &AppHandle) -> bool {
+    app.store("settings.json")
+        .ok()
+        .and_then(|store| store.get("allow_system_devices"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
 }
 
-/// Copy the decompressed image to a per-flash temp file and inject the autoconfig preset into the copy.
-/// Aborts (deleting the copy) if the image has no writable ext4 rootfs, since a profile was requested.
-fn prepare_autoconfig_copy(
-    source: &std::path::Path,
-    config: &AutoconfigConfig,
-) -> Result<PathBuf, String> {
-    let temp_dir = app_cache_dir().join("autoconfig-temp");
-    std::fs::create_dir_all(&temp_dir)
-        .map_err(|e| format!("Failed to create autoconfig temp directory: {}", e))?;
-
-    // Unique per-flash name to avoid collisions across concurrent/repeat flashes.
-    let stem = source
-        .file_name()
-        .map(|n| n.to_string_lossy().to_string())
-        .unwrap_or_else(|| "image.img".to_string());
-    let unique = format!(
-        "{}.{}.{}",
-        std::process::id(),
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_nanos())
-            .unwrap_or(0),
-        stem
-    );
-    let copy_path = temp_dir.join(unique);
+/// Look for an interrupted flash of this image whose card is attached again.
+/// Returns where it can resume, or None when a fresh flash is needed, as it always
+/// is with `autoconfig` (see [`flash_image`]).
+#[tauri::command]
+pub async fn find_resumable_flash(
+    image_path: String,
+    autoconfig: Option<AutoconfigConfig>,
+) -> Result<Option<ResumableFlash>, String> {
+    if autoconfig.is_some() {
+        return Ok(None);
+    }
+    let identity = ImageIdentity::from_path(std::path::Path::new(&image_path), None)?;
 
-    log_info!(
-        "operations",
-        "Copying image for autoconfig injection: {} -> {}",
-        source.display(),
-        copy_path.display()
-    );
-    std::fs::copy(source, &copy_path)
-        .map_err(|e| format!("Failed to copy image for autoconfig: {}", e))?;
-
-    if let Err(e) = crate::autoconfig::inject_into_image(&copy_path, config) {
-        // Clean up the half-prepared copy before bubbling up.
-        let _ = std::fs::remove_file(&copy_path);
-        let message = match e {
-            WriteConfError::UnsupportedImage(_) | WriteConfError::NoExt4Rootfs(_) => format!(
-                "This image does not have a writable ext4 root filesystem, so the selected autoconfig profile cannot be applied: {}",
-                e
-            ),
-            other => format!("Failed to apply autoconfig profile: {}", other),
-        };
-        return Err(message);
+    let resumable = tokio::task::spawn_blocking(move || checkpoint::find_resumable(&identity))
+        .await
+        .map_err(|e| format!("Checkpoint lookup failed: {}", e))?;
+
+    if let Some(ref r) = resumable {
+        log_info!(
+            "operations",
+            "Found resumable flash on {} at byte {} of {}",
+            r.device_path,
+            r.durable_offset,
+            r.total_bytes
+        );
     }
+    Ok(resumable)
+}
 
-    Ok(copy_path)
+/// Forget the stored flash checkpoint (the user chose to start over).
+#[tauri::command]
+pub async fn discard_flash_checkpoint() -> Result<(), String> {
+    log_info!("operations", "Discarding flash checkpoint");
+    checkpoint::clear();
+    Ok(())
 }
 
 /// Force-delete a cached image (bypasses cache_enabled), for when a file looks corrupted
@@ -299,13 +583,33 @@ pub async fn delete_downloaded_image(image_path: String, app: AppHandle) -> Resu
 
 /// Finish a download that stalled on an unavailable SHA, reusing the downloaded file.
 #[tauri::command]
-pub async fn continue_download_without_sha(state: State<'_, AppState>) -> Result<String, String> {
+pub async fn continue_download_without_sha(
+    client_ref: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
     log_info!("operations", "Continuing download without SHA verification");
 
     let download_dir = images_dir();
-    let download_state = state.download_state.clone();
-
-    let result = crate::download::continue_without_sha(download_state, &download_dir).await;
+    let pending = state
+        .jobs
+        .latest(&[JobKind::Download])
+        .ok_or("No pending download to continue")?;
+    let download_state = pending.download_state().unwrap_or_default();
+
+    // The pending file lives in the failed download's state; finish it as a new job.
+    let job = state.jobs.create(
+        JobKind::Decompress,
+        &pending.target,
+        JobProgressState::Download(download_state.clone()),
+        client_ref,
+    );
+    let result = state
+        .jobs
+        .run(
+            &job,
+            crate::download::continue_without_sha(download_state, &download_dir),
+        )
+        .await;
 
     match &result {
         Ok(path) => {
@@ -323,6 +627,12 @@ pub async fn continue_download_without_sha(state: State<'_, AppState>) -> Result
 #[tauri::command]
 pub async fn cleanup_failed_download(state: State<'_, AppState>) -> Result<(), String> {
     log_info!("operations", "Cleaning up failed download");
-    crate::download::cleanup_pending_download(state.download_state.clone()).await;
+    if let Some(state) = state
+        .jobs
+        .latest(&[JobKind::Download])
+        .and_then(|job| job.download_state())
+    {
+        crate::download::cleanup_pending_download(state).await;
+    }
     Ok(())
 }
@@ -3,6 +3,10 @@
 use serde::{Deserialize, Serialize};
 use tauri::State;
 
+use crate::download::DownloadState;
+use crate::flash::FlashState;
+use crate::jobs::JobKind;
+
 use super::state::AppState;
 
 /// Download progress information
@@ -33,13 +37,37 @@ pub struct FlashProgress {
     pub partitions_total: u64,
     /// Number of partitions programmed so far in QDL mode
     pub partitions_written: u64,
+    /// Whether the current flash only writes chunks that differ (delta mode)
+    pub is_delta: bool,
+    /// Delta mode: bytes read back and compared with the image
+    pub compared_bytes: u64,
+    /// Delta mode: bytes already matching on the device, not rewritten
+    pub skipped_bytes: u64,
+    /// Read-compare throughput in MB/s (delta mode)
+    pub compare_speed_mbps: f64,
+    /// Throughput of actual device writes in MB/s, skipped chunks excluded
+    pub write_speed_mbps: f64,
+}
+
+/// MB/s for `bytes` processed in `micros` microseconds; 0 before anything was timed.
+fn throughput_mbps(bytes: u64, micros: u64) -> f64 {
+    if micros == 0 {
+        return 0.0;
+    }
+    crate::utils::bytes_to_mb(bytes) / (micros as f64 / 1_000_000.0)
 }
 
-/// Get current download progress
+/// Get current download progress. Kept for polling clients; the UI follows progress events.
 #[tauri::command]
 pub async fn get_download_progress(state: State<'_, AppState>) -> Result<DownloadProgress, String> {
-    let ds = &state.download_state;
+    let latest = state
+        .jobs
+        .latest(&[JobKind::Download, JobKind::Decompress])
+        .and_then(|job| job.download_state());
+    Ok(download_progress(&latest.unwrap_or_default()).await)
+}
 
+pub(super) async fn download_progress(ds: &DownloadState) -> DownloadProgress {
     let total = ds.total_bytes.load(std::sync::atomic::Ordering::SeqCst);
     let downloaded = ds
         .downloaded_bytes
@@ -59,21 +87,27 @@ pub async fn get_download_progress(state: State<'_, AppState>) -> Result<Downloa
 
     let error = ds.error.lock().await.clone();
 
-    Ok(DownloadProgress {
+    DownloadProgress {
         total_bytes: total,
         downloaded_bytes: downloaded,
         is_verifying_sha,
         is_decompressing,
         progress_percent: progress,
         error,
-    })
+    }
 }
 
-/// Get current flash progress
+/// Get current flash progress. Kept for polling clients; the UI follows progress events.
 #[tauri::command]
 pub async fn get_flash_progress(state: State<'_, AppState>) -> Result<FlashProgress, String> {
-    let fs = &state.flash_state;
+    let latest = state
+        .jobs
+        .latest(&[JobKind::Flash, JobKind::QdlFlash])
+        .and_then(|job| job.flash_state());
+    Ok(flash_progress(&latest.unwrap_or_default()).await)
+}
 
+pub(super) async fn flash_progress(fs: &FlashState) -> FlashProgress {
     let total = fs.total_bytes.load(std::sync::atomic::Ordering::SeqCst);
     let written = fs.written_bytes.load(std::sync::atomic::Ordering::SeqCst);
     let verified = fs.verified_bytes.load(std::sync::atomic::Ordering::SeqCst);
@@ -114,7 +148,20 @@ pub async fn get_flash_progress(state: State<'_, AppState>) -> Result<FlashProgr
         .partitions_written
         .load(std::sync::atomic::Ordering::SeqCst);
 
-    Ok(FlashProgress {
+    let is_delta = fs.is_delta.load(std::sync::atomic::Ordering::SeqCst);
+    let compared_bytes = fs.compared_bytes.load(std::sync::atomic::Ordering::SeqCst);
+    let skipped_bytes = fs.skipped_bytes.load(std::sync::atomic::Ordering::SeqCst);
+    let compare_speed_mbps = throughput_mbps(
+        compared_bytes,
+        fs.compare_micros.load(std::sync::atomic::Ordering::SeqCst),
+    );
+    let write_speed_mbps = throughput_mbps(
+        fs.device_written_bytes
+            .load(std::sync::atomic::Ordering::SeqCst),
+        fs.write_micros.load(std::sync::atomic::Ordering::SeqCst),
+    );
+
+    FlashProgress {
         total_bytes: total,
         written_bytes: written,
         verified_bytes: verified,
@@ -125,19 +172,29 @@ pub async fn get_flash_progress(state: State<'_, AppState>) -> Result<FlashProgr
         qdl_stage,
         partitions_total,
         partitions_written,
-    })
+        is_delta,
+        compared_bytes,
+        skipped_bytes,
+        compare_speed_mbps,
+        write_speed_mbps,
+    }
 }
 
-/// Cancel current operation
+/// Cancel the UI's running operations; kept for compatibility, the UI cancels its own
+/// job with `cancel_job` once it knows the id.
 #[tauri::command]
 pub async fn cancel_operation(state: State<'_, AppState>) -> Result<(), String> {
-    state
-        .download_state
-        .is_cancelled
-        .store(true, std::sync::atomic::Ordering::SeqCst);
-    state
-        .flash_state
-        .is_cancelled
-        .store(true, std::sync::atomic::Ordering::SeqCst);
+    state.jobs.cancel_client_jobs();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput_mbps() {
+        assert_eq!(throughput_mbps(1024 * 1024, 0), 0.0);
+        assert!((throughput_mbps(10 * 1024 * 1024, 500_000) - 20.0).abs() < 1e-9);
+    }
+}
@@ -1,8 +1,12 @@
 //! Tauri command handlers for QDL (Qualcomm EDL) device detection and flashing.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tauri::State;
 
+use crate::flash::FlashState;
+use crate::history::{self, FlashContext, ImageHasher, TargetInfo};
+use crate::jobs::{JobKind, JobProgressState};
 use crate::qdl;
 use crate::qdl::QdlDevice;
 use crate::utils::qdl_temp_dir;
@@ -23,56 +27,78 @@ pub async fn flash_qdl_image(
     tar_path: String,
     serial: Option<String>,
     autoconfig: Option<crate::autoconfig::AutoconfigConfig>,
+    context: Option<FlashContext>,
+    client_ref: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     log_info!("qdl_operations", "Starting QDL flash: {}", tar_path);
 
-    let flash_state = state.flash_state.clone();
-    flash_state.reset();
-
-    let tar_path = PathBuf::from(&tar_path);
-    let extract_dir = qdl_temp_dir();
-
-    let flash_dir = qdl::extract::extract_qdl_archive(&tar_path, &extract_dir).map_err(|e| {
-        log_error!("qdl_operations", "TAR extraction failed: {}", e);
-        e
-    })?;
-
-    log_info!(
-        "qdl_operations",
-        "Extracted flash files to: {}",
-        flash_dir.display()
+    let flash_state = Arc::new(FlashState::new());
+    let job = state.jobs.create(
+        JobKind::QdlFlash,
+        &qdl_target(serial.as_deref()),
+        JobProgressState::Flash(flash_state.clone()),
+        client_ref,
     );
+    let target = TargetInfo::edl(serial.as_deref());
+    let mut image = ImageHasher::new(Path::new(&tar_path));
+
+    let flash = async {
+        image.start();
+        let tar_path = PathBuf::from(&tar_path);
+        let extract_dir = qdl_temp_dir();
+
+        let flash_dir =
+            qdl::extract::extract_qdl_archive(&tar_path, &extract_dir).map_err(|e| {
+                log_error!("qdl_operations", "TAR extraction failed: {}", e);
+                e
+            })?;
+
+        log_info!(
+            "qdl_operations",
+            "Extracted flash files to: {}",
+            flash_dir.display()
+        );
+
+        // qdlrs is synchronous, so run the flash off the async runtime.
+        let flash_dir_clone = flash_dir.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            qdl::flash::qdl_flash(&flash_dir_clone, serial, autoconfig, flash_state)
+        })
+        .await
+        .map_err(|e| {
+            let msg = e.to_string();
+            // Tag error codes so the frontend can map them to i18n keys.
+            if msg.contains("Error sending data") || msg.contains("Error receiving data") {
+                "[QDL_DISCONNECTED]".to_string()
+            } else if msg.contains("cancelled") || msg.contains("Interrupted") {
+                "[QDL_CANCELLED]".to_string()
+            } else {
+                format!("[QDL_ERROR] {}", msg)
+            }
+        })?;
 
-    // qdlrs is synchronous, so run the flash off the async runtime.
-    let flash_dir_clone = flash_dir.clone();
-    let result = tokio::task::spawn_blocking(move || {
-        qdl::flash::qdl_flash(&flash_dir_clone, serial, autoconfig, flash_state)
-    })
-    .await
-    .map_err(|e| {
-        let msg = e.to_string();
-        // Tag error codes so the frontend can map them to i18n keys.
-        if msg.contains("Error sending data") || msg.contains("Error receiving data") {
-            "[QDL_DISCONNECTED]".to_string()
-        } else if msg.contains("cancelled") || msg.contains("Interrupted") {
-            "[QDL_CANCELLED]".to_string()
-        } else {
-            format!("[QDL_ERROR] {}", msg)
-        }
-    })?;
-
-    qdl::extract::cleanup_extraction(&extract_dir);
+        qdl::extract::cleanup_extraction(&extract_dir);
 
-    match &result {
-        Ok(()) => {
-            log_info!("qdl_operations", "QDL flash completed successfully");
+        match &result {
+            Ok(()) => {
+                log_info!("qdl_operations", "QDL flash completed successfully");
+            }
+            Err(e) => {
+                log_error!("qdl_operations", "QDL flash failed: {}", e);
+            }
         }
-        Err(e) => {
-            log_error!("qdl_operations", "QDL flash failed: {}", e);
-        }
-    }
 
+        result
+    };
+    let result = state.jobs.run(&job, flash).await;
+    history::record_job(
+        &job,
+        &image.finish().await,
+        target,
+        &context.unwrap_or_default(),
+        false,
+    );
     result
 }
 
@@ -86,55 +112,85 @@ pub async fn flash_qdl_ufs_image(
     board_slug: String,
     serial: Option<String>,
     autoconfig: Option<crate::autoconfig::AutoconfigConfig>,
+    context: Option<FlashContext>,
+    client_ref: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     log_info!("qdl_operations", "Starting QDL UFS flash: {}", image_path);
 
-    let flash_state = state.flash_state.clone();
-    flash_state.reset();
+    let flash_state = Arc::new(FlashState::new());
+    let job = state.jobs.create(
+        JobKind::QdlFlash,
+        &qdl_target(serial.as_deref()),
+        JobProgressState::Flash(flash_state.clone()),
+        client_ref,
+    );
+    let target = TargetInfo::edl(serial.as_deref());
+    let mut image = ImageHasher::new(Path::new(&image_path));
+
+    let flash = async {
+        let loader_path = qdl::loader::ensure_loader(&soc, &board_slug)
+            .await
+            .map_err(|e| {
+                log_error!("qdl_operations", "Firehose loader unavailable: {}", e);
+                e
+            })?;
+
+        // Provisioning descriptor for setting up a brand-new (unprovisioned) module.
+        let provision = qdl::provision::ensure_provision_xml(&soc, &board_slug).await;
+        if let qdl::provision::ProvisionSource::Unavailable(reason) = &provision {
+            log_warn!("qdl_operations", "Provision XML unavailable: {}", reason);
+        }
 
-    let loader_path = qdl::loader::ensure_loader(&soc, &board_slug)
+        image.start();
+        let image_path = PathBuf::from(&image_path);
+        // qdlrs is synchronous, so run the flash off the async runtime.
+        let result = tokio::task::spawn_blocking(move || {
+            qdl::flash::qdl_flash_ufs(
+                &image_path,
+                &loader_path,
+                serial,
+                autoconfig,
+                provision,
+                flash_state,
+            )
+        })
         .await
         .map_err(|e| {
-            log_error!("qdl_operations", "Firehose loader unavailable: {}", e);
-            e
+            let msg = e.to_string();
+            if msg.contains("Error sending data") || msg.contains("Error receiving data") {
+                "[QDL_DISCONNECTED]".to_string()
+            } else if msg.contains("cancelled") || msg.contains("Interrupted") {
+                "[QDL_CANCELLED]".to_string()
+            } else {
+                format!("[QDL_ERROR] {}", msg)
+            }
         })?;
 
-    // Provisioning descriptor for setting up a brand-new (unprovisioned) module.
-    let provision = qdl::provision::ensure_provision_xml(&soc, &board_slug).await;
-    if let qdl::provision::ProvisionSource::Unavailable(reason) = &provision {
-        log_warn!("qdl_operations", "Provision XML unavailable: {}", reason);
-    }
-
-    let image_path = PathBuf::from(&image_path);
-    // qdlrs is synchronous, so run the flash off the async runtime.
-    let result = tokio::task::spawn_blocking(move || {
-        qdl::flash::qdl_flash_ufs(
-            &image_path,
-            &loader_path,
-            serial,
-            autoconfig,
-            provision,
-            flash_state,
-        )
-    })
-    .await
-    .map_err(|e| {
-        let msg = e.to_string();
-        if msg.contains("Error sending data") || msg.contains("Error receiving data") {
-            "[QDL_DISCONNECTED]".to_string()
-        } else if msg.contains("cancelled") || msg.contains("Interrupted") {
-            "[QDL_CANCELLED]".to_string()
-        } else {
-            format!("[QDL_ERROR] {}", msg)
+        match &result {
+            Ok(()) => log_info!("qdl_operations", "QDL UFS flash completed successfully"),
+            Err(e) => log_error!("qdl_operations", "QDL UFS flash failed: {}", e),
         }
-    })?;
+        result
+    };
+    let result = state.jobs.run(&job, flash).await;
+    history::record_job(
+        &job,
+        &image.finish().await,
+        target,
+        &context.unwrap_or_default(),
+        false,
+    );
+    result
+}
 
-    match &result {
-        Ok(()) => log_info!("qdl_operations", "QDL UFS flash completed successfully"),
-        Err(e) => log_error!("qdl_operations", "QDL UFS flash failed: {}", e),
+/// Job target for an EDL flash: the requested device, or EDL as a whole when the
+/// flash takes whichever device is connected.
+fn qdl_target(serial: Option<&str>) -> String {
+    match serial {
+        Some(serial) => format!("edl:{}", serial),
+        None => "edl".to_string(),
     }
-    result
 }
 
 /// EDL-entry method ("button" or "jumper") for a board's on-screen hint, or null if unknown.
@@ -1,11 +1,9 @@
 //! Defines the shared application state used across commands.
 
-use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::download::DownloadState;
-use crate::flash::FlashState;
 use crate::images::{ApiBoardSummary, ApiVendor};
+use crate::jobs::JobManager;
 
 /// Application state shared across all commands
 pub struct AppState {
@@ -13,8 +11,8 @@ pub struct AppState {
     pub boards: Mutex<Option<Vec<ApiBoardSummary>>>,
     /// Cached vendor list from the REST API
     pub vendors: Mutex<Option<Vec<ApiVendor>>>,
-    pub download_state: Arc<DownloadState>,
-    pub flash_state: Arc<FlashState>,
+    /// Downloads, decompressions and flashes, each with its own progress state
+    pub jobs: JobManager,
 }
 
 impl Default for AppState {
@@ -22,8 +20,7 @@ impl Default for AppState {
         Self {
             boards: Mutex::new(None),
             vendors: Mutex::new(None),
-            download_state: Arc::new(DownloadState::new()),
-            flash_state: Arc::new(FlashState::new()),
+            jobs: JobManager::default(),
         }
     }
 }
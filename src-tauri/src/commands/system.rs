@@ -1,7 +1,7 @@
 //! Platform-specific system utilities: opening URLs, locale and Armbian detection.
 
+use crate::devices::ArmbianReleaseInfo;
 use crate::{log_debug, log_info, log_warn};
-use serde::{Deserialize, Serialize};
 use sys_locale::get_locale;
 
 const MODULE: &str = "commands::system";
@@ -222,13 +222,6 @@ pub async fn check_connectivity() -> bool {
 
 // Armbian System Detection
 
-/// Board identification read from /etc/armbian-release
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ArmbianReleaseInfo {
-    pub board: String,
-    pub board_name: String,
-}
-
 /// Parse /etc/armbian-release (Linux only); None when absent or unreadable
 #[tauri::command]
 pub fn get_armbian_release() -> Option<ArmbianReleaseInfo> {
@@ -251,40 +244,19 @@ pub fn get_armbian_release() -> Option<ArmbianReleaseInfo> {
             }
         };
 
-        let mut board = String::new();
-        let mut board_name = String::new();
-
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-
-            if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim();
-                let value = value.trim().trim_matches('"').trim_matches('\'');
-
-                match key {
-                    "BOARD" => board = value.to_string(),
-                    "BOARD_NAME" => board_name = value.to_string(),
-                    _ => {}
-                }
-            }
-        }
-
-        if board.is_empty() {
+        let Some(info) = ArmbianReleaseInfo::parse(&content) else {
             log_warn!(MODULE, "Invalid {}: missing BOARD field", path);
             return None;
-        }
+        };
 
         log_info!(
             MODULE,
             "Detected Armbian system: {} ({})",
-            board_name,
-            board
+            info.board_name,
+            info.board
         );
 
-        Some(ArmbianReleaseInfo { board, board_name })
+        Some(info)
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -55,6 +55,72 @@ pub mod flash {
 
     /// Delay after unmount before writing (milliseconds)
     pub const UNMOUNT_DELAY_MS: u64 = 500;
+
+    /// Diagnostics: size of each write/read-back probe (64 KB)
+    pub const DIAG_PROBE_SIZE: usize = 64 * 1024;
+
+    /// Diagnostics: bytes probed on each side of the failed range (4 MB)
+    pub const DIAG_PROBE_MARGIN: u64 = 4 * 1024 * 1024;
+
+    /// Diagnostics: give up probing after this long, a dying card can take seconds per I/O
+    pub const DIAG_TIME_LIMIT_SECS: u64 = 60;
+
+    /// Multi-device flashing: chunks queued per device ahead of its writer (8 x 4 MB)
+    pub const FANOUT_QUEUE_CHUNKS: usize = 8;
+}
+
+/// Device hotplug watcher settings
+pub mod devices {
+    /// Rescan interval where no hotplug notifications are available (milliseconds)
+    pub const WATCH_POLL_INTERVAL_MS: u64 = 2000;
+
+    /// Quiet time after a uevent before rescanning, so a disk and its partitions
+    /// coalesce into one rescan and udev has filled in its database (milliseconds)
+    pub const UEVENT_SETTLE_MS: i32 = 300;
+}
+
+/// Headless command-line mode settings
+pub mod cli {
+    /// Interval between JSON progress lines (milliseconds)
+    pub const PROGRESS_INTERVAL_MS: u64 = 500;
+}
+
+/// Progress events pushed to the UI
+pub mod progress {
+    /// Minimum interval between progress events of one job (milliseconds)
+    pub const EVENT_INTERVAL_MS: u64 = 250;
+    /// Weight of the newest sample in the moving-average speed (0..1)
+    pub const SPEED_SMOOTHING: f64 = 0.3;
+}
+
+/// Local JSON-RPC control API (opt-in via the `rpc_enabled` setting)
+pub mod rpc {
+    /// Port on 127.0.0.1 when the `rpc_port` setting is absent
+    pub const DEFAULT_PORT: u16 = 7650;
+
+    /// Largest accepted request header block (16 KB)
+    pub const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+    /// Largest accepted request body (1 MB)
+    pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+    /// Time a client gets to send its whole request (seconds)
+    pub const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+    /// Events buffered per `/events` subscriber before it starts missing some
+    pub const EVENT_BUFFER: usize = 256;
+
+    /// Keep-alive comment interval on `/events`, also how a gone client is noticed (seconds)
+    pub const SSE_KEEPALIVE_SECS: u64 = 15;
+
+    /// Interval at which a started download/flash is looked up for its job ID (milliseconds)
+    pub const JOB_POLL_MS: u64 = 50;
+}
+
+/// Operation manager settings
+pub mod jobs {
+    /// Finished jobs kept for listing; older ones are dropped as new jobs start
+    pub const FINISHED_JOBS_KEPT: usize = 50;
 }
 
 /// Log file management settings
@@ -122,3 +188,9 @@ pub mod cache {
     /// Default maximum cache size (20 GB)
     pub const DEFAULT_MAX_SIZE: u64 = 20 * 1024 * 1024 * 1024;
 }
+
+/// Image inspection settings
+pub mod inspect {
+    /// Largest file returned to the image file browser (1 MB)
+    pub const MAX_READ_BYTES: u64 = 1024 * 1024;
+}
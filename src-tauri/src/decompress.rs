@@ -16,7 +16,7 @@ use zstd::stream::read::Decoder as ZstdDecoder;
 use crate::config;
 use crate::download::DownloadState;
 use crate::log_info;
-use crate::utils::{get_recommended_threads, strip_compression_ext, ProgressTracker};
+use crate::utils::{get_recommended_threads, phase, strip_compression_ext, ProgressTracker};
 
 const MODULE: &str = "decompress";
 
@@ -126,7 +126,8 @@ fn decompress_with_reader_mt<R: Read>(
         MODULE,
         0,
         config::logging::DECOMPRESS_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::DECOMPRESS);
 
     loop {
         if state.is_cancelled.load(Ordering::SeqCst) {
@@ -1,118 +1,76 @@
-//! Linux device detection via lsblk.
+//! Linux device detection from sysfs and mountinfo (see [`super::sysfs`]).
 
-use std::fs;
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
 
-use crate::log_error;
 use crate::utils::format_size;
+use crate::{log_debug, log_error};
+
+use super::sysfs::{self, MountEntry};
+use super::types::{normalize_bus_type, ArmbianReleaseInfo, BlockDevice, PartitionInfo};
+
+const RELEASE_FILE: &str = "/etc/armbian-release";
 
-use super::types::{normalize_bus_type, BlockDevice};
+/// Existing-OS lookups by device path, with the card they were made against.
+/// Enumeration runs every couple of seconds and a raw ext4 read is not free, so a
+/// card is only re-read when it or its partitions change. The watcher drops the
+/// entry of a removed device.
+static EXISTING_OS: Lazy<Mutex<HashMap<String, OsCacheEntry>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
-/// Whether a device is write-protected, per /sys/block/{device}/ro.
-fn is_device_read_only(device_name: &str) -> bool {
-    let base_name = device_name
-        .trim_start_matches("/dev/")
-        .split('p')
-        .next()
-        .unwrap_or(device_name);
+type OsCacheEntry = (OsCacheKey, Option<ArmbianReleaseInfo>);
 
-    let ro_path = format!("/sys/block/{}/ro", base_name);
+/// The card an existing-OS lookup was made against. Partition UUIDs need udev, so
+/// without it the disk's size and serial and the partition sizes tell cards apart.
+#[derive(PartialEq)]
+struct OsCacheKey {
+    size: u64,
+    serial: Option<String>,
+    partitions: Vec<(u64, Option<String>)>,
+}
 
-    fs::read_to_string(&ro_path)
-        .map(|s| s.trim() == "1")
-        .unwrap_or(false)
+/// Whether a /sys/block entry is a disk we could flash (skips loop, zram, dm, md, sr
+/// and the eMMC boot/rpmb hardware partitions).
+fn is_candidate_disk(name: &str) -> bool {
+    let is_disk = ["sd", "hd", "vd", "nvme", "mmcblk"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix));
+    is_disk && !name.contains("boot") && !name.contains("rpmb")
 }
 
 /// Get list of block devices on Linux
 pub fn get_block_devices() -> Result<Vec<BlockDevice>, String> {
-    // JSON output parses reliably even when model names contain spaces.
-    let output = Command::new("lsblk")
-        .args(["-dpJo", "NAME,SIZE,MODEL,RM,HOTPLUG,TRAN", "-b"])
-        .output()
-        .map_err(|e| {
-            log_error!("devices", "Failed to run lsblk: {}", e);
-            format!("Failed to run lsblk: {}", e)
-        })?;
-
-    if !output.status.success() {
-        log_error!(
-            "devices",
-            "lsblk command failed with status: {:?}",
-            output.status
-        );
-        return Err("lsblk command failed".to_string());
-    }
+    let names = sysfs::disk_names().map_err(|e| {
+        log_error!("devices", "Failed to enumerate block devices: {}", e);
+        e
+    })?;
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mounts = sysfs::mounts();
+    let system_disks = get_system_disks(&mounts);
     let mut devices = Vec::new();
-    let system_disks = get_system_disks();
-
-    let json: serde_json::Value =
-        serde_json::from_str(&stdout).map_err(|e| format!("Failed to parse lsblk JSON: {}", e))?;
-
-    let blockdevices = json["blockdevices"]
-        .as_array()
-        .ok_or("Invalid lsblk JSON structure")?;
-
-    for dev in blockdevices {
-        let path = dev["name"].as_str().unwrap_or("");
-
-        // Only consider real disk device paths.
-        if !path.starts_with("/dev/sd")
-            && !path.starts_with("/dev/hd")
-            && !path.starts_with("/dev/vd")
-            && !path.starts_with("/dev/nvme")
-            && !path.starts_with("/dev/mmcblk")
-        {
-            continue;
-        }
 
-        // Skip mmcblk boot/rpmb partitions
-        if path.contains("boot") || path.contains("rpmb") {
+    for name in names.into_iter().filter(|n| is_candidate_disk(n)) {
+        // The disk may disappear between listing and reading it.
+        let Some(disk) = sysfs::read_disk(&name) else {
             continue;
-        }
-
-        let dev_name = path.strip_prefix("/dev/").unwrap_or(path);
-
-        // The disk backing the running root/boot mounts is always treated as system.
-        let is_running_system = system_disks
-            .iter()
-            .any(|sys| sys.starts_with(dev_name) || dev_name.starts_with(sys));
-
-        // lsblk JSON may encode size as a number or a string.
-        let size: u64 = match &dev["size"] {
-            serde_json::Value::Number(n) => n.as_u64().unwrap_or(0),
-            serde_json::Value::String(s) => s.parse().unwrap_or(0),
-            _ => 0,
         };
-        if size == 0 {
+        if disk.size == 0 {
             continue;
         }
 
-        let model = dev["model"].as_str().unwrap_or("").trim().to_string();
-
-        // RM is "1"/true when the device is removable.
-        let is_removable = match &dev["rm"] {
-            serde_json::Value::Bool(b) => *b,
-            serde_json::Value::String(s) => s == "1",
-            serde_json::Value::Number(n) => n.as_u64() == Some(1),
-            _ => false,
-        };
+        let path = format!("/dev/{}", name);
 
-        // Fallback signal for the unknown-bus case below.
-        let is_hotplug = match &dev["hotplug"] {
-            serde_json::Value::Bool(b) => *b,
-            serde_json::Value::String(s) => s == "1",
-            serde_json::Value::Number(n) => n.as_u64() == Some(1),
-            _ => false,
-        };
+        // The disk backing the running root/boot mounts is always treated as system.
+        let is_running_system = system_disks.contains(&name);
 
-        // Prefer the TRAN field, then infer the bus from the device path.
-        let tran = dev["tran"].as_str().unwrap_or("");
-        let bus_type = normalize_bus_type(tran).or_else(|| {
-            if path.contains("mmcblk") {
+        // Prefer the sysfs transport, then infer the bus from the device name.
+        let bus_type = normalize_bus_type(&disk.transport).or_else(|| {
+            if name.starts_with("mmcblk") {
                 Some("SD".to_string())
-            } else if path.contains("nvme") {
+            } else if name.starts_with("nvme") {
                 Some("NVMe".to_string())
             } else {
                 None
@@ -123,57 +81,179 @@ pub fn get_block_devices() -> Result<Vec<BlockDevice>, String> {
         let is_system = match bus_type.as_deref() {
             Some("USB") | Some("SD") => false,
             Some(_) => true,
-            None => !(is_removable || is_hotplug),
+            None => !(disk.removable || disk.hotplug),
         } || is_running_system;
 
-        let is_read_only = is_device_read_only(dev_name);
+        let partitions = read_partitions(&name, &mounts);
+        let key = OsCacheKey {
+            size: disk.size,
+            serial: disk.serial.clone(),
+            partitions: partitions
+                .iter()
+                .map(|p| (p.size, p.uuid.clone()))
+                .collect(),
+        };
+        let existing_os = existing_os(&path, key, &partitions);
 
         devices.push(BlockDevice {
-            path: path.to_string(),
-            name: dev_name.to_string(),
-            size,
-            size_formatted: format_size(size),
-            model,
-            is_removable,
+            path,
+            name,
+            size: disk.size,
+            size_formatted: format_size(disk.size),
+            model: disk.model,
+            is_removable: disk.removable,
             is_system,
             bus_type,
-            is_read_only,
+            is_read_only: disk.read_only,
+            vendor: disk.vendor,
+            serial: disk.serial,
+            partitions,
+            existing_os,
+            policy_denied: None,
         });
     }
 
     Ok(devices)
 }
 
-/// Block device names backing the root and boot mounts.
-fn get_system_disks() -> Vec<String> {
-    let mut system_disks = Vec::new();
-
-    for mount in &["/", "/boot", "/boot/efi"] {
-        if let Ok(output) = Command::new("findmnt")
-            .args(["-n", "-o", "SOURCE", mount])
-            .output()
-        {
-            let source = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !source.is_empty() {
-                if let Ok(pkname_output) = Command::new("lsblk")
-                    .args(["-no", "PKNAME", &source])
-                    .output()
-                {
-                    let pkname = String::from_utf8_lossy(&pkname_output.stdout)
-                        .trim()
-                        .to_string();
-                    if !pkname.is_empty() {
-                        system_disks.push(pkname);
-                    }
-                }
-                if let Some(name) = source.split('/').next_back() {
-                    system_disks.push(name.to_string());
-                }
+/// Whole disks backing the root and boot mounts, looking through partitions and
+/// device-mapper/md stacks (LVM, dm-crypt, RAID) down to the physical members.
+fn get_system_disks(mounts: &[MountEntry]) -> HashSet<String> {
+    ["/", "/boot", "/boot/efi"]
+        .iter()
+        .filter_map(|target| {
+            // The last entry for a mount point is the one currently visible.
+            mounts.iter().rev().find(|m| m.mount_point == *target)
+        })
+        .filter_map(sysfs::mount_device_name)
+        .flat_map(|name| sysfs::backing_disks(&name))
+        .collect()
+}
+
+fn read_partitions(disk: &str, mounts: &[MountEntry]) -> Vec<PartitionInfo> {
+    sysfs::partitions(disk)
+        .iter()
+        .filter_map(|name| sysfs::read_partition(disk, name))
+        .map(|part| {
+            // The first mount of a device is the one the user mounted; later ones are binds.
+            let mountpoint = part
+                .dev
+                .and_then(|dev| mounts.iter().find(|m| m.dev == dev))
+                .map(|m| m.mount_point.clone());
+            PartitionInfo {
+                path: format!("/dev/{}", part.name),
+                name: part.name,
+                size: part.size,
+                fs_type: part.fs_type,
+                label: part.label,
+                uuid: part.uuid,
+                mountpoint,
             }
+        })
+        .collect()
+}
+
+/// Armbian install on the device, cached until the card at `path` changes.
+fn existing_os(
+    path: &str,
+    key: OsCacheKey,
+    partitions: &[PartitionInfo],
+) -> Option<ArmbianReleaseInfo> {
+    let mut cache = EXISTING_OS.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some((cached_key, info)) = cache.get(path) {
+        if *cached_key == key {
+            return info.clone();
         }
     }
 
-    system_disks.sort();
-    system_disks.dedup();
-    system_disks
+    let info = read_existing_os(path, partitions);
+    cache.insert(path.to_string(), (key, info.clone()));
+    info
+}
+
+/// Forget the existing-OS lookup for a removed device, so the next card at the
+/// same path is read afresh.
+pub(crate) fn forget_existing_os(path: &str) {
+    EXISTING_OS
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .remove(path);
+}
+
+/// Read armbian-release from a mounted rootfs if there is one, otherwise straight
+/// from the ext4 on the raw device. Non-ext4 cards and unreadable devices (no
+/// permission on /dev before authorization) simply report nothing.
+fn read_existing_os(path: &str, partitions: &[PartitionInfo]) -> Option<ArmbianReleaseInfo> {
+    // udev knows the filesystem types; without it (containers) just try.
+    let has_udev = partitions.iter().any(|p| p.fs_type.is_some());
+    if partitions.is_empty()
+        || (has_udev
+            && !partitions
+                .iter()
+                .any(|p| p.fs_type.as_deref() == Some("ext4")))
+    {
+        return None;
+    }
+
+    let mounted = partitions
+        .iter()
+        .filter(|p| p.fs_type.as_deref() == Some("ext4"))
+        .filter_map(|p| p.mountpoint.as_deref())
+        .find_map(|mp| std::fs::read_to_string(Path::new(mp).join(&RELEASE_FILE[1..])).ok());
+
+    let content = match mounted {
+        Some(content) => content,
+        None => match armbian_write_conf::read_file_from_image(Path::new(path), RELEASE_FILE) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(e) => {
+                log_debug!("devices", "No armbian-release on {}: {}", path, e);
+                return None;
+            }
+        },
+    };
+
+    ArmbianReleaseInfo::parse(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_candidate_disk() {
+        assert!(is_candidate_disk("sda"));
+        assert!(is_candidate_disk("mmcblk0"));
+        assert!(is_candidate_disk("nvme0n1"));
+        assert!(!is_candidate_disk("mmcblk0boot0"));
+        assert!(!is_candidate_disk("mmcblk0rpmb"));
+        assert!(!is_candidate_disk("loop0"));
+        assert!(!is_candidate_disk("dm-0"));
+        assert!(!is_candidate_disk("zram0"));
+    }
+
+    #[test]
+    fn existing_os_lookup_follows_the_card() {
+        let path = "/dev/imager-test-card";
+        let key = |serial: &str| OsCacheKey {
+            size: 8 << 30,
+            serial: Some(serial.to_string()),
+            partitions: vec![(8 << 30, None)],
+        };
+        let cached = |serial: &str| {
+            EXISTING_OS
+                .lock()
+                .unwrap()
+                .get(path)
+                .is_some_and(|(k, _)| *k == key(serial))
+        };
+
+        existing_os(path, key("A"), &[]);
+        assert!(cached("A"));
+        // Same path, same (UUID-less) layout, another card: looked up again.
+        existing_os(path, key("B"), &[]);
+        assert!(cached("B"));
+
+        forget_existing_os(path);
+        assert!(EXISTING_OS.lock().unwrap().get(path).is_none());
+    }
 }
@@ -69,6 +69,7 @@ mod da {
         pub static kDADiskDescriptionMediaWholeKey: CFStringRef;
         pub static kDADiskDescriptionMediaIconKey: CFStringRef;
         pub static kDADiskDescriptionDeviceModelKey: CFStringRef;
+        pub static kDADiskDescriptionDeviceVendorKey: CFStringRef;
         pub static kDADiskDescriptionMediaContentKey: CFStringRef;
     }
 
@@ -189,6 +190,9 @@ unsafe fn process_disk(disk: da::DADiskRef) -> Option<BlockDevice> {
     let is_writable = da::get_bool(&desc, da::kDADiskDescriptionMediaWritableKey).unwrap_or(true);
     let media_name = da::get_string(&desc, da::kDADiskDescriptionMediaNameKey).unwrap_or_default();
     let model = da::get_string(&desc, da::kDADiskDescriptionDeviceModelKey).unwrap_or_default();
+    let vendor = da::get_string(&desc, da::kDADiskDescriptionDeviceVendorKey)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
 
     // Bus type from protocol, falling back to media name then icon resource.
     let bus_type = normalize_bus_type(&protocol)
@@ -211,6 +215,11 @@ unsafe fn process_disk(disk: da::DADiskRef) -> Option<BlockDevice> {
         is_system: is_internal && !is_removable,
         bus_type,
         is_read_only: !is_writable,
+        vendor,
+        serial: None,
+        partitions: Vec::new(),
+        existing_os: None,
+        policy_denied: None,
     })
 }
 
@@ -1,23 +1,56 @@
 //! Platform-specific block device detection.
 
+pub mod policy;
 mod types;
+pub mod watcher;
 
 #[cfg(target_os = "macos")]
 mod macos;
 
 #[cfg(target_os = "linux")]
 mod linux;
+#[cfg(target_os = "linux")]
+pub(crate) mod sysfs;
 
 #[cfg(target_os = "windows")]
 mod windows;
 
-pub use types::BlockDevice;
+pub use types::{ArmbianReleaseInfo, BlockDevice};
 
 #[cfg(target_os = "macos")]
 pub use macos::get_block_devices;
 
+#[cfg(target_os = "linux")]
+pub(crate) use linux::forget_existing_os;
 #[cfg(target_os = "linux")]
 pub use linux::get_block_devices;
 
 #[cfg(target_os = "windows")]
 pub use windows::get_block_devices;
+
+/// Refuse a flash to `device_path` unless it is a listed disk that is not
+/// write-protected and, unless `allow_system` (the `allow_system_devices`
+/// setting) is set, not a system disk.
+pub fn check_flash_target(
+    devices: &[BlockDevice],
+    device_path: &str,
+    allow_system: bool,
+) -> Result<(), String> {
+    let device = devices
+        .iter()
+        .find(|d| d.path == device_path)
+        .ok_or_else(|| format!("[DEVICE_NOT_FOUND] {} is not a flashable disk", device_path))?;
+    if device.is_read_only {
+        return Err(format!(
+            "[DEVICE_READ_ONLY] {} is write-protected",
+            device_path
+        ));
+    }
+    if device.is_system && !allow_system {
+        return Err(format!(
+            "[DEVICE_SYSTEM] {} is a system disk, allow system disks in the settings to flash it",
+            device_path
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,437 @@
+//! Device policy for flashing stations: allow/deny rules matched against a device's
+//! serial, model, vendor, bus, size or /dev/disk/by-id name. An admin-managed policy
+//! file takes precedence over the `device_policy` setting, so a lab machine cannot be
+//! unlocked from the UI. First matching rule wins; `default` applies otherwise.
+
+use std::path::Path;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use super::{get_block_devices, BlockDevice};
+use crate::utils::system_policy_path;
+use crate::{log_debug, log_error, log_info};
+
+const MODULE: &str = "devices::policy";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// One rule. Every criterion that is set must match; text criteria are
+/// case-insensitive globs (`*`, `?`), sizes are in bytes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DeviceRule {
+    pub action: RuleAction,
+    #[serde(default)]
+    pub serial: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub vendor: Option<String>,
+    /// Normalized bus type ("USB", "SD", "SATA", "NVMe", "SAS")
+    #[serde(default)]
+    pub bus: Option<String>,
+    #[serde(default)]
+    pub min_size: Option<u64>,
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Name under /dev/disk/by-id (Linux only)
+    #[serde(default)]
+    pub by_id: Option<String>,
+    /// Shown to the user when this rule denies a device
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DevicePolicy {
+    #[serde(default)]
+    pub rules: Vec<DeviceRule>,
+    /// Action when no rule matches; `deny` turns the rules into an allowlist
+    #[serde(default)]
+    pub default: RuleAction,
+    /// Leave denied devices out of the list instead of showing them locked
+    #[serde(default)]
+    pub hide_denied: bool,
+    /// Where the policy came from, for the logs
+    #[serde(skip)]
+    pub source: String,
+}
+
+/// Outcome of evaluating a device against the policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyDecision {
+    pub allowed: bool,
+    pub reason: String,
+}
+
+static POLICY: Lazy<RwLock<DevicePolicy>> = Lazy::new(|| RwLock::new(DevicePolicy::default()));
+
+impl DeviceRule {
+    /// Whether the rule sets any criterion; one without would match every disk.
+    pub fn has_criteria(&self) -> bool {
+        self.serial.is_some()
+            || self.model.is_some()
+            || self.vendor.is_some()
+            || self.bus.is_some()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.by_id.is_some()
+    }
+
+    fn matches(&self, device: &BlockDevice, by_id: &[String]) -> bool {
+        let text = |pattern: &Option<String>, value: Option<&str>| match pattern {
+            None => true,
+            Some(p) => value.is_some_and(|v| glob_match(p.trim(), v.trim())),
+        };
+
+        text(&self.serial, device.serial.as_deref())
+            && text(&self.model, Some(&device.model))
+            && text(&self.vendor, device.vendor.as_deref())
+            && text(&self.bus, device.bus_type.as_deref())
+            && self.min_size.is_none_or(|min| device.size >= min)
+            && self.max_size.is_none_or(|max| device.size <= max)
+            && self.by_id.as_ref().is_none_or(|p| {
+                let p = p.trim().trim_start_matches("/dev/disk/by-id/");
+                by_id.iter().any(|name| glob_match(p, name))
+            })
+    }
+}
+
+impl DevicePolicy {
+    /// Policy that denies everything, used when a policy file exists but is invalid:
+    /// a station with a broken policy must not fall back to flashing anything.
+    fn deny_all(source: String, reason: String) -> Self {
+        Self {
+            rules: Vec::new(),
+            default: RuleAction::Deny,
+            hide_denied: false,
+            source: format!("{} ({})", source, reason),
+        }
+    }
+
+    pub fn evaluate(&self, device: &BlockDevice) -> PolicyDecision {
+        let by_id = if self.rules.iter().any(|r| r.by_id.is_some()) {
+            by_id_names(&device.path)
+        } else {
+            Vec::new()
+        };
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.matches(device, &by_id) {
+                return PolicyDecision {
+                    allowed: rule.action == RuleAction::Allow,
+                    reason: rule
+                        .reason
+                        .clone()
+                        .unwrap_or_else(|| format!("matched rule {}", index + 1)),
+                };
+            }
+        }
+
+        PolicyDecision {
+            allowed: self.default == RuleAction::Allow,
+            reason: match self.default {
+                RuleAction::Allow => "no rule matched".to_string(),
+                RuleAction::Deny => "not on the device allowlist".to_string(),
+            },
+        }
+    }
+
+    /// Decision for flashing `device_path`. A device missing from the scan cannot
+    /// be matched against any rule, so the default action decides.
+    pub fn evaluate_path(&self, device_path: &str) -> PolicyDecision {
+        let device = get_block_devices()
+            .ok()
+            .and_then(|devices| devices.into_iter().find(|d| d.path == device_path));
+
+        match device {
+            Some(device) => self.evaluate(&device),
+            None => PolicyDecision {
+                allowed: self.default == RuleAction::Allow,
+                reason: format!(
+                    "{} not found, default {:?} applies",
+                    device_path, self.default
+                ),
+            },
+        }
+    }
+
+    /// Check a flash attempt against the policy, logging the decision either way.
+    pub fn authorize_flash(&self, device_path: &str) -> Result<(), String> {
+        let decision = self.evaluate_path(device_path);
+        let verdict = if decision.allowed {
+            "allowed"
+        } else {
+            "denied"
+        };
+        let source = if self.source.is_empty() {
+            "none"
+        } else {
+            &self.source
+        };
+        log_info!(
+            MODULE,
+            "Flash to {} {} ({}; policy: {})",
+            device_path,
+            verdict,
+            decision.reason,
+            source
+        );
+
+        if decision.allowed {
+            Ok(())
+        } else {
+            Err(format!("[POLICY_DENIED] {}", decision.reason))
+        }
+    }
+
+    /// Hide or lock the devices the policy denies.
+    pub fn apply(&self, devices: Vec<BlockDevice>) -> Vec<BlockDevice> {
+        if self.rules.is_empty() && self.default == RuleAction::Allow {
+            return devices;
+        }
+
+        devices
+            .into_iter()
+            .filter_map(|mut device| {
+                let decision = self.evaluate(&device);
+                if decision.allowed {
+                    return Some(device);
+                }
+                if self.hide_denied {
+                    return None;
+                }
+                device.policy_denied = Some(decision.reason);
+                Some(device)
+            })
+            .collect()
+    }
+}
+
+/// Reload the policy (policy file first, then the `device_policy` setting) and make
+/// it the one the device watcher applies.
+pub fn load(app: &AppHandle) -> DevicePolicy {
+    let setting = app
+        .store("settings.json")
+        .ok()
+        .and_then(|store| store.get("device_policy"));
+    reload(setting)
+}
+
+/// Reload the policy from the policy file, falling back to `setting` (the value of
+/// the `device_policy` setting, absent outside the GUI).
+pub fn reload(setting: Option<serde_json::Value>) -> DevicePolicy {
+    let path = system_policy_path();
+    let policy = if path.exists() {
+        read_policy_file(&path)
+    } else {
+        setting
+            .filter(|v| !v.is_null())
+            .map(parse_policy_setting)
+            .unwrap_or_default()
+    };
+
+    *POLICY.write().unwrap_or_else(|p| p.into_inner()) = policy.clone();
+    policy
+}
+
+/// The policy as last loaded.
+pub fn current() -> DevicePolicy {
+    POLICY.read().unwrap_or_else(|p| p.into_inner()).clone()
+}
+
+fn parse_policy_setting(value: serde_json::Value) -> DevicePolicy {
+    let source = "device_policy setting".to_string();
+    match serde_json::from_value::<DevicePolicy>(value) {
+        Ok(policy) => DevicePolicy { source, ..policy },
+        Err(e) => {
+            log_error!(MODULE, "Invalid device_policy setting: {}", e);
+            DevicePolicy::deny_all(source, "invalid".to_string())
+        }
+    }
+}
+
+fn read_policy_file(path: &Path) -> DevicePolicy {
+    let source = path.display().to_string();
+    let parsed = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            serde_json::from_str::<DevicePolicy>(&content).map_err(|e| e.to_string())
+        });
+
+    match parsed {
+        Ok(policy) => {
+            log_debug!(
+                MODULE,
+                "Loaded {} device rule(s) from {}",
+                policy.rules.len(),
+                source
+            );
+            DevicePolicy { source, ..policy }
+        }
+        Err(e) => {
+            log_error!(MODULE, "Invalid device policy {}: {}", source, e);
+            DevicePolicy::deny_all(source, "invalid".to_string())
+        }
+    }
+}
+
+/// Names under /dev/disk/by-id that resolve to `device_path`.
+#[cfg(target_os = "linux")]
+fn by_id_names(device_path: &str) -> Vec<String> {
+    let Ok(target) = std::fs::canonicalize(device_path) else {
+        return Vec::new();
+    };
+    std::fs::read_dir("/dev/disk/by-id")
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| std::fs::canonicalize(e.path()).is_ok_and(|p| p == target))
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn by_id_names(_device_path: &str) -> Vec<String> {
+    Vec::new()
+}
+
+/// Case-insensitive glob match supporting `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, mark)) = backtrack {
+            // Let the last `*` swallow one more character.
+            pi = star + 1;
+            ti = mark + 1;
+            backtrack = Some((star, mark + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(serial: Option<&str>, model: &str, bus: &str, size: u64) -> BlockDevice {
+        BlockDevice {
+            path: "/dev/sdz".to_string(),
+            name: "sdz".to_string(),
+            size,
+            size_formatted: String::new(),
+            model: model.to_string(),
+            is_removable: true,
+            is_system: false,
+            bus_type: Some(bus.to_string()),
+            is_read_only: false,
+            vendor: Some("Generic".to_string()),
+            serial: serial.map(str::to_string),
+            partitions: Vec::new(),
+            existing_os: None,
+            policy_denied: None,
+        }
+    }
+
+    fn policy(json: &str) -> DevicePolicy {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("STORAGE*", "storage device"));
+        assert!(glob_match("*1206", "000000001206"));
+        assert!(glob_match("sd?", "SDB"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(!glob_match("sd?", "sdbb"));
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let p = policy(
+            r#"{"rules": [
+                {"action": "deny", "serial": "000000001206", "reason": "Lab backup disk"},
+                {"action": "allow", "bus": "usb"}
+            ]}"#,
+        );
+        let backup = device(Some("000000001206"), "STORAGE DEVICE", "USB", 32 << 30);
+        let card = device(Some("000000001207"), "STORAGE DEVICE", "USB", 32 << 30);
+
+        assert_eq!(
+            p.evaluate(&backup),
+            PolicyDecision {
+                allowed: false,
+                reason: "Lab backup disk".to_string()
+            }
+        );
+        assert!(p.evaluate(&card).allowed);
+    }
+
+    #[test]
+    fn test_allowlist_with_size_range() {
+        let p = policy(
+            r#"{"default": "deny", "rules": [
+                {"action": "allow", "bus": "SD", "max_size": 137438953472}
+            ]}"#,
+        );
+        assert!(p.evaluate(&device(None, "SD64G", "SD", 64 << 30)).allowed);
+        assert!(!p.evaluate(&device(None, "SD256G", "SD", 256 << 30)).allowed);
+        assert!(!p.evaluate(&device(None, "Disk", "USB", 64 << 30)).allowed);
+    }
+
+    #[test]
+    fn test_missing_serial_does_not_match() {
+        let p = policy(r#"{"rules": [{"action": "deny", "serial": "*"}]}"#);
+        assert!(p.evaluate(&device(None, "Disk", "USB", 1 << 30)).allowed);
+        assert!(!p.evaluate(&device(Some("X1"), "Disk", "USB", 1)).allowed);
+    }
+
+    #[test]
+    fn test_apply_locks_or_hides() {
+        let json = r#"{"rules": [{"action": "deny", "model": "Backup*"}]}"#;
+        let devices = || {
+            vec![
+                device(None, "Backup Drive", "USB", 1 << 40),
+                device(None, "SD Card", "SD", 32 << 30),
+            ]
+        };
+
+        let locked = policy(json).apply(devices());
+        assert_eq!(locked.len(), 2);
+        assert_eq!(locked[0].policy_denied.as_deref(), Some("matched rule 1"));
+        assert!(locked[1].policy_denied.is_none());
+
+        let hidden = DevicePolicy {
+            hide_denied: true,
+            ..policy(json)
+        }
+        .apply(devices());
+        assert_eq!(hidden.len(), 1);
+        assert_eq!(hidden[0].model, "SD Card");
+    }
+}
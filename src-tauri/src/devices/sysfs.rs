@@ -0,0 +1,445 @@
+//! Native Linux block device discovery from sysfs, the udev database and
+//! `/proc/self/mountinfo`, so enumeration works without lsblk/findmnt on PATH
+//! (minimal containers, AppImage with an unusual environment).
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+const SYS_BLOCK: &str = "/sys/block";
+const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+const SYS_DEV_BLOCK: &str = "/sys/dev/block";
+const UDEV_DATA: &str = "/run/udev/data";
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Whole-disk attributes read from `/sys/block/<name>`.
+#[derive(Debug, Clone, Default)]
+pub struct DiskInfo {
+    pub name: String,
+    /// Size in bytes (sysfs always counts 512-byte sectors)
+    pub size: u64,
+    pub removable: bool,
+    /// Sits behind a hot-pluggable port (lsblk's HOTPLUG)
+    pub hotplug: bool,
+    pub read_only: bool,
+    pub model: String,
+    pub vendor: Option<String>,
+    pub serial: Option<String>,
+    /// Transport in lsblk's terms (usb, mmc, nvme, sata, sas), empty if unknown
+    pub transport: String,
+}
+
+/// Partition attributes from sysfs, with filesystem details from the udev database.
+#[derive(Debug, Clone, Default)]
+pub struct PartInfo {
+    pub name: String,
+    pub dev: Option<(u32, u32)>,
+    /// Size in bytes
+    pub size: u64,
+    pub fs_type: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+}
+
+/// One entry of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub dev: (u32, u32),
+    pub mount_point: String,
+    pub source: String,
+}
+
+/// Trimmed contents of a sysfs attribute; None when missing or empty.
+fn read_attr(path: impl AsRef<Path>) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Entry names of a sysfs directory (`holders/`, `slaves/`); empty when absent.
+fn list_dir(path: impl AsRef<Path>) -> Vec<String> {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_dev(value: &str) -> Option<(u32, u32)> {
+    let (major, minor) = value.trim().split_once(':')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Names of all whole disks the kernel knows about.
+pub fn disk_names() -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = fs::read_dir(SYS_BLOCK)
+        .map_err(|e| format!("Failed to read {}: {}", SYS_BLOCK, e))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// `major:minor` of a block device (disk or partition) by kernel name.
+pub fn dev_of(name: &str) -> Option<(u32, u32)> {
+    parse_dev(&read_attr(
+        Path::new(SYS_CLASS_BLOCK).join(name).join("dev"),
+    )?)
+}
+
+/// Kernel name of the block device with the given `major:minor`.
+pub fn name_of_dev(dev: (u32, u32)) -> Option<String> {
+    let link = Path::new(SYS_DEV_BLOCK).join(format!("{}:{}", dev.0, dev.1));
+    let real = fs::canonicalize(link).ok()?;
+    Some(real.file_name()?.to_string_lossy().to_string())
+}
+
+/// Parse the `E:KEY=VALUE` property lines of a udev database entry.
+pub fn parse_udev_db(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("E:"))
+        .filter_map(|prop| prop.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// udev properties of a block device; empty when udev is not running (containers).
+pub fn udev_properties(dev: (u32, u32)) -> HashMap<String, String> {
+    fs::read_to_string(Path::new(UDEV_DATA).join(format!("b{}:{}", dev.0, dev.1)))
+        .map(|content| parse_udev_db(&content))
+        .unwrap_or_default()
+}
+
+/// Read a whole disk's attributes, None if it vanished mid-enumeration.
+pub fn read_disk(name: &str) -> Option<DiskInfo> {
+    let base = Path::new(SYS_BLOCK).join(name);
+    let sectors: u64 = read_attr(base.join("size"))?.parse().ok()?;
+    let real = fs::canonicalize(&base).ok()?;
+    let udev = dev_of(name).map(udev_properties).unwrap_or_default();
+
+    // SCSI/NVMe expose `model`, MMC/SD cards their CID product `name`.
+    let model = read_attr(base.join("device/model"))
+        .or_else(|| read_attr(base.join("device/name")))
+        .or_else(|| udev.get("ID_MODEL").map(|m| m.replace('_', " ")))
+        .unwrap_or_default();
+
+    // SCSI (USB readers, SATA) exposes a `vendor` string; virtio has a PCI id there
+    // and MMC only a numeric manfid, neither of which means anything to a user.
+    let vendor = read_attr(base.join("device/vendor"))
+        .filter(|v| !v.starts_with("0x"))
+        .or_else(|| udev.get("ID_VENDOR").map(|v| v.replace('_', " ")));
+
+    let serial = udev
+        .get("ID_SERIAL_SHORT")
+        .cloned()
+        .or_else(|| read_serial(name));
+
+    let path = real.to_string_lossy();
+    let transport = if name.starts_with("mmcblk") {
+        "mmc"
+    } else if name.starts_with("nvme") {
+        "nvme"
+    } else if path.contains("/usb") {
+        "usb"
+    } else if path.contains("/end_device-") {
+        "sas"
+    } else if path.contains("/ata") {
+        "sata"
+    } else {
+        match udev.get("ID_BUS").map(String::as_str) {
+            Some("usb") => "usb",
+            Some("ata") => "sata",
+            _ => "",
+        }
+    }
+    .to_string();
+
+    Some(DiskInfo {
+        name: name.to_string(),
+        size: sectors * 512,
+        removable: read_attr(base.join("removable")).as_deref() == Some("1"),
+        hotplug: is_hotplug(&real),
+        read_only: read_attr(base.join("ro")).as_deref() == Some("1"),
+        model,
+        vendor,
+        serial,
+        transport,
+    })
+}
+
+/// Whether any ancestor of the device reports itself as on a removable port.
+fn is_hotplug(real: &Path) -> bool {
+    real.ancestors()
+        .take_while(|p| p.starts_with("/sys/devices"))
+        .any(|p| read_attr(p.join("removable")).as_deref() == Some("removable"))
+}
+
+/// Serial number from sysfs: the disk's own `device/serial` (MMC, NVMe) or the
+/// nearest ancestor that has one (the USB device for card readers and sticks).
+pub fn read_serial(name: &str) -> Option<String> {
+    let device_dir = fs::canonicalize(Path::new(SYS_BLOCK).join(name).join("device")).ok()?;
+    device_dir
+        .ancestors()
+        .take_while(|p| p.starts_with("/sys/devices"))
+        .find_map(|p| read_attr(p.join("serial")))
+}
+
+/// Partition names of a disk (sda1, mmcblk0p1, nvme0n1p2).
+pub fn partitions(disk: &str) -> Vec<String> {
+    let base = Path::new(SYS_BLOCK).join(disk);
+    let mut parts: Vec<String> = list_dir(&base)
+        .into_iter()
+        .filter(|entry| base.join(entry).join("partition").exists())
+        .collect();
+    parts.sort();
+    parts
+}
+
+/// Read a partition of `disk`, None if it vanished mid-enumeration.
+pub fn read_partition(disk: &str, name: &str) -> Option<PartInfo> {
+    let sectors: u64 = read_attr(Path::new(SYS_BLOCK).join(disk).join(name).join("size"))?
+        .parse()
+        .ok()?;
+    let dev = dev_of(name);
+    let udev = dev.map(udev_properties).unwrap_or_default();
+
+    // The _ENC variant keeps spaces and non-ASCII that ID_FS_LABEL replaces with '_'.
+    let label = udev
+        .get("ID_FS_LABEL_ENC")
+        .map(|l| unescape_udev(l.as_str()))
+        .or_else(|| udev.get("ID_FS_LABEL").cloned())
+        .filter(|l| !l.is_empty());
+
+    Some(PartInfo {
+        name: name.to_string(),
+        dev,
+        size: sectors * 512,
+        fs_type: udev.get("ID_FS_TYPE").cloned().filter(|t| !t.is_empty()),
+        label,
+        uuid: udev.get("ID_FS_UUID").cloned().filter(|u| !u.is_empty()),
+    })
+}
+
+/// Decode udev's `\xNN` escaping.
+fn unescape_udev(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x') && i + 4 <= bytes.len() {
+            let code = std::str::from_utf8(&bytes[i + 2..i + 4])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(code) = code {
+                out.push(code);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Every device stacked on `name` (dm-crypt, LVM, md), recursively.
+pub fn holders(name: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut pending = vec![name.to_string()];
+    while let Some(current) = pending.pop() {
+        for holder in list_dir(Path::new(SYS_CLASS_BLOCK).join(&current).join("holders")) {
+            if !out.contains(&holder) {
+                pending.push(holder.clone());
+                out.push(holder);
+            }
+        }
+    }
+    out
+}
+
+/// The disk, its partitions and everything stacked on any of them.
+pub fn related_devices(disk: &str) -> HashSet<String> {
+    let mut related: HashSet<String> = HashSet::new();
+    for name in std::iter::once(disk.to_string()).chain(partitions(disk)) {
+        related.extend(holders(&name));
+        related.insert(name);
+    }
+    related
+}
+
+/// Whole disks under a block device: partitions resolve to their disk, and
+/// device-mapper/md devices to the disks of their members (`slaves/`).
+pub fn backing_disks(name: &str) -> Vec<String> {
+    let mut disks = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![name.to_string()];
+
+    while let Some(current) = pending.pop() {
+        if !seen.insert(current.clone()) {
+            continue;
+        }
+        let class = Path::new(SYS_CLASS_BLOCK).join(&current);
+
+        let members = list_dir(class.join("slaves"));
+        if !members.is_empty() {
+            pending.extend(members);
+        } else if class.join("partition").exists() {
+            // A partition's sysfs directory lives inside its disk's.
+            let parent = fs::canonicalize(&class)
+                .ok()
+                .and_then(|real| Some(real.parent()?.file_name()?.to_string_lossy().to_string()));
+            if let Some(parent) = parent {
+                pending.push(parent);
+            }
+        } else if !disks.contains(&current) {
+            disks.push(current);
+        }
+    }
+
+    disks
+}
+
+/// Undo mountinfo's octal escaping of spaces, tabs, newlines and backslashes.
+fn unescape_mount_field(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let code = std::str::from_utf8(&bytes[i + 1..i + 4])
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+            if let Some(code) = code {
+                out.push(code);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Parse `/proc/self/mountinfo`: `id parent maj:min root mountpoint opts [optional...] - fstype source superopts`.
+pub fn parse_mountinfo(content: &str) -> Vec<MountEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (left, right) = line.split_once(" - ")?;
+            let fields: Vec<&str> = left.split_whitespace().collect();
+            let tail: Vec<&str> = right.split_whitespace().collect();
+            Some(MountEntry {
+                dev: parse_dev(fields.get(2)?)?,
+                mount_point: unescape_mount_field(fields.get(4)?),
+                source: unescape_mount_field(tail.get(1)?),
+            })
+        })
+        .collect()
+}
+
+/// Current mount table of this process's namespace.
+pub fn mounts() -> Vec<MountEntry> {
+    fs::read_to_string(MOUNTINFO)
+        .map(|content| parse_mountinfo(&content))
+        .unwrap_or_default()
+}
+
+/// Block device behind a mount. btrfs reports an anonymous `0:N` device, so fall
+/// back to resolving the `/dev/...` source.
+pub fn mount_device_name(mount: &MountEntry) -> Option<String> {
+    name_of_dev(mount.dev).or_else(|| {
+        if !mount.source.starts_with("/dev/") {
+            return None;
+        }
+        let real = fs::canonicalize(&mount.source).ok()?;
+        Some(real.file_name()?.to_string_lossy().to_string())
+    })
+}
+
+/// Mounts backed by `disk`, its partitions or anything stacked on them (LUKS, LVM),
+/// as (mount point, device name), deepest mount point first so nested mounts can
+/// be released before their parents.
+pub fn disk_mounts(disk: &str) -> Vec<(String, String)> {
+    let related = related_devices(disk);
+    let mut found: Vec<(String, String)> = mounts()
+        .iter()
+        .filter_map(|m| {
+            let name = mount_device_name(m)?;
+            related
+                .contains(&name)
+                .then(|| (m.mount_point.clone(), name))
+        })
+        .collect();
+
+    found.sort();
+    found.dedup();
+    found.sort_by_key(|(mount_point, _)| std::cmp::Reverse(mount_point.len()));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let content = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+25 22 259:1 / /boot/efi rw,relatime shared:5 - vfat /dev/nvme0n1p1 rw,fmask=0077
+41 22 0:35 / /home rw,relatime shared:20 master:3 - btrfs /dev/mapper/luks-home rw,ssd
+60 22 8:17 / /media/user/My\\040Card rw,nosuid shared:40 - vfat /dev/sdb1 rw
+";
+        let mounts = parse_mountinfo(content);
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(mounts[0].dev, (259, 2));
+        assert_eq!(mounts[0].mount_point, "/");
+        assert_eq!(mounts[1].source, "/dev/nvme0n1p1");
+        assert_eq!(mounts[2].dev, (0, 35));
+        assert_eq!(mounts[2].source, "/dev/mapper/luks-home");
+        assert_eq!(mounts[3].mount_point, "/media/user/My Card");
+    }
+
+    #[test]
+    fn test_parse_mountinfo_skips_malformed_lines() {
+        assert!(parse_mountinfo("garbage\n22 1 x:y / / rw - ext4 /dev/sda1 rw\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_udev_db() {
+        let content = "\
+S:disk/by-id/usb-Generic_STORAGE_DEVICE_000000001206-0:0
+E:ID_BUS=usb
+E:ID_SERIAL_SHORT=000000001206
+E:ID_FS_LABEL=armbi_root
+G:systemd
+";
+        let props = parse_udev_db(content);
+        assert_eq!(props.get("ID_BUS").map(String::as_str), Some("usb"));
+        assert_eq!(
+            props.get("ID_SERIAL_SHORT").map(String::as_str),
+            Some("000000001206")
+        );
+        assert_eq!(props.len(), 3);
+    }
+
+    #[test]
+    fn test_unescape_udev() {
+        assert_eq!(unescape_udev("armbi\\x20root"), "armbi root");
+        assert_eq!(unescape_udev("\\xc3\\xa9t\\xc3\\xa9"), "été");
+        assert_eq!(unescape_udev("plain"), "plain");
+        assert_eq!(unescape_udev("cut\\x2"), "cut\\x2");
+    }
+
+    #[test]
+    fn test_unescape_mount_field() {
+        assert_eq!(unescape_mount_field("/mnt/a\\040b"), "/mnt/a b");
+        assert_eq!(unescape_mount_field("/mnt/plain"), "/mnt/plain");
+        assert_eq!(unescape_mount_field("/mnt/trailing\\"), "/mnt/trailing\\");
+    }
+}
@@ -3,7 +3,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Represents a block device (disk) on the system
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BlockDevice {
     /// Device path (e.g., /dev/sda, /dev/disk2, \\.\PhysicalDrive1)
     pub path: String,
@@ -23,6 +23,86 @@ pub struct BlockDevice {
     pub bus_type: Option<String>,
     /// Whether the device is read-only (e.g., SD card with write-protect lock)
     pub is_read_only: bool,
+    /// Device vendor (often "Generic" for card readers)
+    pub vendor: Option<String>,
+    /// Hardware serial number, the only thing telling identical cards apart
+    pub serial: Option<String>,
+    /// Partitions currently on the device, in table order
+    pub partitions: Vec<PartitionInfo>,
+    /// Armbian install found on the device's rootfs
+    pub existing_os: Option<ArmbianReleaseInfo>,
+    /// Why the device policy forbids flashing this device, if it does
+    pub policy_denied: Option<String>,
+}
+
+/// A partition of a [`BlockDevice`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionInfo {
+    /// Device path (e.g., /dev/sda1)
+    pub path: String,
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Filesystem type (e.g., "ext4", "vfat")
+    pub fs_type: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// Where it is mounted, if anywhere
+    pub mountpoint: Option<String>,
+}
+
+/// Board identification read from /etc/armbian-release
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArmbianReleaseInfo {
+    pub board: String,
+    pub board_name: String,
+    /// Armbian release (e.g., "25.8.1"); empty in very old images
+    #[serde(default)]
+    pub version: String,
+    /// Kernel branch (e.g., "current", "vendor")
+    #[serde(default)]
+    pub branch: String,
+    /// Kernel family (e.g., "rockchip64")
+    #[serde(default)]
+    pub linux_family: String,
+}
+
+impl ArmbianReleaseInfo {
+    /// Parse armbian-release's shell-style `KEY=value` lines; None without a BOARD.
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut info = Self {
+            board: String::new(),
+            board_name: String::new(),
+            version: String::new(),
+            branch: String::new(),
+            linux_family: String::new(),
+        };
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value
+                    .trim()
+                    .trim_matches('"')
+                    .trim_matches('\'')
+                    .to_string();
+                match key.trim() {
+                    "BOARD" => info.board = value,
+                    "BOARD_NAME" => info.board_name = value,
+                    "VERSION" => info.version = value,
+                    "BRANCH" => info.branch = value,
+                    "LINUXFAMILY" => info.linux_family = value,
+                    _ => {}
+                }
+            }
+        }
+
+        (!info.board.is_empty()).then_some(info)
+    }
 }
 
 /// Normalize a platform transport/protocol string into a canonical bus type; None if empty.
@@ -58,3 +138,26 @@ pub fn detect_sd_from_name(name: &str) -> Option<String> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_armbian_release() {
+        let content = "# PLEASE DO NOT EDIT THIS FILE\nBOARD=orangepi5\nBOARD_NAME=\"Orange Pi 5\"\nBOARDFAMILY=rockchip-rk3588\nVERSION=25.8.1\nLINUXFAMILY=rockchip64\nBRANCH=vendor\n";
+        let info = ArmbianReleaseInfo::parse(content).unwrap();
+        assert_eq!(info.board, "orangepi5");
+        assert_eq!(info.board_name, "Orange Pi 5");
+        assert_eq!(info.version, "25.8.1");
+        assert_eq!(info.branch, "vendor");
+        assert_eq!(info.linux_family, "rockchip64");
+    }
+
+    #[test]
+    fn test_parse_armbian_release_requires_board() {
+        assert!(
+            ArmbianReleaseInfo::parse("BOARD_NAME=\"Orange Pi 5\"\nVERSION=25.8.1\n").is_none()
+        );
+    }
+}
@@ -0,0 +1,446 @@
+//! Hotplug watcher: keeps an in-memory registry of block devices and EDL devices and
+//! pushes add/remove/change events to the UI, so it no longer has to poll.
+//! Linux listens to kernel uevents, USB EDL devices come from nusb hotplug, and
+//! anything without notifications falls back to a periodic rescan.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use super::{get_block_devices, BlockDevice};
+use crate::config;
+use crate::flash::FlashState;
+use crate::qdl::detect::get_qdl_devices;
+use crate::qdl::QdlDevice;
+use crate::{log_debug, log_info, log_warn};
+
+const MODULE: &str = "devices::watcher";
+
+/// Tauri event carrying a [`DeviceEvent`]
+pub const DEVICE_EVENT: &str = "device-event";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceAction {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    Block,
+    Qdl,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum WatchedDevice {
+    Block(BlockDevice),
+    Qdl(QdlDevice),
+}
+
+/// One registry change, as sent to the frontend.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceEvent {
+    pub action: DeviceAction,
+    pub kind: DeviceKind,
+    pub device: WatchedDevice,
+}
+
+#[derive(Default)]
+struct Registry {
+    block: BTreeMap<String, BlockDevice>,
+    qdl: BTreeMap<String, QdlDevice>,
+    /// Devices being flashed, keyed by path, whose removal must stop the writer
+    targets: BTreeMap<String, Arc<FlashState>>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// Keeps a flash target registered with the watcher until dropped.
+pub struct TargetWatch {
+    device_path: String,
+}
+
+impl Drop for TargetWatch {
+    fn drop(&mut self) {
+        registry().targets.remove(&self.device_path);
+    }
+}
+
+/// Have the watcher flag `state.target_removed` if `device_path` disappears
+/// while the returned guard is alive.
+pub fn watch_flash_target(device_path: &str, state: Arc<FlashState>) -> TargetWatch {
+    registry().targets.insert(device_path.to_string(), state);
+    TargetWatch {
+        device_path: device_path.to_string(),
+    }
+}
+
+/// Populate the registry and start the background watchers, off the setup thread
+/// since enumeration shells out to diskutil on macOS and USB scans can be slow.
+pub fn start(app: AppHandle) {
+    std::thread::spawn(move || {
+        super::policy::load(&app);
+
+        // Seed silently: the UI fetches its initial lists itself.
+        refresh_block();
+        refresh_qdl();
+        {
+            let reg = registry();
+            log_info!(
+                MODULE,
+                "Device watcher started with {} block and {} EDL device(s)",
+                reg.block.len(),
+                reg.qdl.len()
+            );
+        }
+
+        match nusb::watch_devices() {
+            Ok(watch) => {
+                tauri::async_runtime::spawn(watch_qdl(app.clone(), watch));
+            }
+            Err(e) => {
+                log_warn!(
+                    MODULE,
+                    "USB hotplug unavailable ({}), polling for EDL devices",
+                    e
+                );
+                let qdl_app = app.clone();
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(poll_interval());
+                    emit_all(&qdl_app, refresh_qdl());
+                });
+            }
+        }
+
+        watch_block(app);
+    });
+}
+
+fn poll_interval() -> Duration {
+    Duration::from_millis(config::devices::WATCH_POLL_INTERVAL_MS)
+}
+
+#[cfg(target_os = "linux")]
+fn watch_block(app: AppHandle) {
+    let socket = match uevent::UeventSocket::open() {
+        Ok(s) => s,
+        Err(e) => {
+            log_warn!(
+                MODULE,
+                "Kernel uevents unavailable ({}), polling for block devices",
+                e
+            );
+            return poll_block(app);
+        }
+    };
+
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e) => {
+                log_warn!(MODULE, "uevent receive failed ({}), polling instead", e);
+                return poll_block(app);
+            }
+        };
+
+        let Some(event) = uevent::parse(&buf[..len]) else {
+            continue;
+        };
+        if event.subsystem != Some("block") {
+            continue;
+        }
+        log_debug!(
+            MODULE,
+            "uevent {} {}",
+            event.action,
+            event.devname.unwrap_or("?")
+        );
+
+        // A disk arrives together with its partitions: wait for the burst to end.
+        while socket.wait_readable(config::devices::UEVENT_SETTLE_MS) {
+            if socket.recv(&mut buf).is_err() {
+                break;
+            }
+        }
+
+        emit_all(&app, refresh_block());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_block(app: AppHandle) {
+    poll_block(app)
+}
+
+fn poll_block(app: AppHandle) {
+    loop {
+        std::thread::sleep(poll_interval());
+        emit_all(&app, refresh_block());
+    }
+}
+
+async fn watch_qdl(app: AppHandle, mut watch: nusb::hotplug::HotplugWatch) {
+    use futures_util::StreamExt;
+
+    // Disconnect events only carry an opaque id, so rescan on any event and diff.
+    while watch.next().await.is_some() {
+        let events = tokio::task::spawn_blocking(refresh_qdl)
+            .await
+            .unwrap_or_default();
+        emit_all(&app, events);
+    }
+    log_warn!(MODULE, "USB hotplug stream ended");
+}
+
+/// Rescan block devices, update the registry and flag vanished flash targets.
+fn refresh_block() -> Vec<DeviceEvent> {
+    let devices = match get_block_devices() {
+        Ok(d) => super::policy::current().apply(d),
+        Err(e) => {
+            log_warn!(MODULE, "Block device rescan failed: {}", e);
+            return Vec::new();
+        }
+    };
+    let current: BTreeMap<String, BlockDevice> =
+        devices.into_iter().map(|d| (d.path.clone(), d)).collect();
+
+    let mut reg = registry();
+    let changes = diff(&reg.block, &current);
+    reg.block = current;
+
+    for (action, device) in &changes {
+        if *action != DeviceAction::Removed {
+            continue;
+        }
+        #[cfg(target_os = "linux")]
+        super::forget_existing_os(&device.path);
+        if let Some(state) = reg.targets.get(&device.path) {
+            log_warn!(MODULE, "Flash target {} was removed", device.path);
+            state
+                .target_removed
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    changes
+        .into_iter()
+        .map(|(action, device)| DeviceEvent {
+            action,
+            kind: DeviceKind::Block,
+            device: WatchedDevice::Block(device),
+        })
+        .collect()
+}
+
+fn refresh_qdl() -> Vec<DeviceEvent> {
+    let devices = match get_qdl_devices() {
+        Ok(d) => d,
+        Err(e) => {
+            log_warn!(MODULE, "EDL device rescan failed: {}", e);
+            return Vec::new();
+        }
+    };
+    let current: BTreeMap<String, QdlDevice> = devices
+        .into_iter()
+        .map(|d| (format!("{}:{}", d.bus_id, d.device_address), d))
+        .collect();
+
+    let mut reg = registry();
+    let changes = diff(&reg.qdl, &current);
+    reg.qdl = current;
+
+    changes
+        .into_iter()
+        .map(|(action, device)| DeviceEvent {
+            action,
+            kind: DeviceKind::Qdl,
+            device: WatchedDevice::Qdl(device),
+        })
+        .collect()
+}
+
+fn emit_all(app: &AppHandle, events: Vec<DeviceEvent>) {
+    for event in events {
+        log_info!(MODULE, "Device {:?}: {:?}", event.action, event.device);
+        if let Err(e) = app.emit(DEVICE_EVENT, &event) {
+            log_debug!(MODULE, "Failed to emit device event: {}", e);
+        }
+    }
+}
+
+/// Changes from `old` to `new`, keyed by device identity.
+fn diff<V: Clone + PartialEq>(
+    old: &BTreeMap<String, V>,
+    new: &BTreeMap<String, V>,
+) -> Vec<(DeviceAction, V)> {
+    let mut changes = Vec::new();
+    for (key, device) in old {
+        if !new.contains_key(key) {
+            changes.push((DeviceAction::Removed, device.clone()));
+        }
+    }
+    for (key, device) in new {
+        match old.get(key) {
+            None => changes.push((DeviceAction::Added, device.clone())),
+            Some(prev) if prev != device => changes.push((DeviceAction::Changed, device.clone())),
+            Some(_) => {}
+        }
+    }
+    changes
+}
+
+/// Minimal NETLINK_KOBJECT_UEVENT listener; no libudev dependency.
+#[cfg(target_os = "linux")]
+mod uevent {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    /// Multicast group the kernel broadcasts its own uevents on
+    const KERNEL_GROUP: u32 = 1;
+
+    pub struct UeventSocket(OwnedFd);
+
+    impl UeventSocket {
+        pub fn open() -> io::Result<Self> {
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_KOBJECT_UEVENT,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = KERNEL_GROUP;
+            let rc = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if rc != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self(fd))
+        }
+
+        pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = unsafe {
+                libc::recv(
+                    self.0.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        }
+
+        /// Whether another message arrives within `timeout_ms`.
+        pub fn wait_readable(&self, timeout_ms: i32) -> bool {
+            let mut pfd = libc::pollfd {
+                fd: self.0.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            unsafe { libc::poll(&mut pfd, 1, timeout_ms) > 0 }
+        }
+    }
+
+    pub struct Uevent<'a> {
+        pub action: &'a str,
+        pub subsystem: Option<&'a str>,
+        pub devname: Option<&'a str>,
+    }
+
+    /// Parse a kernel uevent: `ACTION@DEVPATH` followed by NUL-separated `KEY=VALUE` pairs.
+    pub fn parse(msg: &[u8]) -> Option<Uevent<'_>> {
+        let mut fields = msg
+            .split(|&b| b == 0)
+            .filter_map(|f| std::str::from_utf8(f).ok());
+        let (action, _devpath) = fields.next()?.split_once('@')?;
+
+        let mut event = Uevent {
+            action,
+            subsystem: None,
+            devname: None,
+        };
+        for field in fields {
+            match field.split_once('=') {
+                Some(("SUBSYSTEM", v)) => event.subsystem = Some(v),
+                Some(("DEVNAME", v)) => event.devname = Some(v),
+                _ => {}
+            }
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        entries.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn test_diff_reports_each_action() {
+        let old = map(&[("/dev/sda", 1), ("/dev/sdb", 2)]);
+        let new = map(&[("/dev/sdb", 3), ("/dev/sdc", 4)]);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                (DeviceAction::Removed, 1),
+                (DeviceAction::Changed, 3),
+                (DeviceAction::Added, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_unchanged_is_empty() {
+        let devices = map(&[("/dev/sda", 1)]);
+        assert!(diff(&devices, &devices).is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_uevent() {
+        let msg = b"add@/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdc\0\
+ACTION=add\0DEVPATH=/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdc\0\
+SUBSYSTEM=block\0MAJOR=8\0MINOR=32\0DEVNAME=sdc\0DEVTYPE=disk\0SEQNUM=4711\0";
+        let event = uevent::parse(msg).unwrap();
+        assert_eq!(event.action, "add");
+        assert_eq!(event.subsystem, Some("block"));
+        assert_eq!(event.devname, Some("sdc"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_uevent_rejects_garbage() {
+        assert!(uevent::parse(b"libudev\0\xfe\xed").is_none());
+    }
+}
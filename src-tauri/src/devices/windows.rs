@@ -216,10 +216,43 @@ fn is_disk_read_only(handle: HANDLE) -> bool {
     (media_info.media_characteristics & MEDIA_WRITE_PROTECTED) != 0
 }
 
+/// Identity fields of a STORAGE_DEVICE_DESCRIPTOR
+struct DeviceProperties {
+    model: String,
+    is_removable: bool,
+    bus_type: Option<String>,
+    vendor: Option<String>,
+    serial: Option<String>,
+}
+
+impl DeviceProperties {
+    fn unknown() -> Self {
+        Self {
+            model: "Physical Drive".to_string(),
+            is_removable: false,
+            bus_type: None,
+            vendor: None,
+            serial: None,
+        }
+    }
+}
+
+/// Optional descriptor string whose offset is stored at `field`; None when absent or blank.
+fn descriptor_string(buffer: &[u8], field: usize) -> Option<String> {
+    let offset = u32::from_le_bytes(buffer[field..field + 4].try_into().ok()?) as usize;
+    if offset == 0 {
+        return None;
+    }
+    let value = extract_ascii_string(buffer, offset);
+    (!value.is_empty() && value != "Physical Drive").then_some(value)
+}
+
 /// Queries device properties via IOCTL_STORAGE_QUERY_PROPERTY
-fn query_device_properties(disk_number: i32) -> Result<(String, bool, Option<String>), String> {
+fn query_device_properties(disk_number: i32) -> Result<DeviceProperties, String> {
     const MIN_DESCRIPTOR_SIZE: u32 = 33;
+    const VENDOR_ID_OFFSET: usize = 12;
     const PRODUCT_ID_OFFSET: usize = 16;
+    const SERIAL_NUMBER_OFFSET: usize = 24;
     const BUS_TYPE_OFFSET: usize = 28;
 
     let device_path = format!("\\\\.\\PhysicalDrive{}", disk_number);
@@ -227,7 +260,7 @@ fn query_device_properties(disk_number: i32) -> Result<(String, bool, Option<Str
 
     let handle = match try_open_device(&device_path_utf16) {
         Ok(h) => h,
-        Err(_) => return Ok(("Physical Drive".to_string(), false, None)),
+        Err(_) => return Ok(DeviceProperties::unknown()),
     };
 
     let query = STORAGE_PROPERTY_QUERY {
@@ -255,7 +288,7 @@ fn query_device_properties(disk_number: i32) -> Result<(String, bool, Option<Str
     unsafe { CloseHandle(handle) };
 
     if result == 0 || bytes_returned < MIN_DESCRIPTOR_SIZE {
-        return Ok(("Physical Drive".to_string(), false, None));
+        return Ok(DeviceProperties::unknown());
     }
 
     let bus_type_enum = buffer[BUS_TYPE_OFFSET];
@@ -278,7 +311,13 @@ fn query_device_properties(disk_number: i32) -> Result<(String, bool, Option<Str
         None => disk_number > 0,
     };
 
-    Ok((model, is_removable, bus_type))
+    Ok(DeviceProperties {
+        model,
+        is_removable,
+        bus_type,
+        vendor: descriptor_string(&buffer, VENDOR_ID_OFFSET),
+        serial: descriptor_string(&buffer, SERIAL_NUMBER_OFFSET),
+    })
 }
 
 /// Retrieves drive letters mounted on a specific physical disk
@@ -439,7 +478,13 @@ pub fn get_block_devices() -> Result<Vec<BlockDevice>, String> {
                 continue;
             }
 
-            let (model, is_removable, bus_type) = query_device_properties(disk_number)?;
+            let DeviceProperties {
+                model,
+                is_removable,
+                bus_type,
+                vendor,
+                serial,
+            } = query_device_properties(disk_number)?;
             let drive_letters = get_drive_letters_for_disk(disk_number);
 
             let has_c_drive = drive_letters
@@ -465,6 +510,11 @@ pub fn get_block_devices() -> Result<Vec<BlockDevice>, String> {
                 is_system,
                 bus_type,
                 is_read_only,
+                vendor,
+                serial,
+                partitions: Vec::new(),
+                existing_os: None,
+                policy_denied: None,
             });
         }
 
@@ -12,11 +12,20 @@ use tokio::sync::Mutex;
 
 use crate::config;
 use crate::decompress::decompress_with_rust_xz;
-use crate::utils::{bytes_to_mb, validate_cache_path, ProgressTracker};
+use crate::utils::{bytes_to_mb, phase, validate_cache_path, ProgressTracker};
 use crate::{log_debug, log_error, log_info, log_warn};
 
 const MODULE: &str = "download";
 
+/// Where the expected SHA256 of a download comes from
+#[derive(Debug, Clone, Copy)]
+pub enum ExpectedSha<'a> {
+    /// A published `.sha` file, fetched after the download
+    Url(&'a str),
+    /// A hex digest known up front (from a provisioning manifest)
+    Pinned(&'a str),
+}
+
 /// Download progress state
 pub struct DownloadState {
     pub total_bytes: AtomicU64,
@@ -28,6 +37,8 @@ pub struct DownloadState {
     pub output_path: Mutex<Option<PathBuf>>,
     /// Temp file kept when SHA is unavailable, so the user can decide to proceed.
     pub temp_path: Mutex<Option<PathBuf>>,
+    /// Job this state reports progress for, 0 outside the job manager
+    pub job_id: AtomicU64,
 }
 
 impl DownloadState {
@@ -41,6 +52,7 @@ impl DownloadState {
             error: Mutex::new(None),
             output_path: Mutex::new(None),
             temp_path: Mutex::new(None),
+            job_id: AtomicU64::new(0),
         }
     }
 
@@ -132,6 +144,9 @@ fn calculate_file_sha256(path: &Path, state: &Arc<DownloadState>) -> Result<Stri
     let mut hasher = Sha256::new();
     let mut buffer = [0u8; config::logging::SHA_BUFFER_SIZE];
     let mut bytes_processed = 0u64;
+    let size = path.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut tracker = ProgressTracker::new("SHA256", MODULE, size, 0)
+        .with_events(state.job_id.load(Ordering::SeqCst), phase::VERIFY_SHA);
 
     loop {
         if state.is_cancelled.load(Ordering::SeqCst) {
@@ -147,6 +162,7 @@ fn calculate_file_sha256(path: &Path, state: &Arc<DownloadState>) -> Result<Stri
         }
         hasher.update(&buffer[..bytes_read]);
         bytes_processed += bytes_read as u64;
+        tracker.update(bytes_read as u64);
 
         if bytes_processed % (10 * 1024 * 1024) == 0 {
             log_debug!(
@@ -157,6 +173,7 @@ fn calculate_file_sha256(path: &Path, state: &Arc<DownloadState>) -> Result<Stri
         }
     }
 
+    tracker.finish();
     let result = hasher.finalize();
     let hash = format!("{:x}", result);
     log_debug!(MODULE, "Calculated SHA256: {}", hash);
@@ -167,14 +184,17 @@ fn calculate_file_sha256(path: &Path, state: &Arc<DownloadState>) -> Result<Stri
 async fn verify_sha256(
     client: &Client,
     file_path: &Path,
-    sha_url: &str,
+    sha: ExpectedSha<'_>,
     state: &Arc<DownloadState>,
 ) -> Result<(), String> {
     if state.is_cancelled.load(Ordering::SeqCst) {
         return Err("SHA256 verification cancelled".to_string());
     }
 
-    let expected = fetch_expected_sha(client, sha_url).await?;
+    let expected = match sha {
+        ExpectedSha::Url(sha_url) => fetch_expected_sha(client, sha_url).await?,
+        ExpectedSha::Pinned(hash) => hash.to_lowercase(),
+    };
 
     if state.is_cancelled.load(Ordering::SeqCst) {
         return Err("SHA256 verification cancelled".to_string());
@@ -199,10 +219,10 @@ async fn verify_sha256(
     }
 }
 
-/// Download and decompress an Armbian image; when sha_url is given, verifies the compressed file first.
+/// Download and decompress an Armbian image; when `sha` is given, verifies the compressed file first.
 pub async fn download_image(
     url: &str,
-    sha_url: Option<&str>,
+    sha: Option<ExpectedSha<'_>>,
     output_dir: &PathBuf,
     state: Arc<DownloadState>,
 ) -> Result<PathBuf, String> {
@@ -273,7 +293,8 @@ pub async fn download_image(
         MODULE,
         total_size,
         config::logging::DOWNLOAD_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::DOWNLOAD);
 
     while let Some(chunk) = stream.next().await {
         if state.is_cancelled.load(Ordering::SeqCst) {
@@ -305,10 +326,10 @@ pub async fn download_image(
     drop(temp_file);
     tracker.finish();
 
-    if let Some(sha_url) = sha_url {
+    if let Some(sha) = sha {
         state.is_verifying_sha.store(true, Ordering::SeqCst);
         log_info!(MODULE, "Verifying SHA256...");
-        match verify_sha256(&client, &temp_path, sha_url, &state).await {
+        match verify_sha256(&client, &temp_path, sha, &state).await {
             Ok(()) => {
                 log_info!(MODULE, "SHA256 verification successful");
             }
@@ -339,7 +360,7 @@ pub async fn download_image(
         }
         state.is_verifying_sha.store(false, Ordering::SeqCst);
     } else {
-        log_warn!(MODULE, "No SHA URL provided, skipping verification");
+        log_warn!(MODULE, "No SHA256 provided, skipping verification");
     }
 
     if filename.ends_with(".xz") {
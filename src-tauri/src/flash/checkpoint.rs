@@ -0,0 +1,455 @@
+//! Flash checkpoints: the last durably synced offset of a write, tied to the image
+//! and the target card, so an interrupted flash can resume instead of restarting.
+
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices::{get_block_devices, BlockDevice};
+use crate::utils::flash_checkpoint_path;
+use crate::{log_debug, log_info, log_warn};
+
+const MODULE: &str = "flash::checkpoint";
+
+/// Identity of the flashed content: the source image plus the applied profile.
+/// Autoconfig flashes go through a fresh temp copy, so the copy's path is useless here.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageIdentity {
+    pub name: String,
+    pub size: u64,
+    /// Source modification time (seconds since the epoch)
+    pub modified: u64,
+    /// [`crate::autoconfig::profile_hash`] of the autoconfig profile; never the profile itself
+    pub profile_hash: Option<String>,
+}
+
+impl ImageIdentity {
+    pub fn from_path(path: &Path, profile_hash: Option<String>) -> Result<Self, String> {
+        let metadata =
+            std::fs::metadata(path).map_err(|e| format!("Failed to read image metadata: {}", e))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Ok(Self {
+            name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size: metadata.len(),
+            modified,
+            profile_hash,
+        })
+    }
+}
+
+/// Stable properties of the target card, used to recognise it after a USB reset
+/// re-enumerates it under a different device path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceFingerprint {
+    pub size: u64,
+    pub model: String,
+    pub bus_type: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl DeviceFingerprint {
+    pub fn from_device(device: &BlockDevice) -> Self {
+        Self {
+            size: device.size,
+            model: device.model.clone(),
+            bus_type: device.bus_type.clone(),
+            serial: device.serial.clone(),
+        }
+    }
+
+    /// Fingerprint of a currently attached device, None if it is not present.
+    pub fn for_path(device_path: &str) -> Option<Self> {
+        get_block_devices()
+            .ok()?
+            .iter()
+            .find(|d| d.path == device_path)
+            .map(Self::from_device)
+    }
+}
+
+/// A partially completed flash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashCheckpoint {
+    pub image: ImageIdentity,
+    pub device: DeviceFingerprint,
+    /// Device path at the time of the last sync (may change after a reset)
+    pub device_path: String,
+    /// Bytes confirmed on the device by the last successful fdatasync
+    pub durable_offset: u64,
+    /// Last update (seconds since the epoch)
+    pub updated_at: u64,
+}
+
+impl FlashCheckpoint {
+    pub fn new(image: ImageIdentity, device: DeviceFingerprint, device_path: &str) -> Self {
+        Self {
+            image,
+            device,
+            device_path: device_path.to_string(),
+            durable_offset: 0,
+            updated_at: now_secs(),
+        }
+    }
+
+    /// Record a new durable offset and persist it.
+    pub fn advance(&mut self, offset: u64) {
+        self.durable_offset = offset;
+        self.updated_at = now_secs();
+        save(self);
+    }
+}
+
+/// A checkpoint whose image and card are both available again.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResumableFlash {
+    /// Where the card is attached now
+    pub device_path: String,
+    pub durable_offset: u64,
+    pub total_bytes: u64,
+    pub updated_at: u64,
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Load the stored checkpoint, if any.
+pub fn load() -> Option<FlashCheckpoint> {
+    let data = std::fs::read_to_string(flash_checkpoint_path()).ok()?;
+    match serde_json::from_str(&data) {
+        Ok(cp) => Some(cp),
+        Err(e) => {
+            log_warn!(MODULE, "Ignoring unreadable flash checkpoint: {}", e);
+            None
+        }
+    }
+}
+
+/// Persist the checkpoint. Best-effort: a failure here must never fail the flash itself.
+pub fn save(checkpoint: &FlashCheckpoint) {
+    let path = flash_checkpoint_path();
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+
+    let json = match serde_json::to_string(checkpoint) {
+        Ok(j) => j,
+        Err(e) => {
+            log_warn!(MODULE, "Failed to serialize flash checkpoint: {}", e);
+            return;
+        }
+    };
+
+    // Write-then-rename so a crash mid-save never leaves a truncated checkpoint.
+    let tmp = path.with_extension("json.tmp");
+    if let Err(e) = std::fs::write(&tmp, json).and_then(|_| std::fs::rename(&tmp, &path)) {
+        log_warn!(MODULE, "Failed to save flash checkpoint: {}", e);
+        return;
+    }
+
+    log_debug!(
+        MODULE,
+        "Checkpoint saved at byte {} for {}",
+        checkpoint.durable_offset,
+        checkpoint.device_path
+    );
+}
+
+/// Drop the stored checkpoint (flash finished, or the user chose to start over).
+pub fn clear() {
+    let path = flash_checkpoint_path();
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            log_warn!(MODULE, "Failed to remove flash checkpoint: {}", e);
+        }
+    }
+}
+
+/// Find the stored checkpoint for `image` among the attached devices.
+pub fn find_resumable(image: &ImageIdentity) -> Option<ResumableFlash> {
+    let checkpoint = load()?;
+    let devices = get_block_devices().ok()?;
+    resumable_on(&checkpoint, image, &devices)
+}
+
+/// Where `checkpoint` can resume `image` among `devices`. Prefers the original path;
+/// otherwise requires exactly one matching card so two identical readers can never
+/// be confused.
+fn resumable_on(
+    checkpoint: &FlashCheckpoint,
+    image: &ImageIdentity,
+    devices: &[BlockDevice],
+) -> Option<ResumableFlash> {
+    if checkpoint.image != *image || checkpoint.durable_offset == 0 {
+        return None;
+    }
+
+    let matches: Vec<&BlockDevice> = devices
+        .iter()
+        .filter(|d| DeviceFingerprint::from_device(d) == checkpoint.device)
+        .collect();
+
+    let device = matches
+        .iter()
+        .find(|d| d.path == checkpoint.device_path)
+        .or_else(|| (matches.len() == 1).then(|| &matches[0]))?;
+
+    Some(ResumableFlash {
+        device_path: device.path.clone(),
+        durable_offset: checkpoint.durable_offset,
+        total_bytes: checkpoint.image.size,
+        updated_at: checkpoint.updated_at,
+    })
+}
+
+/// Build the checkpoint for a new flash, or reload the stored one when resuming.
+/// Resuming requires the stored image identity and card fingerprint to match exactly.
+pub fn prepare(
+    image_path: &Path,
+    profile_hash: Option<String>,
+    device_path: &str,
+    resume: bool,
+) -> Result<Option<FlashCheckpoint>, String> {
+    let image = ImageIdentity::from_path(image_path, profile_hash)?;
+    let device = DeviceFingerprint::for_path(device_path);
+    let stored = if resume { load() } else { None };
+    checkpoint_for(image, device, stored, device_path, resume)
+}
+
+/// [`prepare`] once the image, the card and the stored checkpoint are known.
+fn checkpoint_for(
+    image: ImageIdentity,
+    device: Option<DeviceFingerprint>,
+    stored: Option<FlashCheckpoint>,
+    device_path: &str,
+    resume: bool,
+) -> Result<Option<FlashCheckpoint>, String> {
+    let device = match device {
+        Some(d) => d,
+        None if resume => {
+            return Err(format!(
+                "[RESUME_UNAVAILABLE] Device {} is not attached",
+                device_path
+            ))
+        }
+        None => {
+            log_warn!(
+                MODULE,
+                "Cannot fingerprint {}, flash will not be resumable",
+                device_path
+            );
+            return Ok(None);
+        }
+    };
+
+    if !resume {
+        return Ok(Some(FlashCheckpoint::new(image, device, device_path)));
+    }
+
+    let stored = stored
+        .filter(|cp| cp.image == image && cp.device == device && cp.durable_offset > 0)
+        .ok_or_else(|| {
+            "[RESUME_UNAVAILABLE] No checkpoint matches this image and device".to_string()
+        })?;
+
+    log_info!(
+        MODULE,
+        "Resuming {} on {} from byte {}",
+        image.name,
+        device_path,
+        stored.durable_offset
+    );
+
+    Ok(Some(FlashCheckpoint {
+        device_path: device_path.to_string(),
+        ..stored
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(serial: Option<&str>) -> DeviceFingerprint {
+        DeviceFingerprint {
+            size: 32_017_047_552,
+            model: "STORAGE DEVICE".to_string(),
+            bus_type: Some("USB".to_string()),
+            serial: serial.map(str::to_string),
+        }
+    }
+
+    fn image(profile_hash: Option<&str>) -> ImageIdentity {
+        ImageIdentity {
+            name: "Armbian_25.8.1_Orangepi5_trixie_current_6.12.41.img".to_string(),
+            size: 2_147_483_648,
+            modified: 1_700_000_000,
+            profile_hash: profile_hash.map(str::to_string),
+        }
+    }
+
+    fn card(path: &str, serial: Option<&str>) -> BlockDevice {
+        let fingerprint = fingerprint(serial);
+        BlockDevice {
+            path: path.to_string(),
+            name: path.trim_start_matches("/dev/").to_string(),
+            size: fingerprint.size,
+            size_formatted: String::new(),
+            model: fingerprint.model,
+            is_removable: true,
+            is_system: false,
+            bus_type: fingerprint.bus_type,
+            is_read_only: false,
+            vendor: None,
+            serial: fingerprint.serial,
+            partitions: Vec::new(),
+            existing_os: None,
+            policy_denied: None,
+        }
+    }
+
+    fn interrupted(serial: Option<&str>, path: &str) -> FlashCheckpoint {
+        let mut cp = FlashCheckpoint::new(image(None), fingerprint(serial), path);
+        cp.durable_offset = 64 * 1024 * 1024;
+        cp
+    }
+
+    #[test]
+    fn test_fingerprint_distinguishes_serials() {
+        assert_eq!(
+            fingerprint(Some("000000001206")),
+            fingerprint(Some("000000001206"))
+        );
+        assert_ne!(
+            fingerprint(Some("000000001206")),
+            fingerprint(Some("000000001207"))
+        );
+        assert_ne!(fingerprint(Some("000000001206")), fingerprint(None));
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let cp = interrupted(Some("1206"), "/dev/sdb");
+
+        let json = serde_json::to_string(&cp).unwrap();
+        let back: FlashCheckpoint = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.image, cp.image);
+        assert_eq!(back.device, cp.device);
+        assert_eq!(back.durable_offset, cp.durable_offset);
+    }
+
+    #[test]
+    fn identity_follows_the_image_file_and_profile() {
+        let path = std::env::temp_dir().join(format!("checkpoint-test-{}.img", std::process::id()));
+        std::fs::write(&path, [0u8; 4096]).unwrap();
+        let plain = ImageIdentity::from_path(&path, None).unwrap();
+        let profiled = ImageIdentity::from_path(&path, Some("ab12".to_string())).unwrap();
+        std::fs::write(&path, [0u8; 8192]).unwrap();
+        let grown = ImageIdentity::from_path(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(plain.size, 4096);
+        assert_ne!(plain, profiled);
+        assert_ne!(plain, grown);
+        assert!(ImageIdentity::from_path(&path, None).is_err());
+    }
+
+    #[test]
+    fn prepare_starts_fresh_or_resumes_a_matching_checkpoint() {
+        let fresh = checkpoint_for(
+            image(None),
+            Some(fingerprint(Some("1206"))),
+            None,
+            "/dev/sdb",
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(fresh.durable_offset, 0);
+        assert_eq!(fresh.device_path, "/dev/sdb");
+
+        // The card came back under another path after a reset.
+        let stored = interrupted(Some("1206"), "/dev/sdb");
+        let resumed = checkpoint_for(
+            image(None),
+            Some(fingerprint(Some("1206"))),
+            Some(stored.clone()),
+            "/dev/sdc",
+            true,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(resumed.durable_offset, stored.durable_offset);
+        assert_eq!(resumed.device_path, "/dev/sdc");
+
+        // No fingerprint: a fresh flash just is not resumable, a resume fails.
+        assert!(checkpoint_for(image(None), None, None, "/dev/sdb", false)
+            .unwrap()
+            .is_none());
+        assert!(checkpoint_for(image(None), None, Some(stored), "/dev/sdb", true).is_err());
+    }
+
+    #[test]
+    fn prepare_refuses_a_mismatched_resume() {
+        let stored = interrupted(Some("1206"), "/dev/sdb");
+        let resume = |image: ImageIdentity, serial: &str, stored: &FlashCheckpoint| {
+            checkpoint_for(
+                image,
+                Some(fingerprint(Some(serial))),
+                Some(stored.clone()),
+                "/dev/sdb",
+                true,
+            )
+        };
+
+        let err = resume(image(Some("ab12")), "1206", &stored).unwrap_err();
+        assert!(err.starts_with("[RESUME_UNAVAILABLE]"));
+        assert!(resume(image(None), "1207", &stored).is_err());
+
+        let mut synced_nothing = stored.clone();
+        synced_nothing.durable_offset = 0;
+        assert!(resume(image(None), "1206", &synced_nothing).is_err());
+        assert!(resume(image(None), "1206", &stored).is_ok());
+    }
+
+    #[test]
+    fn find_resumable_needs_the_image_and_one_matching_card() {
+        let cp = interrupted(Some("1206"), "/dev/sdb");
+
+        let moved = resumable_on(&cp, &image(None), &[card("/dev/sdd", Some("1206"))]).unwrap();
+        assert_eq!(moved.device_path, "/dev/sdd");
+        assert_eq!(moved.durable_offset, cp.durable_offset);
+        assert_eq!(moved.total_bytes, cp.image.size);
+
+        // Another profile or another card does not match.
+        assert!(
+            resumable_on(&cp, &image(Some("ab12")), &[card("/dev/sdb", Some("1206"))]).is_none()
+        );
+        assert!(resumable_on(&cp, &image(None), &[card("/dev/sdb", Some("1207"))]).is_none());
+
+        // Two identical cards: only the original path is trusted.
+        let twins = interrupted(None, "/dev/sdb");
+        let both = [card("/dev/sdc", None), card("/dev/sdb", None)];
+        assert_eq!(
+            resumable_on(&twins, &image(None), &both)
+                .unwrap()
+                .device_path,
+            "/dev/sdb"
+        );
+        let neither = [card("/dev/sdc", None), card("/dev/sdd", None)];
+        assert!(resumable_on(&twins, &image(None), &neither).is_none());
+    }
+}
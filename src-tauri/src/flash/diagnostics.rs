@@ -0,0 +1,352 @@
+//! Bad-region diagnostics for failed device writes. Probes around the failing offset
+//! with small write/read cycles, maps the ranges that misbehave and records what the
+//! kernel counted, as a JSON report stored with the logs for card triage.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::utils::diagnostics_dir;
+use crate::{log_info, log_warn};
+
+const MODULE: &str = "flash::diagnostics";
+
+/// How a probed region misbehaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionFault {
+    /// The write (or the sync that flushed it) returned an error
+    Unwritable,
+    /// The write succeeded but reading it back failed
+    Unreadable,
+    /// Read back fine but returned different bytes (fake capacity, dying flash)
+    Mismatch,
+}
+
+/// A contiguous device range with the same fault.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BadRange {
+    pub start: u64,
+    pub end: u64,
+    pub fault: RegionFault,
+}
+
+/// Outcome of a diagnostic run, serialized to `diagnostics/diag-<timestamp>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticReport {
+    pub created_at: String,
+    pub device_path: String,
+    pub device_size: u64,
+    /// The error that ended the flash
+    pub error: String,
+    /// Range the writer was committing when it failed
+    pub failed_start: u64,
+    pub failed_end: u64,
+    /// Range actually probed (may stop early on the time limit)
+    pub probe_start: u64,
+    pub probe_end: u64,
+    pub probe_size: u64,
+    pub bad_ranges: Vec<BadRange>,
+    /// Kernel-side counters for the device (sysfs on Linux)
+    pub kernel_counters: BTreeMap<String, String>,
+    pub timed_out: bool,
+}
+
+/// Run diagnostics after a write failure and store the report. Never fails: any
+/// problem is logged and the original flash error stays what the user sees.
+pub fn run(
+    device: &mut File,
+    image_path: &Path,
+    device_path: &str,
+    failed: Range<u64>,
+    error: &str,
+) -> Option<PathBuf> {
+    log_info!(
+        MODULE,
+        "Running bad-region diagnostics on {} around bytes {}..{}",
+        device_path,
+        failed.start,
+        failed.end
+    );
+
+    let device_size = device.seek(SeekFrom::End(0)).unwrap_or(0);
+    let probe_size = config::flash::DIAG_PROBE_SIZE as u64;
+    let margin = config::flash::DIAG_PROBE_MARGIN;
+
+    // Align to the probe size so every probe stays sector-aligned for raw devices.
+    let start = failed.start.saturating_sub(margin) / probe_size * probe_size;
+    let mut end = failed.end.saturating_add(margin).div_ceil(probe_size) * probe_size;
+    if device_size > 0 {
+        end = end.min(device_size / probe_size * probe_size);
+    }
+
+    let mut image = File::open(image_path).ok();
+    let (faults, probed_end, timed_out) =
+        probe(device, image.as_mut(), start..end, probe_size as usize);
+
+    let report = DiagnosticReport {
+        created_at: chrono::Local::now().to_rfc3339(),
+        device_path: device_path.to_string(),
+        device_size,
+        error: error.to_string(),
+        failed_start: failed.start,
+        failed_end: failed.end,
+        probe_start: start,
+        probe_end: probed_end,
+        probe_size,
+        bad_ranges: merge_faults(&faults, probe_size),
+        kernel_counters: kernel_counters(device_path),
+        timed_out,
+    };
+
+    log_info!(
+        MODULE,
+        "Diagnostics found {} bad range(s) in {}..{}{}",
+        report.bad_ranges.len(),
+        report.probe_start,
+        report.probe_end,
+        if timed_out {
+            " (time limit reached)"
+        } else {
+            ""
+        }
+    );
+    for range in &report.bad_ranges {
+        log_warn!(
+            MODULE,
+            "Bad range {}..{}: {:?}",
+            range.start,
+            range.end,
+            range.fault
+        );
+    }
+
+    save_report(&report)
+}
+
+/// Write, sync and read back each probe-sized block of `window`. Writes the image's own
+/// bytes where it has them so probing leaves the card no worse than the flash did.
+/// Returns the faulty block offsets, where probing stopped and whether it timed out.
+fn probe(
+    device: &mut File,
+    mut image: Option<&mut File>,
+    window: Range<u64>,
+    probe_size: usize,
+) -> (Vec<(u64, RegionFault)>, u64, bool) {
+    let deadline =
+        Instant::now() + std::time::Duration::from_secs(config::flash::DIAG_TIME_LIMIT_SECS);
+    let mut data = vec![0u8; probe_size];
+    let mut readback = vec![0u8; probe_size];
+    let mut faults = Vec::new();
+    let mut offset = window.start;
+
+    while offset < window.end {
+        if Instant::now() >= deadline {
+            return (faults, offset, true);
+        }
+
+        data.fill(0);
+        if let Some(img) = image.as_deref_mut() {
+            let _ = img
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| read_up_to(img, &mut data));
+        }
+
+        let written = device
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| device.write_all(&data))
+            .and_then(|_| device.sync_data());
+
+        let fault = match written {
+            Err(_) => Some(RegionFault::Unwritable),
+            Ok(()) => {
+                drop_cached(device, offset, probe_size);
+                match device
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| device.read_exact(&mut readback))
+                {
+                    Err(_) => Some(RegionFault::Unreadable),
+                    Ok(()) if readback != data => Some(RegionFault::Mismatch),
+                    Ok(()) => None,
+                }
+            }
+        };
+
+        if let Some(fault) = fault {
+            faults.push((offset, fault));
+        }
+        offset += probe_size as u64;
+    }
+
+    (faults, offset, false)
+}
+
+/// Fill as much of `buf` as the reader has; short at end of file.
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(())
+}
+
+/// Make the read-back hit the media rather than the page cache.
+#[cfg(target_os = "linux")]
+fn drop_cached(device: &File, offset: u64, len: usize) {
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        libc::posix_fadvise(
+            device.as_raw_fd(),
+            offset as i64,
+            len as i64,
+            libc::POSIX_FADV_DONTNEED,
+        );
+    }
+}
+
+/// No portable cache drop; macOS raw disks are uncached, elsewhere this is best effort.
+#[cfg(not(target_os = "linux"))]
+fn drop_cached(_device: &File, _offset: u64, _len: usize) {}
+
+/// Collapse per-block faults into contiguous ranges of the same kind.
+fn merge_faults(faults: &[(u64, RegionFault)], block: u64) -> Vec<BadRange> {
+    let mut ranges: Vec<BadRange> = Vec::new();
+    for &(offset, fault) in faults {
+        match ranges.last_mut() {
+            Some(last) if last.end == offset && last.fault == fault => last.end += block,
+            _ => ranges.push(BadRange {
+                start: offset,
+                end: offset + block,
+                fault,
+            }),
+        }
+    }
+    ranges
+}
+
+/// I/O error counters the kernel keeps for the disk.
+#[cfg(target_os = "linux")]
+fn kernel_counters(device_path: &str) -> BTreeMap<String, String> {
+    let name = device_path.trim_start_matches("/dev/");
+    let base = PathBuf::from("/sys/block").join(name);
+    let mut counters = BTreeMap::new();
+
+    // SCSI (USB mass storage, SATA) error/timeout counters, hex-encoded by the kernel.
+    for attr in [
+        "device/ioerr_cnt",
+        "device/iotmo_cnt",
+        "device/iodone_cnt",
+        "device/iorequest_cnt",
+        "device/state",
+        "stat",
+        "ro",
+        "size",
+    ] {
+        if let Ok(value) = std::fs::read_to_string(base.join(attr)) {
+            counters.insert(
+                attr.to_string(),
+                value.split_whitespace().collect::<Vec<_>>().join(" "),
+            );
+        }
+    }
+
+    counters
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel_counters(_device_path: &str) -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+fn save_report(report: &DiagnosticReport) -> Option<PathBuf> {
+    let dir = diagnostics_dir();
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log_warn!(MODULE, "Failed to create diagnostics directory: {}", e);
+        return None;
+    }
+
+    let path = dir.join(format!(
+        "diag-{}.json",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let json = serde_json::to_string_pretty(report).ok()?;
+    match std::fs::write(&path, json) {
+        Ok(()) => {
+            log_info!(MODULE, "Diagnostic report saved: {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            log_warn!(MODULE, "Failed to save diagnostic report: {}", e);
+            None
+        }
+    }
+}
+
+/// Stored reports, newest first.
+pub fn recent_reports(limit: usize) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(diagnostics_dir()) else {
+        return Vec::new();
+    };
+
+    let mut reports: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+
+    // Timestamped names sort chronologically.
+    reports.sort();
+    reports.reverse();
+    reports.truncate(limit);
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_faults_joins_adjacent_blocks() {
+        let faults = [
+            (0, RegionFault::Unwritable),
+            (64, RegionFault::Unwritable),
+            (128, RegionFault::Mismatch),
+            (320, RegionFault::Mismatch),
+        ];
+        let ranges = merge_faults(&faults, 64);
+        assert_eq!(
+            ranges,
+            vec![
+                BadRange {
+                    start: 0,
+                    end: 128,
+                    fault: RegionFault::Unwritable
+                },
+                BadRange {
+                    start: 128,
+                    end: 192,
+                    fault: RegionFault::Mismatch
+                },
+                BadRange {
+                    start: 320,
+                    end: 384,
+                    fault: RegionFault::Mismatch
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_faults_empty() {
+        assert!(merge_faults(&[], 64).is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+//! Read an image once and hand each chunk to several writers, for flashing one image
+//! to many devices. Chunks are shared (`Arc`), and each writer has a bounded queue,
+//! so the batch runs at the pace of the slowest device without buffering the image.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+use crate::config;
+use crate::{log_debug, log_error};
+
+const MODULE: &str = "flash::fanout";
+
+type Chunk = Result<Arc<[u8]>, String>;
+
+/// One writer's view of the shared image stream.
+pub struct FanoutReader {
+    rx: Receiver<Chunk>,
+    chunk: Arc<[u8]>,
+    pos: usize,
+}
+
+impl Read for FanoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Ok(Err(e)) => return Err(io::Error::other(e)),
+                // The reader thread is done: end of image.
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A [`FanoutReader`] carried in [`super::FlashOptions`]; the writer takes it
+/// in place of opening the image itself.
+#[derive(Clone)]
+pub struct SharedSource(Arc<Mutex<Option<FanoutReader>>>);
+
+impl SharedSource {
+    pub fn new(reader: FanoutReader) -> Self {
+        Self(Arc::new(Mutex::new(Some(reader))))
+    }
+
+    pub fn take(&self) -> Option<FanoutReader> {
+        self.0.lock().unwrap_or_else(|p| p.into_inner()).take()
+    }
+}
+
+impl fmt::Debug for SharedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedSource")
+    }
+}
+
+/// Start reading `image_path` on a background thread, returning one reader per
+/// consumer. Dropping a reader (a failed or cancelled writer) detaches it without
+/// holding up the others.
+pub fn fan_out(image_path: &Path, consumers: usize) -> Result<Vec<FanoutReader>, String> {
+    let file = File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+
+    let (senders, readers): (Vec<_>, Vec<_>) = (0..consumers)
+        .map(|_| {
+            let (tx, rx) = sync_channel(config::flash::FANOUT_QUEUE_CHUNKS);
+            let reader = FanoutReader {
+                rx,
+                chunk: Arc::from(Vec::new()),
+                pos: 0,
+            };
+            (tx, reader)
+        })
+        .unzip();
+
+    std::thread::Builder::new()
+        .name("image fan-out".to_string())
+        .spawn(move || pump(file, senders))
+        .map_err(|e| format!("Failed to start image reader: {}", e))?;
+
+    Ok(readers)
+}
+
+fn pump(mut file: File, mut senders: Vec<SyncSender<Chunk>>) {
+    let mut total: u64 = 0;
+
+    while !senders.is_empty() {
+        let mut buf = vec![0u8; config::flash::CHUNK_SIZE];
+        let filled = match read_full(&mut file, &mut buf) {
+            Ok(n) => n,
+            Err(e) => {
+                log_error!(MODULE, "Failed to read image at byte {}: {}", total, e);
+                let msg = format!("Failed to read image: {}", e);
+                for tx in &senders {
+                    let _ = tx.send(Err(msg.clone()));
+                }
+                return;
+            }
+        };
+        if filled == 0 {
+            break;
+        }
+
+        buf.truncate(filled);
+        let chunk: Arc<[u8]> = buf.into();
+        total += filled as u64;
+        senders.retain(|tx| tx.send(Ok(chunk.clone())).is_ok());
+    }
+
+    log_debug!(
+        MODULE,
+        "Image read once ({} bytes), {} writer(s) still attached",
+        total,
+        senders.len()
+    );
+}
+
+/// Fill `buf` unless the file ends first, so every chunk but the last is full
+/// and stays sector-aligned for the writers.
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_image(len: usize) -> (std::path::PathBuf, Vec<u8>) {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let path =
+            std::env::temp_dir().join(format!("fanout-test-{}-{}.img", std::process::id(), len));
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        (path, data)
+    }
+
+    #[test]
+    fn test_every_reader_sees_the_whole_image() {
+        let (path, data) = temp_image(config::flash::CHUNK_SIZE * 2 + 1234);
+        let readers = fan_out(&path, 3).unwrap();
+
+        let handles: Vec<_> = readers
+            .into_iter()
+            .map(|mut reader| {
+                std::thread::spawn(move || {
+                    let mut out = Vec::new();
+                    reader.read_to_end(&mut out).unwrap();
+                    out
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert!(handle.join().unwrap() == data);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_dropped_reader_does_not_block_others() {
+        let chunks = config::flash::FANOUT_QUEUE_CHUNKS + 4;
+        let (path, data) = temp_image(config::flash::CHUNK_SIZE * chunks);
+        let mut readers = fan_out(&path, 2).unwrap();
+        drop(readers.pop());
+
+        let mut out = Vec::new();
+        readers[0].read_to_end(&mut out).unwrap();
+        assert_eq!(out.len(), data.len());
+        let _ = std::fs::remove_file(path);
+    }
+}
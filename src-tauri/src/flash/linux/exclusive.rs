@@ -0,0 +1,186 @@
+//! Exclusive access to the target disk: filesystems are unmounted through UDisks2
+//! (so the desktop sees a clean unmount rather than a lost mount), and the device is
+//! then held open with O_EXCL. While that claim is held the kernel refuses to mount
+//! any of its partitions, which keeps automounters away for the whole flash.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use crate::devices::sysfs;
+use crate::{log_debug, log_info, log_warn};
+
+const MODULE: &str = "flash::linux::exclusive";
+
+/// UDisks2 object path of a block device. Object paths only allow `[A-Za-z0-9_]`,
+/// so UDisks2 hex-escapes everything else (`dm-0` becomes `dm_2d0`).
+pub fn udisks_object_path(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    format!("/org/freedesktop/UDisks2/block_devices/{}", escaped)
+}
+
+/// Unmount every filesystem on the disk, its partitions and stacked devices via
+/// UDisks2 `Filesystem.Unmount`, deepest mount first. Returns an error when
+/// UDisks2 is unreachable; individual unmount failures are logged and left to the
+/// direct fallback and the exclusive open.
+pub async fn unmount_udisks2(device_path: &str) -> Result<(), String> {
+    use udisks2::zbus::zvariant::Value;
+
+    let mounts = sysfs::disk_mounts(device_path.trim_start_matches("/dev/"));
+    if mounts.is_empty() {
+        return Ok(());
+    }
+
+    let client = udisks2::Client::new()
+        .await
+        .map_err(|e| format!("Failed to connect to UDisks2: {}", e))?;
+
+    let mut done = HashSet::new();
+    for (mount_point, name) in mounts {
+        // One Unmount call releases all mount points of a filesystem.
+        if !done.insert(name.clone()) {
+            continue;
+        }
+
+        let object_path = udisks_object_path(&name);
+        let result = async {
+            let object = client
+                .object(object_path.as_str())
+                .map_err(|e| e.to_string())?;
+            let filesystem = object.filesystem().await.map_err(|e| e.to_string())?;
+            let options: HashMap<&str, Value<'_>> = HashMap::new();
+            filesystem.unmount(options).await.map_err(|e| e.to_string())
+        }
+        .await;
+
+        match result {
+            Ok(()) => log_info!(MODULE, "Unmounted {} ({}) via UDisks2", mount_point, name),
+            Err(e) => log_warn!(
+                MODULE,
+                "UDisks2 could not unmount {} ({}): {}",
+                mount_point,
+                name,
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an open error means someone else holds the device.
+pub fn is_busy(message: &str) -> bool {
+    message.contains("Device or resource busy") || message.contains("EBUSY")
+}
+
+/// `[DEVICE_BUSY]` error naming whatever still holds the disk: processes with it
+/// open, remaining mounts and device-mapper/md devices stacked on it.
+pub fn busy_error(device_path: &str) -> String {
+    let disk = device_path.trim_start_matches("/dev/");
+    let mut own: BTreeSet<String> = sysfs::partitions(disk).into_iter().collect();
+    own.insert(disk.to_string());
+
+    let mut reasons: Vec<String> = holder_processes(&own)
+        .into_iter()
+        .map(|(pid, comm)| format!("{} (pid {})", comm, pid))
+        .collect();
+
+    reasons.extend(
+        sysfs::disk_mounts(disk)
+            .into_iter()
+            .map(|(mount_point, _)| format!("mounted at {}", mount_point)),
+    );
+
+    let stacked: BTreeSet<String> = own.iter().flat_map(|name| sysfs::holders(name)).collect();
+    reasons.extend(stacked.into_iter().map(|h| format!("held by /dev/{}", h)));
+
+    log_warn!(MODULE, "{} is busy: {:?}", device_path, reasons);
+
+    if reasons.is_empty() {
+        format!("[DEVICE_BUSY] {} is in use by another program", device_path)
+    } else {
+        format!(
+            "[DEVICE_BUSY] {} is in use: {}",
+            device_path,
+            reasons.join(", ")
+        )
+    }
+}
+
+/// Processes with one of `names` open, from /proc/<pid>/fd. Only processes we may
+/// inspect are found (all of them as root, our own user's otherwise).
+fn holder_processes(names: &BTreeSet<String>) -> Vec<(u32, String)> {
+    let targets: HashSet<String> = names.iter().map(|n| format!("/dev/{}", n)).collect();
+    let own_pid = std::process::id();
+
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut holders = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == own_pid {
+            continue;
+        }
+
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds = fds.filter_map(|fd| fd.ok()).any(|fd| {
+            std::fs::read_link(fd.path())
+                .is_ok_and(|target| targets.contains(target.to_string_lossy().as_ref()))
+        });
+
+        if holds {
+            let comm =
+                std::fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("comm"))
+                    .map(|c| c.trim().to_string())
+                    .unwrap_or_else(|_| "unknown".to_string());
+            log_debug!(MODULE, "Process {} ({}) holds the device", pid, comm);
+            holders.push((pid, comm));
+        }
+    }
+    holders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udisks_object_path_escapes() {
+        assert_eq!(
+            udisks_object_path("sdb"),
+            "/org/freedesktop/UDisks2/block_devices/sdb"
+        );
+        assert_eq!(
+            udisks_object_path("dm-0"),
+            "/org/freedesktop/UDisks2/block_devices/dm_2d0"
+        );
+        assert_eq!(
+            udisks_object_path("mmcblk0p1"),
+            "/org/freedesktop/UDisks2/block_devices/mmcblk0p1"
+        );
+    }
+
+    #[test]
+    fn test_is_busy() {
+        assert!(is_busy(
+            "Failed to open device: Error opening device /dev/sdb: Device or resource busy"
+        ));
+        assert!(!is_busy("Failed to open device: Not authorized"));
+    }
+}
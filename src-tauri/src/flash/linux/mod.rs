@@ -1,6 +1,7 @@
 //! Linux-specific flash implementation. Uses UDisks2 (polkit) for device
 //! access, falling back to a direct root open.
 
+mod exclusive;
 mod privileges;
 mod writer;
 
@@ -7,17 +7,23 @@ use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 
+use super::exclusive;
 use crate::config;
-use crate::flash::{sync_device, unmount_device, FlashState};
-use crate::utils::{bytes_to_gb, ProgressTracker};
+use crate::flash::{
+    checkpoint, diagnostics, sync_device, unmount_device, FlashOptions, FlashState,
+};
+use crate::utils::{bytes_to_gb, phase, ProgressTracker};
 use crate::{log_debug, log_error, log_info};
 
 const MODULE: &str = "flash::linux::writer";
 
 /// Open a block device for writing via UDisks2, which prompts polkit auth as needed.
+/// The open is O_EXCL, so it fails while anything else still has the disk claimed.
 async fn open_device_udisks2(device_path: &str) -> Result<File, String> {
     use std::collections::HashMap;
+    use udisks2::zbus::zvariant::Value;
 
     log_debug!(MODULE, "Opening device via UDisks2: {}", device_path);
 
@@ -25,12 +31,11 @@ async fn open_device_udisks2(device_path: &str) -> Result<File, String> {
         .await
         .map_err(|e| format!("Failed to connect to UDisks2: {}", e))?;
 
-    // /dev/sdX maps to /org/freedesktop/UDisks2/block_devices/sdX.
     let dev_name = device_path
         .strip_prefix("/dev/")
         .ok_or_else(|| format!("Invalid device path: {}", device_path))?;
 
-    let object_path = format!("/org/freedesktop/UDisks2/block_devices/{}", dev_name);
+    let object_path = exclusive::udisks_object_path(dev_name);
 
     log_debug!(MODULE, "UDisks2 object path: {}", object_path);
 
@@ -43,12 +48,20 @@ async fn open_device_udisks2(device_path: &str) -> Result<File, String> {
         .await
         .map_err(|e| format!("Failed to get block interface: {}", e))?;
 
-    let options: HashMap<&str, udisks2::zbus::zvariant::Value<'_>> = HashMap::new();
-
-    let fd = block
-        .open_device("rw", options)
-        .await
-        .map_err(|e| format!("Failed to open device (polkit auth may have failed): {}", e))?;
+    let mut options: HashMap<&str, Value<'_>> = HashMap::new();
+    options.insert("flags", Value::from(libc::O_EXCL));
+
+    let fd = block.open_device("rw", options).await.map_err(|e| {
+        let message = e.to_string();
+        if exclusive::is_busy(&message) {
+            exclusive::busy_error(device_path)
+        } else {
+            format!(
+                "Failed to open device (polkit auth may have failed): {}",
+                message
+            )
+        }
+    })?;
 
     log_debug!(MODULE, "Device opened successfully via UDisks2");
 
@@ -63,14 +76,22 @@ async fn open_device_udisks2(device_path: &str) -> Result<File, String> {
 /// Fallback open requiring root, used when UDisks2 is unavailable.
 fn open_device_direct(device_path: &str) -> Result<File, String> {
     use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
 
     log_debug!(MODULE, "Attempting direct device open: {}", device_path);
 
     OpenOptions::new()
         .read(true)
         .write(true)
+        .custom_flags(libc::O_EXCL)
         .open(device_path)
-        .map_err(|e| format!("Failed to open device {}: {}", device_path, e))
+        .map_err(|e| {
+            if e.raw_os_error() == Some(libc::EBUSY) {
+                exclusive::busy_error(device_path)
+            } else {
+                format!("Failed to open device {}: {}", device_path, e)
+            }
+        })
 }
 
 /// Flash an image to a block device
@@ -78,10 +99,13 @@ pub async fn flash_image(
     image_path: &PathBuf,
     device_path: &str,
     state: Arc<FlashState>,
-    verify: bool,
+    options: FlashOptions,
 ) -> Result<(), String> {
     state.reset();
 
+    let mut checkpoint = options.checkpoint;
+    let resume_from = checkpoint.as_ref().map_or(0, |cp| cp.durable_offset);
+
     log_info!(
         MODULE,
         "Starting flash: {} -> {}",
@@ -102,7 +126,22 @@ pub async fn flash_image(
         bytes_to_gb(image_size)
     );
 
+    if resume_from > image_size {
+        return Err(format!(
+            "[RESUME_UNAVAILABLE] Checkpoint offset {} is past the end of the image",
+            resume_from
+        ));
+    }
+
     log_info!(MODULE, "Unmounting device partitions...");
+    if let Err(e) = exclusive::unmount_udisks2(device_path).await {
+        log_debug!(
+            MODULE,
+            "UDisks2 unmount unavailable ({}), unmounting directly",
+            e
+        );
+    }
+    // Catches mounts UDisks2 does not manage (fstab, manual mounts as root).
     unmount_device(device_path)?;
 
     // Give the unmount a moment to settle before writing.
@@ -114,6 +153,8 @@ pub async fn flash_image(
     log_debug!(MODULE, "Opening device for writing...");
     let mut device = match open_device_udisks2(device_path).await {
         Ok(file) => file,
+        // Busy is busy: a direct open would hit the same claim.
+        Err(e) if e.starts_with("[DEVICE_BUSY]") => return Err(e),
         Err(e) => {
             log_debug!(MODULE, "UDisks2 open failed ({}), trying direct open...", e);
             open_device_direct(device_path)?
@@ -122,21 +163,51 @@ pub async fn flash_image(
 
     let device_fd = device.as_raw_fd();
 
-    quick_erase(&mut device)?;
-
-    let mut image_file =
-        File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+    // A resumed write keeps the partition table already on the card, and a delta
+    // write must see the old contents to compare against.
+    let mut image_file: Box<dyn Read + Send> = if resume_from > 0 {
+        log_info!(MODULE, "Resuming write at byte {}", resume_from);
+        let mut image_file =
+            File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+        let start = resume_check_start(resume_from);
+        // Read from the media, not a page cache left over from before the failure.
+        unsafe {
+            libc::posix_fadvise(
+                device_fd,
+                start as i64,
+                (resume_from - start) as i64,
+                libc::POSIX_FADV_DONTNEED,
+            );
+        }
+        if !prepare_resume(&mut image_file, &mut device, resume_from)? {
+            checkpoint::clear();
+            return Err(format!(
+                "[RESUME_VERIFY_FAILED:{}] Device contents before the resume point do not match the image",
+                start
+            ));
+        }
+        Box::new(image_file)
+    } else {
+        if !options.delta {
+            quick_erase(&mut device)?;
+        }
+        crate::flash::open_image(image_path, &options)?
+    };
 
     let chunk_size = config::flash::CHUNK_SIZE;
     let mut buffer = vec![0u8; chunk_size];
-    let mut written: u64 = 0;
+    let mut delta = options.delta.then(|| DeltaComparer::new(chunk_size));
+    state.is_delta.store(options.delta, Ordering::SeqCst);
+    let mut written: u64 = resume_from;
+    state.written_bytes.store(written, Ordering::SeqCst);
 
     let mut tracker = ProgressTracker::new(
         "Write",
         MODULE,
-        image_size,
+        image_size - resume_from,
         config::logging::WRITE_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::WRITE);
 
     log_info!(MODULE, "Writing image...");
 
@@ -147,6 +218,7 @@ pub async fn flash_image(
         if state.is_cancelled.load(Ordering::SeqCst) {
             return Err("Flash cancelled".to_string());
         }
+        state.ensure_target_present()?;
 
         let bytes_read = image_file
             .read(&mut buffer)
@@ -156,9 +228,25 @@ pub async fn flash_image(
             break;
         }
 
-        if let Err(e) = device.write_all(&buffer[..bytes_read]) {
-            log_error!(MODULE, "Write error at byte {}: {}", written, e);
-            return Err(crate::flash::write_failed_err(written, e));
+        let unchanged = match delta.as_mut() {
+            Some(comparer) => {
+                comparer.chunk_matches(&mut device, written, &buffer[..bytes_read], &state)?
+            }
+            None => false,
+        };
+
+        if !unchanged {
+            let started = Instant::now();
+            if let Err(e) = device.write_all(&buffer[..bytes_read]) {
+                log_error!(MODULE, "Write error at byte {}: {}", written, e);
+                let err = crate::flash::write_failed_err(written, e);
+                if options.diagnose {
+                    let failed = written..written + bytes_read as u64;
+                    diagnostics::run(&mut device, image_path, device_path, failed, &err);
+                }
+                return Err(err);
+            }
+            state.record_write(bytes_read as u64, started);
         }
 
         written += bytes_read as u64;
@@ -166,19 +254,41 @@ pub async fn flash_image(
 
         if bytes_since_sync >= config::logging::LINUX_SYNC_INTERVAL {
             // A failing card often surfaces only here, when buffered pages hit the device.
+            let started = Instant::now();
             if unsafe { libc::fdatasync(device_fd) } != 0 {
                 let e = std::io::Error::last_os_error();
                 log_error!(MODULE, "fdatasync failed at byte {}: {}", written, e);
-                return Err(crate::flash::write_failed_err(written, e));
+                let err = crate::flash::write_failed_err(written, e);
+                if options.diagnose {
+                    // Any of the pages since the last sync may be the bad ones.
+                    let failed = written - bytes_since_sync..written;
+                    diagnostics::run(&mut device, image_path, device_path, failed, &err);
+                }
+                return Err(err);
             }
+            // The sync is where buffered writes really hit the media, so it counts as write time.
+            state.record_write(0, started);
             bytes_since_sync = 0;
             state.written_bytes.store(written, Ordering::SeqCst);
+
+            // Everything up to here is on the media, so a later failure can resume from it.
+            if let Some(cp) = checkpoint.as_mut() {
+                cp.advance(written);
+            }
         }
 
         tracker.update(bytes_read as u64);
     }
 
     tracker.finish();
+    if options.delta {
+        log_info!(
+            MODULE,
+            "Delta write: {} of {} bytes already matched and were skipped",
+            state.skipped_bytes.load(Ordering::SeqCst),
+            state.compared_bytes.load(Ordering::SeqCst)
+        );
+    }
     log_debug!(MODULE, "Syncing...");
 
     device
@@ -187,7 +297,11 @@ pub async fn flash_image(
     crate::flash::fsync_checked(device_fd, written)?;
     sync_device(device_path);
 
-    if verify {
+    if checkpoint.is_some() {
+        checkpoint::clear();
+    }
+
+    if options.verify {
         log_info!(MODULE, "Starting verification...");
         state.is_verifying.store(true, Ordering::SeqCst);
         state.verified_bytes.store(0, Ordering::SeqCst);
@@ -208,6 +322,53 @@ pub async fn flash_image(
     Ok(())
 }
 
+/// Start of the chunk checked before resuming at `offset`.
+fn resume_check_start(offset: u64) -> u64 {
+    offset.saturating_sub(config::flash::CHUNK_SIZE as u64)
+}
+
+/// Check that the chunk just before `offset` already matches the image, leaving both
+/// positioned at `offset`. `false` means the card was swapped or rewritten since the
+/// checkpoint was taken.
+fn prepare_resume<I: Read + Seek, D: Read + Seek>(
+    image: &mut I,
+    device: &mut D,
+    offset: u64,
+) -> Result<bool, String> {
+    let start = resume_check_start(offset);
+    let len = (offset - start) as usize;
+
+    let mut expected = vec![0u8; len];
+    image
+        .seek(SeekFrom::Start(start))
+        .and_then(|_| image.read_exact(&mut expected))
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+
+    let mut actual = vec![0u8; len];
+    device
+        .seek(SeekFrom::Start(start))
+        .and_then(|_| device.read_exact(&mut actual))
+        .map_err(|e| format!("[RESUME_VERIFY_FAILED:{}] {}", start, e))?;
+
+    if expected != actual {
+        log_error!(
+            MODULE,
+            "Resume check failed: device bytes {}..{} differ from the image",
+            start,
+            offset
+        );
+        return Ok(false);
+    }
+
+    log_debug!(
+        MODULE,
+        "Resume check passed for bytes {}..{}",
+        start,
+        offset
+    );
+    Ok(true)
+}
+
 /// Zero the first portion of the device to wipe the old partition table.
 fn quick_erase(device: &mut File) -> Result<(), String> {
     let erase_size = config::flash::QUICK_ERASE_SIZE;
@@ -246,3 +407,66 @@ fn verify_written_data(
 ) -> Result<(), String> {
     crate::flash::verify::verify_data(image_path, device, state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const CHUNK: usize = config::flash::CHUNK_SIZE;
+
+    fn image() -> Vec<u8> {
+        (0..3 * CHUNK).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn resume_continues_at_checkpoint_offset() {
+        let data = image();
+        let offset = 2 * CHUNK as u64;
+        // Card written up to the checkpoint, stale bytes after it.
+        let mut card = data.clone();
+        card[offset as usize..].fill(0xEE);
+
+        let mut image_file = Cursor::new(data.clone());
+        let mut device = Cursor::new(card);
+        assert!(prepare_resume(&mut image_file, &mut device, offset).unwrap());
+        assert_eq!(image_file.position(), offset);
+        assert_eq!(device.position(), offset);
+
+        let mut next = [0u8; 16];
+        image_file.read_exact(&mut next).unwrap();
+        assert_eq!(next, data[offset as usize..offset as usize + 16]);
+    }
+
+    #[test]
+    fn resume_rejects_mismatched_chunk() {
+        let data = image();
+        let offset = 2 * CHUNK as u64;
+        // One byte differs in the chunk just before the checkpoint.
+        let mut card = data.clone();
+        card[offset as usize - 1] ^= 0xFF;
+
+        let mut image_file = Cursor::new(data.clone());
+        let mut device = Cursor::new(card);
+        assert!(!prepare_resume(&mut image_file, &mut device, offset).unwrap());
+
+        // Differences before that chunk are not looked at.
+        let mut card = data.clone();
+        card[0] ^= 0xFF;
+        let mut device = Cursor::new(card);
+        assert!(prepare_resume(&mut Cursor::new(data), &mut device, offset).unwrap());
+    }
+
+    #[test]
+    fn resume_inside_first_chunk_and_read_error() {
+        let data = image();
+        // A checkpoint inside the first chunk compares everything before it.
+        let mut device = Cursor::new(data[..100].to_vec());
+        assert!(prepare_resume(&mut Cursor::new(data.clone()), &mut device, 100).unwrap());
+
+        // A card shorter than the checkpoint is a read error, not a mismatch.
+        let err = prepare_resume(&mut Cursor::new(data), &mut Cursor::new(vec![0u8; 10]), 100)
+            .unwrap_err();
+        assert!(err.starts_with("[RESUME_VERIFY_FAILED:0]"), "{}", err);
+    }
+}
@@ -6,10 +6,12 @@ use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 
 use crate::config;
-use crate::flash::{sync_device, unmount_device, FlashState};
-use crate::utils::{bytes_to_gb, ProgressTracker};
+use crate::flash::delta::DeltaComparer;
+use crate::flash::{diagnostics, sync_device, unmount_device, FlashOptions, FlashState};
+use crate::utils::{bytes_to_gb, phase, ProgressTracker};
 use crate::{log_debug, log_error, log_info};
 
 use super::authorization::{free_authorization, SAVED_AUTH};
@@ -228,7 +230,7 @@ pub async fn flash_image(
     image_path: &PathBuf,
     device_path: &str,
     state: Arc<FlashState>,
-    verify: bool,
+    options: FlashOptions,
 ) -> Result<(), String> {
     state.reset();
 
@@ -270,7 +272,7 @@ pub async fn flash_image(
         device_fd,
         image_size,
         state,
-        verify,
+        options,
     )
     .await;
 
@@ -290,15 +292,19 @@ async fn do_flash_work(
     device_fd: i32,
     image_size: u64,
     state: Arc<FlashState>,
-    verify: bool,
+    options: FlashOptions,
 ) -> Result<(), String> {
-    quick_erase(device, device_fd)?;
+    // A delta write must see the old contents to compare against.
+    if !options.delta {
+        quick_erase(device, device_fd)?;
+    }
 
-    let mut image_file =
-        File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+    let mut image_file = crate::flash::open_image(image_path, &options)?;
 
     let chunk_size = config::flash::CHUNK_SIZE;
     let mut buffer = vec![0u8; chunk_size];
+    let mut delta = options.delta.then(|| DeltaComparer::new(chunk_size));
+    state.is_delta.store(options.delta, Ordering::SeqCst);
     let mut written: u64 = 0;
 
     let mut tracker = ProgressTracker::new(
@@ -306,7 +312,8 @@ async fn do_flash_work(
         MODULE,
         image_size,
         config::logging::WRITE_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::WRITE);
 
     log_info!(
         MODULE,
@@ -319,6 +326,7 @@ async fn do_flash_work(
         if state.is_cancelled.load(Ordering::SeqCst) {
             return Err("Flash cancelled".to_string());
         }
+        state.ensure_target_present()?;
 
         let bytes_read = image_file
             .read(&mut buffer)
@@ -343,15 +351,31 @@ async fn do_flash_work(
             bytes_read
         };
 
-        if let Err(e) = device.write_all(&buffer[..bytes_to_write]) {
-            log_error!(
-                MODULE,
-                "Write error at byte {}/{}: {}",
-                written,
-                image_size,
-                e
-            );
-            return Err(crate::flash::write_failed_err(written, e));
+        let unchanged = match delta.as_mut() {
+            Some(comparer) => {
+                comparer.chunk_matches(device, written, &buffer[..bytes_to_write], &state)?
+            }
+            None => false,
+        };
+
+        if !unchanged {
+            let started = Instant::now();
+            if let Err(e) = device.write_all(&buffer[..bytes_to_write]) {
+                log_error!(
+                    MODULE,
+                    "Write error at byte {}/{}: {}",
+                    written,
+                    image_size,
+                    e
+                );
+                let err = crate::flash::write_failed_err(written, e);
+                if options.diagnose {
+                    let failed = written..written + bytes_to_write as u64;
+                    diagnostics::run(device, image_path, device_path, failed, &err);
+                }
+                return Err(err);
+            }
+            state.record_write(bytes_to_write as u64, started);
         }
 
         // Count real image bytes, not the sector padding.
@@ -371,7 +395,7 @@ async fn do_flash_work(
     sync_device(device_path);
 
     // Verification reuses the same fd, so no extra auth prompt.
-    if verify {
+    if options.verify {
         log_info!(MODULE, "Starting verification");
         verify_written_data(image_path, device, device_fd, state.clone())?;
     }
@@ -1,7 +1,12 @@
 //! Platform-specific image flashing: privilege escalation + raw device writing.
 //! macOS uses authopen (Touch ID), Linux uses pkexec, Windows needs Administrator.
 
-mod verify;
+pub mod checkpoint;
+mod delta;
+pub mod diagnostics;
+pub mod fanout;
+pub mod multi;
+pub mod verify;
 
 #[cfg(target_os = "linux")]
 mod linux;
@@ -55,6 +60,21 @@ pub struct FlashState {
     pub is_cancelled: AtomicBool,
     pub error: Mutex<Option<String>>,
     pub qdl: QdlProgress,
+    /// Delta mode: only chunks that differ from the device are written
+    pub is_delta: AtomicBool,
+    /// Delta mode: bytes read back from the device and compared
+    pub compared_bytes: AtomicU64,
+    /// Delta mode: bytes already identical on the device, not rewritten
+    pub skipped_bytes: AtomicU64,
+    /// Time spent in read-compare (microseconds)
+    pub compare_micros: AtomicU64,
+    /// Bytes actually sent to the device and the time spent writing them (microseconds)
+    pub device_written_bytes: AtomicU64,
+    pub write_micros: AtomicU64,
+    /// Set by the device watcher when the flash target disappears mid-write
+    pub target_removed: AtomicBool,
+    /// Job this state reports progress for, 0 outside the job manager
+    pub job_id: AtomicU64,
 }
 
 impl FlashState {
@@ -67,6 +87,14 @@ impl FlashState {
             is_cancelled: AtomicBool::new(false),
             error: Mutex::new(None),
             qdl: QdlProgress::new(),
+            is_delta: AtomicBool::new(false),
+            compared_bytes: AtomicU64::new(0),
+            skipped_bytes: AtomicU64::new(0),
+            compare_micros: AtomicU64::new(0),
+            device_written_bytes: AtomicU64::new(0),
+            write_micros: AtomicU64::new(0),
+            target_removed: AtomicBool::new(false),
+            job_id: AtomicU64::new(0),
         }
     }
 
@@ -77,9 +105,68 @@ impl FlashState {
         self.is_verifying.store(false, Ordering::SeqCst);
         self.is_cancelled.store(false, Ordering::SeqCst);
         self.qdl.reset();
+        self.is_delta.store(false, Ordering::SeqCst);
+        self.compared_bytes.store(0, Ordering::SeqCst);
+        self.skipped_bytes.store(0, Ordering::SeqCst);
+        self.compare_micros.store(0, Ordering::SeqCst);
+        self.device_written_bytes.store(0, Ordering::SeqCst);
+        self.write_micros.store(0, Ordering::SeqCst);
+        self.target_removed.store(false, Ordering::SeqCst);
+    }
+
+    /// Account a timed device write, for write throughput separate from read-compare.
+    pub fn record_write(&self, bytes: u64, started: std::time::Instant) {
+        self.device_written_bytes.fetch_add(bytes, Ordering::SeqCst);
+        self.write_micros
+            .fetch_add(started.elapsed().as_micros() as u64, Ordering::SeqCst);
+    }
+
+    /// Fail fast once the watcher has seen the target go away, rather than
+    /// filling the page cache of a device that no longer exists.
+    pub fn ensure_target_present(&self) -> Result<(), String> {
+        if self.target_removed.load(Ordering::SeqCst) {
+            return Err(device_removed_err());
+        }
+        Ok(())
     }
 }
 
+impl Default for FlashState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-flash options handed to the platform writers.
+#[derive(Debug, Clone, Default)]
+pub struct FlashOptions {
+    /// Read the device back and compare it with the image after writing
+    pub verify: bool,
+    /// Checkpoint updated at each durable sync (Linux only); a non-zero
+    /// `durable_offset` resumes the write from that byte
+    pub checkpoint: Option<checkpoint::FlashCheckpoint>,
+    /// Probe around the failing offset and store a bad-region report on write failure
+    pub diagnose: bool,
+    /// Read-compare each chunk first and only write the ones that differ
+    pub delta: bool,
+    /// Image stream shared with other writers; `None` reads `image_path` directly
+    pub source: Option<fanout::SharedSource>,
+}
+
+/// The image data a writer should consume: the shared stream when flashing
+/// several devices at once, else the image file itself.
+pub(crate) fn open_image(
+    image_path: &std::path::Path,
+    options: &FlashOptions,
+) -> Result<Box<dyn std::io::Read + Send>, String> {
+    if let Some(reader) = options.source.as_ref().and_then(|s| s.take()) {
+        return Ok(Box::new(reader));
+    }
+    let file =
+        std::fs::File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+    Ok(Box::new(file))
+}
+
 #[cfg(target_os = "linux")]
 pub use linux::flash_image;
 #[cfg(target_os = "macos")]
@@ -111,16 +198,11 @@ pub(crate) fn unmount_device(device_path: &str) -> Result<(), String> {
 
     #[cfg(target_os = "linux")]
     {
-        let output = Command::new("lsblk")
-            .args(["-ln", "-o", "NAME", device_path])
-            .output();
+        use crate::devices::sysfs;
 
-        if let Ok(output) = output {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            for line in stdout.lines() {
-                let part_path = format!("/dev/{}", line.trim());
-                let _ = Command::new("umount").arg(&part_path).output();
-            }
+        let mounts = sysfs::disk_mounts(device_path.trim_start_matches("/dev/"));
+        for (mount_point, _) in mounts {
+            unmount_mount_point(&mount_point);
         }
     }
 
@@ -132,6 +214,23 @@ pub(crate) fn unmount_device(device_path: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Unmount one mount point: umount2(2) directly when privileged, else umount(8),
+/// whose helper hands udisks-managed mounts back to UDisks2 for an unprivileged user.
+#[cfg(target_os = "linux")]
+fn unmount_mount_point(mount_point: &str) {
+    if let Ok(c_path) = std::ffi::CString::new(mount_point) {
+        if unsafe { libc::umount2(c_path.as_ptr(), 0) } == 0 {
+            return;
+        }
+    }
+    let _ = Command::new("umount").arg(mount_point).output();
+}
+
+/// Tagged error for a flash target that disappeared; the frontend maps `[DEVICE_REMOVED]`.
+pub(crate) fn device_removed_err() -> String {
+    "[DEVICE_REMOVED] The target device was disconnected during the flash".to_string()
+}
+
 /// Tagged device-write failure; the frontend maps `[WRITE_FAILED:<offset>]` to a translated message.
 pub(crate) fn write_failed_err(offset: u64, e: impl std::fmt::Display) -> String {
     format!("[WRITE_FAILED:{}] {}", offset, e)
@@ -0,0 +1,156 @@
+//! Flash one image to several devices at once. Each device gets its own
+//! [`FlashState`] (progress, cancellation, error) and its own thread running the
+//! regular platform writer; the image is read once through [`fanout`]. A device
+//! that fails or is cancelled only drops out of the batch.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use super::fanout::{self, SharedSource};
+use super::{device_removed_err, flash_image, FlashOptions, FlashState};
+use crate::{log_error, log_info};
+
+const MODULE: &str = "flash::multi";
+
+/// One device of a batch.
+pub struct FlashTarget {
+    pub device_path: String,
+    pub state: Arc<FlashState>,
+    /// Set once this device is done, successfully or not
+    pub finished: AtomicBool,
+}
+
+impl FlashTarget {
+    pub fn new(device_path: &str) -> Self {
+        Self {
+            device_path: device_path.to_string(),
+            state: Arc::new(FlashState::new()),
+            finished: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Outcome for one device of a batch.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceFlashResult {
+    pub device_path: String,
+    pub success: bool,
+    /// Whether the device was read back and matched the image
+    pub verified: bool,
+    pub error: Option<String>,
+}
+
+/// Flash `image_path` to every target in parallel and wait for all of them.
+/// Results are in target order.
+pub async fn flash_many(
+    image_path: &Path,
+    targets: &[Arc<FlashTarget>],
+    options: FlashOptions,
+) -> Vec<DeviceFlashResult> {
+    log_info!(
+        MODULE,
+        "Flashing {} to {} device(s)",
+        image_path.display(),
+        targets.len()
+    );
+
+    let readers = match fanout::fan_out(image_path, targets.len()) {
+        Ok(readers) => readers,
+        Err(e) => {
+            return targets
+                .iter()
+                .map(|target| finish(target, Err(e.clone()), false))
+                .collect()
+        }
+    };
+
+    // The writers do blocking I/O, so each gets a thread of its own: sharing runtime
+    // workers would stall the fan-out once there are more devices than workers.
+    let verify = options.verify;
+    let handles: Vec<_> = targets
+        .iter()
+        .zip(readers)
+        .map(|(target, reader)| {
+            let thread_target = target.clone();
+            let image_path = image_path.to_path_buf();
+            let options = FlashOptions {
+                source: Some(SharedSource::new(reader)),
+                ..options.clone()
+            };
+            let spawned = std::thread::Builder::new()
+                .name(format!("flash {}", target.device_path))
+                .spawn(move || {
+                    let target = thread_target;
+                    let result = tauri::async_runtime::block_on(flash_image(
+                        &image_path,
+                        &target.device_path,
+                        target.state.clone(),
+                        options,
+                    ));
+                    finish(&target, result, verify)
+                });
+            (target.clone(), spawned)
+        })
+        .collect();
+
+    let joined = tokio::task::spawn_blocking(move || {
+        handles
+            .into_iter()
+            .map(|(target, spawned)| {
+                let result = match spawned {
+                    Ok(handle) => handle.join().map_err(|_| "Flash thread panicked"),
+                    Err(_) => Err("Failed to start flash thread"),
+                };
+                result.unwrap_or_else(|e| finish(&target, Err(e.to_string()), verify))
+            })
+            .collect::<Vec<_>>()
+    })
+    .await;
+
+    match joined {
+        Ok(results) => {
+            let failed = results.iter().filter(|r| !r.success).count();
+            log_info!(
+                MODULE,
+                "Batch finished: {} succeeded, {} failed",
+                results.len() - failed,
+                failed
+            );
+            results
+        }
+        Err(e) => targets
+            .iter()
+            .map(|target| finish(target, Err(e.to_string()), verify))
+            .collect(),
+    }
+}
+
+/// Record a device's outcome in its state and turn it into a result.
+fn finish(target: &FlashTarget, result: Result<(), String>, verify: bool) -> DeviceFlashResult {
+    // A write into a vanished device surfaces as an I/O error; report the real cause.
+    let result = result.map_err(|e| {
+        if target.state.target_removed.load(Ordering::SeqCst) {
+            device_removed_err()
+        } else {
+            e
+        }
+    });
+
+    if let Err(e) = &result {
+        log_error!(MODULE, "{} failed: {}", target.device_path, e);
+        if let Ok(mut error) = target.state.error.try_lock() {
+            *error = Some(e.clone());
+        }
+    }
+    target.finished.store(true, Ordering::SeqCst);
+
+    DeviceFlashResult {
+        device_path: target.device_path.clone(),
+        success: result.is_ok(),
+        verified: verify && result.is_ok(),
+        error: result.err(),
+    }
+}
@@ -3,10 +3,10 @@
 #![allow(dead_code)]
 
 use crate::config;
-use crate::utils::{bytes_to_gb, ProgressTracker};
+use crate::utils::{bytes_to_gb, phase, ProgressTracker};
 use crate::{log_error, log_info};
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufReader, Read};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -45,7 +45,8 @@ pub fn verify_data<R: Read>(
         MODULE,
         image_size,
         config::logging::WRITE_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::VERIFY);
 
     log_info!(
         MODULE,
@@ -117,6 +118,24 @@ pub fn verify_data<R: Read>(
     Ok(())
 }
 
+/// Compare a device with an image without writing it first (`cli verify`).
+pub fn verify_device(
+    image_path: &PathBuf,
+    device_path: &str,
+    state: Arc<FlashState>,
+) -> Result<(), String> {
+    let image_size = std::fs::metadata(image_path)
+        .map_err(|e| format!("Failed to read image metadata: {}", e))?
+        .len();
+    state.total_bytes.store(image_size, Ordering::SeqCst);
+
+    let device =
+        File::open(device_path).map_err(|e| format!("Failed to open device for reading: {}", e))?;
+    // Raw devices on macOS and Windows reject reads that are not sector-aligned.
+    let mut reader = BufReader::with_capacity(config::flash::CHUNK_SIZE, device);
+    verify_data(image_path, &mut reader, state)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -1,13 +1,15 @@
 //! Windows-specific flash implementation. Requires Administrator for raw disk access.
 
-use super::FlashState;
+use super::delta::DeltaComparer;
+use super::{diagnostics, FlashOptions, FlashState};
 use crate::config;
-use crate::utils::{bytes_to_gb, ProgressTracker};
+use crate::utils::{bytes_to_gb, phase, ProgressTracker};
 use crate::{log_debug, log_error, log_info, log_warn};
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
 
 const MODULE: &str = "flash::windows";
 
@@ -28,7 +30,7 @@ pub async fn flash_image(
     image_path: &PathBuf,
     device_path: &str,
     state: Arc<FlashState>,
-    verify: bool,
+    options: FlashOptions,
 ) -> Result<(), String> {
     state.reset();
 
@@ -60,8 +62,7 @@ pub async fn flash_image(
         config::flash::UNMOUNT_DELAY_MS,
     ));
 
-    let mut image_file =
-        std::fs::File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+    let mut image_file = super::open_image(image_path, &options)?;
 
     log_debug!(MODULE, "Opening device for writing...");
     let mut device = open_device_for_write(device_path)?;
@@ -69,13 +70,16 @@ pub async fn flash_image(
     let chunk_size = config::flash::CHUNK_SIZE;
     let mut buffer = vec![0u8; chunk_size];
     let mut written: u64 = 0;
+    let mut delta = options.delta.then(|| DeltaComparer::new(chunk_size));
+    state.is_delta.store(options.delta, Ordering::SeqCst);
 
     let mut tracker = ProgressTracker::new(
         "Write",
         MODULE,
         image_size,
         config::logging::WRITE_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::WRITE);
 
     log_info!(MODULE, "Writing image to device...");
 
@@ -84,6 +88,7 @@ pub async fn flash_image(
             log_info!(MODULE, "Flash cancelled by user");
             return Err("Flash cancelled".to_string());
         }
+        state.ensure_target_present()?;
 
         let bytes_read = image_file.read(&mut buffer).map_err(|e| {
             log_error!(MODULE, "Failed to read image: {}", e);
@@ -94,15 +99,31 @@ pub async fn flash_image(
             break;
         }
 
-        device.write_all(&buffer[..bytes_read]).map_err(|e| {
-            log_error!(
-                MODULE,
-                "Failed to write to device at byte {}: {}",
-                written,
-                e
-            );
-            super::write_failed_err(written, e)
-        })?;
+        let unchanged = match delta.as_mut() {
+            Some(comparer) => {
+                comparer.chunk_matches(&mut device, written, &buffer[..bytes_read], &state)?
+            }
+            None => false,
+        };
+
+        if !unchanged {
+            let started = Instant::now();
+            if let Err(e) = device.write_all(&buffer[..bytes_read]) {
+                log_error!(
+                    MODULE,
+                    "Failed to write to device at byte {}: {}",
+                    written,
+                    e
+                );
+                let err = super::write_failed_err(written, e);
+                if options.diagnose {
+                    let failed = written..written + bytes_read as u64;
+                    diagnostics::run(&mut device, image_path, device_path, failed, &err);
+                }
+                return Err(err);
+            }
+            state.record_write(bytes_read as u64, started);
+        }
 
         written += bytes_read as u64;
         state.written_bytes.store(written, Ordering::SeqCst);
@@ -118,7 +139,7 @@ pub async fn flash_image(
 
     tracker.finish();
 
-    if verify {
+    if options.verify {
         log_info!(MODULE, "Starting verification...");
         drop(device);
         std::thread::sleep(std::time::Duration::from_millis(
@@ -421,7 +442,8 @@ fn verify_with_sector_alignment(
         MODULE,
         image_size,
         config::logging::WRITE_LOG_INTERVAL_MB,
-    );
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::VERIFY);
 
     while verified < image_size {
         if state.is_cancelled.load(Ordering::SeqCst) {
@@ -0,0 +1,476 @@
+//! Flash history: one JSON line per finished flash or QDL job in the app data dir,
+//! kept for production traceability. A record names the autoconfig profile used
+//! but never stores its contents.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::async_runtime::JoinHandle;
+
+use crate::devices::{get_block_devices, BlockDevice};
+use crate::jobs::{Job, JobKind, JobStatus};
+use crate::utils::{bytes_to_mb, flash_history_path, image_sha256};
+use crate::{log_info, log_warn};
+
+const MODULE: &str = "history";
+
+/// Serializes access to the history file between concurrent jobs
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// What the caller (UI or CLI) knows about a flash that the backend cannot derive itself
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlashContext {
+    /// Board slug the image was chosen for
+    pub board: Option<String>,
+    /// Name of the autoconfig profile applied, if any
+    pub profile_name: Option<String>,
+}
+
+/// The flashed device as it was before the flash started
+#[derive(Debug, Clone, Default)]
+pub struct TargetInfo {
+    pub path: String,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub size: Option<u64>,
+}
+
+impl TargetInfo {
+    pub fn block(device_path: &str) -> Self {
+        let devices = get_block_devices().unwrap_or_default();
+        Self::from_devices(&devices, device_path)
+    }
+
+    pub fn from_devices(devices: &[BlockDevice], device_path: &str) -> Self {
+        match devices.iter().find(|d| d.path == device_path) {
+            Some(device) => Self {
+                path: device.path.clone(),
+                model: Some(device.model.clone()).filter(|m| !m.is_empty()),
+                serial: device.serial.clone(),
+                size: Some(device.size),
+            },
+            None => Self {
+                path: device_path.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// A Qualcomm device in EDL mode, identified by its USB serial when one was chosen
+    pub fn edl(serial: Option<&str>) -> Self {
+        Self {
+            path: "edl".to_string(),
+            model: Some("Qualcomm EDL".to_string()),
+            serial: serial.map(str::to_string),
+            size: None,
+        }
+    }
+}
+
+/// The image a flash started from, read once for all of the flash's records
+#[derive(Debug, Clone, Default)]
+pub struct FlashedImage {
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+}
+
+/// A flash's image, hashed alongside the write so recording the flash does not wait
+/// for a full read of the image afterwards.
+pub struct ImageHasher {
+    path: PathBuf,
+    hashing: Option<JoinHandle<FlashedImage>>,
+}
+
+impl ImageHasher {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            hashing: None,
+        }
+    }
+
+    /// Start hashing, once the flash is going to write; a refused flash is recorded
+    /// without a hash.
+    pub fn start(&mut self) {
+        if self.hashing.is_none() {
+            let path = self.path.clone();
+            self.hashing = Some(tauri::async_runtime::spawn_blocking(move || FlashedImage {
+                sha256: image_sha256(&path),
+                ..image_file(&path)
+            }));
+        }
+    }
+
+    /// The image to record. Await it before the command returns: the UI deletes a
+    /// downloaded image as soon as the flash command is done.
+    pub async fn finish(self) -> FlashedImage {
+        match self.hashing {
+            Some(hashing) => hashing.await.unwrap_or_else(|_| image_file(&self.path)),
+            None => image_file(&self.path),
+        }
+    }
+}
+
+/// Name and size of `path`, without a hash
+fn image_file(path: &Path) -> FlashedImage {
+    FlashedImage {
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        size: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        sha256: None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlashRecord {
+    /// Unique across app runs: `<started_at>-<job_id>`
+    pub id: String,
+    pub job_id: u64,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub image_name: String,
+    /// SHA256 of the selected image, before any autoconfig injection
+    pub image_sha256: Option<String>,
+    pub image_size: u64,
+    pub board: Option<String>,
+    pub device_path: String,
+    pub device_model: Option<String>,
+    pub device_serial: Option<String>,
+    pub device_size: Option<u64>,
+    pub profile_name: Option<String>,
+    /// Unix time in milliseconds
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub duration_secs: f64,
+    pub bytes_written: u64,
+    pub throughput_mbps: f64,
+    /// None when verification was off or never got to run
+    pub verified: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Criteria for [`query`]; unset fields match everything
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryFilter {
+    pub board: Option<String>,
+    /// Substring of the device path, model or serial (case-insensitive)
+    pub device: Option<String>,
+    pub status: Option<JobStatus>,
+    /// Unix time in milliseconds, inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Return at most this many records, newest first
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, record: &FlashRecord) -> bool {
+        let device = self.device.as_ref().map(|d| d.to_lowercase());
+        self.board
+            .as_ref()
+            .is_none_or(|b| record.board.as_ref() == Some(b))
+            && device.is_none_or(|d| {
+                [
+                    Some(&record.device_path),
+                    record.device_model.as_ref(),
+                    record.device_serial.as_ref(),
+                ]
+                .into_iter()
+                .flatten()
+                .any(|field| field.to_lowercase().contains(&d))
+            })
+            && self.status.is_none_or(|s| record.status == s)
+            && self.since.is_none_or(|t| record.started_at >= t)
+            && self.until.is_none_or(|t| record.started_at <= t)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+/// Record a finished flash job of `image`.
+pub fn record_job(
+    job: &Job,
+    image: &FlashedImage,
+    target: TargetInfo,
+    context: &FlashContext,
+    verify: bool,
+) {
+    let record = job.record();
+    let Some(state) = job.flash_state() else {
+        return;
+    };
+    let started_at = record.started_at.unwrap_or(job.created_at);
+    let finished_at = record.finished_at.unwrap_or(started_at);
+    let duration_secs = finished_at.saturating_sub(started_at) as f64 / 1000.0;
+    let bytes_written = state.written_bytes.load(Ordering::SeqCst);
+    let verified = match record.status {
+        _ if !verify || job.kind == JobKind::QdlFlash => None,
+        JobStatus::Done => Some(true),
+        _ if state.is_verifying.load(Ordering::SeqCst) => Some(false),
+        _ => None,
+    };
+
+    let entry = FlashRecord {
+        id: format!("{}-{}", started_at, job.id),
+        job_id: job.id,
+        kind: job.kind,
+        status: record.status,
+        image_name: image.name.clone(),
+        image_sha256: image.sha256.clone(),
+        image_size: image.size,
+        board: context.board.clone(),
+        device_path: target.path,
+        device_model: target.model,
+        device_serial: target.serial,
+        device_size: target.size,
+        profile_name: context.profile_name.clone(),
+        started_at,
+        finished_at,
+        duration_secs,
+        bytes_written,
+        throughput_mbps: if duration_secs > 0.0 {
+            bytes_to_mb(bytes_written) / duration_secs
+        } else {
+            0.0
+        },
+        verified,
+        error: record.error,
+    };
+
+    match append(&flash_history_path(), &entry) {
+        Ok(()) => log_info!(MODULE, "Recorded flash {}", entry.id),
+        Err(e) => log_warn!(MODULE, "Failed to record flash {}: {}", entry.id, e),
+    }
+}
+
+/// Records matching `filter`, newest first.
+pub fn query(filter: &HistoryFilter) -> Vec<FlashRecord> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+    let mut records: Vec<FlashRecord> = read_records(&flash_history_path())
+        .into_iter()
+        .filter(|r| filter.matches(r))
+        .collect();
+    records.reverse();
+    if let Some(limit) = filter.limit {
+        records.truncate(limit);
+    }
+    records
+}
+
+/// Delete the records with these IDs, returning how many were removed.
+pub fn delete(ids: &[String]) -> Result<usize, String> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+    let path = flash_history_path();
+    let mut records = read_records(&path);
+    let before = records.len();
+    records.retain(|r| !ids.contains(&r.id));
+    let removed = before - records.len();
+    if removed > 0 {
+        write_records(&path, &records)?;
+    }
+    Ok(removed)
+}
+
+/// Render one record as a standalone report.
+pub fn export(id: &str, format: ReportFormat) -> Result<String, String> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+    let record = read_records(&flash_history_path())
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| format!("[HISTORY_NOT_FOUND] {}", id))?;
+
+    match format {
+        ReportFormat::Json => serde_json::to_string_pretty(&record).map_err(|e| e.to_string()),
+        ReportFormat::Csv => to_csv(&record),
+    }
+}
+
+fn append(path: &Path, record: &FlashRecord) -> Result<(), String> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create history directory: {}", e))?;
+    }
+    let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open history: {}", e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("Failed to write history: {}", e))
+}
+
+/// All records, oldest first. Unreadable lines are skipped, not fatal.
+fn read_records(path: &Path) -> Vec<FlashRecord> {
+    let Ok(file) = File::open(path) else {
+        return Vec::new();
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                log_warn!(MODULE, "Skipping unreadable history line: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Replace the history file through a temp file, so a crash cannot truncate it.
+fn write_records(path: &Path, records: &[FlashRecord]) -> Result<(), String> {
+    let mut data = String::new();
+    for record in records {
+        data.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+        data.push('\n');
+    }
+    let temp = path.with_extension("jsonl.tmp");
+    std::fs::write(&temp, data).map_err(|e| format!("Failed to write history: {}", e))?;
+    std::fs::rename(&temp, path).map_err(|e| format!("Failed to replace history: {}", e))
+}
+
+/// CSV columns, in the order of the [`FlashRecord`] fields
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "job_id",
+    "kind",
+    "status",
+    "image_name",
+    "image_sha256",
+    "image_size",
+    "board",
+    "device_path",
+    "device_model",
+    "device_serial",
+    "device_size",
+    "profile_name",
+    "started_at",
+    "finished_at",
+    "duration_secs",
+    "bytes_written",
+    "throughput_mbps",
+    "verified",
+    "error",
+];
+
+/// Header row plus one row.
+fn to_csv(record: &FlashRecord) -> Result<String, String> {
+    let value = serde_json::to_value(record).map_err(|e| e.to_string())?;
+    let row: Vec<String> = CSV_COLUMNS
+        .iter()
+        .map(|column| match &value[*column] {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(s) => csv_field(s),
+            other => csv_field(&other.to_string()),
+        })
+        .collect();
+    Ok(format!(
+        "{}\r\n{}\r\n",
+        CSV_COLUMNS.join(","),
+        row.join(",")
+    ))
+}
+
+/// Quote a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, board: &str, serial: Option<&str>, status: JobStatus) -> FlashRecord {
+        FlashRecord {
+            id: id.to_string(),
+            job_id: 1,
+            kind: JobKind::Flash,
+            status,
+            image_name: "Armbian_25.8.1_Orangepi5_noble_vendor_6.1.115.img".to_string(),
+            image_sha256: None,
+            image_size: 1024,
+            board: Some(board.to_string()),
+            device_path: "/dev/sdb".to_string(),
+            device_model: Some("SD Card Reader".to_string()),
+            device_serial: serial.map(str::to_string),
+            device_size: Some(32_000_000_000),
+            profile_name: Some("Lab, rack 2".to_string()),
+            started_at: 1_000,
+            finished_at: 61_000,
+            duration_secs: 60.0,
+            bytes_written: 1024,
+            throughput_mbps: 0.0,
+            verified: Some(true),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let ok = record("1", "orangepi5", Some("AB12"), JobStatus::Done);
+        let filter = HistoryFilter {
+            device: Some("ab1".to_string()),
+            status: Some(JobStatus::Done),
+            ..Default::default()
+        };
+        assert!(filter.matches(&ok));
+
+        let other_board = HistoryFilter {
+            board: Some("rock-5b".to_string()),
+            ..Default::default()
+        };
+        assert!(!other_board.matches(&ok));
+        assert!(!HistoryFilter {
+            since: Some(2_000),
+            ..Default::default()
+        }
+        .matches(&ok));
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("history-test-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let first = record("1", "orangepi5", None, JobStatus::Done);
+        let second = record("2", "rock-5b", None, JobStatus::Failed);
+        append(&path, &first).unwrap();
+        append(&path, &second).unwrap();
+        assert_eq!(read_records(&path), vec![first.clone(), second]);
+
+        write_records(&path, std::slice::from_ref(&first)).unwrap();
+        assert_eq!(read_records(&path), vec![first]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_csv_quotes_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("Lab, rack 2"), "\"Lab, rack 2\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+
+        let csv = to_csv(&record("1", "orangepi5", None, JobStatus::Done)).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("id,job_id,kind,status,"));
+        let row = lines.next().unwrap();
+        assert!(row.starts_with("1,1,flash,done,"));
+        assert!(row.contains(",\"Lab, rack 2\","));
+    }
+}
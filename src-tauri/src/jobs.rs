@@ -0,0 +1,479 @@
+//! Operation manager: every download, decompress, flash and QDL flash is a job with
+//! an ID, its own progress state and a lifecycle. Jobs on the same target (device,
+//! URL or file) wait for each other; jobs on different targets run side by side.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::download::DownloadState;
+use crate::flash::FlashState;
+use crate::utils::emit_event;
+use crate::{log_info, log_warn};
+
+const MODULE: &str = "jobs";
+
+/// Tauri event carrying a [`JobQueuedEvent`]
+pub const JOB_QUEUED_EVENT: &str = "job-queued";
+
+/// Tauri event carrying a [`JobFinishedEvent`]
+pub const JOB_FINISHED_EVENT: &str = "job-finished";
+
+/// Pushed when a job is registered, before any of its progress events
+#[derive(Debug, Clone, Serialize)]
+pub struct JobQueuedEvent {
+    pub job_id: u64,
+    pub kind: JobKind,
+    pub target: String,
+    pub client_ref: Option<String>,
+}
+
+/// Summary pushed when a job is done, failed or cancelled
+#[derive(Debug, Clone, Serialize)]
+pub struct JobFinishedEvent {
+    pub job_id: u64,
+    pub kind: JobKind,
+    #[serde(flatten)]
+    pub record: JobRecord,
+    /// Time spent running (or queued, for a job cancelled before it started)
+    pub elapsed_secs: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Download,
+    Decompress,
+    Flash,
+    QdlFlash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for an earlier job on the same target
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+/// The progress state a job reports through; owned by the job, never reused.
+#[derive(Clone)]
+pub enum JobProgressState {
+    Download(Arc<DownloadState>),
+    Flash(Arc<FlashState>),
+}
+
+/// Lifecycle fields that change while the job runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub status: JobStatus,
+    /// Unix time in milliseconds
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+}
+
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    /// Device path, URL or file the job works on
+    pub target: String,
+    /// Unix time in milliseconds
+    pub created_at: u64,
+    /// Token the UI passed when starting the job, so it can tell its own job's
+    /// events apart; None for RPC jobs
+    pub client_ref: Option<String>,
+    pub progress: JobProgressState,
+    record: Mutex<JobRecord>,
+    cancelled: AtomicBool,
+}
+
+impl Job {
+    pub fn record(&self) -> JobRecord {
+        self.record
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.record.lock().unwrap_or_else(|p| p.into_inner()).status
+    }
+
+    pub fn download_state(&self) -> Option<Arc<DownloadState>> {
+        match &self.progress {
+            JobProgressState::Download(state) => Some(state.clone()),
+            JobProgressState::Flash(_) => None,
+        }
+    }
+
+    pub fn flash_state(&self) -> Option<Arc<FlashState>> {
+        match &self.progress {
+            JobProgressState::Flash(state) => Some(state.clone()),
+            JobProgressState::Download(_) => None,
+        }
+    }
+
+    /// Stop the job: a queued job never starts, a running one stops at its next chunk.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        match &self.progress {
+            JobProgressState::Download(state) => state.is_cancelled.store(true, Ordering::SeqCst),
+            JobProgressState::Flash(state) => state.is_cancelled.store(true, Ordering::SeqCst),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut JobRecord)) {
+        change(&mut self.record.lock().unwrap_or_else(|p| p.into_inner()));
+    }
+
+    /// Record the outcome of the job body.
+    pub fn finish<T>(&self, result: &Result<T, String>) {
+        let status = match result {
+            Ok(_) => JobStatus::Done,
+            Err(_) if self.cancelled.load(Ordering::SeqCst) => JobStatus::Cancelled,
+            Err(_) => JobStatus::Failed,
+        };
+        self.update(|record| {
+            record.status = status;
+            record.finished_at = Some(now_millis());
+            record.error = result.as_ref().err().cloned();
+        });
+        log_info!(MODULE, "Job {} ({:?}) {:?}", self.id, self.kind, status);
+
+        let record = self.record();
+        let elapsed_ms = record
+            .finished_at
+            .unwrap_or(0)
+            .saturating_sub(record.started_at.unwrap_or(self.created_at));
+        emit_event(
+            JOB_FINISHED_EVENT,
+            &JobFinishedEvent {
+                job_id: self.id,
+                kind: self.kind,
+                record,
+                elapsed_secs: elapsed_ms as f64 / 1000.0,
+            },
+        );
+    }
+}
+
+#[derive(Default)]
+pub struct JobManager {
+    jobs: Mutex<Vec<Arc<Job>>>,
+    next_id: AtomicU64,
+    /// One lock per target, held by the running job on it; an entry goes away
+    /// once no job holds or waits for it
+    targets: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// A job's hold on its target. Jobs waiting for the same target start once it is
+/// dropped.
+pub struct TargetGuard<'a> {
+    manager: &'a JobManager,
+    target: String,
+    lock: Option<Arc<tokio::sync::Mutex<()>>>,
+    held: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl Drop for TargetGuard<'_> {
+    fn drop(&mut self) {
+        let mut targets = self
+            .manager
+            .targets
+            .lock()
+            .unwrap_or_else(|p| p.into_inner());
+        self.held.take();
+        self.lock.take();
+        // Nobody else can clone the lock while the map is locked, so a count of one
+        // means no job holds or waits for this target.
+        if targets
+            .get(&self.target)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            targets.remove(&self.target);
+        }
+    }
+}
+
+impl JobManager {
+    /// Register a queued job. It starts when passed to [`JobManager::run`].
+    pub fn create(
+        &self,
+        kind: JobKind,
+        target: &str,
+        progress: JobProgressState,
+        client_ref: Option<String>,
+    ) -> Arc<Job> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        match &progress {
+            JobProgressState::Download(state) => state.job_id.store(id, Ordering::SeqCst),
+            JobProgressState::Flash(state) => state.job_id.store(id, Ordering::SeqCst),
+        }
+        let job = Arc::new(Job {
+            id,
+            kind,
+            target: target.to_string(),
+            created_at: now_millis(),
+            client_ref,
+            progress,
+            record: Mutex::new(JobRecord {
+                status: JobStatus::Queued,
+                started_at: None,
+                finished_at: None,
+                error: None,
+            }),
+            cancelled: AtomicBool::new(false),
+        });
+
+        let mut jobs = self.jobs.lock().unwrap_or_else(|p| p.into_inner());
+        jobs.push(job.clone());
+        prune_finished(&mut jobs, config::jobs::FINISHED_JOBS_KEPT);
+        drop(jobs);
+
+        log_info!(MODULE, "Job {} ({:?}) queued: {}", job.id, kind, target);
+        emit_event(
+            JOB_QUEUED_EVENT,
+            &JobQueuedEvent {
+                job_id: job.id,
+                kind,
+                target: job.target.clone(),
+                client_ref: job.client_ref.clone(),
+            },
+        );
+        job
+    }
+
+    /// Wait until no other job runs on this job's target, then run `body` and
+    /// record its outcome.
+    pub async fn run<T, F>(&self, job: &Arc<Job>, body: F) -> Result<T, String>
+    where
+        F: Future<Output = Result<T, String>>,
+    {
+        let (result, _target) = match self.begin(job).await {
+            Ok(target) => (body.await, Some(target)),
+            Err(e) => (Err(e), None),
+        };
+        job.finish(&result);
+        result
+    }
+
+    /// Wait until no other job runs on this job's target and mark it running, for
+    /// a job whose body runs outside [`JobManager::run`] (one device of a
+    /// multi-device flash). Finish it with [`Job::finish`] before dropping the guard.
+    pub async fn begin(&self, job: &Arc<Job>) -> Result<TargetGuard<'_>, String> {
+        let mut target = TargetGuard {
+            manager: self,
+            target: job.target.clone(),
+            lock: None,
+            held: None,
+        };
+        let lock = self
+            .targets
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .entry(job.target.clone())
+            .or_default()
+            .clone();
+        target.lock = Some(lock.clone());
+        target.held = Some(lock.lock_owned().await);
+
+        if job.cancelled.load(Ordering::SeqCst) {
+            log_warn!(MODULE, "Job {} cancelled before it started", job.id);
+            return Err("Operation cancelled".to_string());
+        }
+        job.update(|record| {
+            record.status = JobStatus::Running;
+            record.started_at = Some(now_millis());
+        });
+        Ok(target)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .iter()
+            .find(|job| job.id == id)
+            .cloned()
+    }
+
+    /// All known jobs, oldest first.
+    pub fn list(&self) -> Vec<Arc<Job>> {
+        self.jobs.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    /// The most recent job of one of `kinds`.
+    pub fn latest(&self, kinds: &[JobKind]) -> Option<Arc<Job>> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .iter()
+            .rev()
+            .find(|job| kinds.contains(&job.kind))
+            .cloned()
+    }
+
+    /// Cancel the unfinished jobs the UI started (those with a client ref); RPC jobs
+    /// are left to the clients that started them.
+    pub fn cancel_client_jobs(&self) {
+        for job in self.list() {
+            if job.client_ref.is_some() && !job.status().is_finished() {
+                job.cancel();
+            }
+        }
+    }
+}
+
+/// Drop the oldest finished jobs beyond `keep`; unfinished jobs always stay.
+fn prune_finished(jobs: &mut Vec<Arc<Job>>, keep: usize) {
+    let finished = jobs.iter().filter(|j| j.status().is_finished()).count();
+    let mut excess = finished.saturating_sub(keep);
+    jobs.retain(|job| {
+        if excess > 0 && job.status().is_finished() {
+            excess -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flash_job(jobs: &JobManager, target: &str) -> Arc<Job> {
+        jobs.create(
+            JobKind::Flash,
+            target,
+            JobProgressState::Flash(Arc::new(FlashState::new())),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let jobs = JobManager::default();
+        let ok = flash_job(&jobs, "/dev/sdb");
+        let failed = flash_job(&jobs, "/dev/sdc");
+        assert_eq!(ok.status(), JobStatus::Queued);
+        assert_ne!(ok.id, failed.id);
+
+        tauri::async_runtime::block_on(async {
+            jobs.run(&ok, async { Ok(()) }).await.unwrap();
+            let _ = jobs
+                .run(&failed, async { Err::<(), _>("EIO".to_string()) })
+                .await;
+        });
+        assert_eq!(ok.status(), JobStatus::Done);
+        assert!(ok.record().finished_at.is_some());
+        assert_eq!(failed.status(), JobStatus::Failed);
+        assert_eq!(failed.record().error.as_deref(), Some("EIO"));
+    }
+
+    #[test]
+    fn test_cancelled_job_never_runs() {
+        let jobs = JobManager::default();
+        let job = flash_job(&jobs, "/dev/sdb");
+        job.cancel();
+
+        let ran = AtomicBool::new(false);
+        let result = tauri::async_runtime::block_on(jobs.run(&job, async {
+            ran.store(true, Ordering::SeqCst);
+            Ok(())
+        }));
+        assert!(result.is_err());
+        assert!(!ran.load(Ordering::SeqCst));
+        assert_eq!(job.status(), JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn cancelling_ui_jobs_leaves_rpc_jobs_running() {
+        let jobs = JobManager::default();
+        let rpc = flash_job(&jobs, "/dev/sdb");
+        let ui = jobs.create(
+            JobKind::Flash,
+            "/dev/sdc",
+            JobProgressState::Flash(Arc::new(FlashState::new())),
+            Some("ui-1".to_string()),
+        );
+
+        jobs.cancel_client_jobs();
+        let ui_result = tauri::async_runtime::block_on(jobs.run(&ui, async { Ok(()) }));
+        let rpc_result = tauri::async_runtime::block_on(jobs.run(&rpc, async { Ok(()) }));
+        assert!(ui_result.is_err());
+        assert_eq!(ui.status(), JobStatus::Cancelled);
+        assert!(rpc_result.is_ok());
+        assert_eq!(rpc.status(), JobStatus::Done);
+    }
+
+    #[test]
+    fn second_job_on_a_device_waits_for_the_first() {
+        let jobs = JobManager::default();
+        let first = flash_job(&jobs, "/dev/sdb");
+        let second = flash_job(&jobs, "/dev/sdb");
+        let other = flash_job(&jobs, "/dev/sdc");
+
+        tauri::async_runtime::block_on(async {
+            let held = jobs.begin(&first).await.unwrap();
+            assert_eq!(first.status(), JobStatus::Running);
+
+            // Another device is not held up.
+            jobs.run(&other, async { Ok(()) }).await.unwrap();
+
+            let waiting = jobs.run(&second, async { Ok(()) });
+            tokio::pin!(waiting);
+            let wait = std::time::Duration::from_millis(50);
+            assert!(tokio::time::timeout(wait, &mut waiting).await.is_err());
+            assert_eq!(second.status(), JobStatus::Queued);
+
+            first.finish(&Ok::<_, String>(()));
+            drop(held);
+            waiting.await.unwrap();
+        });
+        assert_eq!(second.status(), JobStatus::Done);
+        assert!(jobs
+            .targets
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_prune_keeps_unfinished_jobs() {
+        let jobs = JobManager::default();
+        let running = flash_job(&jobs, "/dev/sdb");
+        for _ in 0..3 {
+            let job = flash_job(&jobs, "/dev/sdc");
+            let _ = tauri::async_runtime::block_on(jobs.run(&job, async { Ok(()) }));
+        }
+
+        let mut list = jobs.list();
+        prune_finished(&mut list, 1);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].id, running.id);
+        assert_eq!(jobs.latest(&[JobKind::Flash]).unwrap().id, list[1].id);
+    }
+}
@@ -235,6 +235,14 @@ pub fn set_log_level(debug_enabled: bool) {
     }
 }
 
+/// Turn stderr logging on or off; the log file is unaffected. The CLI silences the
+/// console with `--quiet` so scripts only see its JSON output.
+pub fn set_console_output(enabled: bool) {
+    if let Ok(mut logger) = LOGGER.lock() {
+        logger.config.console_output = enabled;
+    }
+}
+
 /// Log a message with format arguments (debug level)
 #[macro_export]
 macro_rules! log_debug {
@@ -6,17 +6,22 @@
 
 mod autoconfig;
 mod cache;
+mod cli;
 mod commands;
 mod config;
 mod decompress;
 mod devices;
 mod download;
 mod flash;
+mod history;
 mod images;
+mod jobs;
 mod logging;
+mod manifest;
 mod paste;
 mod picture_cache;
 mod qdl;
+mod rpc;
 mod utils;
 
 use commands::AppState;
@@ -99,6 +104,11 @@ fn is_appimage() -> bool {
 fn main() {
     logging::init();
 
+    // `armbian-imager cli <command>` runs headless and never opens a window.
+    if let Some(code) = cli::run() {
+        std::process::exit(code);
+    }
+
     log_info!("main", "=== Armbian Imager Starting ===");
     log_info!("main", "Version: {}", env!("CARGO_PKG_VERSION"));
     log_info!(
@@ -153,11 +163,25 @@ fn main() {
             commands::operations::request_write_authorization,
             commands::operations::download_image,
             commands::operations::flash_image,
+            commands::operations::flash_image_multi,
+            commands::operations::find_resumable_flash,
+            commands::operations::discard_flash_checkpoint,
             commands::operations::delete_downloaded_image,
             commands::operations::force_delete_cached_image,
             commands::operations::continue_download_without_sha,
             commands::operations::cleanup_failed_download,
             commands::progress::cancel_operation,
+            commands::jobs::list_jobs,
+            commands::jobs::get_job,
+            commands::jobs::cancel_job,
+            commands::history::get_flash_history,
+            commands::history::delete_flash_history,
+            commands::history::export_flash_report,
+            commands::inspect::inspect_image_partitions,
+            commands::inspect::inspect_read_dir,
+            commands::inspect::inspect_stat,
+            commands::inspect::inspect_read_file,
+            commands::inspect::inspect_image_overlays,
             commands::progress::get_download_progress,
             commands::progress::get_flash_progress,
             commands::qdl_operations::get_qdl_devices,
@@ -169,6 +193,7 @@ fn main() {
             commands::custom_image::decompress_custom_image,
             commands::custom_image::delete_decompressed_custom_image,
             commands::custom_image::detect_board_from_filename,
+            commands::custom_image::detect_board_from_image,
             commands::custom_image::check_is_qdl_image,
             commands::system::open_url,
             commands::system::get_system_locale,
@@ -223,6 +248,15 @@ fn main() {
 
             manage_download_cache(app);
 
+            // Progress of running jobs is pushed as events; the polling commands stay.
+            utils::init_progress_events(app.handle().clone());
+
+            // Local JSON-RPC API for lab automation, only when enabled in settings.
+            rpc::start(app.handle().clone());
+
+            // Push device hotplug events to the UI instead of relying on polling alone.
+            devices::watcher::start(app.handle().clone());
+
             // Background asset cache: refresh stale entries, then pre-populate everything.
             tauri::async_runtime::spawn(async {
                 picture_cache::refresh_stale_assets().await;
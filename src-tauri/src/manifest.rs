@@ -0,0 +1,650 @@
+//! Provisioning manifests: a JSON file naming the board, the image (a catalog
+//! selector, or a URL pinned to a SHA256), autoconfig settings, the devices to
+//! flash and steps to run afterwards. `armbian-imager cli run <manifest>` carries
+//! one out; `cli validate` only checks it.
+//!
+//! Secrets never sit in the manifest: any autoconfig value can be
+//! `{"env": "NAME"}` or `{"file": "/path"}`, read when the manifest is run.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::autoconfig::AutoconfigConfig;
+use crate::devices::policy::{DevicePolicy, DeviceRule, RuleAction};
+use crate::devices::BlockDevice;
+use crate::download::ExpectedSha;
+use crate::images::{fetch_images_for_board, map_images, ImageInfo};
+use crate::log_info;
+
+const MODULE: &str = "manifest";
+
+/// Manifest format version this build reads
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub version: u32,
+    /// Shown in logs and the run report
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Board slug, as in `cli boards`
+    pub board: String,
+    pub image: ImageSelector,
+    /// Settings in the GUI profile format (camelCase keys); values may be secret references
+    #[serde(default)]
+    pub autoconfig: Option<Map<String, Value>>,
+    pub devices: DeviceMatch,
+    #[serde(default = "verify_by_default")]
+    pub verify: bool,
+    #[serde(default)]
+    pub delta: bool,
+    #[serde(default)]
+    pub post_flash: Vec<PostFlashStep>,
+}
+
+fn verify_by_default() -> bool {
+    true
+}
+
+/// Either `url` + `sha256`, or catalog filters; the newest promoted image matching
+/// the filters is taken.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageSelector {
+    #[serde(default)]
+    pub url: Option<String>,
+    /// SHA256 of the file at `url` (before decompression)
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub distribution: Option<String>,
+    /// Kernel branch (`vendor`, `current`, `edge`)
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub variant: Option<String>,
+    /// Armbian release, e.g. `25.8.1`
+    #[serde(default)]
+    pub release: Option<String>,
+    #[serde(default)]
+    pub stability: Option<String>,
+    /// Preinstalled application; empty for images without one
+    #[serde(default)]
+    pub application: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceMatch {
+    /// Rules in the device policy format; the first matching rule decides, and a
+    /// device no rule matches is left alone
+    #[serde(rename = "match")]
+    pub rules: Vec<DeviceRule>,
+    /// Exact number of devices expected; without it, every matching device
+    #[serde(default)]
+    pub count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PostFlashStep {
+    /// Unmount partitions an automounter picked up after the flash
+    Unmount,
+    /// Run a program with `IMAGER_DEVICE` and `IMAGER_IMAGE` in its environment
+    Command {
+        run: Vec<String>,
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+    },
+}
+
+impl PostFlashStep {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unmount => "unmount",
+            Self::Command { .. } => "command",
+        }
+    }
+}
+
+/// Image chosen for a run.
+#[derive(Debug, Clone)]
+pub struct ResolvedImage {
+    pub url: String,
+    /// Published checksum file of a catalog image
+    pub sha_url: Option<String>,
+    /// Digest pinned in the manifest
+    pub sha256: Option<String>,
+    /// Catalog entry, when the image came from a selector
+    pub info: Option<ImageInfo>,
+}
+
+impl ResolvedImage {
+    pub fn expected_sha(&self) -> Option<ExpectedSha<'_>> {
+        match (&self.sha256, &self.sha_url) {
+            (Some(hash), _) => Some(ExpectedSha::Pinned(hash)),
+            (None, Some(url)) => Some(ExpectedSha::Url(url)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A problem in a manifest, located by its field path (`image.sha256`,
+/// `devices.match[1]`) or by line and column for syntax errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestError {
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+fn error(location: impl Into<String>, message: impl Into<String>) -> ManifestError {
+    ManifestError {
+        location: location.into(),
+        message: message.into(),
+    }
+}
+
+/// Read and validate a manifest. Every problem found is reported, tagged
+/// `[MANIFEST_INVALID]` and prefixed with the file name.
+pub fn load(path: &Path) -> Result<Manifest, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("[USAGE] Cannot read manifest {}: {}", path.display(), e))?;
+    let manifest = parse(&source).map_err(|errors| invalid(path, &errors))?;
+    log_info!(
+        MODULE,
+        "Loaded manifest {} ({})",
+        path.display(),
+        manifest.name.as_deref().unwrap_or(&manifest.board)
+    );
+    Ok(manifest)
+}
+
+fn invalid(path: &Path, errors: &[ManifestError]) -> String {
+    let details: Vec<String> = errors
+        .iter()
+        .map(|e| format!("{}:{}", path.display(), e))
+        .collect();
+    format!("[MANIFEST_INVALID] {}", details.join("; "))
+}
+
+pub fn parse(source: &str) -> Result<Manifest, Vec<ManifestError>> {
+    let manifest: Manifest = serde_json::from_str(source).map_err(|e| vec![syntax_error(&e)])?;
+    let errors = manifest.validate();
+    if errors.is_empty() {
+        Ok(manifest)
+    } else {
+        Err(errors)
+    }
+}
+
+/// serde_json appends " at line L column C" to its messages; move that to the front.
+fn syntax_error(e: &serde_json::Error) -> ManifestError {
+    let message = e.to_string();
+    let suffix = format!(" at line {} column {}", e.line(), e.column());
+    error(
+        format!("{}:{}", e.line(), e.column()),
+        message.strip_suffix(&suffix).unwrap_or(&message),
+    )
+}
+
+impl Manifest {
+    fn validate(&self) -> Vec<ManifestError> {
+        let mut errors = Vec::new();
+
+        if self.version != MANIFEST_VERSION {
+            errors.push(error(
+                "version",
+                format!(
+                    "unsupported version {} (this build reads {})",
+                    self.version, MANIFEST_VERSION
+                ),
+            ));
+        }
+        if self.board.trim().is_empty() {
+            errors.push(error("board", "must not be empty"));
+        }
+        self.image.validate(&mut errors);
+        if let Some(settings) = &self.autoconfig {
+            // Only the shape of secret references is checked; they are read at run time.
+            if let Err(e) = resolve_autoconfig(settings, |_, _| Ok(String::new())) {
+                errors.extend(e);
+            }
+        }
+        self.devices.validate(&mut errors);
+        for (index, step) in self.post_flash.iter().enumerate() {
+            if let PostFlashStep::Command { run, timeout_secs } = step {
+                let location = format!("post_flash[{}]", index);
+                if run.first().is_none_or(|program| program.trim().is_empty()) {
+                    errors.push(error(format!("{}.run", location), "needs a program to run"));
+                }
+                if *timeout_secs == Some(0) {
+                    errors.push(error(
+                        format!("{}.timeout_secs", location),
+                        "must be greater than 0",
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// The autoconfig settings with secret references read; None when the manifest has none.
+    pub fn autoconfig_config(&self) -> Result<Option<AutoconfigConfig>, String> {
+        match &self.autoconfig {
+            None => Ok(None),
+            Some(settings) => {
+                resolve_autoconfig(settings, read_secret)
+                    .map(Some)
+                    .map_err(|errors| {
+                        let details: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                        format!("[MANIFEST_INVALID] {}", details.join("; "))
+                    })
+            }
+        }
+    }
+
+    /// Pick the image to flash: the pinned URL, or the best catalog match.
+    pub async fn resolve_image(&self) -> Result<ResolvedImage, String> {
+        let selector = &self.image;
+        if let (Some(url), Some(sha256)) = (&selector.url, &selector.sha256) {
+            return Ok(ResolvedImage {
+                url: url.clone(),
+                sha_url: None,
+                sha256: Some(sha256.clone()),
+                info: None,
+            });
+        }
+
+        let api_images = fetch_images_for_board(
+            &self.board,
+            selector.variant.as_deref(),
+            selector.distribution.as_deref(),
+            selector.branch.as_deref(),
+            None,
+        )
+        .await?;
+        let image = map_images(api_images)
+            .into_iter()
+            .find(|image| selector.matches(image))
+            .ok_or_else(|| {
+                format!(
+                    "[MANIFEST_NO_IMAGE] No flashable image for {} matches the image selector",
+                    self.board
+                )
+            })?;
+
+        log_info!(
+            MODULE,
+            "Selected {} {} {} ({}) for {}",
+            image.release,
+            image.distro_release,
+            image.kernel_branch,
+            image.image_variant,
+            self.board
+        );
+        Ok(ResolvedImage {
+            url: image.direct_url.clone(),
+            sha_url: image.sha_url.clone(),
+            sha256: None,
+            info: Some(image),
+        })
+    }
+
+    /// The devices to flash out of `devices`. System and write-protected disks
+    /// are never picked; the count must match when the manifest sets one.
+    pub fn select_devices(&self, devices: Vec<BlockDevice>) -> Result<Vec<BlockDevice>, String> {
+        let rules = DevicePolicy {
+            rules: self.devices.rules.clone(),
+            default: RuleAction::Deny,
+            hide_denied: true,
+            source: "manifest".to_string(),
+        };
+        let selected: Vec<BlockDevice> = devices
+            .into_iter()
+            .filter(|d| !d.is_system && !d.is_read_only && rules.evaluate(d).allowed)
+            .collect();
+
+        if selected.is_empty() {
+            return Err(
+                "[DEVICE_NOT_FOUND] No connected device matches the manifest's device rules"
+                    .to_string(),
+            );
+        }
+        if let Some(count) = self.devices.count.filter(|&n| n != selected.len()) {
+            let paths: Vec<&str> = selected.iter().map(|d| d.path.as_str()).collect();
+            return Err(format!(
+                "[DEVICE_COUNT] The manifest expects {} device(s), {} match: {}",
+                count,
+                selected.len(),
+                paths.join(", ")
+            ));
+        }
+        Ok(selected)
+    }
+}
+
+impl ImageSelector {
+    fn validate(&self, errors: &mut Vec<ManifestError>) {
+        let filters = [
+            ("distribution", &self.distribution),
+            ("branch", &self.branch),
+            ("variant", &self.variant),
+            ("release", &self.release),
+            ("stability", &self.stability),
+            ("application", &self.application),
+        ];
+
+        match &self.url {
+            Some(url) => {
+                if !url.starts_with("https://") && !url.starts_with("http://") {
+                    errors.push(error("image.url", "must be an http(s) URL"));
+                }
+                match &self.sha256 {
+                    None => errors.push(error(
+                        "image.sha256",
+                        "is required with image.url, so the download can be checked",
+                    )),
+                    Some(hash) if !is_sha256(hash) => {
+                        errors.push(error("image.sha256", "must be 64 hex characters"))
+                    }
+                    Some(_) => {}
+                }
+                for (name, value) in filters {
+                    if value.is_some() {
+                        errors.push(error(
+                            format!("image.{}", name),
+                            "cannot be combined with image.url",
+                        ));
+                    }
+                }
+            }
+            None => {
+                if self.sha256.is_some() {
+                    errors.push(error(
+                        "image.sha256",
+                        "only applies to image.url; catalog images are checked against their published SHA256",
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Filters the images API cannot apply itself.
+    fn matches(&self, image: &ImageInfo) -> bool {
+        let field =
+            |filter: &Option<String>, value: &str| filter.as_ref().is_none_or(|f| f == value);
+        matches!(image.format.as_str(), "sd" | "block")
+            && field(&self.release, &image.release)
+            && field(&self.stability, &image.stability)
+            && field(&self.application, &image.preinstalled_application)
+    }
+}
+
+impl DeviceMatch {
+    fn validate(&self, errors: &mut Vec<ManifestError>) {
+        if self.rules.is_empty() {
+            errors.push(error("devices.match", "needs at least one rule"));
+        }
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.has_criteria() {
+                errors.push(error(
+                    format!("devices.match[{}]", index),
+                    "sets no criteria and would match every disk",
+                ));
+            }
+        }
+        if self.count == Some(0) {
+            errors.push(error("devices.count", "must be at least 1"));
+        }
+    }
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Replace the secret references in `settings` with what `read` returns for them and
+/// check each setting against the profile format, one key at a time so an error
+/// names the offending key.
+fn resolve_autoconfig(
+    settings: &Map<String, Value>,
+    read: impl Fn(&str, &str) -> Result<String, String>,
+) -> Result<AutoconfigConfig, Vec<ManifestError>> {
+    let mut errors = Vec::new();
+    let mut resolved = Map::new();
+
+    for (key, value) in settings {
+        let location = format!("autoconfig.{}", key);
+        let secret = secret_ref(value).map(|r| r.and_then(|(kind, name)| read(kind, name)));
+        let value = match secret {
+            Some(Ok(secret)) => Value::String(secret),
+            Some(Err(message)) => {
+                errors.push(error(location, message));
+                continue;
+            }
+            None => value.clone(),
+        };
+
+        let single = Value::Object(Map::from_iter([(key.clone(), value.clone())]));
+        match serde_json::from_value::<AutoconfigConfig>(single) {
+            Err(e) => errors.push(error(location, e.to_string())),
+            // Nothing was set, so serde skipped the key as unknown.
+            Ok(config) if config == AutoconfigConfig::default() && !value.is_null() => {
+                errors.push(error(
+                    location,
+                    "unknown setting (keys use the profile format, e.g. wifiSsid)",
+                ))
+            }
+            Ok(_) => {
+                resolved.insert(key.clone(), value);
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    serde_json::from_value(Value::Object(resolved))
+        .map_err(|e| vec![error("autoconfig", e.to_string())])
+}
+
+/// `Some` when `value` is an object, i.e. meant as a secret reference: the
+/// reference kind and name, or why it is not a valid one.
+fn secret_ref(value: &Value) -> Option<Result<(&str, &str), String>> {
+    let object = value.as_object()?;
+    let mut entries = object.iter();
+    Some(match (entries.next(), entries.next()) {
+        (Some((kind, Value::String(name))), None) if kind == "env" || kind == "file" => {
+            Ok((kind.as_str(), name.as_str()))
+        }
+        _ => Err(r#"a secret reference is {"env": "NAME"} or {"file": "/path"}"#.to_string()),
+    })
+}
+
+/// Read a secret. Errors name the variable or file but never include the value.
+fn read_secret(kind: &str, name: &str) -> Result<String, String> {
+    if kind == "env" {
+        return std::env::var(name)
+            .map_err(|_| format!("environment variable {} is not set", name));
+    }
+    let content = std::fs::read_to_string(name)
+        .map_err(|e| format!("cannot read secret file {}: {}", name, e))?;
+    Ok(content.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "4f2a0c1d8e3b5a6f7c9d0e1f2a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d";
+
+    fn device(path: &str, serial: &str, is_system: bool) -> BlockDevice {
+        BlockDevice {
+            path: path.to_string(),
+            name: path.trim_start_matches("/dev/").to_string(),
+            size: 32 << 30,
+            size_formatted: String::new(),
+            model: "STORAGE DEVICE".to_string(),
+            is_removable: !is_system,
+            is_system,
+            bus_type: Some("USB".to_string()),
+            is_read_only: false,
+            vendor: Some("Generic".to_string()),
+            serial: Some(serial.to_string()),
+            partitions: Vec::new(),
+            existing_os: None,
+            policy_denied: None,
+        }
+    }
+
+    fn locations(source: &str) -> Vec<String> {
+        parse(source)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.location)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_valid_manifest() {
+        let manifest = parse(
+            r#"{
+                "version": 1,
+                "name": "Lab rack 2",
+                "board": "orangepi5",
+                "image": {"distribution": "noble", "branch": "vendor", "variant": "minimal"},
+                "autoconfig": {"locale": "en_US.UTF-8", "useStaticIp": false},
+                "devices": {"match": [{"action": "allow", "bus": "usb", "model": "STORAGE*"}], "count": 2},
+                "post_flash": [{"step": "unmount"}, {"step": "command", "run": ["./label.sh"]}]
+            }"#,
+        )
+        .unwrap();
+        assert!(manifest.verify);
+        assert_eq!(manifest.post_flash.len(), 2);
+        let config = manifest.autoconfig_config().unwrap().unwrap();
+        assert_eq!(config.locale.as_deref(), Some("en_US.UTF-8"));
+        assert_eq!(config.use_static_ip, Some(false));
+    }
+
+    #[test]
+    fn test_errors_are_located() {
+        let found = locations(
+            r#"{
+                "version": 2,
+                "board": "orangepi5",
+                "image": {"url": "https://example.com/a.img.xz", "sha256": "abc", "branch": "edge"},
+                "autoconfig": {"useStaticIp": "yes", "wifiPasword": "x", "wifiSsid": "lab"},
+                "devices": {"match": [{"action": "allow"}]},
+                "post_flash": [{"step": "command", "run": []}]
+            }"#,
+        );
+        assert_eq!(
+            found,
+            [
+                "version",
+                "image.sha256",
+                "image.branch",
+                "autoconfig.useStaticIp",
+                "autoconfig.wifiPasword",
+                "devices.match[0]",
+                "post_flash[0].run",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors_have_line_and_column() {
+        let errors = parse("{\n  \"version\": 1,\n  \"bord\": \"orangepi5\"\n}").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location, "3:8");
+        assert!(errors[0].message.starts_with("unknown field `bord`"));
+    }
+
+    #[test]
+    fn test_secret_references() {
+        let path = std::env::temp_dir().join(format!("manifest-secret-{}", std::process::id()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let source = format!(
+            r#"{{
+                "version": 1,
+                "board": "orangepi5",
+                "image": {{"url": "https://example.com/a.img.xz", "sha256": "{}"}},
+                "autoconfig": {{
+                    "rootPassword": {{"file": "{}"}},
+                    "wifiKey": {{"env": "IMAGER_TEST_UNSET_SECRET"}}
+                }},
+                "devices": {{"match": [{{"action": "allow", "serial": "AB12"}}]}}
+            }}"#,
+            SHA,
+            path.display()
+        );
+
+        // Validation leaves the secrets alone; running reads them.
+        let manifest = parse(&source).unwrap();
+        let error = manifest.autoconfig_config().unwrap_err();
+        assert!(error.contains("autoconfig.wifiKey"));
+        assert!(error.contains("IMAGER_TEST_UNSET_SECRET"));
+        assert!(!error.contains("hunter2"));
+
+        let malformed = source.replace(
+            r#"{"env": "IMAGER_TEST_UNSET_SECRET"}"#,
+            r#"{"env": "A", "file": "/b"}"#,
+        );
+        let errors = parse(&malformed).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location, "autoconfig.wifiKey");
+
+        let without_env = source.replace(
+            r#""wifiKey": {"env": "IMAGER_TEST_UNSET_SECRET"}"#,
+            r#""wifiSsid": "lab""#,
+        );
+        let config = parse(&without_env)
+            .unwrap()
+            .autoconfig_config()
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.root_password.as_deref(), Some("hunter2"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_select_devices() {
+        let manifest = parse(&format!(
+            r#"{{
+                "version": 1,
+                "board": "orangepi5",
+                "image": {{"url": "https://example.com/a.img.xz", "sha256": "{}"}},
+                "devices": {{"match": [
+                    {{"action": "deny", "serial": "BACKUP"}},
+                    {{"action": "allow", "bus": "usb"}}
+                ], "count": 2}}
+            }}"#,
+            SHA
+        ))
+        .unwrap();
+
+        let devices = vec![
+            device("/dev/sda", "SYSTEM", true),
+            device("/dev/sdb", "AB12", false),
+            device("/dev/sdc", "BACKUP", false),
+            device("/dev/sdd", "AB13", false),
+        ];
+        let selected = manifest.select_devices(devices.clone()).unwrap();
+        let paths: Vec<&str> = selected.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, ["/dev/sdb", "/dev/sdd"]);
+
+        let err = manifest.select_devices(devices[..3].to_vec()).unwrap_err();
+        assert!(err.starts_with("[DEVICE_COUNT]"));
+    }
+}
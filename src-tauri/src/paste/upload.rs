@@ -113,6 +113,23 @@ fn collect_logs() -> Result<String, String> {
         }
     }
 
+    // Bad-region reports from failed flashes, the first thing asked for in card triage.
+    for report in crate::flash::diagnostics::recent_reports(2) {
+        content.push_str(&format!(
+            "\n=== Diagnostic Report: {} ===\n",
+            report.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        match fs::read_to_string(&report) {
+            Ok(json) => {
+                content.push_str(&json);
+                content.push('\n');
+            }
+            Err(e) => {
+                content.push_str(&format!("Error reading report: {}\n", e));
+            }
+        }
+    }
+
     Ok(content)
 }
 
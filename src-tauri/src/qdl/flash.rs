@@ -22,7 +22,9 @@ use xmltree::{Element, XMLNode};
 use super::extract::FIREHOSE_ELF;
 use super::provision::ProvisionSource;
 use super::QdlStorage;
+use crate::config;
 use crate::flash::FlashState;
+use crate::utils::{phase, ProgressTracker};
 use crate::{log_info, log_warn};
 
 /// Execute the full QDL flash: upload firehose, program partitions from `flash_dir`,
@@ -99,6 +101,7 @@ pub fn qdl_flash_ufs(
         check_cancelled(&state)?;
         crate::autoconfig::inject_into_image(image_path, cfg)
             .map_err(|e| format!("[QDL_AUTOCONFIG_FAILED] {}", e))?;
+        warn_boot_env_skipped(cfg);
     }
 
     check_cancelled(&state)?;
@@ -112,10 +115,7 @@ pub fn qdl_flash_ufs(
     let total_bytes = num_sectors as u64 * sector_size as u64;
     state.qdl.partitions_total.store(1, Ordering::SeqCst);
     state.total_bytes.store(total_bytes, Ordering::SeqCst);
-    {
-        let mut stage = state.qdl.stage.lock().unwrap_or_else(|p| p.into_inner());
-        *stage = "partition:system".to_string();
-    }
+    update_qdl_stage(&state, "partition:system");
 
     log_info!(
         "qdl::flash",
@@ -183,14 +183,34 @@ fn write_ufs_image(
     state: &Arc<FlashState>,
 ) -> Result<(), String> {
     let file = fs::File::open(image_path).map_err(|e| format!("Failed to open image: {}", e))?;
+    let mut tracker = write_tracker(
+        state,
+        num_sectors as u64 * QdlStorage::Ufs.sector_size() as u64,
+    );
     let progress_state = state.clone();
-    let mut reader = ProgressReader::new(file, state.clone(), move |bytes_transferred| {
+    let mut reported = 0;
+    let mut reader = ProgressReader::new(file, state.clone(), |bytes_transferred| {
         progress_state
             .written_bytes
             .store(bytes_transferred, Ordering::SeqCst);
+        tracker.update(bytes_transferred - reported);
+        reported = bytes_transferred;
     });
     firehose_program_storage(device, &mut reader, "system", num_sectors, 0, 0, "0")
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    tracker.finish();
+    Ok(())
+}
+
+/// Tracker pushing write progress events for a firehose write of `total_bytes`
+fn write_tracker(state: &FlashState, total_bytes: u64) -> ProgressTracker {
+    ProgressTracker::new(
+        "QDL write",
+        "qdl::flash",
+        total_bytes,
+        config::logging::WRITE_LOG_INTERVAL_MB,
+    )
+    .with_events(state.job_id.load(Ordering::SeqCst), phase::WRITE)
 }
 
 /// Provision a blank UFS module in the current session from the qcombin `<ufs>` descriptor.
@@ -352,6 +372,17 @@ fn connect_and_configure(
     Ok(device)
 }
 
+/// Qualcomm boards boot through UEFI, not Armbian's u-boot script, so armbianEnv.txt
+/// settings from the profile do not apply.
+fn warn_boot_env_skipped(config: &crate::autoconfig::AutoconfigConfig) {
+    if crate::autoconfig::has_boot_env_edits(config) {
+        log_warn!(
+            "qdl::flash",
+            "Autoconfig: boot settings (overlays, console, kernel args) are not applied on QDL flashes"
+        );
+    }
+}
+
 /// Offset of the ext4 superblock magic within a bare ext4 image, and its value.
 const EXT4_SB_OFFSET: u64 = 0x438;
 const EXT4_MAGIC: [u8; 2] = [0x53, 0xEF];
@@ -363,6 +394,7 @@ fn inject_autoconfig(
     config: &crate::autoconfig::AutoconfigConfig,
 ) -> Result<(), String> {
     let rawprogram_path = flash_dir.join("rawprogram0.xml");
+    warn_boot_env_skipped(config);
 
     let (rootfs_path, window_bytes) = match find_rootfs_image(flash_dir) {
         Some(found) => found,
@@ -572,6 +604,7 @@ fn program_from_xml<T: QdlChan>(
         })
         .sum();
     state.total_bytes.store(total_bytes, Ordering::SeqCst);
+    let mut tracker = write_tracker(state, total_bytes);
 
     let mut bytes_written: u64 = 0;
     let mut partition_idx: u64 = 0;
@@ -586,6 +619,7 @@ fn program_from_xml<T: QdlChan>(
                         flash_dir,
                         &e.attributes,
                         state,
+                        &mut tracker,
                         &mut bytes_written,
                         &mut partition_idx,
                     )?;
@@ -601,6 +635,7 @@ fn program_from_xml<T: QdlChan>(
         .qdl
         .partitions_written
         .store(partition_idx, Ordering::SeqCst);
+    tracker.finish();
     log_info!("qdl::flash", "All partitions programmed successfully");
 
     Ok(())
@@ -612,6 +647,7 @@ fn program_single_partition<T: QdlChan>(
     flash_dir: &Path,
     attrs: &IndexMap<String, String>,
     state: &Arc<FlashState>,
+    tracker: &mut ProgressTracker,
     bytes_written: &mut u64,
     partition_idx: &mut u64,
 ) -> Result<(), String> {
@@ -655,10 +691,7 @@ fn program_single_partition<T: QdlChan>(
     check_cancelled(state)?;
 
     let display_label = if label.is_empty() { filename } else { label };
-    {
-        let mut stage = state.qdl.stage.lock().unwrap_or_else(|p| p.into_inner());
-        *stage = format!("partition:{}", display_label);
-    }
+    update_qdl_stage(state, &format!("partition:{}", display_label));
     state
         .qdl
         .partitions_written
@@ -686,10 +719,13 @@ fn program_single_partition<T: QdlChan>(
     let base_bytes = *bytes_written;
     let progress_state = state.clone();
     let cancel_state = state.clone();
-    let mut reader = ProgressReader::new(file, cancel_state, move |bytes_transferred| {
+    let mut reported = 0;
+    let mut reader = ProgressReader::new(file, cancel_state, |bytes_transferred| {
         progress_state
             .written_bytes
             .store(base_bytes + bytes_transferred, Ordering::SeqCst);
+        tracker.update(bytes_transferred - reported);
+        reported = bytes_transferred;
     });
 
     firehose_program_storage(
@@ -785,10 +821,15 @@ fn patch_from_xml<T: QdlChan>(channel: &mut T, patch_path: &Path) -> Result<(),
     Ok(())
 }
 
-/// Update the QDL stage name in the shared flash state
+/// Update the QDL stage name in the shared flash state and announce it to the UI
 fn update_qdl_stage(state: &FlashState, stage: &str) {
     let mut s = state.qdl.stage.lock().unwrap_or_else(|p| p.into_inner());
     *s = stage.to_string();
+    drop(s);
+    crate::utils::emit_phase(
+        state.job_id.load(Ordering::SeqCst),
+        &format!("qdl:{}", stage),
+    );
 }
 
 /// Check if the operation has been cancelled and return an error if so
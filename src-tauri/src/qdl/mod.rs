@@ -43,7 +43,7 @@ impl QdlStorage {
 }
 
 /// Represents a Qualcomm device in EDL mode detected via USB
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct QdlDevice {
     /// USB serial number (may be empty on some devices)
     pub serial: String,
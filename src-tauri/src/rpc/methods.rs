@@ -0,0 +1,377 @@
+//! JSON-RPC envelope and method table. Methods are named after the Tauri commands
+//! they call and take the same (snake_case) arguments as named params; command
+//! errors come back as code -32000 with the `[TAG]` message unchanged.
+
+use std::future::Future;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager};
+
+use super::MODULE;
+use crate::autoconfig::AutoconfigConfig;
+use crate::commands::{self, AppState};
+use crate::config;
+use crate::history::{FlashContext, HistoryFilter, ReportFormat};
+use crate::log_debug;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const COMMAND_FAILED: i64 = -32000;
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Call {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    /// Absent for notifications, which get no response
+    id: Option<Value>,
+}
+
+/// Answer a request body: a single call or a batch. None when there is nothing to
+/// send back (only notifications).
+pub(super) async fn handle_body(app: &AppHandle, body: &[u8]) -> Option<Vec<u8>> {
+    let response = match serde_json::from_slice::<Value>(body) {
+        Err(e) => Some(error_response(
+            Value::Null,
+            RpcError::new(PARSE_ERROR, e.to_string()),
+        )),
+        Ok(Value::Array(calls)) if calls.is_empty() => Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "Empty batch"),
+        )),
+        Ok(Value::Array(calls)) => {
+            let mut responses = Vec::new();
+            for call in calls {
+                responses.extend(handle_call(app, call).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        Ok(call) => handle_call(app, call).await,
+    };
+    response.map(|r| r.to_string().into_bytes())
+}
+
+async fn handle_call(app: &AppHandle, call: Value) -> Option<Value> {
+    let call = match parse_call(call) {
+        Ok(call) => call,
+        Err(e) => return Some(error_response(Value::Null, e)),
+    };
+    log_debug!(MODULE, "Call {}", call.method);
+    let result = dispatch(app, &call.method, call.params).await;
+    let id = call.id?;
+    Some(match result {
+        Ok(value) => json!({ "jsonrpc": "2.0", "id": id, "result": value }),
+        Err(e) => error_response(id, e),
+    })
+}
+
+fn parse_call(call: Value) -> Result<Call, RpcError> {
+    let call: Call =
+        serde_json::from_value(call).map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))?;
+    if call.jsonrpc != "2.0" {
+        return Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""));
+    }
+    Ok(call)
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+/// Named params into `T`; missing params count as `{}`.
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn reply<T: Serialize>(result: Result<T, String>) -> Result<Value, RpcError> {
+    let value = result.map_err(|e| RpcError::new(COMMAND_FAILED, e))?;
+    serde_json::to_value(value).map_err(|e| RpcError::new(COMMAND_FAILED, e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct ImagesParams {
+    board_slug: String,
+    preapp_filter: Option<String>,
+    kernel_filter: Option<String>,
+    variant_filter: Option<String>,
+    stability: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceParams {
+    device_path: String,
+}
+
+#[derive(Deserialize)]
+struct DownloadParams {
+    file_url: String,
+    sha_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FlashParams {
+    image_path: String,
+    device_path: String,
+    #[serde(default = "default_verify")]
+    verify: bool,
+    autoconfig: Option<AutoconfigConfig>,
+    resume: Option<bool>,
+    delta: Option<bool>,
+    context: Option<FlashContext>,
+}
+
+fn default_verify() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct JobParams {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    filter: Option<HistoryFilter>,
+}
+
+#[derive(Deserialize)]
+struct ReportParams {
+    id: String,
+    format: ReportFormat,
+}
+
+async fn dispatch(app: &AppHandle, method: &str, args: Value) -> Result<Value, RpcError> {
+    use commands::{board_queries, history, jobs, operations, progress, qdl_operations};
+
+    let state = app.state::<AppState>();
+    match method {
+        "get_boards" => reply(board_queries::get_boards(state).await),
+        "get_images_for_board" => {
+            let p: ImagesParams = params(args)?;
+            reply(
+                board_queries::get_images_for_board(
+                    p.board_slug,
+                    p.preapp_filter,
+                    p.kernel_filter,
+                    p.variant_filter,
+                    p.stability,
+                )
+                .await,
+            )
+        }
+        "get_block_devices" => reply(board_queries::get_block_devices(app.clone()).await),
+        "get_qdl_devices" => reply(qdl_operations::get_qdl_devices().await),
+        "request_write_authorization" => {
+            let p: DeviceParams = params(args)?;
+            reply(operations::request_write_authorization(p.device_path).await)
+        }
+        "download_image" => {
+            let p: DownloadParams = params(args)?;
+            let target = p.file_url.clone();
+            let app = app.clone();
+            start_job(&state, &target, async move {
+                operations::download_image(p.file_url, p.sha_url, None, app.state())
+                    .await
+                    .map(drop)
+            })
+            .await
+        }
+        "flash_image" => {
+            let p: FlashParams = params(args)?;
+            let target = p.device_path.clone();
+            let app = app.clone();
+            start_job(&state, &target, async move {
+                operations::flash_image(
+                    p.image_path,
+                    p.device_path,
+                    p.verify,
+                    p.autoconfig,
+                    p.resume,
+                    p.delta,
+                    p.context,
+                    None,
+                    app.state(),
+                    app.clone(),
+                )
+                .await
+            })
+            .await
+        }
+        "cancel_job" => {
+            let p: JobParams = params(args)?;
+            reply(jobs::cancel_job(p.id, state).await)
+        }
+        "list_jobs" => reply(jobs::list_jobs(state).await),
+        "get_job" => {
+            let p: JobParams = params(args)?;
+            reply(jobs::get_job(p.id, state).await)
+        }
+        "get_download_progress" => reply(progress::get_download_progress(state).await),
+        "get_flash_progress" => reply(progress::get_flash_progress(state).await),
+        "get_flash_history" => {
+            let p: HistoryParams = params(args)?;
+            reply(history::get_flash_history(p.filter).await)
+        }
+        "export_flash_report" => {
+            let p: ReportParams = params(args)?;
+            reply(history::export_flash_report(p.id, p.format).await)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+/// Run a job-creating command in the background and answer with `{"job_id"}` as
+/// soon as its job is registered, or with the command's error if it fails first
+/// (bad image path, denied authorization). Progress then comes from `/events`
+/// or `get_job`.
+async fn start_job<F>(state: &AppState, target: &str, command: F) -> Result<Value, RpcError>
+where
+    F: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let newest = state
+        .jobs
+        .list()
+        .iter()
+        .map(|job| job.id)
+        .max()
+        .unwrap_or(0);
+    let new_job = || {
+        state
+            .jobs
+            .list()
+            .into_iter()
+            .find(|job| job.id > newest && job.target == target)
+            .map(|job| job.id)
+    };
+
+    let mut task = tokio::spawn(command);
+    let mut poll = tokio::time::interval(Duration::from_millis(config::rpc::JOB_POLL_MS));
+    loop {
+        tokio::select! {
+            finished = &mut task => {
+                finished
+                    .map_err(|e| RpcError::new(COMMAND_FAILED, e.to_string()))?
+                    .map_err(|e| RpcError::new(COMMAND_FAILED, e))?;
+                return Ok(json!({ "job_id": new_job() }));
+            }
+            _ = poll.tick() => {
+                if let Some(id) = new_job() {
+                    return Ok(json!({ "job_id": id }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{check_flash_target, BlockDevice};
+
+    #[test]
+    fn test_parse_call() {
+        let call = parse_call(json!({
+            "jsonrpc": "2.0",
+            "method": "get_job",
+            "params": { "id": 3 },
+            "id": 1,
+        }))
+        .unwrap();
+        assert_eq!(call.method, "get_job");
+        assert_eq!(call.id, Some(json!(1)));
+        assert_eq!(params::<JobParams>(call.params).unwrap().id, 3);
+
+        let notification = parse_call(json!({ "jsonrpc": "2.0", "method": "list_jobs" })).unwrap();
+        assert_eq!(notification.id, None);
+
+        let err =
+            parse_call(json!({ "jsonrpc": "1.0", "method": "list_jobs", "id": 1 })).unwrap_err();
+        assert_eq!(err.code, INVALID_REQUEST);
+        assert_eq!(parse_call(json!([1])).unwrap_err().code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn test_params_errors_and_defaults() {
+        let err = params::<JobParams>(json!({ "id": "three" })).err().unwrap();
+        assert_eq!(err.code, INVALID_PARAMS);
+
+        let flash: FlashParams =
+            params(json!({ "image_path": "a.img", "device_path": "/dev/sdb" })).unwrap();
+        assert!(flash.verify);
+        assert!(flash.autoconfig.is_none());
+
+        let err = reply::<()>(Err("[JOB_NOT_FOUND] 9".to_string())).unwrap_err();
+        assert_eq!(err, RpcError::new(COMMAND_FAILED, "[JOB_NOT_FOUND] 9"));
+        assert_eq!(
+            error_response(json!(1), err)["error"]["message"],
+            "[JOB_NOT_FOUND] 9"
+        );
+    }
+
+    #[test]
+    fn flash_to_a_system_disk_is_refused() {
+        // `flash_image` and `flash_image_multi` check their targets like this, so
+        // an RPC call gets the same refusal as the UI.
+        let disk = |path: &str, is_system: bool, is_read_only: bool| BlockDevice {
+            path: path.to_string(),
+            name: path.trim_start_matches("/dev/").to_string(),
+            size: 64 << 30,
+            size_formatted: String::new(),
+            model: String::new(),
+            is_removable: !is_system,
+            is_system,
+            bus_type: None,
+            is_read_only,
+            vendor: None,
+            serial: None,
+            partitions: Vec::new(),
+            existing_os: None,
+            policy_denied: None,
+        };
+        let devices = [
+            disk("/dev/sda", true, false),
+            disk("/dev/sdb", false, true),
+            disk("/dev/sdc", false, false),
+        ];
+        let refusal = |path: &str, allow_system: bool| {
+            let err = reply(check_flash_target(&devices, path, allow_system)).unwrap_err();
+            assert_eq!(err.code, COMMAND_FAILED);
+            err.message
+        };
+
+        assert!(refusal("/dev/sda", false).starts_with("[DEVICE_SYSTEM] /dev/sda"));
+        assert!(refusal("/dev/sdb", true).starts_with("[DEVICE_READ_ONLY] /dev/sdb"));
+        assert!(refusal("/dev/sdx", true).starts_with("[DEVICE_NOT_FOUND] /dev/sdx"));
+        assert!(check_flash_target(&devices, "/dev/sda", true).is_ok());
+        assert!(check_flash_target(&devices, "/dev/sdc", false).is_ok());
+    }
+}
@@ -0,0 +1,345 @@
+//! Opt-in local control API for lab automation: JSON-RPC 2.0 over HTTP on
+//! 127.0.0.1, exposing the same operations as the Tauri commands (see [`methods`])
+//! plus a Server-Sent Events stream of the events the UI receives.
+//!
+//! Off unless the `rpc_enabled` setting is true. Every request must carry
+//! `Authorization: Bearer <token>`, where the token is the `rpc_token` setting
+//! (generated and saved on first start when missing).
+//!
+//! - `POST /rpc`: a JSON-RPC request or batch
+//! - `GET /events`: `event: <name>` / `data: <json>` for progress and job events
+
+mod methods;
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::config;
+use crate::utils::subscribe_events;
+use crate::{log_debug, log_error, log_info, log_warn};
+
+const MODULE: &str = "rpc";
+
+/// Start the API in the background if the `rpc_enabled` setting is on.
+pub fn start(app: AppHandle) {
+    let Some(settings) = RpcSettings::load(&app) else {
+        return;
+    };
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = serve(app, settings).await {
+            log_error!(MODULE, "{}", e);
+        }
+    });
+}
+
+struct RpcSettings {
+    port: u16,
+    token: String,
+}
+
+impl RpcSettings {
+    /// None when the API is disabled or the settings cannot be read.
+    fn load(app: &AppHandle) -> Option<Self> {
+        let store = app
+            .store("settings.json")
+            .map_err(|e| log_warn!(MODULE, "Failed to access settings store: {}", e))
+            .ok()?;
+        let enabled = store
+            .get("rpc_enabled")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let port = store
+            .get("rpc_port")
+            .and_then(|v| v.as_u64())
+            .and_then(|p| u16::try_from(p).ok())
+            .unwrap_or(config::rpc::DEFAULT_PORT);
+        let token = match store
+            .get("rpc_token")
+            .and_then(|v| v.as_str().map(str::to_string))
+            .filter(|t| !t.is_empty())
+        {
+            Some(token) => token,
+            None => {
+                let token = new_token()?;
+                store.set("rpc_token", token.clone());
+                if let Err(e) = store.save() {
+                    log_warn!(MODULE, "Failed to save the generated RPC token: {}", e);
+                }
+                log_info!(MODULE, "Generated a new rpc_token in settings.json");
+                token
+            }
+        };
+        Some(Self { port, token })
+    }
+}
+
+fn new_token() -> Option<String> {
+    let mut bytes = [0u8; 32];
+    match getrandom::fill(&mut bytes) {
+        Ok(()) => Some(hex::encode(bytes)),
+        Err(e) => {
+            log_error!(MODULE, "Cannot generate an RPC token, API disabled: {}", e);
+            None
+        }
+    }
+}
+
+async fn serve(app: AppHandle, settings: RpcSettings) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, settings.port))
+        .await
+        .map_err(|e| format!("Cannot listen on 127.0.0.1:{}: {}", settings.port, e))?;
+    log_info!(
+        MODULE,
+        "JSON-RPC API listening on http://127.0.0.1:{}",
+        settings.port
+    );
+
+    let token = Arc::new(settings.token);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log_warn!(MODULE, "Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        let (app, token) = (app.clone(), token.clone());
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = handle_connection(stream, &app, &token).await {
+                log_debug!(MODULE, "Connection ended: {}", e);
+            }
+        });
+    }
+}
+
+/// An HTTP request line, headers (names lowercased) and body
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// One request per connection; the response always closes it.
+async fn handle_connection(stream: TcpStream, app: &AppHandle, token: &str) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let timeout = Duration::from_secs(config::rpc::REQUEST_TIMEOUT_SECS);
+    let request = match tokio::time::timeout(timeout, read_request(&mut reader)).await {
+        Ok(Ok(request)) => request,
+        Ok(Err((status, message))) => {
+            return respond(&mut write, status, "text/plain", message.as_bytes()).await
+        }
+        Err(_) => return respond(&mut write, 408, "text/plain", b"Request timed out").await,
+    };
+
+    if !authorized(&request.headers, token) {
+        log_warn!(
+            MODULE,
+            "Rejected unauthorized {} {}",
+            request.method,
+            request.path
+        );
+        return respond(&mut write, 401, "text/plain", b"Missing or invalid token").await;
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/rpc") => match methods::handle_body(app, &request.body).await {
+            Some(body) => respond(&mut write, 200, "application/json", &body).await,
+            None => respond(&mut write, 204, "application/json", b"").await,
+        },
+        ("GET", "/events") => stream_events(&mut write).await,
+        _ => respond(&mut write, 404, "text/plain", b"Not found").await,
+    }
+}
+
+/// Read one request, or fail with the HTTP status to answer.
+async fn read_request<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> Result<Request, (u16, String)> {
+    let io_error = |e: std::io::Error| (400, e.to_string());
+
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        let limit = (config::rpc::MAX_HEAD_BYTES + 1 - head.len()) as u64;
+        let read = (&mut *reader)
+            .take(limit)
+            .read_line(&mut line)
+            .await
+            .map_err(io_error)?;
+        if read == 0 {
+            return Err((400, "Connection closed mid-request".to_string()));
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        head.push_str(&line);
+        if head.len() > config::rpc::MAX_HEAD_BYTES {
+            return Err((431, "Request headers too large".to_string()));
+        }
+    }
+
+    let (method, path, headers) = parse_head(&head).map_err(|e| (400, e))?;
+    let length = match headers.get("content-length") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| (400, "Invalid Content-Length".to_string()))?,
+        None => 0,
+    };
+    if length > config::rpc::MAX_BODY_BYTES {
+        return Err((413, "Request body too large".to_string()));
+    }
+
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await.map_err(io_error)?;
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// Split a request head into method, path (without query) and lowercased headers.
+fn parse_head(head: &str) -> Result<(String, String, HashMap<String, String>), String> {
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(format!("Malformed request line: {}", request_line));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(format!("Unsupported protocol: {}", version));
+    }
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Malformed header: {}", line))?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let path = target.split('?').next().unwrap_or(target);
+    Ok((method.to_string(), path.to_string(), headers))
+}
+
+fn authorized(headers: &HashMap<String, String>, token: &str) -> bool {
+    headers
+        .get("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+/// Compare without returning early, so the response time says nothing about the token.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn respond<W: AsyncWrite + Unpin>(
+    write: &mut W,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    );
+    write.write_all(head.as_bytes()).await?;
+    write.write_all(body).await?;
+    write.shutdown().await
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    }
+}
+
+/// Forward every emitted event until the client goes away.
+async fn stream_events<W: AsyncWrite + Unpin>(write: &mut W) -> std::io::Result<()> {
+    let mut events = subscribe_events();
+    write
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+              Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    write.flush().await?;
+
+    let mut keepalive = tokio::time::interval(Duration::from_secs(config::rpc::SSE_KEEPALIVE_SECS));
+    loop {
+        let chunk = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => format!("event: {}\ndata: {}\n\n", event.name, event.payload),
+                Err(RecvError::Lagged(missed)) => {
+                    log_warn!(MODULE, "Event subscriber fell behind, {} events dropped", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = keepalive.tick() => ": keep-alive\n\n".to_string(),
+        };
+        write.write_all(chunk.as_bytes()).await?;
+        write.flush().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_head() {
+        let (method, path, headers) = parse_head(
+            "POST /rpc?x=1 HTTP/1.1\r\nHost: 127.0.0.1\r\nAuthorization: Bearer abc\r\nContent-Length: 12\r\n",
+        )
+        .unwrap();
+        assert_eq!(method, "POST");
+        assert_eq!(path, "/rpc");
+        assert_eq!(headers["authorization"], "Bearer abc");
+        assert_eq!(headers["content-length"], "12");
+
+        assert!(parse_head("GET /events\r\n").is_err());
+        assert!(parse_head("GET /events HTTP/1.1\r\nno colon\r\n").is_err());
+    }
+
+    #[test]
+    fn test_authorized() {
+        let mut headers = HashMap::new();
+        assert!(!authorized(&headers, "secret"));
+        headers.insert("authorization".to_string(), "Bearer secret".to_string());
+        assert!(authorized(&headers, "secret"));
+        assert!(!authorized(&headers, "secreT"));
+        assert!(!authorized(&headers, "secret2"));
+        headers.insert("authorization".to_string(), "Basic secret".to_string());
+        assert!(!authorized(&headers, "secret"));
+    }
+}
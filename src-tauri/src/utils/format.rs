@@ -109,6 +109,43 @@ pub fn normalize_slug(slug: &str) -> String {
         .join("-")
 }
 
+/// The `fdtfile=` value of an armbianEnv.txt (e.g. "rockchip/rk3588s-orangepi-5.dtb").
+pub fn parse_armbian_env_fdtfile(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        (key.trim() == "fdtfile" && !value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Pick the board a device tree is for. DTB names are `{soc}-{board}.dtb` with the
+/// board spelled like the slug give or take hyphens, so a slug must equal everything
+/// after one of the name's hyphens (or the whole name), never part of a word. The
+/// longest match wins: rk3588-orangepi-5-plus is orangepi5plus, not orangepi5.
+pub fn match_fdtfile_to_slug<'a>(
+    fdtfile: &str,
+    slugs: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let compact = |s: &str| {
+        s.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase()
+    };
+    let name = fdtfile.rsplit('/').next().unwrap_or(fdtfile);
+    let stem = name.strip_suffix(".dtb").unwrap_or(name);
+    let boards: Vec<String> = std::iter::once(stem)
+        .chain(stem.match_indices('-').map(|(i, _)| &stem[i + 1..]))
+        .map(compact)
+        .filter(|board| !board.is_empty())
+        .collect();
+
+    slugs
+        .into_iter()
+        .filter(|slug| boards.contains(&compact(slug)))
+        .max_by_key(|slug| compact(slug).len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -203,4 +240,39 @@ mod tests {
     fn test_parse_armbian_filename_too_short() {
         assert!(parse_armbian_filename("Armbian_25.02.0.img").is_none());
     }
+
+    #[test]
+    fn test_parse_armbian_env_fdtfile() {
+        let env = "verbosity=1\nfdtfile=rockchip/rk3588s-orangepi-5.dtb\nrootdev=UUID=abc\n";
+        assert_eq!(
+            parse_armbian_env_fdtfile(env).as_deref(),
+            Some("rockchip/rk3588s-orangepi-5.dtb")
+        );
+        assert!(parse_armbian_env_fdtfile("verbosity=1\nfdtfile=\n").is_none());
+    }
+
+    #[test]
+    fn test_match_fdtfile_to_slug() {
+        let slugs = ["orangepi5", "orangepi5-plus", "odroidn2", "rpi4b"];
+        let m = |fdt| match_fdtfile_to_slug(fdt, slugs);
+        assert_eq!(m("rockchip/rk3588s-orangepi-5.dtb"), Some("orangepi5"));
+        assert_eq!(
+            m("rockchip/rk3588-orangepi-5-plus.dtb"),
+            Some("orangepi5-plus")
+        );
+        assert_eq!(m("amlogic/meson-g12b-odroid-n2.dtb"), Some("odroidn2"));
+        assert_eq!(m("broadcom/bcm2711-rpi-4-b.dtb"), Some("rpi4b"));
+        assert_eq!(m("rockchip/rk3399-rockpro64.dtb"), None);
+    }
+
+    #[test]
+    fn test_match_fdtfile_to_slug_needs_whole_words() {
+        // "pi5" and "rock5b" end the compacted names but start mid-word
+        let slugs = ["pi5", "orangepi5", "rock5b"];
+        let m = |fdt| match_fdtfile_to_slug(fdt, slugs);
+        assert_eq!(m("rockchip/rk3588s-orangepi-5.dtb"), Some("orangepi5"));
+        assert_eq!(m("rockchip/rk3588-opi-5.dtb"), None);
+        assert_eq!(m("rockchip/rk3588-rock-5b.dtb"), Some("rock5b"));
+        assert_eq!(m("rockchip/rk3588-xrock-5b.dtb"), None);
+    }
 }
@@ -0,0 +1,54 @@
+//! Image hashing shared by flash history and the overlay cache.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+/// An image file as (path, size, mtime)
+type ImageKey = (PathBuf, u64, u64);
+
+/// Image SHA256 by [`ImageKey`], so reflashing or re-inspecting an image does not rehash it
+static SHA_CACHE: Lazy<Mutex<HashMap<ImageKey, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// SHA256 of an image file, rehashed only when its path, size or mtime changes
+pub fn image_sha256(path: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let key = (path.to_path_buf(), metadata.len(), modified);
+
+    if let Some(hash) = SHA_CACHE
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .get(&key)
+    {
+        return Some(hash.clone());
+    }
+
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buffer).ok()?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    let hash = hex::encode(hasher.finalize());
+    SHA_CACHE
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .insert(key, hash.clone());
+    Some(hash)
+}
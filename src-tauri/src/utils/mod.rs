@@ -1,13 +1,15 @@
-//! Shared helpers for formatting, system info, path management, and progress
-//! tracking.
+//! Shared helpers for formatting, image hashing, system info, path management,
+//! and progress tracking.
 
 mod format;
+mod hash;
 mod http;
 mod path;
 mod progress;
 mod system;
 
 pub use format::*;
+pub use hash::*;
 pub use http::*;
 pub use path::*;
 pub use progress::*;
@@ -1,10 +1,118 @@
 //! Reusable progress tracker with speed calculation for download, flash,
-//! verification, SHA256, and decompression operations.
+//! verification, SHA256, and decompression operations. Trackers attached to a job
+//! also push [`ProgressEvent`]s to the UI, throttled by time.
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast;
 
 use super::bytes_to_mb;
-use crate::{log_debug, log_info};
+use crate::config;
+use crate::{log_debug, log_info, log_warn};
+
+/// Tauri event carrying a [`ProgressEvent`]
+pub const PROGRESS_EVENT: &str = "progress";
+
+/// Phase names carried by progress events; QDL stages are sent as `qdl:<stage>`.
+pub mod phase {
+    pub const DOWNLOAD: &str = "download";
+    pub const VERIFY_SHA: &str = "verify-sha";
+    pub const DECOMPRESS: &str = "decompress";
+    pub const INJECT: &str = "inject";
+    pub const WRITE: &str = "write";
+    pub const VERIFY: &str = "verify";
+}
+
+static EVENT_SINK: OnceCell<AppHandle> = OnceCell::new();
+
+/// Start pushing progress events to the UI. Without it (CLI mode) nothing is emitted.
+pub fn init_progress_events(app: AppHandle) {
+    let _ = EVENT_SINK.set(app);
+}
+
+/// An emitted event as seen by subscribers outside the UI (the RPC `/events` stream)
+#[derive(Debug, Clone)]
+pub struct ForwardedEvent {
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+static EVENT_FORWARD: Lazy<broadcast::Sender<ForwardedEvent>> =
+    Lazy::new(|| broadcast::channel(config::rpc::EVENT_BUFFER).0);
+
+/// Receive every event emitted from now on, in addition to the UI.
+pub fn subscribe_events() -> broadcast::Receiver<ForwardedEvent> {
+    EVENT_FORWARD.subscribe()
+}
+
+/// Emit an event to the UI, if there is one, and to any event subscribers.
+pub fn emit_event<S: Serialize + Clone>(event: &str, payload: &S) {
+    if let Some(app) = EVENT_SINK.get() {
+        if let Err(e) = app.emit(event, payload) {
+            log_warn!("utils::progress", "Failed to emit {} event: {}", event, e);
+        }
+    }
+    if EVENT_FORWARD.receiver_count() > 0 {
+        if let Ok(payload) = serde_json::to_value(payload) {
+            let _ = EVENT_FORWARD.send(ForwardedEvent {
+                name: event.to_string(),
+                payload,
+            });
+        }
+    }
+}
+
+/// Progress of one phase of a job
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub job_id: u64,
+    pub phase: String,
+    pub bytes: u64,
+    /// 0 when the size is unknown (decompression)
+    pub total_bytes: u64,
+    pub percent: f64,
+    /// Moving average of recent throughput in MB/s
+    pub speed_mbps: f64,
+    /// Seconds left at the current speed, when the size is known
+    pub eta_secs: Option<f64>,
+    /// Set on the last event of a phase
+    pub summary: Option<ProgressSummary>,
+}
+
+impl ProgressEvent {
+    fn new(job_id: u64, phase: &str) -> Self {
+        Self {
+            job_id,
+            phase: phase.to_string(),
+            bytes: 0,
+            total_bytes: 0,
+            percent: 0.0,
+            speed_mbps: 0.0,
+            eta_secs: None,
+            summary: None,
+        }
+    }
+}
+
+/// Announce a phase that has no byte progress of its own (inject, QDL stages).
+pub fn emit_phase(job_id: u64, phase: &str) {
+    if job_id != 0 {
+        emit_event(PROGRESS_EVENT, &ProgressEvent::new(job_id, phase));
+    }
+}
+
+/// Event state of a tracker attached to a job
+struct EventEmitter {
+    job_id: u64,
+    phase: String,
+    last_emit_time: Instant,
+    last_emit_bytes: u64,
+    /// Smoothed speed in MB/s, None before the first sample
+    speed_mbps: Option<f64>,
+}
 
 /// Progress tracker for operations with speed calculation
 pub struct ProgressTracker {
@@ -24,6 +132,8 @@ pub struct ProgressTracker {
     last_log_bytes: u64,
     /// Interval in bytes between progress logs
     log_interval_bytes: u64,
+    /// Set when the tracker reports to the UI
+    events: Option<EventEmitter>,
 }
 
 /// Progress update data
@@ -39,6 +149,7 @@ pub struct ProgressUpdate {
 }
 
 /// Final summary data
+#[derive(Debug, Clone, Serialize)]
 pub struct ProgressSummary {
     /// Total MB processed
     pub total_mb: f64,
@@ -61,12 +172,29 @@ impl ProgressTracker {
             last_log_time: now,
             last_log_bytes: 0,
             log_interval_bytes: log_interval_mb * 1024 * 1024,
+            events: None,
+        }
+    }
+
+    /// Also push progress events for `phase` of job `job_id`; job 0 (no job) emits nothing.
+    pub fn with_events(mut self, job_id: u64, phase: &str) -> Self {
+        if job_id != 0 {
+            emit_phase(job_id, phase);
+            self.events = Some(EventEmitter {
+                job_id,
+                phase: phase.to_string(),
+                last_emit_time: self.start_time,
+                last_emit_bytes: 0,
+                speed_mbps: None,
+            });
         }
+        self
     }
 
     /// Add progress; returns Some(ProgressUpdate) and logs only when a log interval is crossed.
     pub fn update(&mut self, bytes_added: u64) -> Option<ProgressUpdate> {
         self.processed_bytes += bytes_added;
+        self.maybe_emit();
 
         if self.log_interval_bytes == 0 {
             return None;
@@ -152,6 +280,91 @@ impl ProgressTracker {
             summary.avg_speed_mbps
         );
 
+        if let Some(mut event) = self.event(summary.avg_speed_mbps) {
+            event.eta_secs = Some(0.0);
+            event.summary = Some(summary.clone());
+            emit_event(PROGRESS_EVENT, &event);
+        }
+
         summary
     }
+
+    /// Emit a progress event if the event interval has passed since the last one.
+    fn maybe_emit(&mut self) {
+        let Some(events) = &mut self.events else {
+            return;
+        };
+        let now = Instant::now();
+        let elapsed = now.duration_since(events.last_emit_time);
+        if elapsed < Duration::from_millis(config::progress::EVENT_INTERVAL_MS) {
+            return;
+        }
+
+        let sample =
+            bytes_to_mb(self.processed_bytes - events.last_emit_bytes) / elapsed.as_secs_f64();
+        let speed = smoothed_speed(events.speed_mbps, sample);
+        events.speed_mbps = Some(speed);
+        events.last_emit_time = now;
+        events.last_emit_bytes = self.processed_bytes;
+
+        if let Some(event) = self.event(speed) {
+            emit_event(PROGRESS_EVENT, &event);
+        }
+    }
+
+    /// The current progress as an event, when the tracker reports to the UI.
+    fn event(&self, speed_mbps: f64) -> Option<ProgressEvent> {
+        let events = self.events.as_ref()?;
+        let remaining = self.total_bytes.saturating_sub(self.processed_bytes);
+        Some(ProgressEvent {
+            bytes: self.processed_bytes,
+            total_bytes: self.total_bytes,
+            percent: if self.total_bytes > 0 {
+                (self.processed_bytes as f64 / self.total_bytes as f64) * 100.0
+            } else {
+                0.0
+            },
+            speed_mbps,
+            eta_secs: (self.total_bytes > 0)
+                .then(|| eta_secs(remaining, speed_mbps))
+                .flatten(),
+            ..ProgressEvent::new(events.job_id, &events.phase)
+        })
+    }
+}
+
+/// Exponential moving average of throughput samples, so one slow or fast chunk
+/// does not make the displayed speed and ETA jump.
+fn smoothed_speed(previous: Option<f64>, sample_mbps: f64) -> f64 {
+    match previous {
+        Some(previous) => {
+            let weight = config::progress::SPEED_SMOOTHING;
+            weight * sample_mbps + (1.0 - weight) * previous
+        }
+        None => sample_mbps,
+    }
+}
+
+/// Seconds to process `remaining` bytes at `speed_mbps`; None while there is no speed yet.
+fn eta_secs(remaining: u64, speed_mbps: f64) -> Option<f64> {
+    (speed_mbps > 0.0).then(|| bytes_to_mb(remaining) / speed_mbps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smoothed_speed() {
+        assert_eq!(smoothed_speed(None, 40.0), 40.0);
+        let weight = config::progress::SPEED_SMOOTHING;
+        let expected = weight * 10.0 + (1.0 - weight) * 40.0;
+        assert!((smoothed_speed(Some(40.0), 10.0) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_eta_secs() {
+        assert_eq!(eta_secs(100 * 1024 * 1024, 0.0), None);
+        assert_eq!(eta_secs(100 * 1024 * 1024, 20.0), Some(5.0));
+    }
 }
@@ -39,6 +39,28 @@ pub fn get_cache_dir(app_name: &str) -> PathBuf {
         .join(app_name)
 }
 
+/// Application data directory, for records that must outlive cache cleanup.
+/// On Linux under pkexec/sudo, prefers the original user's data directory.
+pub fn app_data_dir() -> PathBuf {
+    #[cfg(target_os = "linux")]
+    {
+        let euid = unsafe { libc::geteuid() };
+        if euid == 0 {
+            if let Some(home) = get_original_user_home() {
+                return PathBuf::from(home)
+                    .join(".local")
+                    .join("share")
+                    .join(config::app::NAME);
+            }
+        }
+    }
+
+    match dirs::data_dir() {
+        Some(dir) => dir.join(config::app::NAME),
+        None => app_cache_dir(),
+    }
+}
+
 /// Root application cache directory.
 pub fn app_cache_dir() -> PathBuf {
     get_cache_dir(config::app::NAME)
@@ -74,6 +96,35 @@ pub fn logs_dir() -> PathBuf {
     app_cache_dir().join("logs")
 }
 
+/// Directory holding bad-region diagnostic reports, kept alongside the logs.
+pub fn diagnostics_dir() -> PathBuf {
+    logs_dir().join("diagnostics")
+}
+
+/// Checkpoint of the last interrupted flash, used to offer a resume.
+pub fn flash_checkpoint_path() -> PathBuf {
+    app_cache_dir().join("flash-checkpoint.json")
+}
+
+/// Flash history, one JSON record per line.
+pub fn flash_history_path() -> PathBuf {
+    app_data_dir().join("flash-history.jsonl")
+}
+
+/// System-wide device policy file, managed by the station admin rather than the app.
+pub fn system_policy_path() -> PathBuf {
+    #[cfg(target_os = "linux")]
+    let base = PathBuf::from("/etc");
+    #[cfg(target_os = "macos")]
+    let base = PathBuf::from("/Library/Application Support");
+    #[cfg(target_os = "windows")]
+    let base = std::env::var_os("ProgramData")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"));
+
+    base.join(config::app::NAME).join("device-policy.json")
+}
+
 /// Get the original user's home directory when running as root via pkexec/sudo
 #[cfg(target_os = "linux")]
 fn get_original_user_home() -> Option<String> {
@@ -4,7 +4,7 @@ import { Header, HomePage, WelcomePage } from './components/layout';
 import { ArmbianBoardModal } from './components/modals';
 import { FlashProgress } from './components/flash';
 import { CacheManagerModal } from './components/settings';
-import { selectCustomImage, detectBoardFromFilename, logInfo, logWarn, getArmbianRelease, getBoards, getSystemInfo, getCachedBoardImage, checkNeedsDecompression, decompressCustomImage, checkIsQdlImage } from './hooks/useTauri';
+import { selectCustomImage, detectBoardFromImage, logInfo, logWarn, getArmbianRelease, getBoards, getSystemInfo, getCachedBoardImage, checkNeedsDecompression, decompressCustomImage, checkIsQdlImage } from './hooks/useTauri';
 import { useDeviceMonitor } from './hooks/useDeviceMonitor';
 import { useConnectivity } from './hooks/useConnectivity';
 import { ToastProvider, useToasts } from './hooks/useToasts';
@@ -41,6 +41,7 @@ function AppContent() {
   // Opt-in autoconfig profile id picked at flash time; null means unchanged behaviour.
   const [selectedProfileId, setSelectedProfileId] = useState<string | null>(null);
   const [autoconfig, setAutoconfig] = useState<AutoconfigConfig | null>(null);
+  const [profileName, setProfileName] = useState<string | null>(null);
 
   const { showSuccess, showError } = useToasts();
 
@@ -101,15 +102,20 @@ function AppContent() {
   useEffect(() => {
     if (!selectedProfileId) {
       setAutoconfig(null);
+      setProfileName(null);
       return;
     }
     let cancelled = false;
     getAutoconfigProfile(selectedProfileId)
       .then((profile) => {
-        if (!cancelled) setAutoconfig(profile?.config ?? null);
+        if (cancelled) return;
+        setAutoconfig(profile?.config ?? null);
+        setProfileName(profile?.name ?? null);
       })
       .catch(() => {
-        if (!cancelled) setAutoconfig(null);
+        if (cancelled) return;
+        setAutoconfig(null);
+        setProfileName(null);
       });
     return () => {
       cancelled = true;
@@ -248,16 +254,6 @@ function AppContent() {
 
       logInfo('app', `Reusing cached image: ${filename}`);
 
-      let matchedBoard: BoardInfo | null = null;
-      try {
-        matchedBoard = await detectBoardFromFilename(filename);
-        if (matchedBoard) {
-          logInfo('app', `Detected board from cached filename: ${matchedBoard.name}`);
-        }
-      } catch {
-        // Ignore detection errors
-      }
-
       let imagePath = path;
       try {
         const needsDecompress = await checkNeedsDecompression(path);
@@ -270,6 +266,17 @@ function AppContent() {
         // Continue with original path
       }
 
+      // Detect after decompression so the image contents can be read
+      let matchedBoard: BoardInfo | null = null;
+      try {
+        matchedBoard = await detectBoardFromImage(imagePath);
+        if (matchedBoard) {
+          logInfo('app', `Detected board from cached image: ${matchedBoard.name}`);
+        }
+      } catch {
+        // Ignore detection errors
+      }
+
       const cachedImage: ImageInfo = {
         release: 'Cached',
         distro_release: filename,
@@ -370,15 +377,16 @@ function AppContent() {
     try {
       const result = await selectCustomImage();
       if (result) {
+        // A compressed image is matched by filename here, then again once decompressed for flashing
         let detectedBoard: BoardInfo | null = null;
         try {
-          detectedBoard = await detectBoardFromFilename(result.name);
+          detectedBoard = await detectBoardFromImage(result.path);
           if (detectedBoard) {
-            logInfo('app', `Detected board from filename: ${detectedBoard.name} (${detectedBoard.slug})`);
+            logInfo('app', `Detected board from image: ${detectedBoard.name} (${detectedBoard.slug})`);
           }
         } catch (err) {
           // Ignore detection errors, fall back to generic
-          logWarn('app', `Failed to detect board from filename: ${err}`);
+          logWarn('app', `Failed to detect board from image: ${err}`);
         }
 
         // QDL (Qualcomm EDL) TAR archives flash differently than block images
@@ -443,6 +451,18 @@ function AppContent() {
     }
   }
 
+  // Adopt the board read from a custom image decompressed for flashing
+  function handleBoardDetected(board: BoardInfo) {
+    logInfo('app', `Detected board from decompressed image: ${board.name} (${board.slug})`);
+    setSelectedManufacturer({
+      id: board.vendor,
+      name: board.vendor_name,
+      color: '#6b7280',
+      boardCount: 1,
+    });
+    setSelectedBoard(board);
+  }
+
   function handleComplete() {
     setIsFlashing(false);
     resetSelectionsFrom('manufacturer');
@@ -517,6 +537,8 @@ function AppContent() {
               image={selectedImage}
               device={selectedDevice}
               autoconfig={autoconfig}
+              profileName={profileName}
+              onBoardDetected={handleBoardDetected}
               onComplete={handleComplete}
               onBack={handleBackFromFlash}
             />
@@ -20,6 +20,10 @@ interface FlashProgressProps {
   device: BlockDevice;
   /** Opt-in autoconfig profile config to write on first boot; null when none selected. */
   autoconfig?: AutoconfigConfig | null;
+  /** Name of that profile, recorded in the flash history. */
+  profileName?: string | null;
+  /** A decompressed custom image turned out to be for another board. */
+  onBoardDetected?: (board: BoardInfo) => void;
   onComplete: () => void;
   onBack: () => void;
 }
@@ -29,6 +33,8 @@ export function FlashProgress({
   image,
   device,
   autoconfig,
+  profileName,
+  onBoardDetected,
   onComplete,
   onBack,
 }: FlashProgressProps) {
@@ -41,12 +47,24 @@ export function FlashProgress({
     progress,
     error,
     showShaWarning,
+    resumeOffer,
     handleCancel,
     handleRetry,
     handleBack,
     handleShaWarningConfirm,
     handleShaWarningCancel,
-  } = useFlashOperation({ image, device, soc: board.soc, boardSlug: board.slug, autoconfig, onBack });
+    handleResumeConfirm,
+    handleResumeDecline,
+  } = useFlashOperation({
+    image,
+    device,
+    soc: board.soc,
+    boardSlug: board.slug,
+    autoconfig,
+    profileName,
+    onBoardDetected,
+    onBack,
+  });
 
   useEffect(() => {
     getCachedBoardImage(board.slug)
@@ -212,6 +230,21 @@ export function FlashProgress({
           onConfirm={handleShaWarningConfirm}
         />
       )}
+
+      {resumeOffer && (
+        <ConfirmationDialog
+          isOpen={resumeOffer !== null}
+          title={t('flash.resumeTitle')}
+          message={t('flash.resumeMessage', {
+            percent: Math.floor((resumeOffer.durable_offset / resumeOffer.total_bytes) * 100),
+          })}
+          confirmText={t('flash.resume')}
+          cancelText={t('flash.startOver')}
+          isDanger={false}
+          onCancel={handleResumeDecline}
+          onConfirm={handleResumeConfirm}
+        />
+      )}
     </div>
   );
 }
@@ -10,7 +10,8 @@ import { getBlockDevices, getQdlDevices, getQdlEdlEntry } from '../../hooks/useT
 import { getAutoconfigProfiles, getAllowSystemDevices } from '../../hooks/useSettings';
 import { useAsyncData } from '../../hooks/useAsyncData';
 import { useSkeletonLoading } from '../../hooks/useSkeletonLoading';
-import { POLLING, UI, EVENTS, qdlInstructionsKey } from '../../config';
+import { useDeviceEvents } from '../../hooks/useDeviceEvents';
+import { UI, EVENTS, qdlInstructionsKey } from '../../config';
 import { getDeviceColors } from '../../config/deviceColors';
 import { getDeviceType, devicesChanged, sortDevices, qdlToBlockDevice } from '../../utils/deviceUtils';
 
@@ -165,8 +166,8 @@ export function DevicePanel({
     }
   }, [rawDevices]);
 
-  // Poll for device changes (detect new USB/SD insertions) while browsing.
-  const pollDevices = useCallback(async () => {
+  // Re-list devices after a pushed hotplug event (new USB/SD insertions, removals).
+  const refreshDevices = useCallback(async () => {
     try {
       let newDevices: BlockDevice[];
       if (isQdlMode) {
@@ -180,15 +181,13 @@ export function DevicePanel({
         setDevices(sortDevices(newDevices));
       }
     } catch {
-      // Ignore transient polling errors
+      // Ignore transient listing errors
     }
   }, [isQdlMode]);
 
-  useEffect(() => {
-    if (selectedDevice) return;
-    const interval = setInterval(pollDevices, POLLING.DEVICE_CHECK);
-    return () => clearInterval(interval);
-  }, [selectedDevice, pollDevices]);
+  useDeviceEvents((event) => {
+    if (event.kind === (isQdlMode ? 'qdl' : 'block')) refreshDevices();
+  }, !selectedDevice);
 
   function handleDeviceClick(device: BlockDevice) {
     if (device.is_read_only || (device.is_system && !allowSystemDevices)) return;
@@ -381,7 +380,10 @@ export function DevicePanel({
                   const deviceType = getDeviceType(device);
                   const badge = getDeviceBadge(deviceType, t);
                   const colors = getDeviceColors(deviceType);
-                  const isDisabled = device.is_read_only || (device.is_system && !allowSystemDevices);
+                  const isDisabled =
+                    device.is_read_only ||
+                    !!device.policy_denied ||
+                    (device.is_system && !allowSystemDevices);
                   // Unlocked system disk: show its real drive glyph, not the lock (it is selectable).
                   const iconType =
                     device.is_system && allowSystemDevices
@@ -410,12 +412,21 @@ export function DevicePanel({
                         <span className="device-card__sub">
                           {device.name}
                           {device.size_formatted ? ` • ${device.size_formatted}` : ''}
+                          {device.serial ? ` • ${device.serial}` : ''}
                         </span>
+                        {device.existing_os && (
+                          <span className="device-card__sub">
+                            {t('device.existingArmbian', {
+                              board: device.existing_os.board_name || device.existing_os.board,
+                              version: device.existing_os.version ?? '',
+                            })}
+                          </span>
+                        )}
                       </span>
-                      {device.is_read_only ? (
-                        <span className="device-card__lock">
+                      {device.is_read_only || device.policy_denied ? (
+                        <span className="device-card__lock" title={device.policy_denied ?? undefined}>
                           <Lock size={11} />
-                          {t('device.locked')}
+                          {device.is_read_only ? t('device.locked') : t('device.policyLocked')}
                         </span>
                       ) : (
                         !isDisabled && <ArrowRight className="device-card__arrow" size={18} />
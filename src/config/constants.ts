@@ -2,9 +2,6 @@
 
 /** Polling intervals in milliseconds */
 export const POLLING = {
-  DEVICE_CHECK: 2000,
-  DOWNLOAD_PROGRESS: 250,
-  FLASH_PROGRESS: 250,
   CONNECTIVITY_CHECK: 30000,
 } as const;
 
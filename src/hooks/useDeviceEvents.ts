@@ -0,0 +1,33 @@
+import { useEffect, useRef } from 'react';
+import type { UnlistenFn } from '@tauri-apps/api/event';
+import { onDeviceEvent } from './useTauri';
+import type { DeviceEvent } from '../types';
+
+/** Run `handler` for every device hotplug event pushed by the backend watcher while `enabled` */
+export function useDeviceEvents(handler: (event: DeviceEvent) => void, enabled: boolean = true) {
+  // Latest handler without re-subscribing on every render
+  const handlerRef = useRef(handler);
+  useEffect(() => {
+    handlerRef.current = handler;
+  }, [handler]);
+
+  useEffect(() => {
+    if (!enabled) return;
+
+    let unlisten: UnlistenFn | null = null;
+    let disposed = false;
+    onDeviceEvent((event) => handlerRef.current(event))
+      .then((fn) => {
+        if (disposed) fn();
+        else unlisten = fn;
+      })
+      .catch(() => {
+        // No event stream: lists still refresh on demand
+      });
+
+    return () => {
+      disposed = true;
+      unlisten?.();
+    };
+  }, [enabled]);
+}
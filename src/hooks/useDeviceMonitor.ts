@@ -1,6 +1,6 @@
 import { useEffect, useCallback } from 'react';
 import { getBlockDevices, getQdlDevices } from './useTauri';
-import { POLLING } from '../config';
+import { useDeviceEvents } from './useDeviceEvents';
 import type { BlockDevice } from '../types';
 
 /** Monitor the selected device and clear it if it disconnects */
@@ -30,16 +30,22 @@ export function useDeviceMonitor(
         onDeviceDisconnected();
       }
     } catch {
-      // Silently ignore polling errors
+      // Silently ignore lookup errors
     }
   }, [selectedDevice, onDeviceDisconnected]);
 
+  // Catch a removal that happened before the event subscription was in place.
   useEffect(() => {
     if (!enabled || !selectedDevice) return;
-
     checkDevice();
-
-    const interval = setInterval(checkDevice, POLLING.DEVICE_CHECK);
-    return () => clearInterval(interval);
   }, [enabled, selectedDevice, checkDevice]);
+
+  useDeviceEvents((event) => {
+    if (!selectedDevice || event.action !== 'removed') return;
+    if (event.kind === 'qdl') {
+      if (selectedDevice.path.startsWith('qdl://')) checkDevice();
+    } else if (event.device.path === selectedDevice.path) {
+      onDeviceDisconnected();
+    }
+  }, enabled && selectedDevice !== null);
 }
@@ -2,7 +2,7 @@
 
 import { useState, useEffect, useRef, useCallback } from 'react';
 import { useTranslation } from 'react-i18next';
-import type { ImageInfo, BlockDevice, AutoconfigConfig } from '../types';
+import type { BoardInfo, ImageInfo, BlockDevice, AutoconfigConfig, FlashContext, ResumableFlash, ProgressEvent, JobQueuedEvent } from '../types';
 import { FLASH_METHOD, deriveFlashMethod, isEdlMethod } from '../types';
 import { PHASE_ORDER, type FlashStage, type FlashPhase } from '../components/flash/FlashStageIcon';
 import {
@@ -10,23 +10,28 @@ import {
   flashImage,
   flashQdlImage,
   flashQdlUfsImage,
-  getDownloadProgress,
-  getFlashProgress,
   cancelOperation,
+  cancelJob,
   deleteDownloadedImage,
   deleteDecompressedCustomImage,
   forceDeleteCachedImage,
   requestWriteAuthorization,
   checkNeedsDecompression,
   decompressCustomImage,
+  detectBoardFromImage,
   getBlockDevices,
   getQdlDevices,
   continueDownloadWithoutSha,
   cleanupFailedDownload,
   listCachedImages,
+  findResumableFlash,
+  discardFlashCheckpoint,
+  logWarn,
 } from './useTauri';
 import { getSkipVerify } from './useSettings';
-import { POLLING, CACHE, STORAGE_KEYS } from '../config';
+import { useDeviceEvents } from './useDeviceEvents';
+import { useJobQueuedEvents, useProgressEvents } from './useProgressEvents';
+import { CACHE, STORAGE_KEYS } from '../config';
 import { getErrorMessage, armbianIdentityKey, isCompressedImage } from '../utils';
 import { isDeviceConnected } from '../utils/deviceUtils';
 import { isShaUnavailableError, translateFlashError } from '../utils/errorUtils';
@@ -40,6 +45,10 @@ interface UseFlashOperationProps {
   boardSlug?: string;
   /** Opt-in autoconfig profile config written into the image on first boot; null when none. */
   autoconfig?: AutoconfigConfig | null;
+  /** Name of the selected profile, recorded in the flash history. */
+  profileName?: string | null;
+  /** Board read from a custom image once decompressed, when it differs from `boardSlug`. */
+  onBoardDetected?: (board: BoardInfo) => void;
   onBack: () => void;
 }
 
@@ -50,11 +59,15 @@ interface UseFlashOperationReturn {
   error: string | null;
   imagePath: string | null;
   showShaWarning: boolean;
+  /** Interrupted write of this image on this card, offered before flashing */
+  resumeOffer: ResumableFlash | null;
   handleCancel: () => Promise<void>;
   handleRetry: () => Promise<void>;
   handleBack: () => Promise<void>;
   handleShaWarningConfirm: () => Promise<void>;
   handleShaWarningCancel: () => Promise<void>;
+  handleResumeConfirm: () => void;
+  handleResumeDecline: () => Promise<void>;
 }
 
 /** Delete a custom (decompressed) or downloaded image file, ignoring errors */
@@ -75,6 +88,9 @@ async function cleanupImageSafely(
 }
 
 
+/** Backend operation whose progress events the view follows */
+type TrackedOperation = 'download' | 'flash';
+
 function buildPhases(opts: { download: boolean; prepare: boolean; verify: boolean }): FlashPhase[] {
   const phases: FlashPhase[] = [];
   if (opts.download) phases.push('download');
@@ -90,6 +106,8 @@ export function useFlashOperation({
   soc,
   boardSlug,
   autoconfig,
+  profileName,
+  onBoardDetected,
   onBack,
 }: UseFlashOperationProps): UseFlashOperationReturn {
   const { t } = useTranslation();
@@ -101,14 +119,18 @@ export function useFlashOperation({
   const [error, setError] = useState<string | null>(null);
   const [imagePath, setImagePath] = useState<string | null>(null);
   const [showShaWarning, setShowShaWarning] = useState(false);
+  const [resumeOffer, setResumeOffer] = useState<ResumableFlash | null>(null);
+  // Image path the resume offer is for
+  const resumePathRef = useRef<string | null>(null);
 
   // Refs for lifecycle management
-  const intervalRef = useRef<number | null>(null);
-  const deviceMonitorRef = useRef<number | null>(null);
   const maxProgressRef = useRef<number>(0);
-  // True once this flash's write phase is observed; guards against a stale is_verifying from a
-  // previous run latching the UI onto "verifying" with a full bar before this run writes.
-  const flashWriteSeenRef = useRef<boolean>(false);
+  // Operation whose progress events drive the view, the token its command was started
+  // with, and its job once the job-queued event carrying that token names it
+  const operationRef = useRef<TrackedOperation | null>(null);
+  const clientRefRef = useRef<string | null>(null);
+  const jobIdRef = useRef<number | null>(null);
+  const eventPhaseRef = useRef<string | null>(null);
   const hasStartedRef = useRef<boolean>(false);
   const deviceDisconnectedRef = useRef<boolean>(false);
   const userCancelledRef = useRef<boolean>(false);
@@ -120,6 +142,8 @@ export function useFlashOperation({
   // Keep the latest opt-in profile config for the event-driven flash flow.
   const autoconfigRef = useRef<AutoconfigConfig | null>(autoconfig ?? null);
   autoconfigRef.current = autoconfig ?? null;
+  const contextRef = useRef<FlashContext>({});
+  contextRef.current = { board: boardSlug, profile_name: profileName ?? undefined };
 
   // Failure tracking via sessionStorage
   const failureStorageKey = `${STORAGE_KEYS.FLASH_FAILURE_PREFIX}${image.file_url}`;
@@ -145,18 +169,34 @@ export function useFlashOperation({
     }
   };
 
-  /** Clear all active polling intervals */
-  const clearIntervals = useCallback(() => {
-    if (intervalRef.current) {
-      clearInterval(intervalRef.current);
-      intervalRef.current = null;
-    }
-    if (deviceMonitorRef.current) {
-      clearInterval(deviceMonitorRef.current);
-      deviceMonitorRef.current = null;
+  /** Follow the progress events of a newly started operation, or stop following (null).
+   * Returns the token to start the operation's command with. */
+  const trackOperation = useCallback((operation: TrackedOperation | null): string | undefined => {
+    operationRef.current = operation;
+    clientRefRef.current = operation ? crypto.randomUUID() : null;
+    jobIdRef.current = null;
+    eventPhaseRef.current = null;
+    maxProgressRef.current = 0;
+    return clientRefRef.current ?? undefined;
+  }, []);
+
+  /** Cancel this view's running job; before its id is known, every job the UI started */
+  const cancelOwnJob = useCallback(async (jobId: number | null) => {
+    if (jobId !== null) {
+      await cancelJob(jobId);
+    } else {
+      await cancelOperation();
     }
   }, []);
 
+  /** Raise the bar to `percent`; progress never moves backwards within a phase */
+  const advanceProgress = (percent: number) => {
+    if (percent >= maxProgressRef.current) {
+      maxProgressRef.current = percent;
+      setProgress(percent);
+    }
+  };
+
   /** Single exit into the error screen: never empty, honors the precedence latch. */
   const failFlash = useCallback(
     (message: string, specific = true) => {
@@ -205,12 +245,13 @@ export function useFlashOperation({
     if (deviceDisconnectedRef.current) return;
     deviceDisconnectedRef.current = true;
     setShowShaWarning(false);
-    clearIntervals();
+    const jobId = jobIdRef.current;
+    trackOperation(null);
     // Error state first, synchronously: the UI must never wait on backend cleanup.
     failFlash(t('error.deviceDisconnected'), false);
     pendingCleanupRef.current = (async () => {
       try {
-        await cancelOperation();
+        await cancelOwnJob(jobId);
       } catch {
         // Ignore
       }
@@ -221,33 +262,87 @@ export function useFlashOperation({
       }
     })();
     await pendingCleanupRef.current;
-  }, [t, clearIntervals, failFlash]);
+  }, [t, trackOperation, failFlash, cancelOwnJob]);
 
-  // Monitor device connection during active operations.
+  // Monitor device connection during active operations, re-checking on each pushed hotplug event.
   // QDL flash stages are excluded: the USB device is busy/resets during Sahara/Firehose (expected).
+  const monitorStages: FlashStage[] = isEdlFlash
+    ? ['downloading', 'verifying_sha', 'decompressing']
+    : ['downloading', 'verifying_sha', 'decompressing',
+       'flashing', 'verifying'];
+  const monitorDevice = monitorStages.includes(stage);
+
+  // Catch a removal that happened before the event subscription was in place.
   useEffect(() => {
-    const activeStages: FlashStage[] = isEdlFlash
-      ? ['downloading', 'verifying_sha', 'decompressing']
-      : ['downloading', 'verifying_sha', 'decompressing',
-         'flashing', 'verifying'];
-    if (!activeStages.includes(stage)) {
-      if (deviceMonitorRef.current) {
-        clearInterval(deviceMonitorRef.current);
-        deviceMonitorRef.current = null;
+    if (monitorDevice) checkDeviceOrDisconnect();
+  }, [monitorDevice, checkDeviceOrDisconnect]);
+
+  useDeviceEvents((event) => {
+    if (event.action === 'removed' && event.kind === (isEdlFlash ? 'qdl' : 'block')) {
+      checkDeviceOrDisconnect();
+    }
+  }, monitorDevice);
+
+  /** Learn the job id of the running operation from the job-queued event carrying its token */
+  const handleJobQueued = (event: JobQueuedEvent) => {
+    if (event.client_ref !== null && event.client_ref === clientRefRef.current) {
+      jobIdRef.current = event.job_id;
+    }
+  };
+
+  useJobQueuedEvents(handleJobQueued);
+
+  /** Move the view along with the running operation's progress events */
+  const handleProgressEvent = (event: ProgressEvent) => {
+    if (!operationRef.current || deviceDisconnectedRef.current) return;
+    // Only the job this view started; other jobs (RPC, earlier runs) are ignored.
+    if (jobIdRef.current === null || event.job_id !== jobIdRef.current) return;
+
+    const phaseChanged = eventPhaseRef.current !== event.phase;
+    eventPhaseRef.current = event.phase;
+
+    if (event.phase.startsWith('qdl:')) {
+      const qdlStage = event.phase.slice('qdl:'.length);
+      if (qdlStage === 'sahara' || qdlStage === 'connecting' || qdlStage === 'configuring' || qdlStage === 'provisioning') {
+        setStage('qdl_sahara');
+      } else if (qdlStage.startsWith('partition:') || qdlStage === 'firehose' || qdlStage === 'patching') {
+        setStage('qdl_firehose');
       }
+      // complete/resetting: the flash command resolves next
       return;
     }
 
-    checkDeviceOrDisconnect();
-    deviceMonitorRef.current = window.setInterval(checkDeviceOrDisconnect, POLLING.DEVICE_CHECK);
+    switch (event.phase) {
+      case 'download':
+      case 'write':
+        advanceProgress(event.percent);
+        break;
+      case 'verify-sha':
+        if (phaseChanged) {
+          setStage('verifying_sha');
+          maxProgressRef.current = 0;
+        }
+        advanceProgress(event.percent);
+        break;
+      case 'decompress':
+        // Output size is unknown while decompressing: show the stage only
+        if (phaseChanged) {
+          setStage('decompressing');
+          maxProgressRef.current = 0;
+          setProgress(0);
+        }
+        break;
+      case 'verify':
+        if (phaseChanged) {
+          setStage('verifying');
+          maxProgressRef.current = 0;
+        }
+        advanceProgress(event.percent);
+        break;
+    }
+  };
 
-    return () => {
-      if (deviceMonitorRef.current) {
-        clearInterval(deviceMonitorRef.current);
-        deviceMonitorRef.current = null;
-      }
-    };
-  }, [stage, device.path, isEdlFlash, handleDeviceDisconnectedInternal, checkDeviceOrDisconnect]);
+  useProgressEvents(handleProgressEvent);
 
   /** Handle custom image flow (decompress if needed, then flash) */
   async function handleCustomImage(customPath: string) {
@@ -266,66 +361,50 @@ export function useFlashOperation({
       if (needsDecompress) {
         setStage('decompressing');
         setProgress(0);
-        const decompressedPath = await decompressCustomImage(customPath);
+        const decompressedPath = await decompressCustomImage(customPath, trackOperation('download'));
         setImagePath(decompressedPath);
+        await redetectBoard(decompressedPath);
         startFlash(decompressedPath);
       } else {
         setImagePath(customPath);
         startFlash(customPath);
       }
     } catch (err) {
+      trackOperation(null);
       const raw = getErrorMessage(err, '');
       if (deviceDisconnectedRef.current && /cancel/i.test(raw)) return;
       failFlash(raw || t('error.decompressionFailed'));
     }
   }
 
-  /** Start download with progress polling */
+  /** Detect the board again from a decompressed custom image, whose contents were unreadable before */
+  async function redetectBoard(path: string) {
+    try {
+      const board = await detectBoardFromImage(path);
+      if (board && board.slug !== boardSlug) {
+        // The flash starts before the parent re-renders with the new board
+        contextRef.current = { ...contextRef.current, board: board.slug };
+        onBoardDetected?.(board);
+      }
+    } catch (err) {
+      logWarn('flash', `Failed to detect board from decompressed image: ${err}`);
+    }
+  }
+
+  /** Start download, following its progress events */
   async function startDownload() {
     setStage('downloading');
     setProgress(0);
     setError(null);
-    maxProgressRef.current = 0;
-
-    intervalRef.current = window.setInterval(async () => {
-      try {
-        const prog = await getDownloadProgress();
-
-        if (prog.is_verifying_sha && stage !== 'verifying_sha') {
-          setStage('verifying_sha');
-          maxProgressRef.current = 0;
-          setProgress(0);
-        } else if (prog.is_decompressing && stage !== 'decompressing') {
-          setStage('decompressing');
-          maxProgressRef.current = 0;
-          setProgress(0);
-        }
-
-        if (!prog.is_decompressing && !prog.is_verifying_sha) {
-          const newProgress = prog.progress_percent;
-          if (newProgress >= maxProgressRef.current) {
-            maxProgressRef.current = newProgress;
-            setProgress(newProgress);
-          }
-        }
-
-        if (prog.error && !deviceDisconnectedRef.current) {
-          failFlash(prog.error);
-          if (intervalRef.current) clearInterval(intervalRef.current);
-        }
-      } catch {
-        // Ignore polling errors
-      }
-    }, POLLING.DOWNLOAD_PROGRESS);
+    const clientRef = trackOperation('download');
 
     try {
       // Use direct_url: it carries the full filename, unlike the extensionless mirror-selector file_url.
-      const path = await downloadImage(image.direct_url, image.sha_url);
+      const path = await downloadImage(image.direct_url, image.sha_url, clientRef);
       setImagePath(path);
-      if (intervalRef.current) clearInterval(intervalRef.current);
       startFlash(path);
     } catch (err) {
-      if (intervalRef.current) clearInterval(intervalRef.current);
+      trackOperation(null);
 
       const errorMsg = getErrorMessage(err, String(err));
 
@@ -340,63 +419,25 @@ export function useFlashOperation({
     }
   }
 
-  /** Start flash with progress polling */
-  async function startFlash(path: string) {
-    setStage(isQdlMode ? 'extracting' : 'flashing');
-    setProgress(0);
-    maxProgressRef.current = 0;
-    flashWriteSeenRef.current = false;
-
-    intervalRef.current = window.setInterval(async () => {
+  /** Start flash, following its progress events. Without an explicit `resume`, a block flash first
+   * offers to resume an interrupted write of this image once its card is attached again. */
+  async function startFlash(path: string, resume?: boolean) {
+    if (resume === undefined && !isEdlFlash) {
       try {
-        const prog = await getFlashProgress();
-
-        if (prog.is_qdl_mode && prog.qdl_stage) {
-          if (prog.qdl_stage === 'sahara' || prog.qdl_stage === 'connecting' || prog.qdl_stage === 'configuring' || prog.qdl_stage === 'provisioning') {
-            setStage('qdl_sahara');
-          } else if (prog.qdl_stage.startsWith('partition:') || prog.qdl_stage === 'firehose' || prog.qdl_stage === 'patching') {
-            setStage('qdl_firehose');
-          } else if (prog.qdl_stage === 'complete' || prog.qdl_stage === 'resetting') {
-            // Done/resetting: don't trigger a device-disconnect error
-            return;
-          }
-
-          if (prog.progress_percent >= maxProgressRef.current) {
-            maxProgressRef.current = prog.progress_percent;
-            setProgress(prog.progress_percent);
-          }
-        } else {
-          // A non-verifying poll means this run is actually writing: open the latch.
-          if (!prog.is_verifying) {
-            flashWriteSeenRef.current = true;
-          }
-          // Only honor verify once the write phase of THIS run has been seen, so a
-          // stale is_verifying from a previous flash can't jump straight to a full bar.
-          const verifying = prog.is_verifying && flashWriteSeenRef.current;
-
-          if (verifying) {
-            setStage('verifying');
-            if (maxProgressRef.current > 50) {
-              maxProgressRef.current = 0;
-            }
-          }
-
-          // Skip stale verify polls (is_verifying true before any write seen).
-          if (verifying || !prog.is_verifying) {
-            if (prog.progress_percent >= maxProgressRef.current) {
-              maxProgressRef.current = prog.progress_percent;
-              setProgress(prog.progress_percent);
-            }
-          }
-        }
-        if (prog.error && !deviceDisconnectedRef.current) {
-          failFlash(prog.error);
-          if (intervalRef.current) clearInterval(intervalRef.current);
+        const resumable = await findResumableFlash(path, autoconfigRef.current ?? undefined);
+        if (resumable && resumable.device_path === device.path) {
+          resumePathRef.current = path;
+          setResumeOffer(resumable);
+          return;
         }
       } catch {
-        // Ignore polling errors
+        // No offer: flash from the start
       }
-    }, POLLING.FLASH_PROGRESS);
+    }
+
+    setStage(isQdlMode ? 'extracting' : 'flashing');
+    setProgress(0);
+    const clientRef = trackOperation('flash');
 
     try {
       // Pass autoconfig only when a profile was selected (else undefined = unchanged)
@@ -407,15 +448,26 @@ export function useFlashOperation({
           soc ?? '',
           boardSlug ?? '',
           undefined,
-          autoconfigRef.current ?? undefined
+          autoconfigRef.current ?? undefined,
+          contextRef.current,
+          clientRef
         );
       } else if (isQdlMode) {
         // QDL path: TAR archive → extract → Sahara → Firehose
-        await flashQdlImage(path, undefined, autoconfigRef.current ?? undefined);
+        await flashQdlImage(path, undefined, autoconfigRef.current ?? undefined, contextRef.current, clientRef);
       } else {
-        await flashImage(path, device.path, !skipVerifyRef.current, autoconfigRef.current ?? undefined);
+        await flashImage(
+          path,
+          device.path,
+          !skipVerifyRef.current,
+          autoconfigRef.current ?? undefined,
+          resume ?? false,
+          false,
+          contextRef.current,
+          clientRef
+        );
       }
-      if (intervalRef.current) clearInterval(intervalRef.current);
+      trackOperation(null);
       setStage('complete');
       setProgress(100);
       setFlashFailureCount(0);
@@ -424,12 +476,12 @@ export function useFlashOperation({
         await cleanupImageSafely(path, image.is_custom);
       }
     } catch (err) {
-      if (intervalRef.current) clearInterval(intervalRef.current);
+      trackOperation(null);
 
       const rawError = getErrorMessage(err, String(err));
       // User-initiated cancel: handleCancel owns navigation/messaging.
       if (userCancelledRef.current) return;
-      // Cancel-shaped rejection triggered by the disconnect handler's own cancelOperation:
+      // Cancel-shaped rejection triggered by the disconnect handler's own cancel:
       // the generic disconnect message is already on screen and more truthful.
       const causedByDisconnectCancel = deviceDisconnectedRef.current && /cancel/i.test(rawError);
       if (!causedByDisconnectCancel) {
@@ -521,9 +573,7 @@ export function useFlashOperation({
     handleAuthorization();
 
     return () => {
-      if (intervalRef.current) {
-        clearInterval(intervalRef.current);
-      }
+      operationRef.current = null;
     };
     // eslint-disable-next-line react-hooks/exhaustive-deps
   }, []);
@@ -533,8 +583,8 @@ export function useFlashOperation({
   const handleCancel = async () => {
     userCancelledRef.current = true;
     try {
-      await cancelOperation();
-      if (intervalRef.current) clearInterval(intervalRef.current);
+      await cancelOwnJob(jobIdRef.current);
+      trackOperation(null);
       // EDL: stay put so the blocking flash command can detect cancel and clean up
       if (isEdlFlash) {
         setStage('authorizing');
@@ -607,10 +657,11 @@ export function useFlashOperation({
     setProgress(0);
 
     try {
-      const path = await continueDownloadWithoutSha();
+      const path = await continueDownloadWithoutSha(trackOperation('download'));
       setImagePath(path);
       startFlash(path);
     } catch (err) {
+      trackOperation(null);
       const raw = getErrorMessage(err, '');
       if (deviceDisconnectedRef.current && /cancel/i.test(raw)) return;
       failFlash(raw || t('error.decompressionFailed'));
@@ -623,6 +674,23 @@ export function useFlashOperation({
     onBack();
   };
 
+  const handleResumeConfirm = () => {
+    const path = resumePathRef.current;
+    setResumeOffer(null);
+    if (path) startFlash(path, true);
+  };
+
+  const handleResumeDecline = async () => {
+    const path = resumePathRef.current;
+    setResumeOffer(null);
+    try {
+      await discardFlashCheckpoint();
+    } catch {
+      // Ignore: a stale checkpoint never matches a fresh write
+    }
+    if (path) startFlash(path, false);
+  };
+
   return {
     stage,
     phases,
@@ -630,10 +698,13 @@ export function useFlashOperation({
     error,
     imagePath,
     showShaWarning,
+    resumeOffer,
     handleCancel,
     handleRetry,
     handleBack,
     handleShaWarningConfirm,
     handleShaWarningCancel,
+    handleResumeConfirm,
+    handleResumeDecline,
   };
 }
@@ -0,0 +1,47 @@
+import { useEffect, useRef } from 'react';
+import type { UnlistenFn } from '@tauri-apps/api/event';
+import { onJobQueued, onProgress } from './useTauri';
+import type { JobQueuedEvent, ProgressEvent } from '../types';
+
+/** Run `handler` for every event from `subscribe` while `enabled` */
+function useBackendEvent<T>(
+  subscribe: (handler: (event: T) => void) => Promise<UnlistenFn>,
+  handler: (event: T) => void,
+  enabled: boolean
+) {
+  // Latest handler without re-subscribing on every render
+  const handlerRef = useRef(handler);
+  useEffect(() => {
+    handlerRef.current = handler;
+  }, [handler]);
+
+  useEffect(() => {
+    if (!enabled) return;
+
+    let unlisten: UnlistenFn | null = null;
+    let disposed = false;
+    subscribe((event) => handlerRef.current(event))
+      .then((fn) => {
+        if (disposed) fn();
+        else unlisten = fn;
+      })
+      .catch(() => {
+        // No event stream: the stage still advances as each command resolves
+      });
+
+    return () => {
+      disposed = true;
+      unlisten?.();
+    };
+  }, [subscribe, enabled]);
+}
+
+/** Run `handler` for every progress event pushed by running jobs while `enabled` */
+export function useProgressEvents(handler: (event: ProgressEvent) => void, enabled: boolean = true) {
+  useBackendEvent(onProgress, handler, enabled);
+}
+
+/** Run `handler` for every newly registered job while `enabled` */
+export function useJobQueuedEvents(handler: (event: JobQueuedEvent) => void, enabled: boolean = true) {
+  useBackendEvent(onJobQueued, handler, enabled);
+}
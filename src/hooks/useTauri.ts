@@ -1,5 +1,6 @@
 import { invoke } from '@tauri-apps/api/core';
-import type { BoardInfo, ImageInfo, BlockDevice, DownloadProgress, FlashProgress, CustomImageInfo, ArmbianReleaseInfo, CachedImageInfo, CacheBreakdown, QdlDevice, VendorInfo, AutoconfigConfig } from '../types';
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+import type { BoardInfo, ImageInfo, BlockDevice, DownloadProgress, FlashProgress, CustomImageInfo, ArmbianReleaseInfo, CachedImageInfo, CacheBreakdown, QdlDevice, DeviceEvent, VendorInfo, AutoconfigConfig, ResumableFlash, DeviceFlashResult, JobInfo, ProgressEvent, JobQueuedEvent, JobFinishedEvent, FlashContext, FlashRecord, HistoryFilter, ReportFormat, ImageLayout, InspectDirEntry, InspectFileStat, InspectFileContent, ImageOverlayListing } from '../types';
 
 export async function getBoards(): Promise<BoardInfo[]> {
   return invoke('get_boards');
@@ -29,37 +30,143 @@ export async function getBlockDevices(): Promise<BlockDevice[]> {
   return invoke('get_block_devices');
 }
 
+/** Subscribe to block and EDL device hotplug events pushed by the backend watcher */
+export async function onDeviceEvent(handler: (event: DeviceEvent) => void): Promise<UnlistenFn> {
+  return listen<DeviceEvent>('device-event', (e) => handler(e.payload));
+}
+
+/** Subscribe to progress pushed by running jobs (throttled); the flash view follows these */
+export async function onProgress(handler: (event: ProgressEvent) => void): Promise<UnlistenFn> {
+  return listen<ProgressEvent>('progress', (e) => handler(e.payload));
+}
+
+/** Subscribe to the announcement of each new job; its `client_ref` names the command that started it */
+export async function onJobQueued(handler: (event: JobQueuedEvent) => void): Promise<UnlistenFn> {
+  return listen<JobQueuedEvent>('job-queued', (e) => handler(e.payload));
+}
+
+/** Subscribe to the summary sent when a job is done, failed or cancelled */
+export async function onJobFinished(handler: (event: JobFinishedEvent) => void): Promise<UnlistenFn> {
+  return listen<JobFinishedEvent>('job-finished', (e) => handler(e.payload));
+}
+
 export async function requestWriteAuthorization(devicePath: string): Promise<boolean> {
   return invoke('request_write_authorization', { devicePath });
 }
 
-export async function downloadImage(fileUrl: string, shaUrl?: string | null): Promise<string> {
-  return invoke('download_image', { fileUrl, shaUrl });
+/** Download an image; `clientRef` comes back in the job's `job-queued` event */
+export async function downloadImage(fileUrl: string, shaUrl?: string | null, clientRef?: string): Promise<string> {
+  return invoke('download_image', { fileUrl, shaUrl, clientRef });
 }
 
+/** Snapshot of the latest download; kept for compatibility, the UI uses {@link onProgress} */
 export async function getDownloadProgress(): Promise<DownloadProgress> {
   return invoke('get_download_progress');
 }
 
 /** Flash an image to a device. With `autoconfig`, the Armbian first-boot file is written
- * into the image; omitting it keeps default behaviour. */
+ * into the image; omitting it keeps default behaviour. `context` goes into the flash history. */
 export async function flashImage(
   imagePath: string,
   devicePath: string,
   verify: boolean = true,
-  autoconfig?: AutoconfigConfig | null
+  autoconfig?: AutoconfigConfig | null,
+  resume: boolean = false,
+  delta: boolean = false,
+  context?: FlashContext,
+  clientRef?: string
 ): Promise<void> {
-  return invoke('flash_image', { imagePath, devicePath, verify, autoconfig });
+  return invoke('flash_image', { imagePath, devicePath, verify, autoconfig, resume, delta, context, clientRef });
 }
 
+/** Flash one image to several devices in parallel; resolves with one result per device. Each device
+ * is a job of its own, announced with `clientRef` and cancelled with {@link cancelJob}. */
+export async function flashImageMulti(
+  imagePath: string,
+  devicePaths: string[],
+  verify: boolean = true,
+  autoconfig?: AutoconfigConfig | null,
+  delta: boolean = false,
+  context?: FlashContext,
+  clientRef?: string
+): Promise<DeviceFlashResult[]> {
+  return invoke('flash_image_multi', { imagePath, devicePaths, verify, autoconfig, delta, context, clientRef });
+}
+
+/** Interrupted flash of this image whose card is attached again, or null. */
+export async function findResumableFlash(
+  imagePath: string,
+  autoconfig?: AutoconfigConfig | null
+): Promise<ResumableFlash | null> {
+  return invoke('find_resumable_flash', { imagePath, autoconfig });
+}
+
+export async function discardFlashCheckpoint(): Promise<void> {
+  return invoke('discard_flash_checkpoint');
+}
+
+/** Snapshot of the latest flash; kept for compatibility, the UI uses {@link onProgress} */
 export async function getFlashProgress(): Promise<FlashProgress> {
   return invoke('get_flash_progress');
 }
 
+/** Cancel every running job the UI started; prefer {@link cancelJob} once the job id is known */
 export async function cancelOperation(): Promise<void> {
   return invoke('cancel_operation');
 }
 
+/** Downloads, decompressions and flashes: running, queued and recently finished */
+export async function listJobs(): Promise<JobInfo[]> {
+  return invoke('list_jobs');
+}
+
+export async function getJob(id: number): Promise<JobInfo> {
+  return invoke('get_job', { id });
+}
+
+export async function cancelJob(id: number): Promise<void> {
+  return invoke('cancel_job', { id });
+}
+
+/** Past flashes matching `filter`, newest first */
+export async function getFlashHistory(filter?: HistoryFilter): Promise<FlashRecord[]> {
+  return invoke('get_flash_history', { filter });
+}
+
+/** Delete flash history records; resolves with how many were removed */
+export async function deleteFlashHistory(ids: string[]): Promise<number> {
+  return invoke('delete_flash_history', { ids });
+}
+
+/** One flash history record as a JSON or CSV report */
+export async function exportFlashReport(id: string, format: ReportFormat): Promise<string> {
+  return invoke('export_flash_report', { id, format });
+}
+
+/** Partitions of an image file and the filesystem on each */
+export async function inspectImagePartitions(imagePath: string): Promise<ImageLayout> {
+  return invoke('inspect_image_partitions', { imagePath });
+}
+
+/** Directory listing inside an ext4 partition of an image */
+export async function inspectReadDir(imagePath: string, partition: number, path: string): Promise<InspectDirEntry[]> {
+  return invoke('inspect_read_dir', { imagePath, partition, path });
+}
+
+export async function inspectStat(imagePath: string, partition: number, path: string): Promise<InspectFileStat> {
+  return invoke('inspect_stat', { imagePath, partition, path });
+}
+
+/** A file inside an ext4 partition of an image, e.g. /etc/armbian-release */
+export async function inspectReadFile(imagePath: string, partition: number, path: string): Promise<InspectFileContent> {
+  return invoke('inspect_read_file', { imagePath, partition, path });
+}
+
+/** Device-tree overlays shipped in an image, cached per image SHA256 */
+export async function inspectImageOverlays(imagePath: string): Promise<ImageOverlayListing> {
+  return invoke('inspect_image_overlays', { imagePath });
+}
+
 export async function deleteDownloadedImage(imagePath: string): Promise<void> {
   return invoke('delete_downloaded_image', { imagePath });
 }
@@ -70,8 +177,8 @@ export async function forceDeleteCachedImage(imagePath: string): Promise<void> {
 }
 
 /** Continue a download without SHA verification, returning the decompressed image path */
-export async function continueDownloadWithoutSha(): Promise<string> {
-  return invoke('continue_download_without_sha');
+export async function continueDownloadWithoutSha(clientRef?: string): Promise<string> {
+  return invoke('continue_download_without_sha', { clientRef });
 }
 
 /** Clean up the temp file left by a failed/cancelled download */
@@ -88,6 +195,11 @@ export async function detectBoardFromFilename(filename: string): Promise<BoardIn
   return invoke('detect_board_from_filename', { filename });
 }
 
+/** Detect board info from an image's armbian-release or armbianEnv.txt, falling back to its filename */
+export async function detectBoardFromImage(imagePath: string): Promise<BoardInfo | null> {
+  return invoke('detect_board_from_image', { imagePath });
+}
+
 // Re-export CustomImageInfo for backward compatibility
 export type { CustomImageInfo } from '../types';
 
@@ -99,8 +211,8 @@ export async function checkNeedsDecompression(imagePath: string): Promise<boolea
   return invoke('check_needs_decompression', { imagePath });
 }
 
-export async function decompressCustomImage(imagePath: string): Promise<string> {
-  return invoke('decompress_custom_image', { imagePath });
+export async function decompressCustomImage(imagePath: string, clientRef?: string): Promise<string> {
+  return invoke('decompress_custom_image', { imagePath, clientRef });
 }
 
 export interface UploadResult {
@@ -224,9 +336,11 @@ export async function getQdlDevices(): Promise<QdlDevice[]> {
 export async function flashQdlImage(
   tarPath: string,
   serial?: string,
-  autoconfig?: AutoconfigConfig | null
+  autoconfig?: AutoconfigConfig | null,
+  context?: FlashContext,
+  clientRef?: string
 ): Promise<void> {
-  return invoke('flash_qdl_image', { tarPath, serial, autoconfig });
+  return invoke('flash_qdl_image', { tarPath, serial, autoconfig, context, clientRef });
 }
 
 /** Flash a decompressed UFS .img to an EDL device via a raw Firehose write; the loader is
@@ -236,9 +350,11 @@ export async function flashQdlUfsImage(
   soc: string,
   boardSlug: string,
   serial?: string,
-  autoconfig?: AutoconfigConfig | null
+  autoconfig?: AutoconfigConfig | null,
+  context?: FlashContext,
+  clientRef?: string
 ): Promise<void> {
-  return invoke('flash_qdl_ufs_image', { imagePath, soc, boardSlug, serial, autoconfig });
+  return invoke('flash_qdl_ufs_image', { imagePath, soc, boardSlug, serial, autoconfig, context, clientRef });
 }
 
 /** Check whether a TAR file is a QDL flash archive (has rawprogram0.xml + firehose ELF) */
@@ -116,6 +116,24 @@ export interface BlockDevice {
   bus_type?: string;
   /** Whether the device is read-only (e.g., SD card with write-protect lock) */
   is_read_only?: boolean;
+  vendor?: string | null;
+  /** Hardware serial number; tells otherwise identical cards apart */
+  serial?: string | null;
+  partitions?: PartitionInfo[];
+  /** Armbian install already on the device (read from its rootfs) */
+  existing_os?: ArmbianReleaseInfo | null;
+  /** Set when the device policy forbids flashing this device, with the reason */
+  policy_denied?: string | null;
+}
+
+export interface PartitionInfo {
+  path: string;
+  name: string;
+  size: number;
+  fs_type: string | null;
+  label: string | null;
+  uuid: string | null;
+  mountpoint: string | null;
 }
 
 export interface DownloadProgress {
@@ -142,6 +160,54 @@ export interface FlashProgress {
   partitions_total: number;
   /** Number of partitions programmed so far in QDL mode */
   partitions_written: number;
+  /** Whether the current flash only writes chunks that differ (delta mode) */
+  is_delta: boolean;
+  /** Delta mode: bytes read back and compared with the image */
+  compared_bytes: number;
+  /** Delta mode: bytes already matching on the device, not rewritten */
+  skipped_bytes: number;
+  /** Read-compare throughput in MB/s (delta mode) */
+  compare_speed_mbps: number;
+  /** Throughput of actual device writes in MB/s, skipped chunks excluded */
+  write_speed_mbps: number;
+}
+
+export type JobKind = 'download' | 'decompress' | 'flash' | 'qdl_flash';
+
+export type JobStatus = 'queued' | 'running' | 'done' | 'failed' | 'cancelled';
+
+/** A download, decompress or flash operation tracked by ID */
+export interface JobInfo {
+  id: number;
+  kind: JobKind;
+  /** Device path, URL or file the job works on */
+  target: string;
+  status: JobStatus;
+  /** Unix times in milliseconds */
+  created_at: number;
+  started_at: number | null;
+  finished_at: number | null;
+  error: string | null;
+  /** DownloadProgress for download/decompress jobs, FlashProgress for flash jobs */
+  progress: DownloadProgress | FlashProgress;
+}
+
+/** Outcome for one device of a multi-device flash */
+export interface DeviceFlashResult {
+  device_path: string;
+  success: boolean;
+  /** Whether the device was read back and matched the image */
+  verified: boolean;
+  error: string | null;
+}
+
+/** Interrupted flash that can continue from its last synced offset */
+export interface ResumableFlash {
+  /** Where the card is attached now (may differ after a USB reset) */
+  device_path: string;
+  durable_offset: number;
+  total_bytes: number;
+  updated_at: number;
 }
 
 /** Represents a Qualcomm device in EDL mode detected via USB */
@@ -152,6 +218,153 @@ export interface QdlDevice {
   description: string;
 }
 
+/** Payload of the backend `device-event` hotplug event */
+export type DeviceEvent =
+  | { action: 'added' | 'removed' | 'changed'; kind: 'block'; device: BlockDevice }
+  | { action: 'added' | 'removed' | 'changed'; kind: 'qdl'; device: QdlDevice };
+
+/** Payload of the backend `progress` event, pushed while a job runs */
+export interface ProgressEvent {
+  job_id: number;
+  /** download, verify-sha, decompress, inject, write, verify, or qdl:<stage> */
+  phase: string;
+  bytes: number;
+  /** 0 when the size is unknown (decompression) */
+  total_bytes: number;
+  percent: number;
+  /** Moving average of recent throughput in MB/s */
+  speed_mbps: number;
+  /** Seconds left at the current speed, when the size is known */
+  eta_secs: number | null;
+  /** Set on the last event of a phase */
+  summary: { total_mb: number; elapsed_secs: number; avg_speed_mbps: number } | null;
+}
+
+/** Payload of the backend `job-queued` event, pushed before any progress of the job */
+export interface JobQueuedEvent {
+  job_id: number;
+  kind: JobKind;
+  /** Device path, URL or file the job works on */
+  target: string;
+  /** Token the UI passed when starting the job; null for RPC jobs */
+  client_ref: string | null;
+}
+
+/** Payload of the backend `job-finished` event */
+export interface JobFinishedEvent {
+  job_id: number;
+  kind: JobKind;
+  status: JobStatus;
+  started_at: number | null;
+  finished_at: number | null;
+  error: string | null;
+  elapsed_secs: number;
+}
+
+/** What the UI knows about a flash, stored in the flash history */
+export interface FlashContext {
+  board?: string;
+  /** Name of the autoconfig profile applied; its contents are never stored */
+  profile_name?: string;
+}
+
+/** One finished flash or QDL job from the persistent flash history */
+export interface FlashRecord {
+  /** Unique across app runs */
+  id: string;
+  job_id: number;
+  kind: JobKind;
+  status: JobStatus;
+  image_name: string;
+  image_sha256: string | null;
+  image_size: number;
+  board: string | null;
+  device_path: string;
+  device_model: string | null;
+  device_serial: string | null;
+  device_size: number | null;
+  profile_name: string | null;
+  /** Unix times in milliseconds */
+  started_at: number;
+  finished_at: number;
+  duration_secs: number;
+  bytes_written: number;
+  throughput_mbps: number;
+  /** null when verification was off or never ran */
+  verified: boolean | null;
+  error: string | null;
+}
+
+/** Flash history query; unset fields match everything */
+export interface HistoryFilter {
+  board?: string;
+  /** Substring of the device path, model or serial */
+  device?: string;
+  status?: JobStatus;
+  /** Unix times in milliseconds, inclusive */
+  since?: number;
+  until?: number;
+  limit?: number;
+}
+
+export type ReportFormat = 'json' | 'csv';
+
+/** Partition table of an image, read before flashing */
+export interface ImageLayout {
+  scheme: 'GPT' | 'MBR';
+  sector_size: number;
+  partitions: ImagePartitionInfo[];
+}
+
+export interface ImagePartitionInfo {
+  /** 1-based table slot, passed to the browse calls */
+  index: number;
+  offset: number;
+  size: number;
+  /** GPT type GUID or MBR type byte ("0x83") */
+  type_id: string;
+  /** GPT partition name, empty on MBR */
+  name: string;
+  filesystem: 'ext4' | 'btrfs' | 'f2fs' | 'vfat' | 'unknown';
+}
+
+/** Metadata of a path inside an image partition; symlinks are not followed */
+export interface InspectFileStat {
+  kind: 'file' | 'dir' | 'symlink' | 'other';
+  size: number;
+  mode: number;
+  uid: number;
+  gid: number;
+  symlink_target: string | null;
+}
+
+export interface InspectDirEntry extends InspectFileStat {
+  name: string;
+}
+
+/** File contents from an image partition, cut at 1 MB */
+export interface InspectFileContent {
+  content: string;
+  size: number;
+  truncated: boolean;
+  /** Not valid UTF-8; content is only a preview */
+  binary: boolean;
+}
+
+/** A device-tree overlay (.dtbo) shipped in an image */
+export interface ImageOverlay {
+  name: string; // e.g., "uart1" - as listed in overlays=
+  path: string; // e.g., "/boot/dtb/rockchip/overlay/rockchip-uart1.dtbo"
+  size: number;
+  params: string[]; // Parameters declared in __overrides__
+}
+
+/** Overlays shipped in an image, with the overlay_prefix from armbianEnv.txt */
+export interface ImageOverlayListing {
+  prefix: string | null;
+  overlays: ImageOverlay[];
+}
+
 /** Manufacturer information for board categorization */
 export interface Manufacturer {
   id: string;
@@ -200,13 +413,20 @@ export interface CacheBreakdown {
 export interface ArmbianReleaseInfo {
   board: string; // e.g., "orangepi-5" - Board identifier for matching
   board_name: string; // e.g., "Orange Pi 5" - Human-readable board name for display
+  version?: string; // e.g., "25.8.1" - Armbian release
+  branch?: string; // e.g., "vendor" - Kernel branch
+  linux_family?: string; // e.g., "rockchip64" - Kernel family
 }
 
 /** Login shell for the first user provisioned via autoconfig */
 export type UserShell = 'bash' | 'zsh';
 
+/** Where the boot script sends the kernel console (`console=` in armbianEnv.txt) */
+export type BootConsole = 'serial' | 'display' | 'both';
+
 /** Armbian first-boot autoconfig settings; all fields optional, only set/non-empty values
- * are written into the image's /root/.not_logged_in_yet file. */
+ * are written into the image's /root/.not_logged_in_yet file, or for the boot settings
+ * into /boot/armbianEnv.txt. */
 export interface AutoconfigConfig {
   applyNetwork?: boolean;
   ethernetEnabled?: boolean;
@@ -230,6 +450,14 @@ export interface AutoconfigConfig {
   userShell?: UserShell;
   userRealName?: string;
   remoteConfigUrl?: string;
+  // Boot settings (armbianEnv.txt)
+  addOverlays?: string[]; // e.g., ["uart1", "i2c7"]
+  removeOverlays?: string[];
+  bootVerbosity?: number; // 0-7
+  bootConsole?: BootConsole;
+  extraArgs?: string;
+  fdtfile?: string; // e.g., "rockchip/rk3588-rock-5b.dtb"
+  rootdev?: string; // e.g., "UUID=..."
 }
 
 /** A named, client-side autoconfig profile the user can select before flashing */
@@ -9,8 +9,13 @@ export function isShaUnavailableError(error: string): boolean {
   return error.includes('[SHA_UNAVAILABLE]');
 }
 
-/** Map tagged backend flash errors ([WRITE_FAILED:offset], [QDL_*]) to translated messages */
+/** Map tagged backend flash errors ([WRITE_FAILED:offset], [DEVICE_REMOVED], [DEVICE_BUSY], [POLICY_DENIED], [QDL_*]) to translated messages */
 export function translateFlashError(error: string, t: TFn): string {
+  if (error.includes('[DEVICE_REMOVED]')) return t('error.deviceRemoved');
+  const busy = error.match(/\[DEVICE_BUSY\] (.*)/);
+  if (busy) return t('error.deviceBusy', { detail: busy[1] });
+  const denied = error.match(/\[POLICY_DENIED\] (.*)/);
+  if (denied) return t('error.policyDenied', { reason: denied[1] });
   const write = error.match(/\[WRITE_FAILED:(\d+)\]/);
   if (write) return t('error.writeFailed', { offset: formatBytes(Number(write[1])) });
   return translateQdlError(error, t);